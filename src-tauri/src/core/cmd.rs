@@ -1,13 +1,19 @@
 use super::window;
 use super::{
     library::{
-        self, Aelement, Alist, Artist, CoreResult, Library, Playlist, Recent, Release, Song,
+        self, Aelement, Alist, Artist, CoreResult, DirRules, DoctorReport, DuplicateGroup,
+        DuplicateMerge, DuplicateOptions, Library, ListenPath, LoudnessHandle, LoudnessOptions,
+        LoudnessStatus, Manifest, Playlist, Recent, Release, RootStatus, ScanHandle, ScanReport,
+        ScanStatus, Song, SongHit, SongPage, SongQuery,
     },
     pic,
-    playback::PlaybackService,
+    playback::{self, PlaybackService},
+    queue::{QueueSnapshot, QueueSource, RepeatMode},
 };
+use crate::store::json::entity::release::ReleaseKey;
 use log::debug;
 use std::{
+    collections::HashMap,
     path::PathBuf,
     sync::{Arc, Mutex},
 };
@@ -40,6 +46,25 @@ pub fn get_all_songs(library: State<'_, Arc<Mutex<Library>>>) -> Vec<Song> {
     library::get_all_songs(library)
 }
 
+#[tauri::command]
+pub fn query_songs(
+    library: State<'_, Arc<Mutex<Library>>>,
+    query: SongQuery,
+) -> CoreResult<SongPage> {
+    debug!("Received query_songs command");
+    library::query_songs(library, query)
+}
+
+#[tauri::command]
+pub fn search_songs(
+    library: State<'_, Arc<Mutex<Library>>>,
+    query: String,
+    limit: Option<usize>,
+) -> Vec<SongHit> {
+    debug!("Received search_songs command");
+    library::search_songs(library, query, limit)
+}
+
 #[tauri::command]
 pub fn get_all_releases(library: State<'_, Arc<Mutex<Library>>>) -> Vec<Release> {
     debug!("Received get_all_releases command");
@@ -76,22 +101,99 @@ pub fn get_glob_dirs(library: State<'_, Arc<Mutex<Library>>>) -> Vec<PathBuf> {
     library::get_glob_dirs(library)
 }
 
+/// starts a background scan and returns right away, follow it with `scan_status`
+/// or the `scan_progress` / `scan_state` events
 #[tauri::command]
-pub fn refresh_library(library: State<'_, Arc<Mutex<Library>>>) -> CoreResult<()> {
+pub fn refresh_library(
+    library: State<'_, Arc<Mutex<Library>>>,
+    scan: State<'_, ScanHandle>,
+) -> CoreResult<()> {
     debug!("Received refresh_library command");
-    library::refresh_library(library)
+    library::refresh_library(library, scan)
+}
+
+#[tauri::command]
+pub fn scan_status(scan: State<'_, ScanHandle>) -> ScanStatus {
+    debug!("Received scan_status command");
+    library::scan_status(scan)
+}
+
+#[tauri::command]
+pub fn get_scan_report(library: State<'_, Arc<Mutex<Library>>>) -> ScanReport {
+    debug!("Received get_scan_report command");
+    library::get_scan_report(library)
+}
+
+/// `files` None retries every reported file
+#[tauri::command]
+pub fn retry_scan_failures(
+    library: State<'_, Arc<Mutex<Library>>>,
+    files: Option<Vec<PathBuf>>,
+) -> CoreResult<ScanReport> {
+    debug!("Received retry_scan_failures command");
+    library::retry_scan_failures(library, files)
+}
+
+#[tauri::command]
+pub fn pause_scan(scan: State<'_, ScanHandle>) {
+    debug!("Received pause_scan command");
+    library::pause_scan(scan)
+}
+
+#[tauri::command]
+pub fn resume_scan(scan: State<'_, ScanHandle>) {
+    debug!("Received resume_scan command");
+    library::resume_scan(scan)
+}
+
+#[tauri::command]
+pub fn cancel_scan(scan: State<'_, ScanHandle>) {
+    debug!("Received cancel_scan command");
+    library::cancel_scan(scan)
 }
 
 #[tauri::command]
-pub fn add_dir(library: State<'_, Arc<Mutex<Library>>>, dir: PathBuf) -> CoreResult<()> {
+pub fn add_dir(
+    library: State<'_, Arc<Mutex<Library>>>,
+    scan: State<'_, ScanHandle>,
+    dir: PathBuf,
+    rules: Option<DirRules>,
+) -> CoreResult<()> {
     debug!("Received add_dir command");
-    library::add_dir(library, dir)
+    library::add_dir(library, scan, dir, rules)
+}
+
+#[tauri::command]
+pub fn get_listen_paths(library: State<'_, Arc<Mutex<Library>>>) -> Vec<ListenPath> {
+    debug!("Received get_listen_paths command");
+    library::get_listen_paths(library)
 }
 
 #[tauri::command]
-pub fn remove_dir(library: State<'_, Arc<Mutex<Library>>>, dir: PathBuf) -> CoreResult<()> {
+pub fn get_root_status(library: State<'_, Arc<Mutex<Library>>>) -> Vec<RootStatus> {
+    debug!("Received get_root_status command");
+    library::get_root_status(library)
+}
+
+#[tauri::command]
+pub fn set_dir_rules(
+    library: State<'_, Arc<Mutex<Library>>>,
+    scan: State<'_, ScanHandle>,
+    dir: PathBuf,
+    rules: DirRules,
+) -> CoreResult<()> {
+    debug!("Received set_dir_rules command");
+    library::set_dir_rules(library, scan, dir, rules)
+}
+
+#[tauri::command]
+pub fn remove_dir(
+    library: State<'_, Arc<Mutex<Library>>>,
+    scan: State<'_, ScanHandle>,
+    dir: PathBuf,
+) -> CoreResult<()> {
     debug!("Received remove_dir command");
-    library::remove_dir(library, dir)
+    library::remove_dir(library, scan, dir)
 }
 
 #[tauri::command]
@@ -338,6 +440,128 @@ pub fn player_set_volume(
     Ok(())
 }
 
+#[tauri::command]
+pub fn player_get_queue(playback_service: State<'_, Arc<Mutex<PlaybackService>>>) -> QueueSnapshot {
+    debug!("Received player_get_queue command");
+    playback_service.lock().unwrap().queue()
+}
+
+#[tauri::command]
+pub fn player_play_queue(
+    library: State<'_, Arc<Mutex<Library>>>,
+    playback_service: State<'_, Arc<Mutex<PlaybackService>>>,
+    source: QueueSource,
+    start: Option<usize>,
+) -> CoreResult<()> {
+    debug!("Received player_play_queue command");
+    let tracks = library::resolve_queue_source(library, source)?;
+    playback_service
+        .lock()
+        .unwrap()
+        .play_queue(tracks, start.unwrap_or_default())
+}
+
+#[tauri::command]
+pub fn player_enqueue(
+    library: State<'_, Arc<Mutex<Library>>>,
+    playback_service: State<'_, Arc<Mutex<PlaybackService>>>,
+    source: QueueSource,
+) -> CoreResult<()> {
+    debug!("Received player_enqueue command");
+    let tracks = library::resolve_queue_source(library, source)?;
+    playback_service.lock().unwrap().enqueue(tracks);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn player_insert_next(
+    library: State<'_, Arc<Mutex<Library>>>,
+    playback_service: State<'_, Arc<Mutex<PlaybackService>>>,
+    source: QueueSource,
+) -> CoreResult<()> {
+    debug!("Received player_insert_next command");
+    let tracks = library::resolve_queue_source(library, source)?;
+    playback_service.lock().unwrap().insert_next(tracks);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn player_remove_from_queue(
+    indices: Vec<usize>,
+    playback_service: State<'_, Arc<Mutex<PlaybackService>>>,
+) -> CoreResult<()> {
+    debug!("Received player_remove_from_queue command");
+    playback_service.lock().unwrap().remove_from_queue(indices)
+}
+
+#[tauri::command]
+pub fn player_move_in_queue(
+    from: usize,
+    to: usize,
+    playback_service: State<'_, Arc<Mutex<PlaybackService>>>,
+) -> CoreResult<()> {
+    debug!("Received player_move_in_queue command");
+    playback_service.lock().unwrap().move_in_queue(from, to);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn player_jump(
+    index: usize,
+    playback_service: State<'_, Arc<Mutex<PlaybackService>>>,
+) -> CoreResult<()> {
+    debug!("Received player_jump command");
+    playback_service.lock().unwrap().jump(index)
+}
+
+#[tauri::command]
+pub fn player_clear_queue(
+    playback_service: State<'_, Arc<Mutex<PlaybackService>>>,
+) -> CoreResult<()> {
+    debug!("Received player_clear_queue command");
+    playback_service.lock().unwrap().clear_queue();
+    Ok(())
+}
+
+#[tauri::command]
+pub fn player_next(playback_service: State<'_, Arc<Mutex<PlaybackService>>>) -> CoreResult<()> {
+    debug!("Received player_next command");
+    playback_service.lock().unwrap().next()
+}
+
+#[tauri::command]
+pub fn player_prev(playback_service: State<'_, Arc<Mutex<PlaybackService>>>) -> CoreResult<()> {
+    debug!("Received player_prev command");
+    playback_service.lock().unwrap().prev()
+}
+
+#[tauri::command]
+pub fn player_set_repeat(
+    repeat: RepeatMode,
+    playback_service: State<'_, Arc<Mutex<PlaybackService>>>,
+) -> CoreResult<()> {
+    debug!("Received player_set_repeat command");
+    playback_service.lock().unwrap().set_repeat(repeat);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn player_set_shuffle(
+    shuffle: bool,
+    playback_service: State<'_, Arc<Mutex<PlaybackService>>>,
+) -> CoreResult<()> {
+    debug!("Received player_set_shuffle command");
+    playback_service.lock().unwrap().set_shuffle(shuffle);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn set_release_gapless(title: String, artist: Option<String>, gapless: bool) -> CoreResult<()> {
+    debug!("Received set_release_gapless command");
+    playback::set_release_gapless(ReleaseKey { title, artist }, gapless);
+    Ok(())
+}
+
 #[tauri::command]
 pub fn create_alist(library: State<'_, Arc<Mutex<Library>>>, alist_name: String) -> CoreResult<()> {
     debug!("Received create_alist command");
@@ -461,3 +685,82 @@ pub fn freeze_alist(library: State<'_, Arc<Mutex<Library>>>, alist_name: String)
     debug!("Received freeze_alist command");
     library::freeze_alist(library, alist_name)
 }
+
+#[tauri::command]
+pub fn export_library(
+    library: State<'_, Arc<Mutex<Library>>>,
+    path: PathBuf,
+    with_caches: bool,
+) -> CoreResult<Manifest> {
+    debug!("Received export_library command");
+    library::export_library(library, path, with_caches)
+}
+
+#[tauri::command]
+pub fn read_library_archive(path: PathBuf) -> CoreResult<Manifest> {
+    debug!("Received read_library_archive command");
+    library::read_library_archive(path)
+}
+
+#[tauri::command]
+pub fn library_doctor(
+    library: State<'_, Arc<Mutex<Library>>>,
+    scan: State<'_, ScanHandle>,
+    repair: bool,
+) -> CoreResult<DoctorReport> {
+    debug!("Received library_doctor command");
+    library::library_doctor(library, scan, repair)
+}
+
+#[tauri::command]
+pub fn find_duplicates(
+    library: State<'_, Arc<Mutex<Library>>>,
+    options: Option<DuplicateOptions>,
+) -> Vec<DuplicateGroup> {
+    debug!("Received find_duplicates command");
+    library::find_duplicates(library, options)
+}
+
+#[tauri::command]
+pub fn merge_duplicates(
+    library: State<'_, Arc<Mutex<Library>>>,
+    scan: State<'_, ScanHandle>,
+    merges: Vec<DuplicateMerge>,
+) -> CoreResult<()> {
+    debug!("Received merge_duplicates command");
+    library::merge_duplicates(library, scan, merges)
+}
+
+#[tauri::command]
+pub fn analyze_loudness(
+    library: State<'_, Arc<Mutex<Library>>>,
+    loudness: State<'_, LoudnessHandle>,
+    options: Option<LoudnessOptions>,
+) -> CoreResult<()> {
+    debug!("Received analyze_loudness command");
+    library::analyze_loudness(library, loudness, options)
+}
+
+#[tauri::command]
+pub fn loudness_status(loudness: State<'_, LoudnessHandle>) -> LoudnessStatus {
+    debug!("Received loudness_status command");
+    library::loudness_status(loudness)
+}
+
+#[tauri::command]
+pub fn cancel_loudness_analysis(loudness: State<'_, LoudnessHandle>) {
+    debug!("Received cancel_loudness_analysis command");
+    library::cancel_loudness_analysis(loudness)
+}
+
+#[tauri::command]
+pub fn import_library(
+    library: State<'_, Arc<Mutex<Library>>>,
+    scan: State<'_, ScanHandle>,
+    path: PathBuf,
+    roots: HashMap<PathBuf, PathBuf>,
+    with_caches: bool,
+) -> CoreResult<Manifest> {
+    debug!("Received import_library command");
+    library::import_library(library, scan, path, roots, with_caches)
+}
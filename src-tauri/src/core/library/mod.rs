@@ -1,6 +1,9 @@
 pub mod model;
+use crate::core::queue::{QueueSource, QueueTrack};
+use crate::store::json::entity::release::ReleaseKey;
 pub use model::*;
 use std::{
+    collections::HashMap,
     path::PathBuf,
     sync::{Arc, Mutex},
 };
@@ -14,6 +17,21 @@ pub fn get_all_songs(library: State<'_, Arc<Mutex<Library>>>) -> Vec<Song> {
     library.lock().unwrap().song_info.clone()
 }
 
+pub fn query_songs(
+    library: State<'_, Arc<Mutex<Library>>>,
+    query: SongQuery,
+) -> CoreResult<SongPage> {
+    query.run(&library.lock().unwrap().song_info)
+}
+
+pub fn search_songs(
+    library: State<'_, Arc<Mutex<Library>>>,
+    query: String,
+    limit: Option<usize>,
+) -> Vec<SongHit> {
+    library.lock().unwrap().search(&query, limit.unwrap_or(50))
+}
+
 pub fn delete_song_file(library: State<'_, Arc<Mutex<Library>>>, song: PathBuf) -> CoreResult<()> {
     library
         .lock()
@@ -57,16 +75,82 @@ pub fn get_glob_dirs(library: State<'_, Arc<Mutex<Library>>>) -> Vec<PathBuf> {
     library.lock().unwrap().dir_path.clone()
 }
 
-pub fn refresh_library(library: State<'_, Arc<Mutex<Library>>>) -> CoreResult<()> {
-    library.lock().unwrap().load()
+pub fn refresh_library(
+    library: State<'_, Arc<Mutex<Library>>>,
+    scan: State<'_, ScanHandle>,
+) -> CoreResult<()> {
+    scan.start(library.inner().clone());
+    Ok(())
+}
+
+pub fn scan_status(scan: State<'_, ScanHandle>) -> ScanStatus {
+    scan.status()
+}
+
+pub fn get_scan_report(library: State<'_, Arc<Mutex<Library>>>) -> ScanReport {
+    library.lock().unwrap().scan_report.clone()
+}
+
+pub fn retry_scan_failures(
+    library: State<'_, Arc<Mutex<Library>>>,
+    files: Option<Vec<PathBuf>>,
+) -> CoreResult<ScanReport> {
+    library.lock().unwrap().retry_failed(files)
+}
+
+pub fn pause_scan(scan: State<'_, ScanHandle>) {
+    scan.pause()
+}
+
+pub fn resume_scan(scan: State<'_, ScanHandle>) {
+    scan.resume()
+}
+
+pub fn cancel_scan(scan: State<'_, ScanHandle>) {
+    scan.cancel()
 }
 
-pub fn add_dir(library: State<'_, Arc<Mutex<Library>>>, dir: PathBuf) -> CoreResult<()> {
-    library.lock().unwrap().add_dir(dir)
+pub fn add_dir(
+    library: State<'_, Arc<Mutex<Library>>>,
+    scan: State<'_, ScanHandle>,
+    dir: PathBuf,
+    rules: Option<DirRules>,
+) -> CoreResult<()> {
+    library
+        .lock()
+        .unwrap()
+        .add_dir(dir, rules.unwrap_or_default())?;
+    scan.start(library.inner().clone());
+    Ok(())
 }
 
-pub fn remove_dir(library: State<'_, Arc<Mutex<Library>>>, dir: PathBuf) -> CoreResult<()> {
-    library.lock().unwrap().remove_dir(dir)
+pub fn get_listen_paths(library: State<'_, Arc<Mutex<Library>>>) -> Vec<ListenPath> {
+    library.lock().unwrap().fs.listen_paths()
+}
+
+pub fn get_root_status(library: State<'_, Arc<Mutex<Library>>>) -> Vec<RootStatus> {
+    library.lock().unwrap().root_status()
+}
+
+pub fn set_dir_rules(
+    library: State<'_, Arc<Mutex<Library>>>,
+    scan: State<'_, ScanHandle>,
+    dir: PathBuf,
+    rules: DirRules,
+) -> CoreResult<()> {
+    library.lock().unwrap().set_dir_rules(dir, rules)?;
+    scan.start(library.inner().clone());
+    Ok(())
+}
+
+pub fn remove_dir(
+    library: State<'_, Arc<Mutex<Library>>>,
+    scan: State<'_, ScanHandle>,
+    dir: PathBuf,
+) -> CoreResult<()> {
+    library.lock().unwrap().remove_dir(dir)?;
+    scan.start(library.inner().clone());
+    Ok(())
 }
 
 pub fn modify(library: State<'_, Arc<Mutex<Library>>>, song: Song) -> CoreResult<()> {
@@ -389,18 +473,148 @@ pub fn list_all_alist_songs(
     library: State<'_, Arc<Mutex<Library>>>,
     name: String,
 ) -> CoreResult<Vec<PathBuf>> {
-    library
-        .lock()
-        .unwrap()
-        .alist_controller
-        .list_all_songs(name)
+    let lib = library.lock().unwrap();
+    lib.alist_controller.list_all_songs(name, &lib.song_info)
 }
 
 pub fn freeze_alist(library: State<'_, Arc<Mutex<Library>>>, name: String) -> CoreResult<()> {
     let mut lib = library.lock().unwrap();
-    let playlist = lib.alist_controller.freeze(name)?;
+    let playlist = lib.alist_controller.freeze(name, &lib.song_info)?;
     lib.playlist_controller.add(&playlist)?;
     lib.playlist = lib.playlist_controller.get_all_playlists()?;
     lib.alist = lib.alist_controller.get_all_alists()?;
     Ok(())
 }
+
+pub fn export_library(
+    library: State<'_, Arc<Mutex<Library>>>,
+    path: PathBuf,
+    with_caches: bool,
+) -> CoreResult<Manifest> {
+    library.lock().unwrap().export_archive(&path, with_caches)
+}
+
+pub fn read_library_archive(path: PathBuf) -> CoreResult<Manifest> {
+    crate::library::archive::read_manifest(&path)
+}
+
+/// a repair rewrites stores a running scan would write over again
+pub fn library_doctor(
+    library: State<'_, Arc<Mutex<Library>>>,
+    scan: State<'_, ScanHandle>,
+    repair: bool,
+) -> CoreResult<DoctorReport> {
+    if repair && scan.is_running() {
+        return Err(CoreError::OtherError(
+            "Cannot repair the library while a scan is running".to_string(),
+        ));
+    }
+    crate::library::doctor::doctor(&library, repair)
+}
+
+pub fn find_duplicates(
+    library: State<'_, Arc<Mutex<Library>>>,
+    options: Option<DuplicateOptions>,
+) -> Vec<DuplicateGroup> {
+    crate::library::duplicates::find_duplicates(&library, &options.unwrap_or_default())
+}
+
+pub fn merge_duplicates(
+    library: State<'_, Arc<Mutex<Library>>>,
+    scan: State<'_, ScanHandle>,
+    merges: Vec<DuplicateMerge>,
+) -> CoreResult<()> {
+    if scan.is_running() {
+        return Err(CoreError::OtherError(
+            "Cannot merge duplicates while a scan is running".to_string(),
+        ));
+    }
+    crate::library::duplicates::merge_duplicates(&library, merges)
+}
+
+/// starts measuring in the background, follow it with `loudness_status`
+/// or the `loudness_progress` / `loudness_state` events
+pub fn analyze_loudness(
+    library: State<'_, Arc<Mutex<Library>>>,
+    loudness: State<'_, LoudnessHandle>,
+    options: Option<LoudnessOptions>,
+) -> CoreResult<()> {
+    if !loudness.start(library.inner().clone(), options.unwrap_or_default()) {
+        return Err(CoreError::OtherError(
+            "A loudness analysis is already running".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub fn loudness_status(loudness: State<'_, LoudnessHandle>) -> LoudnessStatus {
+    loudness.status()
+}
+
+pub fn cancel_loudness_analysis(loudness: State<'_, LoudnessHandle>) {
+    loudness.cancel()
+}
+
+/// the library's tracks for a queue source, songs of offline roots left out
+pub fn resolve_queue_source(
+    library: State<'_, Arc<Mutex<Library>>>,
+    source: QueueSource,
+) -> CoreResult<Vec<QueueTrack>> {
+    let lib = library.lock().unwrap();
+    let paths = match source {
+        QueueSource::Paths { paths } => paths,
+        QueueSource::Playlist { name } => {
+            let by_id = songs_by_id(&lib.song_info);
+            lib.playlist_controller
+                .get_playlist(name)?
+                .songs
+                .iter()
+                .filter_map(|id| by_id.get(id.as_str()).map(|song| song.path.clone()))
+                .collect()
+        }
+        QueueSource::Alist { name } => lib.alist_controller.list_all_songs(name, &lib.song_info)?,
+        QueueSource::Release { title, artist } => lib
+            .release_info
+            .iter()
+            .find(|release| release.title == title && release.artist == artist)
+            .ok_or_else(|| CoreError::OtherError(format!("Release {title:?} not found")))?
+            .songs
+            .iter()
+            .map(PathBuf::from)
+            .collect(),
+    };
+    let by_path = lib
+        .song_info
+        .iter()
+        .map(|song| (&song.path, song))
+        .collect::<HashMap<&PathBuf, &Song>>();
+    Ok(paths
+        .into_iter()
+        .filter(|path| !by_path.get(path).is_some_and(|song| song.unavailable))
+        .map(|path| QueueTrack {
+            release: by_path.get(&path).map(|song| ReleaseKey::of(song)),
+            path,
+        })
+        .collect())
+}
+
+pub fn import_library(
+    library: State<'_, Arc<Mutex<Library>>>,
+    scan: State<'_, ScanHandle>,
+    path: PathBuf,
+    roots: HashMap<PathBuf, PathBuf>,
+    with_caches: bool,
+) -> CoreResult<Manifest> {
+    // 扫描结束时会把它读到的整个 song store 写回去, 覆盖掉导入的
+    if scan.is_running() {
+        return Err(CoreError::OtherError(
+            "Cannot import while a library scan is running".to_string(),
+        ));
+    }
+    let manifest = library
+        .lock()
+        .unwrap()
+        .import_archive(&path, roots, with_caches)?;
+    scan.start(library.inner().clone());
+    Ok(manifest)
+}
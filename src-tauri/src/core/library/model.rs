@@ -5,7 +5,7 @@ pub use crate::store::json::entity::{
     list::Playlist,
     recent::{Recent, Recents},
     release::Release,
-    song::Song,
+    song::{songs_by_id, Song},
 };
 
 #[allow(unused_imports)]
@@ -24,5 +24,32 @@ pub use crate::store::json::controller::{
 #[allow(unused_imports)]
 pub use crate::library::library::Library;
 
+#[allow(unused_imports)]
+pub use crate::library::scan::{ScanHandle, ScanReport, ScanStatus};
+
+#[allow(unused_imports)]
+pub use crate::library::archive::Manifest;
+
+#[allow(unused_imports)]
+pub use crate::library::dir::{DirRules, ListenPath};
+
+#[allow(unused_imports)]
+pub use crate::library::roots::RootStatus;
+
+#[allow(unused_imports)]
+pub use crate::library::doctor::DoctorReport;
+
+#[allow(unused_imports)]
+pub use crate::library::duplicates::{DuplicateGroup, DuplicateMerge, DuplicateOptions};
+
+#[allow(unused_imports)]
+pub use crate::library::loudness::{LoudnessHandle, LoudnessOptions, LoudnessStatus};
+
+#[allow(unused_imports)]
+pub use crate::library::query::{SongPage, SongQuery};
+
+#[allow(unused_imports)]
+pub use crate::library::search::SongHit;
+
 // #[allow(unused_imports)]
 // pub use crate::meta::lyric::{LyricLineContent, LyricMap, LyricWord, ParsedLyric};
@@ -2,4 +2,5 @@ pub mod cmd;
 pub mod library;
 pub mod pic;
 pub mod playback;
+pub mod queue;
 pub mod window;
@@ -1,16 +1,28 @@
-use crate::misc::error::{CoreError, CoreResult};
+use super::queue::{PlayQueue, QueueSnapshot, QueueTrack, RepeatMode};
+use crate::{
+    library::loudness::LoudnessHandle,
+    meta::{loudness, probe},
+    misc::{
+        config::get_global,
+        error::{CoreError, CoreResult},
+    },
+    store::json::entity::release::ReleaseKey,
+};
 use anyhow::Context;
 use kira::{
+    clock::{ClockHandle, ClockSpeed, ClockTime},
     manager::{backend::cpal::CpalBackend, AudioManager},
     sound::{
         streaming::{StreamingSoundData, StreamingSoundHandle, StreamingSoundSettings},
         FromFileError, PlaybackState,
     },
     tween::Tween,
+    StartTime,
 };
+use lofty::file::AudioFile;
 use log::{debug, warn};
 use serde::Serialize;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
@@ -38,6 +50,200 @@ pub struct PlaybackEndedPayload {
     pub path: PathBuf,
 }
 
+/// `prev` further into the track than this goes back to its start instead
+const PREV_RESTART_SECS: f64 = 3.0;
+/// the next track is opened and starts decoding this long before the current one ends
+const PRELOAD_SECS: f64 = 10.0;
+/// finer than one frame at any usual output rate, so the next track lands on the exact frame
+const CLOCK_TICKS_PER_SECOND: f64 = 200_000.0;
+
+// 无缝播放: 当前这首开始 (或者 seek / 继续播放) 的时候起一个 clock, 记下这首在 clock 上哪一刻放完;
+// 快放完时把下一首提前打开, 排在那一刻开始, kira 会在那一帧准时接上.
+// 暂停 / seek 以后位置对不上了, 已经排好的下一首撤掉, 重新起 clock 再排.
+// seek / 继续播放是音频线程下一个 block 才生效的, 不能拿发命令的时间算, 要等 clock 走起来以后
+// 用声音自己报的位置 (按采样率对齐到帧) 倒推
+
+fn gapless_releases() -> Vec<ReleaseKey> {
+    get_global()
+        .get("gapless_releases")
+        .and_then(|v| serde_json::from_value(v).ok())
+        .unwrap_or_default()
+}
+
+/// consecutive tracks of a release flagged in `gapless_releases`
+fn within_gapless_release(current: &QueueTrack, next: &QueueTrack) -> bool {
+    current.release.is_some()
+        && current.release == next.release
+        && gapless_releases()
+            .iter()
+            .any(|release| current.release.as_ref() == Some(release))
+}
+
+/// config `gapless` turns it on for every transition, flagged releases always are
+fn gapless_between(current: &QueueTrack, next: &QueueTrack) -> bool {
+    within_gapless_release(current, next)
+        || get_global()
+            .get("gapless")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true)
+}
+
+/// flag or unflag a release as gapless in the config
+pub fn set_release_gapless(release: ReleaseKey, gapless: bool) {
+    let mut releases = gapless_releases();
+    releases.retain(|r| *r != release);
+    if gapless {
+        releases.push(release);
+    }
+    get_global().set("gapless_releases", serde_json::json!(releases));
+}
+
+fn config_duration(key: &str, default_ms: u64) -> Duration {
+    Duration::from_millis(
+        get_global()
+            .get(key)
+            .and_then(|v| v.as_u64())
+            .unwrap_or(default_ms),
+    )
+}
+
+/// `pause_fade_ms` for pause / resume, `stop_fade_ms` for stop
+fn fade(key: &str) -> Tween {
+    Tween {
+        duration: config_duration(key, 10),
+        ..Default::default()
+    }
+}
+
+/// `crossfade_ms`, 0 is off
+fn crossfade() -> Duration {
+    config_duration("crossfade_ms", 0)
+}
+
+fn fade_at(start: ClockTime, duration: Duration) -> Tween {
+    Tween {
+        start_time: StartTime::ClockTime(start),
+        duration,
+        ..Default::default()
+    }
+}
+
+/// `replay_gain`: "off", "track" or "album"; `replay_gain_preamp_db` on top.
+/// lowered where it would push the peak over full scale. a track with neither tags
+/// nor a measurement gets `replay_gain_default_db` until `PlaybackService::measured`
+fn replay_gain(path: &Path, loudness_handle: &LoudnessHandle) -> f64 {
+    let config = get_global();
+    let mode = config
+        .get("replay_gain")
+        .and_then(|v| v.as_str().map(|s| s.to_string()))
+        .unwrap_or_else(|| "off".to_string());
+    if mode == "off" {
+        return 1.0;
+    }
+    let preamp = config
+        .get("replay_gain_preamp_db")
+        .and_then(|v| v.as_f64())
+        .unwrap_or(0.0);
+    let tags = loudness::read_replay_gain(path);
+    let (gain, peak) = match mode.as_str() {
+        "album" => (
+            tags.album_gain.or(tags.track_gain),
+            tags.album_peak.or(tags.track_peak),
+        ),
+        _ => (
+            tags.track_gain.or(tags.album_gain),
+            tags.track_peak.or(tags.album_peak),
+        ),
+    };
+    // 没有标签就用自己测的响度, 还没测过的先用默认值顶着, 后台测好以后再改过来
+    let (gain, peak) = match gain {
+        Some(gain) => (gain, peak),
+        None => match loudness::cached(&path.to_path_buf()) {
+            Some(measured) => (measured.gain(), Some(measured.true_peak)),
+            None => {
+                loudness_handle.measure(path.to_path_buf());
+                let default = config
+                    .get("replay_gain_default_db")
+                    .and_then(|v| v.as_f64())
+                    .unwrap_or(0.0);
+                (default, None)
+            }
+        },
+    };
+    let factor = 10f64.powf((gain + preamp) / 20.0);
+    match peak {
+        Some(peak) if peak > 0.0 => f64::min(factor, 1.0 / peak),
+        _ => factor,
+    }
+}
+
+/// from the file's header, 0 if it can't be read
+fn sample_rate(path: &Path) -> u32 {
+    probe::open(path)
+        .ok()
+        .and_then(|probe| probe.read().ok())
+        .and_then(|file| file.properties().sample_rate())
+        .unwrap_or_default()
+}
+
+/// the next track to open, from `plan_next`
+struct Planned {
+    track: QueueTrack,
+    start: ClockTime,
+    /// clock seconds of `start`
+    starts_at: f64,
+    /// of the two tracks when crossfading
+    overlap: Option<Duration>,
+    volume: f64,
+    loudness: LoudnessHandle,
+}
+
+/// the next track opened, ready for `schedule_next`
+struct Opened {
+    planned: Planned,
+    sound_data: StreamingSoundData<FromFileError>,
+    gain: f64,
+    sample_rate: u32,
+}
+
+impl Planned {
+    /// reads the tags and opens the stream, without the service's lock
+    fn open(self) -> Option<Opened> {
+        let gain = replay_gain(&self.track.path, &self.loudness);
+        let mut settings = StreamingSoundSettings::new()
+            .volume(self.volume * gain)
+            .start_time(self.start);
+        if let Some(overlap) = self.overlap {
+            settings = settings.fade_in_tween(fade_at(self.start, overlap));
+        }
+        let sound_data = match StreamingSoundData::from_file(&self.track.path, settings) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Failed to pre-open {:?}: {e}", self.track.path);
+                return None;
+            }
+        };
+        Some(Opened {
+            sample_rate: sample_rate(&self.track.path),
+            planned: self,
+            sound_data,
+            gain,
+        })
+    }
+}
+
+/// the next track, already playing into its scheduled start
+struct Prepared {
+    track: QueueTrack,
+    handle: StreamingSoundHandle<FromFileError>,
+    duration: f64,
+    sample_rate: u32,
+    gain: f64,
+    /// clock seconds it starts at, before the current track's end when crossfading
+    starts_at: f64,
+    crossfade: bool,
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 enum InternalState {
     Playing,
@@ -51,12 +257,30 @@ pub struct PlaybackService {
     sound_handle: Arc<Mutex<Option<StreamingSoundHandle<FromFileError>>>>,
     state: Arc<Mutex<InternalState>>,
     current_path: Arc<Mutex<Option<PathBuf>>>,
+    queue: PlayQueue,
+    volume: f64,
+    /// replay gain of the current track, as a factor on `volume`
+    gain: f64,
+    duration: f64,
+    /// the queue entry playing, without a release for `play_file`
+    track: Option<QueueTrack>,
+    /// started where the current track's end is known exactly, see `anchor`
+    clock: Option<ClockHandle>,
+    /// clock seconds at which the current track runs out, `None` after a seek or
+    /// resume until it's read back from the sound, see `ends_at`
+    ends_at: Option<f64>,
+    /// of the current track, to put its position on a frame; 0 if unknown
+    sample_rate: u32,
+    next: Option<Prepared>,
+    /// measures tracks without replay gain tags
+    loudness: LoudnessHandle,
 }
 
 impl PlaybackService {
     pub fn new(
         app: AppHandle,
         audio_manager: AudioManager<CpalBackend>,
+        loudness: LoudnessHandle,
     ) -> CoreResult<Arc<Mutex<Self>>> {
         Ok(Arc::new(Mutex::new(Self {
             app,
@@ -64,15 +288,58 @@ impl PlaybackService {
             sound_handle: Arc::new(Mutex::new(None)),
             state: Arc::new(Mutex::new(InternalState::Stopped)),
             current_path: Arc::new(Mutex::new(None)),
+            queue: PlayQueue::default(),
+            volume: 1.0,
+            gain: 1.0,
+            duration: 0.0,
+            track: None,
+            clock: None,
+            ends_at: None,
+            sample_rate: 0,
+            next: None,
+            loudness,
         })))
     }
 
+    /// a file on its own, outside of any release
     pub fn play_file(&mut self, path: PathBuf) -> CoreResult<()> {
-        debug!("PlaybackService: play_file: {:?}", &path);
+        self.play_track(QueueTrack {
+            path,
+            release: None,
+        })
+    }
+
+    fn play_track(&mut self, track: QueueTrack) -> CoreResult<()> {
+        debug!("PlaybackService: play_track: {:?}", &track.path);
 
-        self.stop_internal();
+        // 正在放的时候换歌, 开了淡入淡出就让两首叠一会儿; 同一张无缝专辑里的不叠
+        let crossfade = match (*self.state.lock().unwrap(), &self.track) {
+            (InternalState::Playing, Some(current)) if within_gapless_release(current, &track) => {
+                Duration::ZERO
+            }
+            (InternalState::Playing, _) => crossfade(),
+            _ => Duration::ZERO,
+        };
+        let path = track.path.clone();
+        self.stop_internal(match crossfade.is_zero() {
+            true => fade("stop_fade_ms"),
+            false => Tween {
+                duration: crossfade,
+                ..Default::default()
+            },
+        });
 
-        let sound_data = StreamingSoundData::from_file(&path, StreamingSoundSettings::default())
+        // 这首挂在新 clock 的第 0 个 tick 上, clock 一启动两边是同一帧
+        self.clock = self.new_clock();
+        let gain = replay_gain(&path, &self.loudness);
+        let mut settings = StreamingSoundSettings::new().volume(self.volume * gain);
+        if let Some(clock) = &self.clock {
+            settings = settings.start_time(clock.time());
+            if !crossfade.is_zero() {
+                settings = settings.fade_in_tween(fade_at(clock.time(), crossfade));
+            }
+        }
+        let sound_data = StreamingSoundData::from_file(&path, settings)
             .with_context(|| format!("Failed to load sound from file: {:?}", &path))
             .map_err(|e| CoreError::OtherError(e.to_string()))?;
 
@@ -84,44 +351,206 @@ impl PlaybackService {
             .unwrap()
             .play(sound_data)
             .map_err(|e| CoreError::OtherError(format!("Failed to play sound: {e}")))?;
+        if let Some(clock) = self.clock.as_mut() {
+            clock.start().ok();
+        }
+        self.gain = gain;
+        self.duration = duration_sec;
+        self.ends_at = Some(duration_sec);
+        self.sample_rate = sample_rate(&path);
+        self.track = Some(track);
 
         *self.sound_handle.lock().unwrap() = Some(handle);
+        self.loaded(path, duration_sec)
+    }
+
+    fn loaded(&mut self, path: PathBuf, duration: f64) -> CoreResult<()> {
         *self.current_path.lock().unwrap() = Some(path.clone());
         self.set_state(InternalState::Playing);
 
         self.app
             .emit(
                 "playback-track-loaded",
-                PlaybackTrackLoadedPayload {
-                    duration: duration_sec,
-                    path: path.clone(),
-                },
+                PlaybackTrackLoadedPayload { duration, path },
             )
             .map_err(CoreError::TauriError)?;
 
         Ok(())
     }
 
+    fn new_clock(&self) -> Option<ClockHandle> {
+        self.audio_manager
+            .lock()
+            .unwrap()
+            .add_clock(ClockSpeed::TicksPerSecond(CLOCK_TICKS_PER_SECOND))
+            .map_err(|e| warn!("Failed to add a playback clock: {e}"))
+            .ok()
+    }
+
+    fn clock_secs(&self) -> Option<f64> {
+        self.clock
+            .as_ref()
+            .map(|clock| clock.time().ticks as f64 / CLOCK_TICKS_PER_SECOND)
+    }
+
+    /// restart the clock after a seek or resume; the end gets read back from the
+    /// sound once the clock has moved, see `ends_at`
+    fn anchor(&mut self) {
+        self.cancel_next();
+        self.clock = self.new_clock();
+        if let Some(clock) = self.clock.as_mut() {
+            clock.start().ok();
+        }
+        self.ends_at = None;
+    }
+
+    /// clock seconds at which the current track runs out. after `anchor` it's the
+    /// clock time plus what's left from the sound's own position, rounded to frames.
+    /// the seek / resume went in before the clock's start, so once the clock has
+    /// ticked the position is past it; `None` until then
+    fn ends_at(&mut self) -> Option<f64> {
+        if self.ends_at.is_some() {
+            return self.ends_at;
+        }
+        let elapsed = self.clock_secs().filter(|elapsed| *elapsed > 0.0)?;
+        let position = self.sound_handle.lock().unwrap().as_ref()?.position();
+        let remaining = match self.sample_rate {
+            0 => self.duration - position,
+            rate => {
+                let rate = rate as f64;
+                ((self.duration * rate).round() - (position * rate).round()) / rate
+            }
+        };
+        self.ends_at = Some(elapsed + f64::max(remaining, 0.0));
+        self.ends_at
+    }
+
+    fn cancel_next(&mut self) {
+        let Some(mut next) = self.next.take() else {
+            return;
+        };
+        next.handle.stop(Tween::default()).ok();
+        // 当前这首已经排了淡出, 撤回来
+        if next.crossfade {
+            if let Some(handle) = self.sound_handle.lock().unwrap().as_mut() {
+                handle
+                    .set_volume(self.volume * self.gain, Tween::default())
+                    .ok();
+            }
+        }
+    }
+
+    /// what to open next and when, once the current track's end is near: on its last
+    /// frame, or `crossfade_ms` before it with the two fading across. tracks of a
+    /// gapless release never crossfade. the emitter calls it every tick
+    fn plan_next(&mut self) -> Option<Planned> {
+        if self.next.is_some() || *self.state.lock().unwrap() != InternalState::Playing {
+            return None;
+        }
+        let ends_at = self.ends_at()?;
+        let (clock, elapsed) = (self.clock.as_ref()?, self.clock_secs()?);
+        let configured = crossfade();
+        if ends_at < elapsed || ends_at - configured.as_secs_f64() - elapsed >= PRELOAD_SECS {
+            return None;
+        }
+        let (current, next) = (self.queue.current()?, self.queue.peek_next()?);
+        let crossfade = match within_gapless_release(current, next) {
+            true => Duration::ZERO,
+            false => configured,
+        };
+        if crossfade.is_zero() && !gapless_between(current, next) {
+            return None;
+        }
+        // 歌比淡入淡出还短的话从现在开始叠
+        let starts_at = f64::max(ends_at - crossfade.as_secs_f64(), elapsed);
+        Some(Planned {
+            track: next.clone(),
+            start: ClockTime {
+                clock: clock.id(),
+                ticks: (starts_at * CLOCK_TICKS_PER_SECOND).round() as u64,
+            },
+            starts_at,
+            overlap: (!crossfade.is_zero()).then(|| Duration::from_secs_f64(ends_at - starts_at)),
+            volume: self.volume,
+            loudness: self.loudness.clone(),
+        })
+    }
+
+    /// start what `Planned::open` opened, unless playback moved on meanwhile
+    fn schedule_next(&mut self, opened: Opened) {
+        let Opened {
+            planned,
+            sound_data,
+            gain,
+            sample_rate,
+        } = opened;
+        let still_next =
+            self.queue.peek_next().map(|track| &track.path) == Some(&planned.track.path);
+        let same_clock = self.clock.as_ref().map(|clock| clock.id()) == Some(planned.start.clock);
+        if self.next.is_some()
+            || *self.state.lock().unwrap() != InternalState::Playing
+            || !still_next
+            || !same_clock
+        {
+            return;
+        }
+        if let Some(overlap) = planned.overlap {
+            if let Some(handle) = self.sound_handle.lock().unwrap().as_mut() {
+                handle.set_volume(0.0, fade_at(planned.start, overlap)).ok();
+            }
+        }
+        let duration = sound_data.duration().as_secs_f64();
+        let track = planned.track;
+        match self.audio_manager.lock().unwrap().play(sound_data) {
+            Ok(mut handle) => {
+                debug!(
+                    "PlaybackService: scheduled {:?} at {:.2}s",
+                    track.path, planned.starts_at
+                );
+                // 打开文件的时候音量可能又调过
+                if planned.volume != self.volume {
+                    handle.set_volume(self.volume * gain, Tween::default()).ok();
+                }
+                self.next = Some(Prepared {
+                    track,
+                    handle,
+                    duration,
+                    sample_rate,
+                    gain,
+                    starts_at: planned.starts_at,
+                    crossfade: planned.overlap.is_some(),
+                })
+            }
+            Err(e) => warn!("Failed to schedule {:?}: {e}", track.path),
+        }
+    }
+
     pub fn play(&mut self) {
         debug!("PlaybackService: play (resume)");
         let resumed = {
             if let Some(handle) = self.sound_handle.lock().unwrap().as_mut() {
-                handle.resume(Tween::default()).ok();
+                handle.resume(fade("pause_fade_ms")).ok();
                 true
             } else {
                 false
             }
         };
         if resumed {
+            // 已经在放的时候位置在走, 原来的 clock 还是准的
+            if *self.state.lock().unwrap() != InternalState::Playing {
+                self.anchor();
+            }
             self.set_state(InternalState::Playing);
         }
     }
 
     pub fn pause(&mut self) {
         debug!("PlaybackService: pause");
+        self.cancel_next();
+        self.clock = None;
         {
             if let Some(handle) = self.sound_handle.lock().unwrap().as_mut() {
-                handle.pause(Tween::default()).ok();
+                handle.pause(fade("pause_fade_ms")).ok();
             }
         }
         self.set_state(InternalState::Paused);
@@ -129,13 +558,16 @@ impl PlaybackService {
 
     pub fn stop(&mut self) {
         debug!("PlaybackService: stop");
-        self.stop_internal();
+        self.stop_internal(fade("stop_fade_ms"));
         self.set_state(InternalState::Stopped);
     }
 
-    fn stop_internal(&mut self) {
+    fn stop_internal(&mut self, fade: Tween) {
+        self.cancel_next();
+        self.clock = None;
+        self.track = None;
         if let Some(mut handle) = self.sound_handle.lock().unwrap().take() {
-            handle.stop(Tween::default()).ok();
+            handle.stop(fade).ok();
         }
         *self.current_path.lock().unwrap() = None;
     }
@@ -147,13 +579,199 @@ impl PlaybackService {
                 .seek_to(position_seconds as f64)
                 .map_err(|e| CoreError::OtherError(format!("Failed to seek: {e}")))?;
         }
+        if *self.state.lock().unwrap() == InternalState::Playing {
+            self.anchor();
+        }
         Ok(())
     }
 
     pub fn set_volume(&mut self, volume: f32) {
         debug!("PlaybackService: set_volume to {volume}");
+        self.volume = volume as f64;
         if let Some(handle) = self.sound_handle.lock().unwrap().as_mut() {
-            handle.set_volume(volume as f64, Tween::default()).ok();
+            handle
+                .set_volume(self.volume * self.gain, Tween::default())
+                .ok();
+        }
+        if let Some(next) = self.next.as_mut() {
+            next.handle
+                .set_volume(self.volume * next.gain, Tween::default())
+                .ok();
+        }
+    }
+
+    /// the loudness of `path` was just measured, the current or the next track
+    /// may still be on the default gain
+    pub fn measured(&mut self, path: &Path) {
+        let crossfading = self.next.as_ref().is_some_and(|next| next.crossfade);
+        if self.current_path.lock().unwrap().as_deref() == Some(path) {
+            self.gain = replay_gain(path, &self.loudness);
+            // 正在淡出的不动, 不然把淡出盖掉了
+            if !crossfading {
+                if let Some(handle) = self.sound_handle.lock().unwrap().as_mut() {
+                    handle
+                        .set_volume(self.volume * self.gain, fade("pause_fade_ms"))
+                        .ok();
+                }
+            }
+        }
+        if let Some(next) = self.next.as_mut().filter(|next| next.track.path == path) {
+            next.gain = replay_gain(path, &self.loudness);
+            // 还没开始的话直接设, 在淡入的话等淡入完再说
+            if !crossfading {
+                next.handle
+                    .set_volume(self.volume * next.gain, Tween::default())
+                    .ok();
+            }
+        }
+    }
+
+    pub fn queue(&self) -> QueueSnapshot {
+        self.queue.snapshot()
+    }
+
+    fn emit_queue(&self) {
+        let _ = self
+            .app
+            .emit("playback-queue-changed", self.queue.snapshot());
+    }
+
+    /// what comes next may have changed, the emitter schedules it again
+    fn queue_changed(&mut self) {
+        self.cancel_next();
+        self.emit_queue();
+    }
+
+    /// load the queue's current track, paused right away if playback was paused;
+    /// stops when the queue has none
+    fn load_current(&mut self) -> CoreResult<()> {
+        let paused = *self.state.lock().unwrap() == InternalState::Paused;
+        match self.queue.current().cloned() {
+            Some(track) => {
+                self.play_track(track)?;
+                if paused {
+                    self.pause();
+                }
+            }
+            None => self.stop(),
+        }
+        Ok(())
+    }
+
+    /// replace the queue and start playing at `start`
+    pub fn play_queue(&mut self, tracks: Vec<QueueTrack>, start: usize) -> CoreResult<()> {
+        self.queue.set(tracks, start);
+        self.emit_queue();
+        match self.queue.current().cloned() {
+            Some(track) => self.play_track(track),
+            None => {
+                self.stop();
+                Ok(())
+            }
+        }
+    }
+
+    pub fn enqueue(&mut self, tracks: Vec<QueueTrack>) {
+        self.queue.enqueue(tracks);
+        self.queue_changed();
+    }
+
+    pub fn insert_next(&mut self, tracks: Vec<QueueTrack>) {
+        self.queue.insert_next(tracks);
+        self.queue_changed();
+    }
+
+    /// removing the playing track moves on to the one after it
+    pub fn remove_from_queue(&mut self, indices: Vec<usize>) -> CoreResult<()> {
+        let playing = *self.state.lock().unwrap() != InternalState::Stopped;
+        let removed_current = self.queue.remove(&indices);
+        self.queue_changed();
+        if removed_current && playing {
+            self.load_current()?;
+        }
+        Ok(())
+    }
+
+    pub fn move_in_queue(&mut self, from: usize, to: usize) {
+        self.queue.move_track(from, to);
+        self.queue_changed();
+    }
+
+    pub fn jump(&mut self, index: usize) -> CoreResult<()> {
+        let track = self
+            .queue
+            .jump(index)
+            .cloned()
+            .ok_or_else(|| CoreError::OtherError(format!("No track {index} in the queue")))?;
+        self.emit_queue();
+        self.play_track(track)
+    }
+
+    pub fn clear_queue(&mut self) {
+        self.queue.clear();
+        self.stop();
+        self.emit_queue();
+    }
+
+    pub fn next(&mut self) -> CoreResult<()> {
+        if self.queue.next(false).is_none() {
+            self.stop();
+            return Ok(());
+        }
+        self.emit_queue();
+        self.load_current()
+    }
+
+    /// restarts the track if it's past the first few seconds, like every player does
+    pub fn prev(&mut self) -> CoreResult<()> {
+        let position = self
+            .sound_handle
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|handle| handle.position())
+            .unwrap_or_default();
+        if position > PREV_RESTART_SECS || self.queue.prev().is_none() {
+            return self.seek(0.0);
+        }
+        self.emit_queue();
+        self.load_current()
+    }
+
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.queue.set_repeat(repeat);
+        self.queue_changed();
+    }
+
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        self.queue.set_shuffle(shuffle);
+        self.queue_changed();
+    }
+
+    /// the emitter saw the track run out; plays what the queue has next,
+    /// `false` once the queue is done
+    fn on_track_ended(&mut self) -> bool {
+        let Some(track) = self.queue.next(true).cloned() else {
+            self.cancel_next();
+            return false;
+        };
+        self.emit_queue();
+        // 排好的那首已经在放了, 接过来就行, clock 接着走
+        if let Some(next) = self.next.take_if(|next| next.track.path == track.path) {
+            *self.sound_handle.lock().unwrap() = Some(next.handle);
+            self.gain = next.gain;
+            self.duration = next.duration;
+            self.ends_at = Some(next.starts_at + next.duration);
+            self.sample_rate = next.sample_rate;
+            self.track = Some(next.track);
+            return self.loaded(track.path, next.duration).is_ok();
+        }
+        match self.play_track(track) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Failed to play the next track in the queue: {e:?}");
+                false
+            }
         }
     }
 
@@ -224,11 +842,22 @@ pub fn spawn_progress_emitter(service: Arc<Mutex<PlaybackService>>, app: AppHand
             );
         }
 
+        // 队列里还有下一首就直接接上, 不再等 webview 来决定
+        let mut advanced = false;
         if let Some(path) = track_ended_path {
             let _ = app.emit("playback-ended", PlaybackEndedPayload { path });
+            advanced = service.lock().unwrap().on_track_ended();
+        }
+
+        // 读标签和打开文件都不拿着 service 的锁
+        if state == InternalState::Playing && !advanced {
+            let planned = service.lock().unwrap().plan_next();
+            if let Some(opened) = planned.and_then(Planned::open) {
+                service.lock().unwrap().schedule_next(opened);
+            }
         }
 
-        if let Some(_state) = new_state_to_emit {
+        if let (Some(_state), false) = (new_state_to_emit, advanced) {
             let _ = app.emit(
                 "playback-state-changed",
                 PlaybackStatePayload {
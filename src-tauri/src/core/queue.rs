@@ -0,0 +1,491 @@
+use crate::store::json::entity::release::ReleaseKey;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    path::PathBuf,
+};
+
+// 播放队列放在后端, 窗口隐藏 / 被节流的时候 webview 的定时器靠不住, 一首放完就停了.
+// tracks 是用户看到的顺序, order 是实际播放的顺序 (tracks 的下标), 不随机的时候就是 0..n
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RepeatMode {
+    #[default]
+    Off,
+    One,
+    All,
+}
+
+/// what to fill the queue from, resolved against the library
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum QueueSource {
+    Paths {
+        paths: Vec<PathBuf>,
+    },
+    Playlist {
+        name: String,
+    },
+    Alist {
+        name: String,
+    },
+    Release {
+        title: String,
+        artist: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct QueueTrack {
+    pub path: PathBuf,
+    /// `None` for files the library doesn't know
+    pub release: Option<ReleaseKey>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueSnapshot {
+    pub tracks: Vec<PathBuf>,
+    /// index into `tracks`
+    pub current: Option<usize>,
+    /// play order, indices into `tracks`
+    pub order: Vec<usize>,
+    pub repeat: RepeatMode,
+    pub shuffle: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct PlayQueue {
+    tracks: Vec<QueueTrack>,
+    order: Vec<usize>,
+    /// position in `order`
+    cursor: Option<usize>,
+    repeat: RepeatMode,
+    shuffle: bool,
+}
+
+/// fisher-yates, no rand dependency for this
+fn shuffle<T>(items: &mut [T]) {
+    let mut hasher = RandomState::new().build_hasher();
+    (1..items.len()).rev().for_each(|i| {
+        hasher.write_usize(i);
+        items.swap(i, hasher.finish() as usize % (i + 1));
+    });
+}
+
+#[allow(unused)]
+impl PlayQueue {
+    pub fn snapshot(&self) -> QueueSnapshot {
+        QueueSnapshot {
+            tracks: self.tracks.iter().map(|track| track.path.clone()).collect(),
+            current: self.current_index(),
+            order: self.order.clone(),
+            repeat: self.repeat,
+            shuffle: self.shuffle,
+        }
+    }
+
+    pub fn current_index(&self) -> Option<usize> {
+        self.cursor.map(|cursor| self.order[cursor])
+    }
+
+    pub fn current(&self) -> Option<&QueueTrack> {
+        self.current_index().map(|index| &self.tracks[index])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+
+    /// play order with `first` (a track index) up front, the rest shuffled if on
+    fn reorder_from(&mut self, first: Option<usize>) {
+        let mut order = (0..self.tracks.len())
+            .filter(|index| Some(*index) != first)
+            .collect::<Vec<usize>>();
+        if self.shuffle {
+            shuffle(&mut order);
+        }
+        match first {
+            Some(first) if self.shuffle => {
+                order.insert(0, first);
+                self.cursor = Some(0);
+            }
+            Some(first) => {
+                order.insert(first, first);
+                self.cursor = Some(first);
+            }
+            None => self.cursor = None,
+        }
+        self.order = order;
+    }
+
+    pub fn set(&mut self, tracks: Vec<QueueTrack>, start: usize) {
+        self.tracks = tracks;
+        let first = (start < self.tracks.len()).then_some(start);
+        self.reorder_from(first);
+    }
+
+    pub fn clear(&mut self) {
+        self.tracks.clear();
+        self.order.clear();
+        self.cursor = None;
+    }
+
+    /// put `tracks` at `at` in the list and at `position` in the play order
+    fn insert(&mut self, at: usize, position: usize, tracks: Vec<QueueTrack>) {
+        let count = tracks.len();
+        self.order
+            .iter_mut()
+            .filter(|index| **index >= at)
+            .for_each(|index| *index += count);
+        self.tracks.splice(at..at, tracks);
+        self.order.splice(position..position, at..at + count);
+    }
+
+    pub fn enqueue(&mut self, tracks: Vec<QueueTrack>) {
+        let at = self.tracks.len();
+        match self.shuffle {
+            // 随机的时候新加的也打散到还没放的那部分里
+            true => {
+                let count = tracks.len();
+                self.tracks.extend(tracks);
+                let start = self.cursor.map(|c| c + 1).unwrap_or_default();
+                self.order.extend(at..at + count);
+                shuffle(&mut self.order[start..]);
+            }
+            false => self.insert(at, at, tracks),
+        }
+    }
+
+    /// right after the current track, both in the list and in the play order
+    pub fn insert_next(&mut self, tracks: Vec<QueueTrack>) {
+        let at = self.current_index().map(|i| i + 1).unwrap_or_default();
+        let position = self.cursor.map(|c| c + 1).unwrap_or_default();
+        self.insert(at, position, tracks);
+    }
+
+    /// drop tracks by index, returns whether the current one was among them;
+    /// the cursor then sits on the track that came after it
+    pub fn remove(&mut self, indices: &[usize]) -> bool {
+        let current = self.current_index();
+        let removed_current = current.is_some_and(|c| indices.contains(&c));
+        // 当前这首被删了, 光标挪到它后面第一首没被删的
+        let successor = self.cursor.and_then(|cursor| {
+            self.order[cursor..]
+                .iter()
+                .find(|index| !indices.contains(index))
+                .copied()
+        });
+        let kept = (0..self.tracks.len())
+            .filter(|index| !indices.contains(index))
+            .collect::<Vec<usize>>();
+        let remap = |index: usize| kept.iter().position(|k| *k == index);
+        self.order = self
+            .order
+            .iter()
+            .filter_map(|index| remap(*index))
+            .collect();
+        self.tracks = kept
+            .iter()
+            .map(|index| self.tracks[*index].clone())
+            .collect();
+        let target = match removed_current {
+            true => successor,
+            false => current,
+        };
+        self.cursor = target
+            .and_then(remap)
+            .and_then(|index| self.order.iter().position(|i| *i == index));
+        removed_current
+    }
+
+    /// move a track in the list; the play order follows unless shuffled
+    pub fn move_track(&mut self, from: usize, to: usize) {
+        if from >= self.tracks.len() || to >= self.tracks.len() || from == to {
+            return;
+        }
+        let current = self.current_index();
+        let track = self.tracks.remove(from);
+        self.tracks.insert(to, track);
+        let remap = |index: usize| match index {
+            i if i == from => to,
+            i if from < to && i > from && i <= to => i - 1,
+            i if to < from && i >= to && i < from => i + 1,
+            i => i,
+        };
+        match self.shuffle {
+            true => self
+                .order
+                .iter_mut()
+                .for_each(|index| *index = remap(*index)),
+            false => {
+                self.order = (0..self.tracks.len()).collect();
+                self.cursor = current.map(remap);
+            }
+        }
+    }
+
+    pub fn jump(&mut self, index: usize) -> Option<&QueueTrack> {
+        let position = self.order.iter().position(|i| *i == index)?;
+        self.cursor = Some(position);
+        self.current()
+    }
+
+    /// move on; `ended` is the track running out on its own, which repeat one replays.
+    /// `None` at the end of the queue
+    pub fn next(&mut self, ended: bool) -> Option<&QueueTrack> {
+        let Some(cursor) = self.cursor else {
+            // 加进来还没放过, 从头开始
+            self.cursor = (!self.order.is_empty()).then_some(0);
+            return self.current();
+        };
+        if ended && self.repeat == RepeatMode::One {
+            return self.current();
+        }
+        match (cursor + 1 < self.order.len(), self.repeat) {
+            (true, _) => self.cursor = Some(cursor + 1),
+            (false, RepeatMode::All) => {
+                if self.shuffle {
+                    shuffle(&mut self.order);
+                }
+                self.cursor = Some(0);
+            }
+            (false, _) => return None,
+        }
+        self.current()
+    }
+
+    /// what `next(true)` will give, without moving; `None` also when a shuffled
+    /// repeat-all queue wraps, the next round isn't shuffled yet
+    pub fn peek_next(&self) -> Option<&QueueTrack> {
+        let cursor = self.cursor?;
+        let position = match (self.repeat, cursor + 1 < self.order.len()) {
+            (RepeatMode::One, _) => cursor,
+            (_, true) => cursor + 1,
+            (RepeatMode::All, false) if !self.shuffle => 0,
+            _ => return None,
+        };
+        Some(&self.tracks[self.order[position]])
+    }
+
+    pub fn prev(&mut self) -> Option<&QueueTrack> {
+        let cursor = self.cursor?;
+        match (cursor, self.repeat) {
+            (0, RepeatMode::All) => self.cursor = Some(self.order.len() - 1),
+            (0, _) => return None,
+            (cursor, _) => self.cursor = Some(cursor - 1),
+        }
+        self.current()
+    }
+
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.repeat = repeat;
+    }
+
+    /// the current track stays current either way
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        if self.shuffle == shuffle {
+            return;
+        }
+        self.shuffle = shuffle;
+        let current = self.current_index();
+        self.reorder_from(current);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(i: usize) -> QueueTrack {
+        QueueTrack {
+            path: PathBuf::from(format!("/music/{i}.flac")),
+            release: None,
+        }
+    }
+
+    fn queue(count: usize, start: usize, shuffle: bool) -> PlayQueue {
+        let mut queue = PlayQueue::default();
+        queue.set_shuffle(shuffle);
+        queue.set((0..count).map(track).collect(), start);
+        queue
+    }
+
+    fn path(track: Option<&QueueTrack>) -> Option<PathBuf> {
+        track.map(|track| track.path.clone())
+    }
+
+    /// the play order as paths
+    fn played(queue: &PlayQueue) -> Vec<PathBuf> {
+        let snapshot = queue.snapshot();
+        snapshot
+            .order
+            .iter()
+            .map(|index| snapshot.tracks[*index].clone())
+            .collect()
+    }
+
+    fn assert_permutation(queue: &PlayQueue) {
+        let mut order = queue.snapshot().order;
+        order.sort_unstable();
+        assert_eq!(
+            order,
+            (0..queue.snapshot().tracks.len()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn set_starts_at_the_picked_track() {
+        for shuffle in [false, true] {
+            let queue = queue(6, 3, shuffle);
+            assert_eq!(path(queue.current()), Some(track(3).path));
+            assert_permutation(&queue);
+        }
+    }
+
+    #[test]
+    fn removing_the_current_track_under_shuffle_moves_on_in_play_order() {
+        let mut queue = queue(6, 2, true);
+        let before = played(&queue);
+        let cursor = before.iter().position(|p| *p == track(2).path).unwrap();
+        let successor = before[cursor + 1].clone();
+        assert!(queue.remove(&[2]));
+        assert_permutation(&queue);
+        assert_eq!(queue.snapshot().tracks.len(), 5);
+        assert_eq!(path(queue.current()), Some(successor));
+        // 其余的播放顺序不变
+        let expected = before
+            .into_iter()
+            .filter(|p| *p != track(2).path)
+            .collect::<Vec<_>>();
+        assert_eq!(played(&queue), expected);
+    }
+
+    #[test]
+    fn removing_other_tracks_keeps_the_current_one() {
+        let mut queue = queue(6, 2, true);
+        assert!(!queue.remove(&[0, 5]));
+        assert_permutation(&queue);
+        assert_eq!(path(queue.current()), Some(track(2).path));
+    }
+
+    #[test]
+    fn removing_the_last_played_track_leaves_no_current() {
+        let mut queue = queue(3, 2, false);
+        assert!(queue.remove(&[2]));
+        assert_eq!(queue.current_index(), None);
+    }
+
+    #[test]
+    fn move_track_without_shuffle_reorders_playback_too() {
+        let mut queue = queue(4, 1, false);
+        queue.move_track(0, 3);
+        let order = (1..4).chain([0]).map(|i| track(i).path).collect::<Vec<_>>();
+        assert_eq!(queue.snapshot().tracks, order);
+        assert_eq!(played(&queue), order);
+        assert_eq!(path(queue.current()), Some(track(1).path));
+        assert_eq!(path(queue.peek_next()), Some(track(2).path));
+    }
+
+    #[test]
+    fn move_track_under_shuffle_keeps_the_play_order() {
+        let mut queue = queue(6, 4, true);
+        let before = played(&queue);
+        queue.move_track(4, 0);
+        queue.move_track(1, 5);
+        assert_permutation(&queue);
+        assert_eq!(played(&queue), before);
+        assert_eq!(path(queue.current()), Some(track(4).path));
+        assert_eq!(queue.snapshot().tracks[0], track(4).path);
+    }
+
+    #[test]
+    fn enqueue_under_shuffle_only_goes_after_the_current_track() {
+        let mut queue = queue(5, 0, true);
+        queue.next(true);
+        queue.next(true);
+        let cursor = 2;
+        let before = played(&queue);
+        queue.enqueue((5..8).map(track).collect());
+        assert_permutation(&queue);
+        let after = played(&queue);
+        assert_eq!(after[..=cursor], before[..=cursor]);
+        assert_eq!(path(queue.current()), Some(before[cursor].clone()));
+        (5..8).for_each(|i| {
+            let position = after.iter().position(|p| *p == track(i).path).unwrap();
+            assert!(position > cursor);
+        });
+    }
+
+    #[test]
+    fn insert_next_under_shuffle_plays_next() {
+        let mut queue = queue(5, 3, true);
+        queue.insert_next(vec![track(9), track(8)]);
+        assert_permutation(&queue);
+        assert_eq!(path(queue.current()), Some(track(3).path));
+        assert_eq!(path(queue.next(true)), Some(track(9).path));
+        assert_eq!(path(queue.next(true)), Some(track(8).path));
+        // 列表里也紧跟在当前这首后面
+        let tracks = queue.snapshot().tracks;
+        let at = tracks.iter().position(|p| *p == track(3).path).unwrap();
+        assert_eq!(tracks[at + 1..at + 3], [track(9).path, track(8).path]);
+    }
+
+    #[test]
+    fn next_and_peek_next_without_repeat_stop_at_the_end() {
+        let mut queue = queue(3, 1, false);
+        assert_eq!(path(queue.peek_next()), Some(track(2).path));
+        assert_eq!(path(queue.next(true)), Some(track(2).path));
+        assert_eq!(path(queue.peek_next()), None);
+        assert_eq!(path(queue.next(true)), None);
+    }
+
+    #[test]
+    fn repeat_one_replays_only_when_the_track_ends() {
+        let mut queue = queue(3, 1, false);
+        queue.set_repeat(RepeatMode::One);
+        assert_eq!(path(queue.peek_next()), Some(track(1).path));
+        assert_eq!(path(queue.next(true)), Some(track(1).path));
+        assert_eq!(path(queue.next(false)), Some(track(2).path));
+        assert_eq!(path(queue.next(false)), None);
+    }
+
+    #[test]
+    fn repeat_all_wraps_around() {
+        let mut ordered = queue(3, 2, false);
+        ordered.set_repeat(RepeatMode::All);
+        assert_eq!(path(ordered.peek_next()), Some(track(0).path));
+        assert_eq!(path(ordered.next(true)), Some(track(0).path));
+
+        // 随机的时候下一轮还没洗, peek 不出来
+        let mut shuffled = queue(3, 0, true);
+        shuffled.set_repeat(RepeatMode::All);
+        shuffled.next(true);
+        shuffled.next(true);
+        assert!(shuffled.peek_next().is_none());
+        assert!(shuffled.next(true).is_some());
+        let snapshot = shuffled.snapshot();
+        assert_eq!(snapshot.current, snapshot.order.first().copied());
+        assert_permutation(&shuffled);
+    }
+
+    #[test]
+    fn next_on_a_fresh_queue_starts_from_the_top() {
+        let mut queue = PlayQueue::default();
+        queue.enqueue((0..3).map(track).collect());
+        assert!(queue.peek_next().is_none());
+        assert_eq!(path(queue.next(true)), Some(track(0).path));
+    }
+
+    #[test]
+    fn prev_wraps_only_with_repeat_all() {
+        let mut queue = queue(3, 0, false);
+        assert!(queue.prev().is_none());
+        assert_eq!(path(queue.current()), Some(track(0).path));
+        queue.set_repeat(RepeatMode::All);
+        assert_eq!(path(queue.prev()), Some(track(2).path));
+        assert_eq!(path(queue.prev()), Some(track(1).path));
+    }
+}
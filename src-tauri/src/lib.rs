@@ -1,23 +1,32 @@
 use core::{
     cmd::{
         add_dir, add_element_to_alist, add_elements_to_alist, add_recents, add_single_song,
-        add_single_songs, add_song_to_playlist, add_songs_to_playlist, clear_alist_elements,
-        clear_playlist, clear_recents, clear_songs_multi, create_alist, create_playlist,
-        delete_alist, delete_playlist, delete_song_file, delete_song_files, freeze_alist,
+        add_single_songs, add_song_to_playlist, add_songs_to_playlist, analyze_loudness,
+        cancel_loudness_analysis, cancel_scan, clear_alist_elements, clear_playlist, clear_recents,
+        clear_songs_multi, create_alist, create_playlist, delete_alist, delete_playlist,
+        delete_song_file, delete_song_files, export_library, find_duplicates, freeze_alist,
         get_all_alists, get_all_artists, get_all_playlists, get_all_recents, get_all_releases,
-        get_all_songs, get_cover_art_path, get_glob_dirs, get_lyric, get_song_by_file,
-        get_songs_by_files, list_all_alist_elements, list_all_alist_songs, modify, modify_multiple,
-        ping, player_pause, player_play, player_play_file, player_seek, player_set_volume,
-        player_stop, refresh_library, remove_alists, remove_dir, remove_element_from_alist_all,
+        get_all_songs, get_cover_art_path, get_glob_dirs, get_listen_paths, get_lyric,
+        get_root_status, get_scan_report, get_song_by_file, get_songs_by_files, import_library,
+        library_doctor, list_all_alist_elements, list_all_alist_songs, loudness_status,
+        merge_duplicates, modify, modify_multiple, pause_scan, ping, player_clear_queue,
+        player_enqueue, player_get_queue, player_insert_next, player_jump, player_move_in_queue,
+        player_next, player_pause, player_play, player_play_file, player_play_queue, player_prev,
+        player_remove_from_queue, player_seek, player_set_repeat, player_set_shuffle,
+        player_set_volume, player_stop, query_songs, read_library_archive, refresh_library,
+        remove_alists, remove_dir, remove_element_from_alist_all,
         remove_element_from_alist_by_index, remove_elements_from_alist_all,
         remove_elements_from_alist_by_indices, remove_recents_by_index, remove_recents_by_song_all,
         remove_song_from_playlist_all, remove_songs_from_playlist_by_index, rename_alist,
-        rename_playlist, update_song_tags, window_pin,
+        rename_playlist, resume_scan, retry_scan_failures, scan_status, search_songs,
+        set_dir_rules, set_release_gapless, update_song_tags, window_pin,
     },
     playback::{spawn_progress_emitter, PlaybackService},
 };
 use kira::manager::{backend::cpal::CpalBackend, AudioManager, AudioManagerSettings};
-use library::library::Library;
+use library::{
+    library::Library, loudness::LoudnessHandle, roots::spawn_root_monitor, scan::ScanHandle,
+};
 use log::{debug, info};
 use misc::config::{init_global, Config};
 use std::{
@@ -25,7 +34,7 @@ use std::{
     sync::{Arc, Mutex},
     time::Duration,
 };
-use tauri::{Listener, Manager, TitleBarStyle, WebviewUrl, WebviewWindowBuilder};
+use tauri::{Listener, Manager, RunEvent, TitleBarStyle, WebviewUrl, WebviewWindowBuilder};
 use tauri_plugin_log::{Target, TargetKind, TimezoneStrategy};
 mod core;
 mod library;
@@ -133,9 +142,17 @@ pub fn run() {
             debug!("Config store initialized. Contents: {:#?}", store.entries());
             app.manage(store);
             let mut library = Library::init(app.handle())?;
-            library.load()?;
+            // 先用存储里的记录顶上, 真正的扫描在后台跑
+            let cached = library.scan_job().cached()?;
+            library.apply_scan(cached)?;
             let library_state: Arc<Mutex<Library>> = Arc::new(Mutex::new(library));
             app.manage(library_state.clone());
+            let scan = ScanHandle::new(app.handle());
+            scan.start(library_state.clone());
+            spawn_root_monitor(library_state.clone(), scan.clone());
+            app.manage(scan);
+            let loudness = LoudnessHandle::new(app.handle());
+            app.manage(loudness.clone());
             let library_added_clone = library_state.clone();
             app.listen("fs-files-added", move |event| {
                 let files: Vec<PathBuf> = serde_json::from_str(event.payload()).unwrap();
@@ -154,6 +171,15 @@ pub fn run() {
                     .on_file_modified(files)
                     .unwrap();
             });
+            let library_moved_clone = library_state.clone();
+            app.listen("fs-files-moved", move |event| {
+                let moves: Vec<(PathBuf, PathBuf)> = serde_json::from_str(event.payload()).unwrap();
+                library_moved_clone
+                    .lock()
+                    .unwrap()
+                    .on_file_moved(moves)
+                    .unwrap();
+            });
             let library_removed_clone = library_state.clone();
             app.listen("fs-files-removed", move |event| {
                 let files: Vec<PathBuf> = serde_json::from_str(event.payload()).unwrap();
@@ -167,8 +193,14 @@ pub fn run() {
             let audio_manager = AudioManager::<CpalBackend>::new(AudioManagerSettings::default())
                 .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
             info!("Initializing PlaybackService...");
-            let playback_service = PlaybackService::new(app_handle.clone(), audio_manager)?;
+            let playback_service =
+                PlaybackService::new(app_handle.clone(), audio_manager, loudness)?;
             spawn_progress_emitter(playback_service.clone(), app_handle.clone());
+            let playback_measured_clone = playback_service.clone();
+            app.listen("loudness-measured", move |event| {
+                let path: PathBuf = serde_json::from_str(event.payload()).unwrap();
+                playback_measured_clone.lock().unwrap().measured(&path);
+            });
             app.manage(playback_service);
             info!("PlaybackService initialized and managed.");
             Ok(())
@@ -177,9 +209,20 @@ pub fn run() {
             ping,
             get_glob_dirs,
             refresh_library,
+            scan_status,
+            get_scan_report,
+            retry_scan_failures,
+            pause_scan,
+            resume_scan,
+            cancel_scan,
             add_dir,
             remove_dir,
+            get_listen_paths,
+            get_root_status,
+            set_dir_rules,
             get_all_songs,
+            query_songs,
+            search_songs,
             modify,
             modify_multiple,
             update_song_tags,
@@ -230,7 +273,34 @@ pub fn run() {
             player_stop,
             player_seek,
             player_set_volume,
+            player_get_queue,
+            player_play_queue,
+            player_enqueue,
+            player_insert_next,
+            player_remove_from_queue,
+            player_move_in_queue,
+            player_jump,
+            player_clear_queue,
+            player_next,
+            player_prev,
+            player_set_repeat,
+            player_set_shuffle,
+            set_release_gapless,
+            export_library,
+            read_library_archive,
+            import_library,
+            library_doctor,
+            find_duplicates,
+            merge_duplicates,
+            analyze_loudness,
+            loudness_status,
+            cancel_loudness_analysis,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|_app, event| {
+            if let RunEvent::Exit = event {
+                store::flush();
+            }
+        });
 }
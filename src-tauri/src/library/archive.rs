@@ -0,0 +1,334 @@
+use crate::{
+    library::{dir::ListenPath, library::Library},
+    meta::{loudness::get_loudness_dir_path, lyric::get_lyric_dir_path, pic::get_art_dir_path},
+    misc::{
+        config::get_global,
+        error::{CoreError, CoreResult},
+        utils::get_path_hash,
+    },
+    store::json::{
+        entity::{alist::Alist, list::Playlist, recent::Recents, song::Song},
+        version::{self, MigrationContext, Versioned},
+    },
+};
+use log::{info, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+use tauri::Manager;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+// 导出包就是一个 zip:
+//   manifest.json
+//   config.json
+//   stores/*.json     和 json 后端一样的带版本信封, 跟本机用哪个后端无关
+//   caches/covers/*   可选, 文件名是 path hash, 导入时按新 path 重新命名
+//   caches/lyrics/*   同上
+//   caches/loudness/* 同上
+
+const ARCHIVE_FORMAT: u32 = 1;
+const MANIFEST_ENTRY: &str = "manifest.json";
+const CONFIG_ENTRY: &str = "config.json";
+const SONGS_ENTRY: &str = "stores/songs.json";
+const PLAYLISTS_ENTRY: &str = "stores/playlists.json";
+const ALISTS_ENTRY: &str = "stores/alists.json";
+const RECENTS_ENTRY: &str = "stores/recents.json";
+const COVERS_PREFIX: &str = "caches/covers/";
+const LYRICS_PREFIX: &str = "caches/lyrics/";
+const LOUDNESS_PREFIX: &str = "caches/loudness/";
+
+/// config keys describing this machine's layout, never taken from an archive
+const LOCAL_KEYS: [&str; 12] = [
+    "store_base",
+    "store_backend",
+    "db_store",
+    "song_store",
+    "playlist_store",
+    "alist_store",
+    "recent_store",
+    "release_cover_store",
+    "lyric_store",
+    "loudness_store",
+    "single_song_store",
+    "listen_paths",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: u32,
+    pub app_version: String,
+    pub created_at: String,
+    /// library roots of the exporting machine, the first one is its single song dir
+    pub roots: Vec<PathBuf>,
+    /// the same roots with their rules, missing in archives from before rules were exported
+    #[serde(default)]
+    pub listen_paths: Vec<ListenPath>,
+    pub songs: usize,
+    pub playlists: usize,
+    pub alists: usize,
+    pub recents: usize,
+    pub caches: bool,
+}
+
+fn zip_options() -> SimpleFileOptions {
+    SimpleFileOptions::default().compression_method(CompressionMethod::Deflated)
+}
+
+fn write_json<W: Write + Seek, T: Serialize>(
+    zip: &mut ZipWriter<W>,
+    name: &str,
+    value: &T,
+) -> CoreResult<()> {
+    zip.start_file(name, zip_options())?;
+    serde_json::to_writer_pretty(&mut *zip, value)
+        .map_err(|e| CoreError::OtherError(format!("Failed to serialize {name}: {e}")))
+}
+
+fn read_json<R: Read + Seek, T: DeserializeOwned>(
+    zip: &mut ZipArchive<R>,
+    name: &str,
+) -> CoreResult<T> {
+    serde_json::from_reader(zip.by_name(name)?)
+        .map_err(|e| CoreError::FsError(format!("Malformed {name} in archive: {e}")))
+}
+
+fn read_store<R: Read + Seek, T: Versioned>(
+    zip: &mut ZipArchive<R>,
+    name: &str,
+    context: &MigrationContext,
+) -> CoreResult<T> {
+    version::upgrade(read_json(zip, name)?, context)
+}
+
+fn add_dir_files<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    dir: &Path,
+    prefix: &str,
+) -> CoreResult<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if path.is_file() {
+            zip.start_file(format!("{prefix}{name}"), zip_options())?;
+            io::copy(&mut File::open(&path)?, zip)?;
+        }
+    }
+    Ok(())
+}
+
+/// longest matching root wins, paths outside every root stay as they are
+fn remap(path: &Path, roots: &[(PathBuf, PathBuf)]) -> Option<PathBuf> {
+    roots
+        .iter()
+        .filter(|(from, _)| path.starts_with(from))
+        .max_by_key(|(from, _)| from.components().count())
+        .and_then(|(from, to)| path.strip_prefix(from).ok().map(|rest| to.join(rest)))
+}
+
+/// peek at an archive before importing it, e.g. to ask where its roots live now
+pub fn read_manifest(path: &Path) -> CoreResult<Manifest> {
+    let mut zip = ZipArchive::new(File::open(path)?)?;
+    let manifest: Manifest = read_json(&mut zip, MANIFEST_ENTRY)?;
+    if manifest.format > ARCHIVE_FORMAT {
+        return Err(CoreError::OtherError(format!(
+            "Archive format {} is newer than supported format {ARCHIVE_FORMAT}",
+            manifest.format
+        )));
+    }
+    Ok(manifest)
+}
+
+#[allow(unused)]
+impl Library {
+    pub fn export_archive(&self, path: &Path, with_caches: bool) -> CoreResult<Manifest> {
+        let songs = self.song_controller.get_all()?;
+        let playlists = self.playlist_controller.get_all_playlists()?;
+        let alists = self.alist_controller.get_all_alists()?;
+        let recents = self.recent_controller.get_all_recents()?;
+        let manifest = Manifest {
+            format: ARCHIVE_FORMAT,
+            app_version: self.app.package_info().version.to_string(),
+            created_at: chrono::Local::now().to_rfc3339(),
+            roots: self.dir_path.clone(),
+            listen_paths: self.fs.listen_paths(),
+            songs: songs.len(),
+            playlists: playlists.len(),
+            alists: alists.len(),
+            recents: recents.len(),
+            caches: with_caches,
+        };
+        info!("Exporting library to {path:?}: {manifest:?}");
+
+        let mut zip = ZipWriter::new(File::create(path)?);
+        write_json(&mut zip, MANIFEST_ENTRY, &manifest)?;
+        let config = get_global()
+            .entries()
+            .into_iter()
+            .collect::<serde_json::Map<String, Value>>();
+        write_json(&mut zip, CONFIG_ENTRY, &config)?;
+        write_json(
+            &mut zip,
+            SONGS_ENTRY,
+            &version::wrap(&songs.into_iter().collect::<HashSet<Song>>()),
+        )?;
+        write_json(
+            &mut zip,
+            PLAYLISTS_ENTRY,
+            &version::wrap(&playlists.into_iter().collect::<HashSet<Playlist>>()),
+        )?;
+        write_json(
+            &mut zip,
+            ALISTS_ENTRY,
+            &version::wrap(&alists.into_iter().collect::<HashSet<Alist>>()),
+        )?;
+        write_json(&mut zip, RECENTS_ENTRY, &version::wrap(&recents))?;
+        if with_caches {
+            add_dir_files(&mut zip, &get_art_dir_path()?, COVERS_PREFIX)?;
+            add_dir_files(&mut zip, &get_lyric_dir_path()?, LYRICS_PREFIX)?;
+            add_dir_files(&mut zip, &get_loudness_dir_path()?, LOUDNESS_PREFIX)?;
+        }
+        zip.finish()?;
+        Ok(manifest)
+    }
+
+    /// replace the current library with an exported one. `roots` maps the archive's
+    /// roots to where they live on this machine, its single song dir maps to ours by default.
+    /// the stores are replaced right away, a scan has to follow to pick them up
+    pub fn import_archive(
+        &mut self,
+        path: &Path,
+        roots: HashMap<PathBuf, PathBuf>,
+        with_caches: bool,
+    ) -> CoreResult<Manifest> {
+        let manifest = read_manifest(path)?;
+        info!("Importing library from {path:?}: {manifest:?}");
+        let mut zip = ZipArchive::new(File::open(path)?)?;
+        let songs: HashSet<Song> = read_store(&mut zip, SONGS_ENTRY, &MigrationContext::default())?;
+        // 老的导出包里歌单可能还是 path, 按包里的 song store 换成 id
+        let context = MigrationContext::with_songs(&songs);
+        let playlists: HashSet<Playlist> = read_store(&mut zip, PLAYLISTS_ENTRY, &context)?;
+        let alists: HashSet<Alist> = read_store(&mut zip, ALISTS_ENTRY, &context)?;
+        let recents: Recents = read_store(&mut zip, RECENTS_ENTRY, &context)?;
+        let config: serde_json::Map<String, Value> = read_json(&mut zip, CONFIG_ENTRY)?;
+
+        let mut roots = roots.into_iter().collect::<Vec<_>>();
+        if let (Some(from), Some(to)) = (manifest.roots.first(), self.dir_path.first()) {
+            if !roots.iter().any(|(r, _)| r == from) {
+                roots.push((from.clone(), to.clone()));
+            }
+        }
+        let moves = songs
+            .iter()
+            .filter_map(|song| remap(&song.path, &roots).map(|to| (song.path.clone(), to)))
+            .collect::<HashMap<PathBuf, PathBuf>>();
+        let songs = songs
+            .into_iter()
+            .map(|song| match moves.get(&song.path) {
+                Some(to) => Song {
+                    path: to.clone(),
+                    ..song
+                },
+                None => song,
+            })
+            .collect::<Vec<Song>>();
+        let alists = alists
+            .into_iter()
+            .map(|mut alist| {
+                alist.rewrite_song_paths(&moves);
+                alist
+            })
+            .collect::<Vec<Alist>>();
+
+        self.song_controller.get_op().save_all(&songs)?;
+        let playlist_op = self.playlist_controller.get_op();
+        playlist_op.clear_all()?;
+        playlist_op.insert_batch(&playlists.into_iter().collect())?;
+        let alist_op = self.alist_controller.get_op();
+        alist_op.clear_all()?;
+        alist_op.insert_batch(&alists)?;
+        let recent_op = self.recent_controller.get_op();
+        recent_op.clear()?;
+        recent_op.insert_batch(&recents)?;
+
+        if with_caches && manifest.caches {
+            // 缓存文件名是旧 path 的 hash
+            let renames = moves
+                .iter()
+                .map(|(from, to)| (get_path_hash(from), get_path_hash(to)))
+                .collect::<HashMap<String, String>>();
+            let art_dir = get_art_dir_path()?;
+            let lyric_dir = get_lyric_dir_path()?;
+            let loudness_dir = get_loudness_dir_path()?;
+            fs::create_dir_all(&art_dir)?;
+            fs::create_dir_all(&lyric_dir)?;
+            fs::create_dir_all(&loudness_dir)?;
+            for i in 0..zip.len() {
+                let mut entry = zip.by_index(i)?;
+                let name = entry.name().to_string();
+                let (dir, file) = match (
+                    name.strip_prefix(COVERS_PREFIX),
+                    name.strip_prefix(LYRICS_PREFIX),
+                    name.strip_prefix(LOUDNESS_PREFIX),
+                ) {
+                    (Some(file), _, _) => (&art_dir, file),
+                    (_, Some(file), _) => (&lyric_dir, file),
+                    (_, _, Some(file)) => (&loudness_dir, file),
+                    _ => continue,
+                };
+                // 只认平铺的文件名, 防止 ../ 之类写到缓存目录外面
+                if entry.is_dir()
+                    || Path::new(file).file_name().and_then(|n| n.to_str()) != Some(file)
+                {
+                    warn!("Skipping archive entry {name:?}");
+                    continue;
+                }
+                let file = match file.split_once('.') {
+                    Some((hash, ext)) => match renames.get(hash) {
+                        Some(new_hash) => format!("{new_hash}.{ext}"),
+                        None => file.to_string(),
+                    },
+                    None => file.to_string(),
+                };
+                io::copy(&mut entry, &mut File::create(dir.join(file))?)?;
+            }
+        }
+
+        let config_store = get_global();
+        config
+            .into_iter()
+            .filter(|(key, _)| !LOCAL_KEYS.contains(&key.as_str()))
+            .for_each(|(key, value)| config_store.set(key, value));
+        // 老的导出包只有 roots, 当作默认规则
+        let listen_paths = match manifest.listen_paths.is_empty() {
+            true => manifest
+                .roots
+                .iter()
+                .map(|root| ListenPath::Plain(root.clone()))
+                .collect(),
+            false => manifest.listen_paths.clone(),
+        };
+        listen_paths.into_iter().skip(1).for_each(|listen_path| {
+            let (root, rules) = listen_path.into_parts();
+            let root = remap(&root, &roots).unwrap_or(root);
+            if self.dir_path.contains(&root) {
+                return;
+            }
+            if let Err(e) = self.fs.add_dir_with(root.clone(), rules) {
+                warn!("Failed to add imported library root {root:?}: {e:?}");
+            }
+        });
+        self.dir_path = self.fs.glob_dirs();
+        config_store.set("listen_paths", json!(self.fs.listen_paths()));
+        Ok(manifest)
+    }
+}
@@ -1,22 +1,155 @@
-use crate::misc::{config::get_global, utils::get_time};
-use log::{debug, error};
-use std::{collections::HashMap, path::PathBuf};
+use crate::{
+    meta::probe::{sniff, AudioFilter},
+    misc::utils::get_time,
+};
+use glob::{MatchOptions, Pattern};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{metadata, read_dir, symlink_metadata},
+    path::{Component, Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+/// what a glob remembers about a file: mtime decides modified, size + mtime pair moves
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FileStamp {
+    pub updated_at: String,
+    pub size: u64,
+}
+
+/// per root filters, stored with the root in `listen_paths`.
+/// a pattern with a `/` is matched against the path relative to the root,
+/// one without against every file / folder name on the way (like .gitignore)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DirRules {
+    /// if not empty, only files matching one of these are picked up
+    pub include: Vec<String>,
+    /// files and folders matching any of these are skipped
+    pub exclude: Vec<String>,
+    /// folder levels below the root to descend, 0 keeps to the root itself
+    pub max_depth: Option<usize>,
+    pub follow_symlinks: bool,
+    /// skip dot files and folders
+    pub skip_hidden: bool,
+    pub watch: WatchMode,
+}
+
+/// how changes under a root are noticed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum WatchMode {
+    /// the os file events, nothing arrives from network shares and FUSE mounts
+    #[default]
+    Native,
+    /// compare mtimes over the whole tree every `interval_secs`, for NFS / SMB / sshfs
+    Poll { interval_secs: u64 },
+    /// not watched, changes come in with a library refresh
+    Manual,
+}
+
+/// an entry of `listen_paths`, a bare path when the root has no rules
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ListenPath {
+    Plain(PathBuf),
+    Rules {
+        path: PathBuf,
+        #[serde(flatten)]
+        rules: DirRules,
+    },
+}
+
+impl ListenPath {
+    pub fn new(path: PathBuf, rules: DirRules) -> Self {
+        match rules == DirRules::default() {
+            true => ListenPath::Plain(path),
+            false => ListenPath::Rules { path, rules },
+        }
+    }
+
+    pub fn into_parts(self) -> (PathBuf, DirRules) {
+        match self {
+            ListenPath::Plain(path) => (path, DirRules::default()),
+            ListenPath::Rules { path, rules } => (path, rules),
+        }
+    }
+}
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+fn compile(patterns: &[String]) -> Vec<Pattern> {
+    patterns
+        .iter()
+        .filter_map(|p| match Pattern::new(p) {
+            Ok(pattern) => Some(pattern),
+            Err(e) => {
+                warn!("Ignoring invalid dir rule pattern {p:?}: {e:?}");
+                None
+            }
+        })
+        .collect()
+}
 
 #[derive(Debug, Clone)]
 pub struct Dir {
     // pub path: PathBuf,
     pub path: PathBuf,
-    pub audio_files: HashMap<PathBuf, String>,
+    pub rules: DirRules,
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    pub audio_files: HashMap<PathBuf, FileStamp>,
+    /// files sniffed as not audio, kept so a re-glob doesn't open them again until they change
+    not_audio: HashMap<PathBuf, FileStamp>,
+    /// false while the root (an unplugged drive, an unmounted share) is missing,
+    /// its index is then kept as it was instead of reporting every file removed
+    pub online: bool,
+}
+
+pub fn under_any(path: &Path, roots: &[PathBuf]) -> bool {
+    roots.iter().any(|root| path.starts_with(root))
+}
+
+/// a root is online when it exists and can be read, an empty one is an empty library.
+/// blocks as long as the file system does, a dead network share can take a while
+pub fn is_reachable(root: &Path) -> bool {
+    read_dir(root).is_ok()
+}
+
+/// result of `Dir::update`, removed files keep the stamp of the last glob
+#[derive(Debug, Default)]
+pub struct DirChanges {
+    pub removed: HashMap<PathBuf, FileStamp>,
+    pub added: HashMap<PathBuf, FileStamp>,
+    pub modified: Vec<PathBuf>,
 }
 
 #[allow(unused)]
 impl Dir {
     pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self::with_rules(path, DirRules::default())
+    }
+
+    pub fn with_rules<P: Into<PathBuf>>(path: P, rules: DirRules) -> Self {
         let mut new = Dir {
             path: path.into(),
+            include: compile(&rules.include),
+            exclude: compile(&rules.exclude),
+            rules,
             audio_files: HashMap::new(),
+            not_audio: HashMap::new(),
+            online: false,
         };
-        new.glob(true);
+        new.online = new.is_online();
+        if new.online {
+            new.glob(true);
+        }
         new
     }
 
@@ -26,83 +159,257 @@ impl Dir {
         new
     }
 
-    fn glob_in(&mut self, recrusive: bool) -> HashMap<PathBuf, String> {
-        self.audio_files.clear();
-        let pattern = if recrusive {
-            format!("{}/**/*", self.path.display())
-        } else {
-            format!("{}/*", self.path.display())
+    pub fn set_rules(&mut self, rules: DirRules) {
+        self.include = compile(&rules.include);
+        self.exclude = compile(&rules.exclude);
+        self.rules = rules;
+        if self.online {
+            self.glob(true);
+        }
+    }
+
+    /// see `is_reachable`
+    pub fn is_online(&self) -> bool {
+        is_reachable(&self.path)
+    }
+
+    pub fn listen_path(&self) -> ListenPath {
+        ListenPath::new(self.path.clone(), self.rules.clone())
+    }
+
+    fn matches(patterns: &[Pattern], relative: &Path) -> bool {
+        patterns.iter().any(|p| match p.as_str().contains('/') {
+            true => p.matches_path_with(relative, MATCH_OPTIONS),
+            false => relative.components().any(|c| match c {
+                Component::Normal(name) => name
+                    .to_str()
+                    .is_some_and(|name| p.matches_with(name, MATCH_OPTIONS)),
+                _ => false,
+            }),
+        })
+    }
+
+    /// excluded by `skip_hidden` / `exclude`, for files and folders alike
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.path) else {
+            return true;
         };
-        for entry in glob::glob(&pattern).expect("Failed to read glob pattern") {
+        let hidden = self.rules.skip_hidden
+            && relative.components().any(
+                |c| matches!(c, Component::Normal(name) if name.to_string_lossy().starts_with('.')),
+            );
+        hidden || Self::matches(&self.exclude, relative)
+    }
+
+    fn is_included(&self, path: &Path) -> bool {
+        self.include.is_empty()
+            || path
+                .strip_prefix(&self.path)
+                .is_ok_and(|relative| Self::matches(&self.include, relative))
+    }
+
+    /// walkdir 的深度从根目录本身的 0 算起, 根目录下的文件是 1
+    fn depth_limit(&self) -> usize {
+        self.rules.max_depth.map_or(usize::MAX, |depth| depth + 1)
+    }
+
+    /// stamp of a file and whether it's audio. files whose stamp didn't change
+    /// keep the verdict of the last look, so only new or changed files get sniffed
+    fn stamp_of(&self, path: &Path, filter: &AudioFilter) -> (FileStamp, bool) {
+        let (_, updated_at) = get_time(path);
+        let size = metadata(path).map(|m| m.len()).unwrap_or_default();
+        let stamp = FileStamp { updated_at, size };
+        let audio = filter.by_extension(path).unwrap_or_else(|| {
+            match (self.audio_files.get(path), self.not_audio.get(path)) {
+                (Some(old), _) if *old == stamp => true,
+                (_, Some(old)) if *old == stamp => false,
+                _ => sniff(path).is_some(),
+            }
+        });
+        (stamp, audio)
+    }
+
+    /// audio and non audio files under `from`, `max_depth` counted from `from`
+    fn walk(
+        &self,
+        from: &Path,
+        max_depth: usize,
+        filter: &AudioFilter,
+    ) -> (HashMap<PathBuf, FileStamp>, HashMap<PathBuf, FileStamp>) {
+        let walker = WalkDir::new(from)
+            .follow_links(self.rules.follow_symlinks)
+            .max_depth(max_depth)
+            .into_iter()
+            .filter_entry(|entry| entry.depth() == 0 || !self.is_ignored(entry.path()));
+        let mut found = HashMap::new();
+        let mut not_audio = HashMap::new();
+        for entry in walker {
             match entry {
-                Ok(path) => {
+                Ok(entry) if entry.file_type().is_file() => {
+                    let path = entry.into_path();
                     debug!("Found file: {path:?}");
-                    if let Some(ext) = path.extension() {
-                        if get_global()
-                            .get("supported_audio_extensions")
-                            .and_then(|v| v.as_array().cloned())
-                            .map(|arr| {
-                                arr.iter()
-                                    .filter_map(|e| e.as_str().map(|s| s.to_string()))
-                                    .collect::<Vec<String>>()
-                            })
-                            .unwrap_or_default()
-                            .iter()
-                            .any(|s| s.eq_ignore_ascii_case(ext.to_str().unwrap_or("")))
-                        {
-                            let (_, updated_at) = get_time(&path);
+                    if !self.is_included(&path) {
+                        continue;
+                    }
+                    match self.stamp_of(&path, filter) {
+                        (stamp, true) => {
                             debug!("Adding audio file: {path:?}");
-                            // self.audio_files.push(path);
-                            self.audio_files.insert(path, updated_at);
+                            found.insert(path, stamp);
+                        }
+                        (stamp, false) => {
+                            not_audio.insert(path, stamp);
                         }
                     }
                 }
+                Ok(_) => {}
+                // 符号链接成环之类的, 跳过这一项接着走
                 Err(e) => error!("Dir glob error: {e:?}"),
             }
         }
+        (found, not_audio)
+    }
+
+    fn glob_in(&mut self, recrusive: bool) -> HashMap<PathBuf, FileStamp> {
+        let max_depth = match recrusive {
+            true => self.depth_limit(),
+            false => 1,
+        };
+        let (found, not_audio) = self.walk(&self.path, max_depth, &AudioFilter::from_config());
+        self.audio_files = found;
+        self.not_audio = not_audio;
         self.audio_files.clone()
     }
 
+    /// take the index of a copy globbed elsewhere, unless the rules changed
+    /// or the root went offline in the meantime
+    pub fn adopt(&mut self, globbed: Dir) {
+        if self.online && self.rules == globbed.rules {
+            self.audio_files = globbed.audio_files;
+            self.not_audio = globbed.not_audio;
+        }
+    }
+
     pub fn glob(&mut self, recrusive: bool) -> Vec<PathBuf> {
         self.glob_in(recrusive);
         self.audio_files.keys().cloned().collect()
     }
 
-    pub fn update(&mut self) -> (Vec<PathBuf>, Vec<PathBuf>, Vec<PathBuf>) {
-        let old = self.audio_files.clone();
-        // self.audio_files.clear();
-        let new = self.glob_in(true);
-        (
-            // old.iter()
-            //     .filter(|x| !new.contains(x))
-            //     .cloned()
-            //     .collect::<Vec<_>>(),
-            // new.iter()
-            //     .filter(|x| !old.contains(x))
-            //     .cloned()
-            //     .collect::<Vec<_>>(),
-            // new.iter()
-            //     .filter(|x| old.contains(x) && )
-            //     .cloned()
-            //     .collect::<Vec<_>>(),
-            old.iter()
-                .filter(|(path, updated_at)| !new.contains_key(*path))
-                .map(|(path, _)| path.clone())
-                .collect::<Vec<_>>(),
-            new.iter()
+    fn diff(old: &HashMap<PathBuf, FileStamp>, new: &HashMap<PathBuf, FileStamp>) -> DirChanges {
+        DirChanges {
+            removed: old
+                .iter()
+                .filter(|(path, _)| !new.contains_key(*path))
+                .map(|(path, stamp)| (path.clone(), stamp.clone()))
+                .collect(),
+            added: new
+                .iter()
                 .filter(|(path, _)| !old.contains_key(*path))
-                .map(|(path, _)| path.clone())
-                .collect::<Vec<_>>(),
-            new.iter()
-                .filter(|(path, updated_at)| {
-                    old.contains_key(*path)
-                        && old
-                            .get(*path)
-                            .map(|old_updated_at| old_updated_at != *updated_at)
-                            .unwrap_or(false)
+                .map(|(path, stamp)| (path.clone(), stamp.clone()))
+                .collect(),
+            modified: new
+                .iter()
+                .filter(|(path, stamp)| {
+                    old.get(*path)
+                        .map(|old_stamp| old_stamp.updated_at != stamp.updated_at)
+                        .unwrap_or(false)
                 })
                 .map(|(path, _)| path.clone())
-                .collect::<Vec<_>>(),
-        )
+                .collect(),
+        }
+    }
+
+    /// full re-glob of the root, the fallback when the events can't be trusted
+    pub fn update(&mut self) -> DirChanges {
+        let old = self.audio_files.clone();
+        let new = self.glob_in(true);
+        Self::diff(&old, &new)
+    }
+
+    /// apply the paths of one debounce window to the index without walking the whole root.
+    /// each path is looked at as it is now: a file is (re)stamped, a folder is walked,
+    /// a missing path drops everything indexed under it. creates, writes, deletes and
+    /// both ends of a rename all come down to one of these
+    pub fn apply_events(&mut self, paths: &HashSet<PathBuf>) -> DirChanges {
+        let filter = AudioFilter::from_config();
+        let mut paths = paths
+            .iter()
+            .filter(|path| path.starts_with(&self.path))
+            .collect::<Vec<_>>();
+        paths.sort();
+        // 排序后父目录在前, 父目录整个看过 (或整个没了) 里面的事件就不用再看
+        paths.dedup_by(|path, parent| path.starts_with(&**parent));
+
+        let limit = self.depth_limit();
+        let mut changes = DirChanges::default();
+        for path in paths {
+            let depth = path
+                .strip_prefix(&self.path)
+                .map_or(0, |relative| relative.components().count());
+            let meta = match self.rules.follow_symlinks {
+                true => metadata(path),
+                false => symlink_metadata(path),
+            };
+            let ignored = depth > 0 && self.is_ignored(path);
+            let (found, not_audio) = match meta {
+                Ok(meta) if meta.is_dir() && !ignored => {
+                    self.walk(path, limit.saturating_sub(depth), &filter)
+                }
+                Ok(meta) if meta.is_file() && !ignored && depth <= limit => {
+                    let mut found = HashMap::new();
+                    let mut not_audio = HashMap::new();
+                    if self.is_included(path) {
+                        match self.stamp_of(path, &filter) {
+                            (stamp, true) => found.insert(path.clone(), stamp),
+                            (stamp, false) => not_audio.insert(path.clone(), stamp),
+                        };
+                    }
+                    (found, not_audio)
+                }
+                // 没了, 或者已经不归这个 root 管
+                _ => (HashMap::new(), HashMap::new()),
+            };
+            // 原来记在这个路径下的全部换成现在看到的
+            let old = self
+                .audio_files
+                .extract_if(|p, _| p.starts_with(path))
+                .collect::<HashMap<_, _>>();
+            self.not_audio.retain(|p, _| !p.starts_with(path));
+            let path_changes = Self::diff(&old, &found);
+            changes.removed.extend(path_changes.removed);
+            changes.added.extend(path_changes.added);
+            changes.modified.extend(path_changes.modified);
+            self.audio_files.extend(found);
+            self.not_audio.extend(not_audio);
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir};
+
+    // 不走 with_rules, 那边上线就会 glob, 要读 Config
+    fn dir(path: PathBuf) -> Dir {
+        Dir {
+            path,
+            rules: DirRules::default(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            audio_files: HashMap::new(),
+            not_audio: HashMap::new(),
+            online: false,
+        }
+    }
+
+    #[test]
+    fn empty_root_is_online() {
+        let root = std::env::temp_dir().join(format!("dir-empty-root-{}", std::process::id()));
+        create_dir_all(&root).unwrap();
+        let dir = dir(root.clone());
+        assert!(dir.is_online());
+        remove_dir(&root).unwrap();
+        assert!(!dir.is_online());
     }
 }
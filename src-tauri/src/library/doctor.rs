@@ -0,0 +1,443 @@
+use crate::{
+    library::library::Library,
+    meta::{lyric::get_lyric_dir_path, pic::get_art_dir_path},
+    misc::{error::CoreResult, utils::get_path_hash},
+    store::json::{
+        controller::{lyric::LyricController, pic::PicController},
+        entity::{
+            alist::{Aelement, Alist},
+            artist::Artist,
+            list::Playlist,
+            recent::Recent,
+            release::Release,
+            song::{songs_by_id, Song, TrackId},
+        },
+    },
+};
+use log::{info, warn};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tauri::Emitter;
+
+// 体检: song store / 歌单 / alist / 最近播放 / 封面歌词缓存之间对不上的地方.
+// 不修的时候只报告, 什么都不动; 修的时候先把记录里文件没了的歌当作已删除,
+// 这样报告里的引用问题和修完以后的状态是一致的
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Issue {
+    /// a song record whose file is gone, songs of offline roots don't count
+    MissingFile {
+        path: PathBuf,
+    },
+    /// a no art / no lyric placeholder although the song has one by now
+    StalePlaceholder {
+        cache: PathBuf,
+        song: PathBuf,
+    },
+    /// a cache file no song hashes to
+    OrphanedCache {
+        cache: PathBuf,
+    },
+    PlaylistRef {
+        playlist: String,
+        song: TrackId,
+    },
+    RecentRef {
+        song: TrackId,
+    },
+    /// an alist element none of whose songs are left
+    AlistMissing {
+        alist: String,
+        index: usize,
+    },
+    /// an alist element whose snapshot no longer matches the library
+    AlistDrifted {
+        alist: String,
+        index: usize,
+    },
+}
+
+impl Issue {
+    fn kind(&self) -> &'static str {
+        match self {
+            Issue::MissingFile { .. } => "missing_file",
+            Issue::StalePlaceholder { .. } => "stale_placeholder",
+            Issue::OrphanedCache { .. } => "orphaned_cache",
+            Issue::PlaylistRef { .. } => "playlist_ref",
+            Issue::RecentRef { .. } => "recent_ref",
+            Issue::AlistMissing { .. } => "alist_missing",
+            Issue::AlistDrifted { .. } => "alist_drifted",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DoctorReport {
+    pub checked_at: String,
+    /// the issues below were fixed
+    pub repaired: bool,
+    /// issues per kind
+    pub counts: BTreeMap<&'static str, usize>,
+    pub issues: Vec<Issue>,
+}
+
+enum Element {
+    Fine,
+    Drifted(Aelement),
+    Missing,
+}
+
+/// what's left of an element with `kept` of its `total` songs
+fn shrink(total: usize, kept: usize, element: impl FnOnce() -> Aelement) -> Element {
+    match (total == kept, kept) {
+        (true, _) => Element::Fine,
+        (false, 0) => Element::Missing,
+        (false, _) => Element::Drifted(element()),
+    }
+}
+
+fn same_song(a: &Song, b: &Song) -> bool {
+    let plain = |song: &Song| {
+        serde_json::to_value(Song {
+            unavailable: false,
+            ..song.clone()
+        })
+        .ok()
+    };
+    plain(a) == plain(b)
+}
+
+fn check_element(
+    element: &Aelement,
+    by_path: &HashMap<&Path, &Song>,
+    by_id: &HashMap<&str, &Song>,
+) -> Element {
+    let kept_paths = |paths: &Vec<String>| {
+        paths
+            .iter()
+            .filter(|path| by_path.contains_key(Path::new(path.as_str())))
+            .cloned()
+            .collect::<Vec<String>>()
+    };
+    match element {
+        Aelement::Song(song) => match by_path
+            .get(song.path.as_path())
+            .or_else(|| by_id.get(song.id.as_str()))
+        {
+            None => Element::Missing,
+            Some(current) if same_song(current, song) => Element::Fine,
+            Some(current) => Element::Drifted(Aelement::Song(Song {
+                unavailable: false,
+                ..(*current).clone()
+            })),
+        },
+        Aelement::Playlist(playlist) => {
+            let songs = playlist
+                .songs
+                .iter()
+                .filter(|id| by_id.contains_key(id.as_str()))
+                .cloned()
+                .collect::<Vec<TrackId>>();
+            shrink(playlist.songs.len(), songs.len(), || {
+                Aelement::Playlist(Playlist {
+                    songs,
+                    ..playlist.clone()
+                })
+            })
+        }
+        Aelement::Release(release) => {
+            let songs = kept_paths(&release.songs);
+            shrink(release.songs.len(), songs.len(), || {
+                Aelement::Release(Release {
+                    songs,
+                    ..release.clone()
+                })
+            })
+        }
+        Aelement::Artist(artist) => {
+            let songs = kept_paths(&artist.songs);
+            shrink(artist.songs.len(), songs.len(), || {
+                Aelement::Artist(Artist {
+                    songs,
+                    ..artist.clone()
+                })
+            })
+        }
+    }
+}
+
+fn cache_files(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| path.is_file())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// what the checks look at, copied out of the library so they run without its lock
+pub struct DoctorSnapshot {
+    songs: Vec<Song>,
+    playlists: Vec<Playlist>,
+    recents: Vec<Recent>,
+    alists: Vec<Alist>,
+    pics: PicController,
+    lyrics: LyricController,
+}
+
+/// the store side of a repair, worked out on the snapshot
+#[derive(Default)]
+struct Repairs {
+    missing: Vec<PathBuf>,
+    dangling: HashSet<TrackId>,
+    stale_recents: HashSet<TrackId>,
+    /// fixed alists, each with the elements it was fixed from
+    alists: Vec<(Vec<Aelement>, Alist)>,
+}
+
+fn check(snapshot: &DoctorSnapshot) -> CoreResult<(Vec<Issue>, Vec<Song>, Repairs)> {
+    let mut issues = Vec::new();
+    let (songs, missing): (Vec<&Song>, Vec<&Song>) = snapshot
+        .songs
+        .iter()
+        .partition(|song| song.unavailable || song.path.exists());
+    missing.iter().for_each(|song| {
+        issues.push(Issue::MissingFile {
+            path: song.path.clone(),
+        })
+    });
+    let by_path = songs
+        .iter()
+        .map(|song| (song.path.as_path(), *song))
+        .collect::<HashMap<&Path, &Song>>();
+    let by_id = songs_by_id(songs.iter().copied());
+
+    // 缓存: 占位文件过期了 (后来有了封面 / 歌词), 以及没有歌对应的缓存文件
+    let mut recache = Vec::new();
+    let art_dir = get_art_dir_path()?;
+    songs
+        .iter()
+        .filter(|song| !song.unavailable)
+        .for_each(|song| {
+            let placeholder = art_dir.join(format!("{}.img", get_path_hash(&song.path)));
+            if placeholder.exists()
+                && (song.check_art_cache().is_ok_and(|(_, _, pic)| pic)
+                    || song.has_release_art().unwrap_or(false))
+            {
+                issues.push(Issue::StalePlaceholder {
+                    cache: placeholder,
+                    song: song.path.clone(),
+                });
+                recache.push((*song).clone());
+            }
+            let Ok(lyric) = song.get_lyric_cache_path() else {
+                return;
+            };
+            let empty = fs::metadata(&lyric).is_ok_and(|m| m.len() == 0);
+            if empty && song.path.with_extension("lrc").exists() {
+                issues.push(Issue::StalePlaceholder {
+                    cache: lyric,
+                    song: song.path.clone(),
+                });
+                recache.push((*song).clone());
+            }
+        });
+    let hashes = songs
+        .iter()
+        .map(|song| get_path_hash(&song.path))
+        .collect::<HashSet<String>>();
+    cache_files(&art_dir)
+        .into_iter()
+        .chain(cache_files(&get_lyric_dir_path()?))
+        .filter(|cache| {
+            let name = cache.file_name().unwrap_or_default().to_string_lossy();
+            !hashes.contains(name.split('.').next().unwrap_or_default())
+        })
+        .for_each(|cache| issues.push(Issue::OrphanedCache { cache }));
+
+    // 按 id 引用的: 歌单, 最近播放
+    let mut repairs = Repairs {
+        missing: missing.iter().map(|song| song.path.clone()).collect(),
+        ..Default::default()
+    };
+    snapshot.playlists.iter().for_each(|playlist| {
+        let mut seen = HashSet::new();
+        playlist
+            .songs
+            .iter()
+            .filter(|id| !by_id.contains_key(id.as_str()) && seen.insert(*id))
+            .for_each(|id| {
+                repairs.dangling.insert(id.clone());
+                issues.push(Issue::PlaylistRef {
+                    playlist: playlist.name.clone(),
+                    song: id.clone(),
+                });
+            });
+    });
+    repairs.stale_recents = snapshot
+        .recents
+        .iter()
+        .map(|recent| &recent.song)
+        .filter(|id| !by_id.contains_key(id.as_str()))
+        .cloned()
+        .collect();
+    repairs
+        .stale_recents
+        .iter()
+        .for_each(|id| issues.push(Issue::RecentRef { song: id.clone() }));
+
+    // alist 里的快照
+    snapshot.alists.iter().for_each(|alist| {
+        let mut fixed = alist.clone();
+        fixed.elements.clear();
+        let mut changed = false;
+        alist
+            .elements
+            .iter()
+            .enumerate()
+            .for_each(
+                |(index, element)| match check_element(element, &by_path, &by_id) {
+                    Element::Fine => fixed.elements.push(element.clone()),
+                    Element::Drifted(element) => {
+                        issues.push(Issue::AlistDrifted {
+                            alist: alist.name.clone(),
+                            index,
+                        });
+                        fixed.elements.push(element);
+                        changed = true;
+                    }
+                    Element::Missing => {
+                        issues.push(Issue::AlistMissing {
+                            alist: alist.name.clone(),
+                            index,
+                        });
+                        changed = true;
+                    }
+                },
+            );
+        if changed {
+            repairs.alists.push((alist.elements.clone(), fixed));
+        }
+    });
+    Ok((issues, recache, repairs))
+}
+
+/// check the stores and caches against each other, and fix what's found if `repair`.
+/// the checks open files (a no art placeholder included), so they run on a snapshot
+/// without the library lock; it's only taken again to apply the repairs
+pub fn doctor(library: &Mutex<Library>, repair: bool) -> CoreResult<DoctorReport> {
+    let snapshot = library.lock().unwrap().doctor_snapshot();
+    let (issues, recache, repairs) = check(&snapshot)?;
+    let mut counts = BTreeMap::new();
+    issues
+        .iter()
+        .for_each(|issue| *counts.entry(issue.kind()).or_insert(0) += 1);
+    info!("Library doctor found {counts:?}");
+    let report = DoctorReport {
+        checked_at: chrono::Local::now().to_rfc3339(),
+        repaired: repair,
+        counts,
+        issues,
+    };
+    if !repair || report.issues.is_empty() {
+        return Ok(report);
+    }
+
+    // 缓存文件不在 store 里, 不用拿锁
+    report.issues.iter().for_each(|issue| match issue {
+        Issue::StalePlaceholder { cache, .. } | Issue::OrphanedCache { cache } => {
+            if let Err(e) = fs::remove_file(cache) {
+                warn!("Failed to remove cache {cache:?}: {e:?}");
+            }
+        }
+        _ => {}
+    });
+    snapshot.pics.get_release_arts(&recache)?;
+    snapshot.lyrics.cache_lyrics(&recache)?;
+    library.lock().unwrap().apply_repairs(repairs)?;
+    info!("Library doctor repaired {} issues", report.issues.len());
+    Ok(report)
+}
+
+#[allow(unused)]
+impl Library {
+    pub fn doctor_snapshot(&self) -> DoctorSnapshot {
+        DoctorSnapshot {
+            songs: self.song_info.clone(),
+            playlists: self.playlist.clone(),
+            recents: self.recent.clone(),
+            alists: self.alist.clone(),
+            pics: self.pic_controller.clone(),
+            lyrics: self.lyric_controller.clone(),
+        }
+    }
+
+    /// the library may have moved on since the snapshot: songs that came back, ids that
+    /// resolve again and alists edited in between are left alone
+    fn apply_repairs(&mut self, repairs: Repairs) -> CoreResult<()> {
+        let missing = repairs
+            .missing
+            .into_iter()
+            .filter(|path| {
+                self.song_info
+                    .iter()
+                    .any(|song| &song.path == path && !song.unavailable)
+                    && !path.exists()
+            })
+            .collect::<Vec<PathBuf>>();
+        let gone = missing.iter().collect::<HashSet<&PathBuf>>();
+        let by_id = songs_by_id(
+            self.song_info
+                .iter()
+                .filter(|song| !gone.contains(&song.path)),
+        );
+        let dangling = repairs
+            .dangling
+            .into_iter()
+            .filter(|id| !by_id.contains_key(id.as_str()))
+            .collect::<Vec<TrackId>>();
+        let stale_recents = repairs
+            .stale_recents
+            .iter()
+            .filter(|id| !by_id.contains_key(id.as_str()))
+            .collect::<Vec<&TrackId>>();
+        if !dangling.is_empty() {
+            self.playlist_controller.remove_song_ids_all(dangling)?;
+            self.playlist = self.playlist_controller.get_all_playlists()?;
+        }
+        if !stale_recents.is_empty() {
+            self.recent_controller.remove_by_song_ids(stale_recents)?;
+            self.recent = self.recent_controller.get_all_recents()?;
+        }
+        let alists = repairs
+            .alists
+            .into_iter()
+            .filter(|(elements, fixed)| {
+                self.alist
+                    .iter()
+                    .any(|alist| alist.name == fixed.name && &alist.elements == elements)
+            })
+            .map(|(_, fixed)| fixed)
+            .collect::<Vec<Alist>>();
+        if !alists.is_empty() {
+            let alist_op = self.alist_controller.get_op();
+            alists.iter().try_for_each(|alist| alist_op.modify(alist))?;
+            self.alist = self.alist_controller.get_all_alists()?;
+        }
+        if !missing.is_empty() {
+            // 顺带发 lib_updated
+            self.on_file_removed(missing)?;
+        } else {
+            let _ = self.app.emit("lib_updated", ());
+        }
+        Ok(())
+    }
+}
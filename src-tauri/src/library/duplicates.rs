@@ -0,0 +1,492 @@
+use crate::{
+    library::{library::Library, search::fold},
+    meta::{decode::PacketDecoder, probe},
+    misc::error::{CoreError, CoreResult},
+    store::json::entity::{
+        score::Score,
+        song::{Song, TrackId},
+    },
+};
+use lofty::{
+    config::{ParseOptions, ParsingMode},
+    file::{AudioFile, FileType},
+    prelude::TaggedFileExt,
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::{max, min},
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tauri::Emitter;
+
+// 同一首歌在几个文件夹里各放了一份 (flac / mp3 / 别人转的 m4a ...).
+// 先按 标题 / 艺术家 / 专辑 + 时长 分组, 要的话再解码比一下指纹把同名不同版本 (live / remix) 拆开.
+// 合并只挪引用 (评分, 歌单, 最近播放, alist), 文件本身不动, 删不删交给用户;
+// 合并之前一定会比时长和指纹, 不是同一段录音的不合.
+// 指纹是 Haitsma / Kalker 那种: 每个窗口里相邻频带能量差的变化方向, 16 个频带差一个窗口 16 bit
+
+/// fingerprint resolution, one sub-fingerprint per window
+const WINDOW_SECS: f64 = 0.05;
+/// only the first two minutes (after the leading silence) are decoded
+const FINGERPRINT_WINDOWS: usize = 2400;
+/// leading silence given up on, so a silent file isn't decoded to its end
+const MAX_SILENT_WINDOWS: usize = 600;
+/// bands log spaced over where most of the melody is, one bit per pair of neighbours
+const BANDS: usize = 17;
+const LOW_HZ: f64 = 300.0;
+const HIGH_HZ: f64 = 3000.0;
+/// shorter overlaps say nothing
+const MIN_WINDOWS: usize = 100;
+/// encoder delay / leading silence differences, in windows
+const MAX_SHIFT: usize = 40;
+/// share of differing bits up to which two prints are the same recording, unrelated audio is near 0.5
+const MAX_BIT_ERROR: f32 = 0.25;
+const SILENCE: f32 = 1e-6;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DuplicateOptions {
+    /// the release has to match too, off to also catch the single + album copies
+    pub match_release: bool,
+    pub duration_tolerance_secs: u32,
+    /// decode the candidates and split groups whose audio differs, slow
+    pub fingerprint: bool,
+}
+
+impl Default for DuplicateOptions {
+    fn default() -> Self {
+        Self {
+            match_release: true,
+            duration_tolerance_secs: 2,
+            fingerprint: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Quality {
+    pub lossless: bool,
+    pub bit_depth: u8,
+    pub sample_rate: u32,
+    /// kbps
+    pub bitrate: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Candidate {
+    pub song: Song,
+    /// `None` when the file couldn't be read
+    pub quality: Option<Quality>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateGroup {
+    /// the suggested keeper, also the first of `songs`
+    pub keeper: PathBuf,
+    /// best first
+    pub songs: Vec<Candidate>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DuplicateMerge {
+    pub keeper: PathBuf,
+    pub others: Vec<PathBuf>,
+}
+
+fn normalize(text: &str) -> String {
+    fold(text).chars().filter(|c| c.is_alphanumeric()).collect()
+}
+
+fn group_key(song: &Song, match_release: bool) -> Option<(String, String, String)> {
+    // 没标题的按文件名, 三个文件夹里的同一首歌文件名多半一样
+    let title = song
+        .title
+        .clone()
+        .or_else(|| Some(song.path.file_stem()?.to_string_lossy().to_string()))
+        .map(|title| normalize(&title))
+        .filter(|title| !title.is_empty())?;
+    let field = |field: &Option<String>| field.as_deref().map(normalize).unwrap_or_default();
+    let release = match match_release {
+        true => field(&song.release),
+        false => String::new(),
+    };
+    Some((title, field(&song.artist), release))
+}
+
+/// runs of songs within `tolerance` of the run's shortest one
+fn split_by_duration(mut songs: Vec<Song>, tolerance: u32) -> Vec<Vec<Song>> {
+    songs.sort_by_key(|song| song.duration);
+    let mut groups: Vec<Vec<Song>> = Vec::new();
+    songs.into_iter().for_each(|song| {
+        let duration = song.duration.unwrap_or_default();
+        match groups.last_mut() {
+            Some(group) if duration - group[0].duration.unwrap_or_default() <= tolerance => {
+                group.push(song)
+            }
+            _ => groups.push(vec![song]),
+        }
+    });
+    groups
+}
+
+fn quality(path: &Path) -> Option<Quality> {
+    let options = ParseOptions::new()
+        .read_properties(true)
+        .parsing_mode(ParsingMode::Relaxed);
+    let file = probe::open(path).ok()?.options(options).read().ok()?;
+    let properties = file.properties();
+    Some(Quality {
+        lossless: matches!(
+            file.file_type(),
+            FileType::Flac | FileType::Wav | FileType::Aiff | FileType::Ape | FileType::WavPack
+        ),
+        bit_depth: properties.bit_depth().unwrap_or_default(),
+        sample_rate: properties.sample_rate().unwrap_or_default(),
+        bitrate: properties.audio_bitrate().unwrap_or_default(),
+    })
+}
+
+fn score_value(score: &Option<Score>) -> u8 {
+    score.as_ref().map(Score::value).unwrap_or_default()
+}
+
+/// best file first: quality, then the one that's scored higher, then the older one
+fn rank(songs: Vec<Song>) -> Vec<Candidate> {
+    let mut candidates = songs
+        .into_iter()
+        .map(|song| Candidate {
+            quality: quality(&song.path),
+            song,
+        })
+        .collect::<Vec<Candidate>>();
+    candidates.sort_by(|a, b| {
+        let key = |c: &Candidate| {
+            (
+                !c.song.unavailable,
+                c.quality.clone(),
+                score_value(&c.song.score),
+            )
+        };
+        key(b)
+            .cmp(&key(a))
+            .then_with(|| a.song.created_at.cmp(&b.song.created_at))
+    });
+    candidates
+}
+
+/// a constant peak gain band pass (RBJ cookbook)
+struct Band {
+    b0: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Band {
+    fn new(center: f64, q: f64, sample_rate: f64) -> Self {
+        let w0 = 2.0 * std::f64::consts::PI * center / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let a0 = 1.0 + alpha;
+        Self {
+            b0: (alpha / a0) as f32,
+            a1: (-2.0 * w0.cos() / a0) as f32,
+            a2: ((1.0 - alpha) / a0) as f32,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    /// b1 is 0 and b2 is -b0 for this band pass
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * (x - self.x2) - self.a1 * self.y1 - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+fn sub_fingerprint(previous: &[f32], current: &[f32]) -> u16 {
+    (0..BANDS - 1).fold(0, |bits, m| {
+        let delta = (current[m] - current[m + 1]) - (previous[m] - previous[m + 1]);
+        (bits << 1) | u16::from(delta > 0.0)
+    })
+}
+
+/// sub-fingerprints over the start of the track, decoded packet by packet and only as far as needed
+fn fingerprint(path: &Path) -> Option<Vec<u16>> {
+    let mut decoder = PacketDecoder::open(path)
+        .map_err(|e| warn!("Failed to decode {path:?} for a fingerprint: {e:?}"))
+        .ok()?;
+    let sample_rate = decoder.sample_rate as f64;
+    let window = max(1, (sample_rate * WINDOW_SECS) as usize);
+    let step = (HIGH_HZ / LOW_HZ).powf(1.0 / (BANDS - 1) as f64);
+    let q = step.sqrt() / (step - 1.0);
+    let mut bands = (0..BANDS)
+        .map(|i| Band::new(LOW_HZ * step.powi(i as i32), q, sample_rate))
+        .collect::<Vec<Band>>();
+    let mut energies = vec![0.0; BANDS];
+    let (mut total, mut filled, mut silent) = (0.0, 0, 0);
+    let mut previous: Option<Vec<f32>> = None;
+    let mut print = Vec::new();
+    'decode: while let Some(chunk) = decoder.next_chunk() {
+        for sample in chunk {
+            total += sample * sample;
+            bands
+                .iter_mut()
+                .zip(energies.iter_mut())
+                .for_each(|(band, energy)| *energy += band.process(sample).powi(2));
+            filled += 1;
+            if filled < window {
+                continue;
+            }
+            let current = std::mem::replace(&mut energies, vec![0.0; BANDS]);
+            let quiet = total / (filled as f32) < SILENCE;
+            (total, filled) = (0.0, 0);
+            // 开头的静音不算, 编码器 / 抓轨软件加的长短不一样
+            if previous.is_none() && quiet {
+                silent += 1;
+                if silent > MAX_SILENT_WINDOWS {
+                    break 'decode;
+                }
+                continue;
+            }
+            if let Some(previous) = &previous {
+                print.push(sub_fingerprint(previous, &current));
+                if print.len() >= FINGERPRINT_WINDOWS {
+                    break 'decode;
+                }
+            }
+            previous = Some(current);
+        }
+    }
+    (print.len() >= MIN_WINDOWS).then_some(print)
+}
+
+fn bit_error(a: &[u16], b: &[u16]) -> Option<f32> {
+    let overlap = min(a.len(), b.len());
+    (overlap >= MIN_WINDOWS).then(|| {
+        let differing = a
+            .iter()
+            .zip(b)
+            .map(|(x, y)| (x ^ y).count_ones())
+            .sum::<u32>();
+        differing as f32 / (overlap * (BANDS - 1)) as f32
+    })
+}
+
+fn same_audio(a: &[u16], b: &[u16]) -> bool {
+    (0..=min(MAX_SHIFT, min(a.len(), b.len()))).any(|shift| {
+        [bit_error(&a[shift..], b), bit_error(a, &b[shift..])]
+            .into_iter()
+            .flatten()
+            .any(|error| error <= MAX_BIT_ERROR)
+    })
+}
+
+/// split a metadata group by audio; songs that can't be decoded stay with the first part
+fn split_by_audio(songs: Vec<Song>) -> Vec<Vec<Song>> {
+    let mut parts: Vec<(Vec<u16>, Vec<Song>)> = Vec::new();
+    let mut undecided = Vec::new();
+    songs
+        .into_iter()
+        .for_each(|song| match fingerprint(&song.path) {
+            None => undecided.push(song),
+            Some(print) => match parts.iter_mut().find(|(p, _)| same_audio(p, &print)) {
+                Some((_, part)) => part.push(song),
+                None => parts.push((print, vec![song])),
+            },
+        });
+    let mut parts = parts
+        .into_iter()
+        .map(|(_, part)| part)
+        .collect::<Vec<Vec<Song>>>();
+    match parts.first_mut() {
+        Some(first) => first.extend(undecided),
+        None => parts.push(undecided),
+    }
+    parts
+}
+
+/// group the library's duplicates. only the candidate list is taken under the lock,
+/// reading / decoding the files happens without it
+pub fn find_duplicates(
+    library: &Arc<Mutex<Library>>,
+    options: &DuplicateOptions,
+) -> Vec<DuplicateGroup> {
+    let candidates = library.lock().unwrap().duplicate_candidates(options);
+    let groups = candidates
+        .into_iter()
+        .flat_map(|group| match options.fingerprint {
+            true => split_by_audio(group),
+            false => vec![group],
+        })
+        .filter(|group| group.len() > 1)
+        .map(|group| {
+            let songs = rank(group);
+            DuplicateGroup {
+                keeper: songs[0].song.path.clone(),
+                songs,
+            }
+        })
+        .collect::<Vec<DuplicateGroup>>();
+    info!(
+        "Found {} duplicate groups ({} files)",
+        groups.len(),
+        groups.iter().map(|g| g.songs.len()).sum::<usize>()
+    );
+    groups
+}
+
+/// a merge is only done for files of the same recording: durations within the default
+/// tolerance and matching fingerprints. the files are decoded without the lock, it's only
+/// taken to look the songs up and for the merge itself
+pub fn merge_duplicates(library: &Mutex<Library>, merges: Vec<DuplicateMerge>) -> CoreResult<()> {
+    let songs = {
+        let library = library.lock().unwrap();
+        let wanted = merges
+            .iter()
+            .flat_map(|merge| std::iter::once(&merge.keeper).chain(&merge.others))
+            .collect::<HashSet<&PathBuf>>();
+        library
+            .song_info
+            .iter()
+            .filter(|song| wanted.contains(&song.path))
+            .map(|song| (song.path.clone(), song.clone()))
+            .collect::<HashMap<PathBuf, Song>>()
+    };
+    let find = |path: &PathBuf| {
+        songs
+            .get(path)
+            .ok_or_else(|| CoreError::OtherError(format!("{path:?} is not in the library")))
+    };
+    let tolerance = DuplicateOptions::default().duration_tolerance_secs;
+    merges.iter().try_for_each(|merge| {
+        let keeper = find(&merge.keeper)?;
+        let keeper_print = fingerprint(&keeper.path);
+        merge.others.iter().try_for_each(|other| {
+            let other = find(other)?;
+            let same_duration = keeper
+                .duration
+                .zip(other.duration)
+                .is_some_and(|(a, b)| a.abs_diff(b) <= tolerance);
+            let same_recording = same_duration
+                && keeper_print
+                    .as_deref()
+                    .zip(fingerprint(&other.path))
+                    .is_some_and(|(a, b)| same_audio(a, &b));
+            match same_recording {
+                true => Ok(()),
+                false => Err(CoreError::OtherError(format!(
+                    "{:?} is not the same recording as {:?}",
+                    other.path, keeper.path
+                ))),
+            }
+        })
+    })?;
+    library.lock().unwrap().merge_duplicates(merges)
+}
+
+#[allow(unused)]
+impl Library {
+    /// songs with the same normalized title / artist (/ release) and about the same duration
+    pub fn duplicate_candidates(&self, options: &DuplicateOptions) -> Vec<Vec<Song>> {
+        let mut buckets: HashMap<(String, String, String), Vec<Song>> = HashMap::new();
+        self.song_info
+            .iter()
+            .filter(|song| song.duration.is_some())
+            .for_each(|song| {
+                if let Some(key) = group_key(song, options.match_release) {
+                    buckets.entry(key).or_default().push(song.clone());
+                }
+            });
+        buckets
+            .into_values()
+            .filter(|songs| songs.len() > 1)
+            .flat_map(|songs| split_by_duration(songs, options.duration_tolerance_secs))
+            .filter(|group| group.len() > 1)
+            .collect()
+    }
+
+    /// move the best score, playlist entries, recents and alist entries of `others` onto
+    /// `keeper`. the files stay where they are
+    pub fn merge_duplicates(&mut self, merges: Vec<DuplicateMerge>) -> CoreResult<()> {
+        let by_path = self
+            .song_info
+            .iter()
+            .map(|song| (song.path.clone(), song))
+            .collect::<HashMap<PathBuf, &Song>>();
+        let mut ids: HashMap<TrackId, TrackId> = HashMap::new();
+        let mut paths: HashMap<PathBuf, PathBuf> = HashMap::new();
+        let mut scored = Vec::new();
+        for merge in &merges {
+            let keeper = by_path.get(&merge.keeper).ok_or_else(|| {
+                CoreError::OtherError(format!("{:?} is not in the library", merge.keeper))
+            })?;
+            let others = merge
+                .others
+                .iter()
+                .filter(|path| **path != merge.keeper)
+                .map(|path| {
+                    by_path.get(path).copied().ok_or_else(|| {
+                        CoreError::OtherError(format!("{path:?} is not in the library"))
+                    })
+                })
+                .collect::<CoreResult<Vec<&Song>>>()?;
+            others.iter().for_each(|other| {
+                // 同一个文件的拷贝 id 本来就一样
+                if !other.id.is_empty() && other.id != keeper.id {
+                    ids.insert(other.id.clone(), keeper.id.clone());
+                }
+                paths.insert(other.path.clone(), keeper.path.clone());
+            });
+            let best = others
+                .iter()
+                .filter_map(|other| other.score.clone())
+                .max_by_key(|score| score.value());
+            if score_value(&best) > score_value(&keeper.score) {
+                scored.push(Song {
+                    score: best,
+                    ..(*keeper).clone()
+                });
+            }
+        }
+        // 一个文件既当 keeper 又被并到别处的话会串起来, 不好说谁该留, 直接拒绝
+        let chained = merges
+            .iter()
+            .filter(|merge| paths.contains_key(&merge.keeper))
+            .map(|merge| merge.keeper.clone())
+            .collect::<HashSet<PathBuf>>();
+        if !chained.is_empty() {
+            return Err(CoreError::OtherError(format!(
+                "{chained:?} are merged into another keeper and kept at the same time"
+            )));
+        }
+
+        if !scored.is_empty() {
+            self.song_controller.update_song_infos(scored)?;
+        }
+        let playlists = self.playlist_controller.relink_ids(&ids)?;
+        let recents = self.recent_controller.relink_ids(&ids)?;
+        self.alist_controller.rewrite_song_paths(&paths)?;
+        info!(
+            "Merged {} duplicates, {playlists} playlists and {recents} recents relinked",
+            paths.len()
+        );
+        self.reload_song_info()?;
+        self.playlist = self.playlist_controller.get_all_playlists()?;
+        self.recent = self.recent_controller.get_all_recents()?;
+        self.alist = self.alist_controller.get_all_alists()?;
+        let _ = self.app.emit("lib_updated", ());
+        Ok(())
+    }
+}
@@ -1,21 +1,70 @@
-use crate::library::dir::Dir;
+use crate::library::dir::{is_reachable, Dir, DirChanges, DirRules, FileStamp, ListenPath};
 use crate::library::watch::DirectoryWatcher;
-use crate::misc::error::CoreResult;
+use crate::meta::id::compute_id;
+use crate::misc::error::{CoreError, CoreResult};
+use crate::store::json::entity::song::{Song, TrackId};
 use log::{debug, info, warn};
 use notify_debouncer_mini::DebouncedEvent;
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     path::PathBuf,
     sync::{Arc, Mutex},
     time::Duration,
 };
 use tauri::{AppHandle, Emitter};
 
+/// paths in one debounce window of one root above which a full re-glob is cheaper
+const FULL_RESCAN_EVENTS: usize = 2000;
+
 #[derive(Debug)]
 pub struct FileSystem {
     pub watcher: DirectoryWatcher,
     pub dirs: Arc<Mutex<Vec<Dir>>>,
     pub ignored_paths: Arc<Mutex<HashSet<PathBuf>>>,
+    pub known_ids: Arc<Mutex<HashMap<PathBuf, TrackId>>>,
+}
+
+/// pair removed / added files of one debounce window that are the same file moved.
+/// size and mtime must match; if we know the old track id the new file has to carry it,
+/// otherwise the stamp has to be unambiguous on both sides.
+/// reads the candidate files, so no lock should be held while it runs
+fn pair_moves(
+    removed: &mut HashMap<PathBuf, FileStamp>,
+    added: &mut HashMap<PathBuf, FileStamp>,
+    known_ids: &HashMap<PathBuf, TrackId>,
+) -> Vec<(PathBuf, PathBuf)> {
+    let mut added_ids: HashMap<PathBuf, Option<TrackId>> = HashMap::new();
+    let mut from_paths = removed.keys().cloned().collect::<Vec<_>>();
+    from_paths.sort();
+    let mut moves = Vec::new();
+    for from in from_paths {
+        let stamp = removed[&from].clone();
+        let mut candidates = added
+            .iter()
+            .filter(|(_, s)| **s == stamp)
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        candidates.sort();
+        let by_id = known_ids.get(&from).and_then(|id| {
+            candidates.iter().find(|to| {
+                added_ids
+                    .entry((*to).clone())
+                    .or_insert_with(|| compute_id(to))
+                    .as_ref()
+                    == Some(id)
+            })
+        });
+        let unique = (candidates.len() == 1
+            && removed.values().filter(|s| **s == stamp).count() == 1)
+            .then(|| &candidates[0]);
+        if let Some(to) = by_id.or(unique).cloned() {
+            debug!("FileSystem Watcher: {from:?} moved to {to:?}");
+            removed.remove(&from);
+            added.remove(&to);
+            moves.push((from, to));
+        }
+    }
+    moves
 }
 
 #[allow(unused)]
@@ -25,84 +74,169 @@ impl FileSystem {
         let dirs_clone = Arc::clone(&dirs);
         let ignored_paths = Arc::new(Mutex::new(HashSet::<PathBuf>::new()));
         let ignored_paths_clone = Arc::clone(&ignored_paths);
+        let known_ids = Arc::new(Mutex::new(HashMap::<PathBuf, TrackId>::new()));
+        let known_ids_clone = Arc::clone(&known_ids);
 
         // big&lovely closure
-        let handler = Box::new(move |res: Result<Vec<DebouncedEvent>, notify::Error>| {
-            if let Ok(events) = res {
-                let locked_dirs = dirs_clone.lock().unwrap();
-                let paths_to_update = events
-                    .iter()
-                    .filter_map(|event| {
-                        locked_dirs
+        let handler = Arc::new(move |res: Result<Vec<DebouncedEvent>, notify::Error>| {
+            // root -> 事件路径, None 表示整个 root 重扫
+            let updates = match res {
+                Ok(events) => {
+                    let locked_dirs = dirs_clone.lock().unwrap();
+                    let mut updates: HashMap<PathBuf, Option<HashSet<PathBuf>>> = HashMap::new();
+                    events.into_iter().for_each(|event| {
+                        // 被规则排除的路径 (下载中的临时文件, 隐藏目录...) 不值得看
+                        if let Some(dir) = locked_dirs
                             .iter()
                             .find(|dir| event.path.starts_with(&dir.path))
-                            .map(|d| d.path.clone())
-                    })
-                    .collect::<HashSet<_>>();
-                drop(locked_dirs);
-                if paths_to_update.is_empty() {
-                    return;
-                }
-                let mut locked_dirs = dirs_clone.lock().unwrap();
-                let mut ignored = ignored_paths_clone.lock().unwrap();
-                paths_to_update.iter().for_each(|path| {
-                    if let Some(dir) = locked_dirs.iter_mut().find(|d| &d.path == path) {
-                        info!("FileSystem Watcher: Updating dir {:?}", dir.path);
-                        let (removed_files, added_files, modified_files) = dir.update();
-                        // if !removed_files.is_empty() || !added_files.is_empty() {
-                        //     info!("FileSystem Watcher: Emitting files-changed");
-                        //     // wip: 改为仅发送通知 然后前端调用通道获取更新后的文件列表
-                        //     app_handle
-                        //         .emit("files-changed", (removed_files, added_files, all_files))
-                        //         .unwrap();
-                        // }
-                        // if !all_files.is_empty() {
-                        //     debug!("FS: Emitting internal signal fs-files-all");
-                        //     app_handle.emit("fs-files-all", all_files).unwrap();
-                        // }
-                        if !removed_files.is_empty() {
-                            debug!("FileSystem Watcher: Emitting internal signal fs-files-removed");
-                            app_handle.emit("fs-files-removed", removed_files).unwrap();
-                        }
-                        if !added_files.is_empty() {
-                            debug!("FileSystem Watcher: Emitting internal signal fs-files-added");
-                            app_handle.emit("fs-files-added", added_files).unwrap();
-                        }
-                        let mut final_modified_files = Vec::new();
-                        for file in modified_files {
-                            if ignored.remove(&file) {
-                                warn!("FS Watcher: Ignoring modification event for {file:?}");
-                            } else {
-                                final_modified_files.push(file);
+                            .filter(|dir| !dir.is_ignored(&event.path))
+                        {
+                            if let Some(paths) = updates
+                                .entry(dir.path.clone())
+                                .or_insert(Some(HashSet::new()))
+                            {
+                                paths.insert(event.path);
                             }
                         }
-                        // if !modified_files.is_empty() {
-                        if !final_modified_files.is_empty() {
+                    });
+                    // 一次来这么多多半是整个目录搬家, 逐个路径看还不如重扫
+                    updates.values_mut().for_each(|paths| {
+                        if paths.as_ref().is_some_and(|p| p.len() > FULL_RESCAN_EVENTS) {
+                            *paths = None;
+                        }
+                    });
+                    updates
+                }
+                Err(e) => {
+                    // 事件可能丢了, 不知道丢在哪就全部重扫
+                    warn!("FileSystem Watcher: {e:?}, rescanning every dir");
+                    dirs_clone
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .map(|dir| (dir.path.clone(), None))
+                        .collect()
+                }
+            };
+            if updates.is_empty() {
+                return;
+            }
+            // 盘被拔掉时的事件不能当成删除, 等 apply_roots 来处理上下线; 看 root 在不在的时候不拿锁
+            let reachable = updates
+                .keys()
+                .filter(|root| is_reachable(root))
+                .cloned()
+                .collect::<HashSet<_>>();
+            let mut locked_dirs = dirs_clone.lock().unwrap();
+            // 一个 debounce 窗口内所有 dir 的变化放在一起, 跨 dir 的移动也能配上
+            let mut changes = DirChanges::default();
+            updates.iter().for_each(|(path, paths)| {
+                if let Some(dir) = locked_dirs.iter_mut().find(|d| &d.path == path) {
+                    if !dir.online || !reachable.contains(path) {
+                        debug!("FileSystem Watcher: Skipping events of offline dir {path:?}");
+                        return;
+                    }
+                    let dir_changes = match paths {
+                        Some(paths) => {
                             debug!(
-                                "FileSystem Watcher: Emitting internal signal fs-files-modified"
+                                "FileSystem Watcher: {} changes in dir {:?}",
+                                paths.len(),
+                                dir.path
                             );
-                            app_handle
-                                .emit("fs-files-modified", final_modified_files)
-                                .unwrap();
+                            dir.apply_events(paths)
                         }
-                    }
-                });
+                        None => {
+                            info!("FileSystem Watcher: Rescanning dir {:?}", dir.path);
+                            dir.update()
+                        }
+                    };
+                    changes.removed.extend(dir_changes.removed);
+                    changes.added.extend(dir_changes.added);
+                    changes.modified.extend(dir_changes.modified);
+                }
+            });
+            drop(locked_dirs);
+            // 只拷被删掉的那几条, 配对时要读文件算 id, 不能拿着锁
+            let known_ids = {
+                let known_ids = known_ids_clone.lock().unwrap();
+                changes
+                    .removed
+                    .keys()
+                    .filter_map(|path| known_ids.get(path).map(|id| (path.clone(), id.clone())))
+                    .collect::<HashMap<_, _>>()
+            };
+            let moved_files = pair_moves(&mut changes.removed, &mut changes.added, &known_ids);
+            let removed_files = changes.removed.into_keys().collect::<Vec<_>>();
+            let added_files = changes.added.into_keys().collect::<Vec<_>>();
+            // if !removed_files.is_empty() || !added_files.is_empty() {
+            //     info!("FileSystem Watcher: Emitting files-changed");
+            //     // wip: 改为仅发送通知 然后前端调用通道获取更新后的文件列表
+            //     app_handle
+            //         .emit("files-changed", (removed_files, added_files, all_files))
+            //         .unwrap();
+            // }
+            if !moved_files.is_empty() {
+                debug!("FileSystem Watcher: Emitting internal signal fs-files-moved");
+                app_handle.emit("fs-files-moved", moved_files).unwrap();
+            }
+            if !removed_files.is_empty() {
+                debug!("FileSystem Watcher: Emitting internal signal fs-files-removed");
+                app_handle.emit("fs-files-removed", removed_files).unwrap();
+            }
+            if !added_files.is_empty() {
+                debug!("FileSystem Watcher: Emitting internal signal fs-files-added");
+                app_handle.emit("fs-files-added", added_files).unwrap();
+            }
+            let mut final_modified_files = Vec::new();
+            let mut ignored = ignored_paths_clone.lock().unwrap();
+            for file in changes.modified {
+                if ignored.remove(&file) {
+                    warn!("FS Watcher: Ignoring modification event for {file:?}");
+                } else {
+                    final_modified_files.push(file);
+                }
+            }
+            drop(ignored);
+            // if !modified_files.is_empty() {
+            if !final_modified_files.is_empty() {
+                debug!("FileSystem Watcher: Emitting internal signal fs-files-modified");
+                app_handle
+                    .emit("fs-files-modified", final_modified_files)
+                    .unwrap();
             }
         });
         FileSystem {
             watcher: DirectoryWatcher::new(Duration::from_millis(debounce_timeout), handler),
             dirs,
             ignored_paths,
+            known_ids,
         }
     }
 
+    /// track ids of the library, lets a move be told apart from a delete + an unrelated add
+    pub fn remember_ids(&self, songs: &[Song]) {
+        *self.known_ids.lock().unwrap() = songs
+            .iter()
+            .map(|song| (song.path.clone(), song.id.clone()))
+            .collect();
+    }
+
     pub fn ignore_next_modify(&mut self, path: &PathBuf) {
         info!("FS: Ignoring next modify event for {path:?}");
         self.ignored_paths.lock().unwrap().insert(path.clone());
     }
 
+    /// undo `ignore_next_modify` when the write it was for didn't happen
+    pub fn unignore_modify(&mut self, path: &PathBuf) {
+        self.ignored_paths.lock().unwrap().remove(path);
+    }
+
     pub fn add_dir<P: Into<PathBuf>>(&mut self, path: P) -> CoreResult<()> {
-        let dir = Dir::new(path);
+        self.add_dir_with(path, DirRules::default())
+    }
+
+    pub fn add_dir_with<P: Into<PathBuf>>(&mut self, path: P, rules: DirRules) -> CoreResult<()> {
+        let dir = Dir::with_rules(path, rules);
         let mut dirs_lock = self.dirs.lock().unwrap();
         dirs_lock
             .iter()
@@ -112,7 +246,12 @@ impl FileSystem {
             .then(|| {
                 dirs_lock.push(dir.clone());
             });
-        self.watcher.watch(&dir.path)?;
+        // 离线的 root 先记着, 回来的时候 apply_roots 再 watch
+        if !dir.online {
+            warn!("FS: {:?} is offline, watching it once it's back", dir.path);
+            return Ok(());
+        }
+        self.watcher.watch_with(&dir.path, &dir.rules.watch)?;
         Ok(())
     }
 
@@ -123,6 +262,34 @@ impl FileSystem {
         Ok(())
     }
 
+    /// re-globs the root with the new rules, the library has to rescan afterwards
+    pub fn set_rules(&mut self, path: &PathBuf, rules: DirRules) -> CoreResult<()> {
+        let mut dirs = self.dirs.lock().unwrap();
+        let dir = dirs
+            .iter_mut()
+            .find(|d| &d.path == path)
+            .ok_or_else(|| CoreError::FsError(format!("{path:?} is not a library root")))?;
+        let rewatch = dir.online && dir.rules.watch != rules.watch;
+        dir.set_rules(rules);
+        let mode = dir.rules.watch.clone();
+        drop(dirs);
+        if rewatch {
+            info!("FS: Watching {path:?} as {mode:?}");
+            self.watcher.rewatch(path, &mode)?;
+        }
+        Ok(())
+    }
+
+    /// roots with their rules, as stored in `listen_paths`
+    pub fn listen_paths(&self) -> Vec<ListenPath> {
+        self.dirs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|d| d.listen_path())
+            .collect()
+    }
+
     pub fn glob_dirs(&self) -> Vec<PathBuf> {
         self.dirs
             .lock()
@@ -132,14 +299,67 @@ impl FileSystem {
             .collect()
     }
 
-    pub fn glob_files(&self) -> Vec<PathBuf> {
+    /// copies of the online roots, to glob without holding `dirs`
+    pub fn online_dirs(&self) -> Vec<Dir> {
         self.dirs
             .lock()
             .unwrap()
-            .iter_mut()
-            .flat_map(|d| d.glob(true))
+            .iter()
+            .filter(|d| d.online)
+            .cloned()
+            .collect()
+    }
+
+    /// take back the index of roots globbed through `online_dirs`
+    pub fn adopt_globs(&self, globbed: Vec<Dir>) {
+        let mut dirs = self.dirs.lock().unwrap();
+        globbed.into_iter().for_each(|globbed| {
+            if let Some(dir) = dirs.iter_mut().find(|d| d.path == globbed.path) {
+                dir.adopt(globbed);
+            }
+        });
+    }
+
+    pub fn offline_roots(&self) -> Vec<PathBuf> {
+        self.dirs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|d| !d.online)
+            .map(|d| d.path.clone())
             .collect()
-        // .flat_map(|d| d.audio_files.clone())
-        // .collect()
+    }
+
+    /// apply what the roots answered (see `roots::probe`), returns the ones that went
+    /// offline / came back as `(offline, online)`. the watch of an unmounted root is
+    /// gone for good, returning roots get a new one
+    pub fn apply_roots(
+        &mut self,
+        reachable: &HashMap<PathBuf, bool>,
+    ) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let mut offline = Vec::new();
+        let mut online = Vec::new();
+        self.dirs
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter_map(|dir| reachable.get(&dir.path).map(|reachable| (*reachable, dir)))
+            .for_each(|(reachable, dir)| match (dir.online, reachable) {
+                (true, false) => {
+                    dir.online = false;
+                    offline.push(dir.path.clone());
+                }
+                (false, true) => {
+                    dir.online = true;
+                    online.push((dir.path.clone(), dir.rules.watch.clone()));
+                }
+                _ => {}
+            });
+        online.iter().for_each(|(path, mode)| {
+            if let Err(e) = self.watcher.rewatch(path, mode) {
+                warn!("FS: Failed to watch {path:?} again: {e:?}");
+            }
+        });
+        (offline, online.into_iter().map(|(path, _)| path).collect())
     }
 }
@@ -1,27 +1,46 @@
 use crate::{
-    library::fs::FileSystem,
+    library::{
+        dir::{DirRules, ListenPath},
+        fs::FileSystem,
+        scan::{concurrent_changes, report_progress, ScanJob, ScanOutcome, ScanReport},
+        search::{SearchIndex, SongHit},
+    },
     misc::{
         config::get_global,
         error::{CoreError, CoreResult},
-        utils::resolve_path,
+        utils::{get_path_hash, get_time, resolve_path},
     },
-    store::json::{
-        controller::{
-            alist::AlistController, artist::ArtistController, list::PlaylistController,
-            lyric::LyricController, pic::PicController, recent::RecentController,
-            release::ReleaseController, song::SongController,
-        },
-        entity::{
-            alist::Alist, artist::Artist, list::Playlist, recent::Recent, release::Release,
-            song::Song,
+    store::{
+        db,
+        json::{
+            controller::{
+                alist::AlistController,
+                artist::ArtistController,
+                list::PlaylistController,
+                lyric::LyricController,
+                pic::PicController,
+                recent::RecentController,
+                release::ReleaseController,
+                song::{ScanControl, ScanProgress, SongController},
+            },
+            entity::{
+                alist::Alist,
+                artist::Artist,
+                list::Playlist,
+                recent::Recent,
+                release::Release,
+                song::{Song, TrackId},
+            },
         },
+        song_repo, Backend,
     },
 };
 
 use log::{debug, info, warn};
 use serde_json::json;
 use std::{
-    fs::{copy, create_dir_all, remove_file},
+    collections::{HashMap, HashSet},
+    fs::{copy, create_dir_all, remove_file, rename},
     path::PathBuf,
 };
 use tauri::{AppHandle, Emitter};
@@ -37,6 +56,12 @@ pub struct Library {
     pub recent: Vec<Recent>,
     pub dir_path: Vec<PathBuf>,
     pub fs: FileSystem,
+    /// songs dropped by the watcher this session, by id, so a moved file that
+    /// comes back as an add keeps its score; playlists / recents keep the id anyway
+    pub removed: HashMap<TrackId, Song>,
+    pub search: SearchIndex,
+    /// files the scans / the watcher could not parse
+    pub scan_report: ScanReport,
     pub song_controller: SongController,
     pub release_controller: ReleaseController,
     pub pic_controller: PicController,
@@ -79,12 +104,20 @@ impl Library {
         get_global().get("listen_paths").and_then(|paths| {
             paths.as_array().map(|arr| {
                 arr.iter().for_each(|p| {
-                    if let Some(path_str) = p.as_str() {
-                        let path = PathBuf::from(path_str);
-                        if let Ok(resolved_path) = resolve_path(&app, path) {
-                            if let Err(e) = fs.add_dir(resolved_path.clone()) {
-                                warn!("Failed to add cached listen path {resolved_path:?}: {e:?}");
-                            }
+                    let Ok(listen_path) = serde_json::from_value::<ListenPath>(p.clone()) else {
+                        warn!("Skipping malformed listen path {p:?}");
+                        return;
+                    };
+                    let (path, rules) = listen_path.into_parts();
+                    if let Ok(resolved_path) = resolve_path(&app, path) {
+                        // 单曲目录上面已经加过了, 只需要带上规则
+                        let added = match fs.glob_dirs().contains(&resolved_path) {
+                            true if rules == DirRules::default() => Ok(()),
+                            true => fs.set_rules(&resolved_path, rules),
+                            false => fs.add_dir_with(resolved_path.clone(), rules),
+                        };
+                        if let Err(e) = added {
+                            warn!("Failed to add cached listen path {resolved_path:?}: {e:?}");
                         }
                     }
                 });
@@ -93,11 +126,16 @@ impl Library {
         // let mut dir_path = vec![single_dir];
         // dir_path.append(&mut fs.glob_dirs());
         let dir_path = fs.glob_dirs();
-        let song_controller = SongController::new()?;
-        let release_controller = ReleaseController::new()?;
+        if Backend::from_config() == Backend::Sqlite {
+            db::init_global()?;
+        }
+        // song / release / artist 共用同一个 song repo
+        let songs = song_repo()?;
+        let song_controller = SongController::with_repo(songs.clone());
+        let release_controller = ReleaseController::with_repo(songs.clone());
         let pic_controller = PicController::new();
         let lyric_controller = LyricController::new();
-        let artist_controller = ArtistController::new()?;
+        let artist_controller = ArtistController::with_repo(songs);
         let playlist_controller = PlaylistController::new()?;
         let alist_controller = AlistController::new()?;
         let recent_controller = RecentController::new()?;
@@ -111,6 +149,9 @@ impl Library {
             recent: Vec::new(),
             dir_path,
             fs,
+            removed: HashMap::new(),
+            search: SearchIndex::default(),
+            scan_report: ScanReport::load(),
             song_controller,
             release_controller,
             pic_controller,
@@ -122,40 +163,82 @@ impl Library {
         })
     }
 
+    /// scan in place, holding the library for the whole scan; the app goes through
+    /// `ScanHandle` instead
     pub fn load(&mut self) -> CoreResult<()> {
-        let files = self.fs.glob_files();
-        let total_files = files.len();
+        let app = self.app.clone();
+        let outcome = self.scan_job().run(
+            &|p: &ScanProgress| report_progress(&app, p),
+            &ScanControl::default(),
+        )?;
+        self.apply_scan(outcome)
+    }
+
+    /// what a scan needs, so it can run without the library locked.
+    /// only copies the roots, the walk happens in `ScanJob::run`
+    pub fn scan_job(&self) -> ScanJob {
         debug!("Fs current dirs: {:?}", self.fs.glob_dirs());
         debug!("current dir paths: {:?}", self.dir_path);
-        debug!("Loading library from {total_files} files");
+        ScanJob {
+            dirs: self.fs.online_dirs(),
+            roots: self.dir_path.clone(),
+            offline: self.fs.offline_roots(),
+            before: self.song_info.clone(),
+            songs: self.song_controller.clone(),
+            pics: self.pic_controller.clone(),
+            lyrics: self.lyric_controller.clone(),
+        }
+    }
 
-        let app_handle = self.app.clone();
-        self.song_info =
-            self.song_controller
-                .get_from_cache_with_progress(files, &|processed, total| {
-                    if total > 0 {
-                        let progress = (processed as f32 / total as f32 * 100.0) as u32;
-                        let _ = app_handle.emit(
-                            "scan_progress",
-                            serde_json::json!({
-                                "processed": processed,
-                                "total": total,
-                                "progress": progress
-                            }),
-                        );
-                        if processed % 50 == 0 || processed == total {
-                            info!("Scan progress: {processed}/{total} ({progress}%)");
-                        }
-                    }
-                })?;
+    /// swap in the result of a scan. edits that landed while it ran (commands, the
+    /// watcher) are newer than what it read, so they are kept and written back over it
+    pub fn apply_scan(&mut self, outcome: ScanOutcome) -> CoreResult<()> {
+        let ScanOutcome {
+            total_files,
+            mut songs,
+            failures,
+            complete,
+            tried,
+            mut search,
+            before,
+            dirs,
+        } = outcome;
+        self.fs.adopt_globs(dirs);
+        match complete {
+            true => self.scan_report.replace(failures),
+            false => self.scan_report.update(&tried, failures),
+        }
+        let (changed, dropped) = concurrent_changes(&before, &self.song_info);
+        if !changed.is_empty() || !dropped.is_empty() {
+            info!(
+                "Keeping {} songs changed and {} removed during the scan",
+                changed.len(),
+                dropped.len()
+            );
+            let changed_paths = changed.iter().map(|s| &s.path).collect::<HashSet<_>>();
+            songs.retain(|song| {
+                !dropped.contains(&song.path) && !changed_paths.contains(&song.path)
+            });
+            songs.extend(changed.iter().cloned());
+            songs.sort();
+            self.song_controller.get_op().save_all(&songs)?;
+            dropped.iter().for_each(|path| search.remove(path));
+            changed.iter().for_each(|song| {
+                let lyric = self.lyric_controller.get_lyric(song).ok().flatten();
+                search.insert(song, lyric.as_deref());
+            });
+        }
 
+        self.song_info = songs;
+        self.mark_unavailable();
+        self.search = search;
+        self.removed.clear();
         self.release_info = self.release_controller.from_songs(&self.song_info);
-        self.pic_controller.get_release_arts(&self.song_info)?;
-        self.lyric_controller.cache_lyrics(&self.song_info)?;
         self.artist_info = self.artist_controller.from_songs(&self.song_info);
         self.playlist = self.playlist_controller.get_all_playlists()?;
         self.alist = self.alist_controller.get_all_alists()?;
         self.recent = self.recent_controller.get_all_recents()?;
+        self.fs.remember_ids(&self.song_info);
 
         let _ = self.app.emit(
             "scan_complete",
@@ -164,6 +247,7 @@ impl Library {
                 "loaded": self.song_info.len()
             }),
         );
+        let _ = self.app.emit("lib_updated", ());
         info!(
             "Library loaded: {} songs from {} files",
             self.song_info.len(),
@@ -173,6 +257,13 @@ impl Library {
         Ok(())
     }
 
+    /// `song_info` from the store again, with the songs of offline roots flagged
+    pub(crate) fn reload_song_info(&mut self) -> CoreResult<()> {
+        self.song_info = self.song_controller.get_all()?;
+        self.mark_unavailable();
+        Ok(())
+    }
+
     pub fn update_song_info(&mut self, new_song: &Song) -> CoreResult<()> {
         self.song_controller.update_song_info(new_song)?;
         self.song_info.retain(|s| s.path != new_song.path);
@@ -182,12 +273,13 @@ impl Library {
         self.artist_info = self.artist_controller.from_songs(&self.song_info);
         self.pic_controller.get_release_arts(&self.song_info)?;
         self.lyric_controller.cache_lyrics(&self.song_info)?;
+        self.reindex(&[new_song.path.clone()]);
         Ok(())
     }
 
     pub fn update_multi_song_info(&mut self, new_songs: &Vec<Song>) -> CoreResult<()> {
+        self.song_controller.update_song_infos(new_songs.clone())?;
         new_songs.iter().for_each(|song| {
-            self.song_controller.update_song_info(song).unwrap();
             self.song_info.retain(|s| s.path != song.path);
             self.song_info.push(song.clone());
         });
@@ -196,6 +288,7 @@ impl Library {
         self.artist_info = self.artist_controller.from_songs(&self.song_info);
         self.pic_controller.get_release_arts(&self.song_info)?;
         self.lyric_controller.cache_lyrics(&self.song_info)?;
+        self.reindex(&new_songs.iter().map(|s| s.path.clone()).collect::<Vec<_>>());
         Ok(())
     }
 
@@ -224,6 +317,7 @@ impl Library {
         self.song_info.sort();
         self.release_info = self.release_controller.from_songs(&self.song_info);
         self.artist_info = self.artist_controller.from_songs(&self.song_info);
+        self.reindex(&[updated_song.path.clone()]);
 
         Ok(())
     }
@@ -250,13 +344,14 @@ impl Library {
         Ok(())
     }
 
-    pub fn add_dir(&mut self, path: PathBuf) -> CoreResult<()> {
+    /// only watches the root, the caller starts a scan to pick up its files
+    pub fn add_dir(&mut self, path: PathBuf, rules: DirRules) -> CoreResult<()> {
         if path == self.dir_path[0] {
             return Err(CoreError::OtherError(
                 "Cannot remove single song dir".to_string(),
             ));
         }
-        self.fs.add_dir(path.clone())?;
+        self.fs.add_dir_with(path.clone(), rules)?;
         self.dir_path = self.fs.glob_dirs();
         let config = get_global();
         // config.get("store").and_then(|store| {
@@ -265,51 +360,219 @@ impl Library {
         //         .and_then(|v| v.as_array())
         //         .map(|arr| {})
         // });
-        config.set("listen_paths", json!(self.dir_path));
-        self.load()?;
+        config.set("listen_paths", json!(self.fs.listen_paths()));
         Ok(())
     }
 
+    /// change the filters of a root, the caller starts a scan to apply them
+    pub fn set_dir_rules(&mut self, path: PathBuf, rules: DirRules) -> CoreResult<()> {
+        self.fs.set_rules(&path, rules)?;
+        get_global().set("listen_paths", json!(self.fs.listen_paths()));
+        Ok(())
+    }
+
+    /// only unwatches the root, the caller starts a scan to drop its files
     pub fn remove_dir(&mut self, path: PathBuf) -> CoreResult<()> {
         self.fs.remove_dir(path.clone())?;
         self.dir_path = self.fs.glob_dirs();
         let config = get_global();
-        config.set("listen_paths", json!(self.dir_path));
-        self.load()?;
+        config.set("listen_paths", json!(self.fs.listen_paths()));
+        Ok(())
+    }
+
+    /// parse reported files again, all of them when `files` is None.
+    /// files that are gone by now just leave the report
+    pub fn retry_failed(&mut self, files: Option<Vec<PathBuf>>) -> CoreResult<ScanReport> {
+        let files = files
+            .unwrap_or_else(|| {
+                self.scan_report
+                    .failures
+                    .iter()
+                    .map(|f| f.path.clone())
+                    .collect()
+            })
+            .into_iter()
+            .filter(|file| self.scan_report.contains(file))
+            .collect::<Vec<PathBuf>>();
+        let (existing, gone): (Vec<PathBuf>, Vec<PathBuf>) =
+            files.into_iter().partition(|file| file.exists());
+        self.scan_report.update(&gone, Vec::new());
+        if !existing.is_empty() {
+            info!("Retrying {} files from the scan report", existing.len());
+            self.on_file_added(existing)?;
+        }
+        Ok(self.scan_report.clone())
+    }
+
+    /// ranked full text search over titles, artists, releases and cached lyrics
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SongHit> {
+        let hits = self.search.search(query, limit);
+        // 一趟扫完 song_info 把命中的都找出来, 不用每个命中各扫一遍
+        let ranks = hits
+            .iter()
+            .enumerate()
+            .map(|(rank, hit)| (&hit.path, rank))
+            .collect::<HashMap<_, _>>();
+        let mut songs: Vec<Option<&Song>> = vec![None; hits.len()];
+        self.song_info.iter().for_each(|song| {
+            if let Some(&rank) = ranks.get(&song.path) {
+                songs[rank] = Some(song);
+            }
+        });
+        hits.iter()
+            .zip(songs)
+            .filter_map(|(hit, song)| {
+                Some(SongHit {
+                    song: song?.clone(),
+                    score: hit.score,
+                    fields: hit.fields.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// refresh the search index for `files` from `song_info`, files gone from it are dropped
+    fn reindex(&mut self, files: &[PathBuf]) {
+        files.iter().for_each(|path| self.search.remove(path));
+        let files = files.iter().collect::<HashSet<_>>();
+        self.song_info
+            .iter()
+            .filter(|song| files.contains(&song.path))
+            .for_each(|song| {
+                let lyric = self.lyric_controller.get_lyric(song).ok().flatten();
+                self.search.insert(song, lyric.as_deref());
+            });
+    }
+
+    /// a new file carrying the id of a song we lost (removed event first, or a record
+    /// whose file is already gone) is that song moved; carry its score over
+    fn relink_moved(&mut self, files: &Vec<PathBuf>) -> CoreResult<()> {
+        let added = self.song_controller.get_by_files(files.clone())?;
+        let relinked = added
+            .into_iter()
+            .filter_map(|song| {
+                let old = self.removed.remove(&song.id).or_else(|| {
+                    self.song_info
+                        .iter()
+                        .find(|s| s.id == song.id && s.path != song.path && !s.path.exists())
+                        .cloned()
+                })?;
+                info!("Re-linked moved file {:?} -> {:?}", old.path, song.path);
+                (song.score.is_none() && old.score.is_some()).then(|| song.inherit(&old))
+            })
+            .collect::<Vec<Song>>();
+        if !relinked.is_empty() {
+            self.song_controller.update_song_infos(relinked)?;
+        }
+        Ok(())
+    }
+
+    /// files the watcher paired as moved: same record at the new path, so score,
+    /// playlists and recents (by id) survive; alists and caches follow the path
+    pub fn on_file_moved(&mut self, moves: Vec<(PathBuf, PathBuf)>) -> CoreResult<()> {
+        info!("Detected file moves: {moves:?}");
+        self.scan_report.update(
+            &moves
+                .iter()
+                .map(|(from, _)| from.clone())
+                .collect::<Vec<_>>(),
+            Vec::new(),
+        );
+        let olds = self
+            .song_controller
+            .get_by_files(moves.iter().map(|(from, _)| from.clone()).collect())?;
+        let moved = olds
+            .iter()
+            .filter_map(|old| {
+                let (_, to) = moves.iter().find(|(from, _)| *from == old.path)?;
+                let (created_at, updated_at) = get_time(to);
+                let song = Song {
+                    path: to.clone(),
+                    created_at,
+                    updated_at,
+                    ..old.clone()
+                };
+                move_caches(old, &song);
+                Some(song)
+            })
+            .collect::<Vec<Song>>();
+        self.song_controller
+            .move_song_infos(olds.iter().map(|song| song.path.clone()).collect(), moved)?;
+        self.alist_controller
+            .rewrite_song_paths(&moves.iter().cloned().collect())?;
+        self.reload_song_info()?;
+        self.release_info = self.release_controller.list_all()?;
+        self.artist_info = self.artist_controller.list_all()?;
+        self.alist = self.alist_controller.get_all_alists()?;
+        self.pic_controller.get_release_arts(&self.song_info)?;
+        self.reindex(
+            &moves
+                .iter()
+                .flat_map(|(from, to)| [from.clone(), to.clone()])
+                .collect::<Vec<_>>(),
+        );
+        self.fs.remember_ids(&self.song_info);
+        // 库里没记录的旧文件, 新位置当普通新增处理
+        let unknown = moves
+            .into_iter()
+            .filter(|(from, _)| !olds.iter().any(|song| &song.path == from))
+            .map(|(_, to)| to)
+            .collect::<Vec<PathBuf>>();
+        if !unknown.is_empty() {
+            return self.on_file_added(unknown);
+        }
+        self.app.emit("lib_updated", ()).unwrap();
         Ok(())
     }
 
     pub fn on_file_added(&mut self, files: Vec<PathBuf>) -> CoreResult<()> {
         info!("Detected file changes: {files:?}");
-        self.song_controller.add_song_infos(files.clone());
-        self.song_info = self.song_controller.get_all()?;
+        let failures = self.song_controller.add_song_infos(files.clone())?;
+        self.scan_report.update(&files, failures);
+        self.relink_moved(&files)?;
+        self.reload_song_info()?;
         self.release_info = self.release_controller.list_all()?;
         self.artist_info = self.artist_controller.list_all()?;
         self.pic_controller.get_release_arts(&self.song_info)?;
+        let added = self
+            .song_info
+            .iter()
+            .filter(|song| files.contains(&song.path))
+            .cloned()
+            .collect::<Vec<Song>>();
+        self.lyric_controller.cache_lyrics(&added)?;
+        self.reindex(&files);
+        self.fs.remember_ids(&self.song_info);
         self.app.emit("lib_updated", ()).unwrap();
         Ok(())
     }
 
     pub fn on_file_modified(&mut self, files: Vec<PathBuf>) -> CoreResult<()> {
         info!("Detected file changes: {files:?}");
-        self.song_controller
+        let failures = self
+            .song_controller
             .update_song_infos_by_files(files.clone())?;
-        self.song_info = self.song_controller.get_all()?;
+        self.scan_report.update(&files, failures);
+        self.reload_song_info()?;
         self.release_info = self.release_controller.list_all()?;
         self.artist_info = self.artist_controller.list_all()?;
         self.pic_controller.get_release_arts(&self.song_info)?;
+        self.reindex(&files);
+        self.fs.remember_ids(&self.song_info);
         self.app.emit("lib_updated", ()).unwrap();
         Ok(())
     }
 
     pub fn on_file_removed(&mut self, files: Vec<PathBuf>) -> CoreResult<()> {
         info!("Detected file removals: {files:?}");
+        let removed_songs = self.song_controller.get_by_files(files.clone())?;
         self.song_controller
             .remove_song_infos_by_files(files.clone())?;
-        self.playlist_controller
-            .remove_song_path_all(files.clone())?;
-        self.recent_controller
-            .remove_by_song_paths(files.iter().collect())?;
+        self.scan_report.update(&files, Vec::new());
+        // playlists / recents keep the ids, a moved file shows up again with the same one
+        removed_songs.into_iter().for_each(|song| {
+            self.removed.insert(song.id.clone(), song);
+        });
         files.iter().for_each(|path| {
             let song_sample = Song::sample(path.clone());
             if let Ok(Some(cache_path)) = song_sample.get_art_cache_path() {
@@ -323,13 +586,33 @@ impl Library {
                 }
             }
         });
-        self.song_info = self.song_controller.get_all()?;
+        self.reload_song_info()?;
         self.release_info = self.release_controller.list_all()?;
         self.artist_info = self.artist_controller.list_all()?;
         // self.playlist = self.playlist_controller.get_all_playlists()?;
         // self.recent = self.recent_controller.get_all_recents()?;
         self.pic_controller.get_release_arts(&self.song_info)?;
+        self.reindex(&files);
+        self.fs.remember_ids(&self.song_info);
         self.app.emit("lib_updated", ()).unwrap();
         Ok(())
     }
 }
+
+/// art / lyric caches are named after the path hash, rename them instead of re-extracting
+fn move_caches(from: &Song, to: &Song) {
+    if let Ok(Some(cache_path)) = from.get_art_cache_path() {
+        let ext = cache_path.extension().unwrap_or_default().to_string_lossy();
+        let target = cache_path.with_file_name(format!("{}.{ext}", get_path_hash(&to.path)));
+        if let Err(e) = rename(&cache_path, &target) {
+            warn!("Failed to move art cache {cache_path:?}: {e:?}");
+        }
+    }
+    if let (Ok(cache_path), Ok(target)) = (from.get_lyric_cache_path(), to.get_lyric_cache_path()) {
+        if cache_path.exists() {
+            if let Err(e) = rename(&cache_path, &target) {
+                warn!("Failed to move lyric cache {cache_path:?}: {e:?}");
+            }
+        }
+    }
+}
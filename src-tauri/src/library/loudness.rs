@@ -0,0 +1,375 @@
+use crate::{
+    library::library::Library,
+    meta::loudness::{self, Loudness, Measurement},
+    misc::{error::CoreResult, utils::get_time},
+    store::json::entity::{release::ReleaseKey, song::Song},
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+use tauri::{AppHandle, Emitter};
+
+// 给没有 ReplayGain 标签的歌补上: 后台按专辑 (跟 ReleaseOp::from_songs 一样分组) 解码测响度.
+// 专辑响度是整张所有块一起算的, 所以一张专辑总是整张测. 单曲结果顺便进缓存, 播放时直接用.
+// 解码和写标签都不拿 Library 的锁, 写之前先让 watcher 忽略这次修改, 写完才拿一下把新的修改时间一次记上.
+// 播放时碰到没测过的单曲也排到这里, 所有解码都在同一个后台线程上, 整库测的时候每首之间顺带测掉
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LoudnessOptions {
+    /// releases to measure, the whole library if empty
+    pub releases: Vec<ReleaseKey>,
+    /// write `REPLAYGAIN_*` tags into the files
+    pub write_tags: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TrackLoudness {
+    pub path: PathBuf,
+    pub loudness: Loudness,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReleaseLoudness {
+    pub title: String,
+    pub artist: Option<String>,
+    /// the release measured as one programme
+    pub loudness: Loudness,
+    pub tracks: Vec<TrackLoudness>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LoudnessFailure {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoudnessState {
+    #[default]
+    Idle,
+    Running,
+    Cancelled,
+    Done,
+}
+
+/// what `loudness_status` returns, also emitted as `loudness_state` when a run starts and ends
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LoudnessStatus {
+    pub state: LoudnessState,
+    /// tracks decoded so far
+    pub processed: usize,
+    pub total: usize,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    pub releases: Vec<ReleaseLoudness>,
+    pub failures: Vec<LoudnessFailure>,
+}
+
+/// a run waiting for the worker
+type Request = (Arc<Mutex<Library>>, LoudnessOptions);
+
+/// managed next to the library, like `ScanHandle`
+#[derive(Clone)]
+pub struct LoudnessHandle {
+    app: AppHandle,
+    status: Arc<Mutex<LoudnessStatus>>,
+    /// a run requested or going
+    running: Arc<AtomicBool>,
+    cancelled: Arc<AtomicBool>,
+    requested: Arc<Mutex<Option<Request>>>,
+    /// single tracks to measure and cache, see `measure`
+    tracks: Arc<Mutex<VecDeque<PathBuf>>>,
+    /// the worker thread is alive
+    working: Arc<AtomicBool>,
+}
+
+#[allow(unused)]
+impl LoudnessHandle {
+    pub fn new(app: &AppHandle) -> Self {
+        Self {
+            app: app.clone(),
+            status: Arc::new(Mutex::new(LoudnessStatus::default())),
+            running: Arc::new(AtomicBool::new(false)),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            requested: Arc::new(Mutex::new(None)),
+            tracks: Arc::new(Mutex::new(VecDeque::new())),
+            working: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn status(&self) -> LoudnessStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// releases measured so far are kept in the status, the one in progress is dropped
+    pub fn cancel(&self) {
+        if self.is_running() {
+            self.cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// measure in the background, `false` if a run is already going
+    pub fn start(&self, library: Arc<Mutex<Library>>, options: LoudnessOptions) -> bool {
+        if self.running.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        self.cancelled.store(false, Ordering::SeqCst);
+        *self.requested.lock().unwrap() = Some((library, options));
+        self.work();
+        true
+    }
+
+    /// measure one track and cache it for playback, once however often it's asked for
+    pub fn measure(&self, path: PathBuf) {
+        {
+            let mut tracks = self.tracks.lock().unwrap();
+            if tracks.contains(&path) {
+                return;
+            }
+            tracks.push_back(path);
+        }
+        self.work();
+    }
+
+    /// start the worker unless it's already there
+    fn work(&self) {
+        if self.working.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let handle = self.clone();
+        thread::spawn(move || loop {
+            handle.drain();
+            handle.working.store(false, Ordering::SeqCst);
+            // 刚放下的时候又来了活, 而且没有别人接手的话接着干
+            if !handle.has_work() || handle.working.swap(true, Ordering::SeqCst) {
+                break;
+            }
+        });
+    }
+
+    fn has_work(&self) -> bool {
+        self.requested.lock().unwrap().is_some() || !self.tracks.lock().unwrap().is_empty()
+    }
+
+    fn drain(&self) {
+        while self.has_work() {
+            let requested = self.requested.lock().unwrap().take();
+            if let Some((library, options)) = requested {
+                self.run(&library, &options);
+                self.running.store(false, Ordering::SeqCst);
+            }
+            self.measure_tracks();
+        }
+    }
+
+    fn measure_tracks(&self) {
+        loop {
+            let Some(path) = self.tracks.lock().unwrap().pop_front() else {
+                return;
+            };
+            match loudness::track_loudness(&path) {
+                // 播放那边可能还在用默认增益
+                Ok(_) => {
+                    let _ = self.app.emit("loudness-measured", path);
+                }
+                Err(e) => warn!("Failed to measure the loudness of {path:?}: {e:?}"),
+            }
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn emit_state(&self) {
+        let _ = self.app.emit("loudness_state", self.status());
+    }
+
+    fn fail(&self, path: &Path, error: String) {
+        warn!("Loudness analysis of {path:?} failed: {error}");
+        self.status.lock().unwrap().failures.push(LoudnessFailure {
+            path: path.to_path_buf(),
+            error,
+        });
+    }
+
+    fn run(&self, library: &Mutex<Library>, options: &LoudnessOptions) {
+        let releases = library.lock().unwrap().release_songs(&options.releases);
+        *self.status.lock().unwrap() = LoudnessStatus {
+            state: LoudnessState::Running,
+            total: releases.iter().map(|(_, songs)| songs.len()).sum(),
+            started_at: Some(chrono::Local::now().to_rfc3339()),
+            ..Default::default()
+        };
+        self.emit_state();
+
+        let mut written = false;
+        for (release, songs) in releases {
+            let mut measured = Vec::new();
+            for song in songs {
+                if self.is_cancelled() {
+                    break;
+                }
+                self.measure_tracks();
+                match loudness::measure(&song.path) {
+                    Ok(measurement) => measured.push((song, measurement)),
+                    Err(e) => self.fail(&song.path, format!("{e:?}")),
+                }
+                let (processed, total) = {
+                    let mut status = self.status.lock().unwrap();
+                    status.processed += 1;
+                    (status.processed, status.total)
+                };
+                let _ = self.app.emit(
+                    "loudness_progress",
+                    json!({ "processed": processed, "total": total }),
+                );
+            }
+            if self.is_cancelled() {
+                break;
+            }
+            if measured.is_empty() {
+                continue;
+            }
+            let release_loudness = Loudness::of(
+                &measured
+                    .iter()
+                    .map(|(_, measurement)| measurement)
+                    .collect::<Vec<&Measurement>>(),
+            );
+            let tracks = measured
+                .iter()
+                .map(|(song, measurement)| (song.clone(), measurement.loudness()))
+                .collect::<Vec<(Song, Loudness)>>();
+            if options.write_tags {
+                let failures = write_replay_gain(library, &tracks, &release_loudness);
+                failures
+                    .into_iter()
+                    .for_each(|(path, error)| self.fail(&path, error));
+                written = true;
+            }
+            // 写过标签的话修改时间变了, 缓存要在写完以后存
+            tracks.iter().for_each(|(song, track)| {
+                if let Err(e) = loudness::cache(&song.path, *track) {
+                    warn!("Failed to cache the loudness of {:?}: {e:?}", song.path);
+                }
+            });
+            self.status.lock().unwrap().releases.push(ReleaseLoudness {
+                title: release.title,
+                artist: release.artist,
+                loudness: release_loudness,
+                tracks: tracks
+                    .into_iter()
+                    .map(|(song, loudness)| TrackLoudness {
+                        path: song.path,
+                        loudness,
+                    })
+                    .collect(),
+            });
+        }
+
+        if written {
+            let _ = self.app.emit("lib_updated", ());
+        }
+        {
+            let mut status = self.status.lock().unwrap();
+            status.state = match self.is_cancelled() {
+                true => LoudnessState::Cancelled,
+                false => LoudnessState::Done,
+            };
+            status.finished_at = Some(chrono::Local::now().to_rfc3339());
+            info!(
+                "Loudness analysis finished: {} releases, {} failures",
+                status.releases.len(),
+                status.failures.len()
+            );
+        }
+        self.emit_state();
+    }
+}
+
+#[allow(unused)]
+impl Library {
+    /// available songs grouped by release, only `releases` unless that's empty
+    pub fn release_songs(&self, releases: &[ReleaseKey]) -> Vec<(ReleaseKey, Vec<Song>)> {
+        let mut grouped = self
+            .song_info
+            .iter()
+            .filter(|song| !song.unavailable)
+            .fold(HashMap::<ReleaseKey, Vec<Song>>::new(), |mut acc, song| {
+                acc.entry(ReleaseKey::of(song))
+                    .or_default()
+                    .push(song.clone());
+                acc
+            })
+            .into_iter()
+            .filter(|(release, _)| releases.is_empty() || releases.contains(release))
+            .collect::<Vec<(ReleaseKey, Vec<Song>)>>();
+        grouped.sort_by(|(a, _), (b, _)| (&a.title, &a.artist).cmp(&(&b.title, &b.artist)));
+        grouped
+    }
+
+    /// take the new timestamps of files whose tags were just written, in one batch
+    pub fn retagged(&mut self, paths: &[PathBuf]) -> CoreResult<()> {
+        // 只改时间, 测的这会儿别的字段可能已经被改过了
+        let paths = paths.iter().collect::<HashSet<_>>();
+        let changed = self
+            .song_info
+            .iter_mut()
+            .filter(|song| paths.contains(&song.path))
+            .map(|song| {
+                (song.created_at, song.updated_at) = get_time(&song.path);
+                song.clone()
+            })
+            .collect::<Vec<Song>>();
+        if changed.is_empty() {
+            return Ok(());
+        }
+        self.song_controller.update_song_infos(changed)
+    }
+}
+
+/// tag the tracks of one release, returns the files that failed. the files are
+/// written without the library locked, it's only taken to apply the new timestamps
+fn write_replay_gain(
+    library: &Mutex<Library>,
+    tracks: &[(Song, Loudness)],
+    release: &Loudness,
+) -> Vec<(PathBuf, String)> {
+    let ignored = library.lock().unwrap().fs.ignored_paths.clone();
+    let mut failures = Vec::new();
+    let written = tracks
+        .iter()
+        .filter_map(|(song, track)| {
+            ignored.lock().unwrap().insert(song.path.clone());
+            match song.write_replay_gain(track, release) {
+                Ok(()) => Some(song.path.clone()),
+                Err(e) => {
+                    // 没写成就不会有修改事件, 留着的话会吞掉下一次真正的修改
+                    ignored.lock().unwrap().remove(&song.path);
+                    failures.push((song.path.clone(), format!("{e:?}")));
+                    None
+                }
+            }
+        })
+        .collect::<Vec<PathBuf>>();
+    if let Err(e) = library.lock().unwrap().retagged(&written) {
+        failures.extend(written.into_iter().map(|path| (path, format!("{e:?}"))));
+    }
+    failures
+}
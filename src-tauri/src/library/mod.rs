@@ -1,4 +1,12 @@
+pub mod archive;
 pub mod dir;
+pub mod doctor;
+pub mod duplicates;
 pub mod fs;
 pub mod library;
+pub mod loudness;
+pub mod query;
+pub mod roots;
+pub mod scan;
+pub mod search;
 pub mod watch;
@@ -0,0 +1,366 @@
+use crate::{
+    misc::error::{CoreError, CoreResult},
+    store::json::entity::{score::Score, song::Song},
+};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+
+// 前端传过来的查询, 形如
+// {
+//   "filter": { "and": [
+//     { "field": "artist", "op": "eq", "value": "foo" },
+//     { "or": [{ "field": "score", "op": "gte", "value": "BigCup" },
+//              { "field": "score", "op": "is_null" }] }
+//   ] },
+//   "sort": [{ "field": "score", "desc": true }, { "field": "title" }],
+//   "offset": 0,
+//   "limit": 50
+// }
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Title,
+    Artist,
+    Release,
+    Path,
+    /// compared by `Score::value`, accepts a score name or a number
+    Score,
+    Duration,
+    /// compared as instants, accepts rfc3339 or epoch millis
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", content = "value", rename_all = "snake_case")]
+pub enum Cmp {
+    Eq(Value),
+    Ne(Value),
+    Lt(Value),
+    Lte(Value),
+    Gt(Value),
+    Gte(Value),
+    In(Vec<Value>),
+    Contains(String),
+    StartsWith(String),
+    IsNull,
+    NotNull,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Predicate {
+    pub field: Field,
+    #[serde(flatten)]
+    pub cmp: Cmp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Filter {
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+    #[serde(untagged)]
+    Pred(Predicate),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SortKey {
+    pub field: Field,
+    #[serde(default)]
+    pub desc: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SongQuery {
+    #[serde(default)]
+    pub filter: Option<Filter>,
+    #[serde(default)]
+    pub sort: Vec<SortKey>,
+    #[serde(default)]
+    pub offset: usize,
+    /// no limit returns everything after `offset`
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SongPage {
+    /// matches before offset / limit
+    pub total: usize,
+    pub offset: usize,
+    pub songs: Vec<Song>,
+}
+
+/// comparable form of a field, text is case folded
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+enum Key {
+    Text(String),
+    Number(f64),
+}
+
+type Matcher = Box<dyn Fn(&Song) -> bool>;
+
+impl Field {
+    fn key(&self, song: &Song) -> Option<Key> {
+        let text = |s: &Option<String>| s.as_ref().map(|s| Key::Text(s.to_lowercase()));
+        let time = |s: &str| {
+            DateTime::parse_from_rfc3339(s)
+                .ok()
+                .map(|t| Key::Number(t.timestamp_millis() as f64))
+        };
+        match self {
+            Field::Title => text(&song.title),
+            Field::Artist => text(&song.artist),
+            Field::Release => text(&song.release),
+            Field::Path => Some(Key::Text(song.path.to_string_lossy().to_lowercase())),
+            Field::Score => song.score.as_ref().map(|s| Key::Number(s.value() as f64)),
+            Field::Duration => song.duration.map(|d| Key::Number(d as f64)),
+            Field::CreatedAt => time(&song.created_at),
+            Field::UpdatedAt => time(&song.updated_at),
+        }
+    }
+
+    /// turn a query value into the same form as `key`
+    fn parse(&self, value: &Value) -> CoreResult<Key> {
+        let invalid = || CoreError::OtherError(format!("Invalid value {value} for field {self:?}"));
+        match (self, value) {
+            (Field::Title | Field::Artist | Field::Release | Field::Path, Value::String(s)) => {
+                Ok(Key::Text(s.to_lowercase()))
+            }
+            (Field::Score, Value::String(_)) => serde_json::from_value::<Score>(value.clone())
+                .map(|s| Key::Number(s.value() as f64))
+                .map_err(|_| invalid()),
+            (Field::CreatedAt | Field::UpdatedAt, Value::String(s)) => {
+                DateTime::parse_from_rfc3339(s)
+                    .map(|t| Key::Number(t.timestamp_millis() as f64))
+                    .map_err(|_| invalid())
+            }
+            (
+                Field::Score | Field::Duration | Field::CreatedAt | Field::UpdatedAt,
+                Value::Number(n),
+            ) => n.as_f64().map(Key::Number).ok_or_else(invalid),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Predicate {
+    fn compile(&self) -> CoreResult<Matcher> {
+        let field = self.field;
+        let ordered = move |value: &Value, accept: fn(Ordering) -> bool| -> CoreResult<Matcher> {
+            let target = field.parse(value)?;
+            Ok(Box::new(move |song| {
+                field
+                    .key(song)
+                    .and_then(|key| key.partial_cmp(&target))
+                    .is_some_and(accept)
+            }))
+        };
+        let text = move |needle: &String, starts: bool| -> Matcher {
+            let needle = needle.to_lowercase();
+            Box::new(move |song| match field.key(song) {
+                Some(Key::Text(s)) if starts => s.starts_with(&needle),
+                Some(Key::Text(s)) => s.contains(&needle),
+                _ => false,
+            })
+        };
+        Ok(match &self.cmp {
+            Cmp::Eq(v) => ordered(v, Ordering::is_eq)?,
+            // 空值也算不等
+            Cmp::Ne(v) => {
+                let target = field.parse(v)?;
+                Box::new(move |song| field.key(song).as_ref() != Some(&target))
+            }
+            Cmp::Lt(v) => ordered(v, Ordering::is_lt)?,
+            Cmp::Lte(v) => ordered(v, Ordering::is_le)?,
+            Cmp::Gt(v) => ordered(v, Ordering::is_gt)?,
+            Cmp::Gte(v) => ordered(v, Ordering::is_ge)?,
+            Cmp::In(values) => {
+                let targets = values
+                    .iter()
+                    .map(|v| field.parse(v))
+                    .collect::<CoreResult<Vec<Key>>>()?;
+                Box::new(move |song| field.key(song).is_some_and(|key| targets.contains(&key)))
+            }
+            Cmp::Contains(s) => text(s, false),
+            Cmp::StartsWith(s) => text(s, true),
+            Cmp::IsNull => Box::new(move |song| field.key(song).is_none()),
+            Cmp::NotNull => Box::new(move |song| field.key(song).is_some()),
+        })
+    }
+}
+
+impl Filter {
+    fn compile(&self) -> CoreResult<Matcher> {
+        Ok(match self {
+            Filter::And(filters) => {
+                let all = filters
+                    .iter()
+                    .map(Filter::compile)
+                    .collect::<CoreResult<Vec<_>>>()?;
+                Box::new(move |song| all.iter().all(|m| m(song)))
+            }
+            Filter::Or(filters) => {
+                let any = filters
+                    .iter()
+                    .map(Filter::compile)
+                    .collect::<CoreResult<Vec<_>>>()?;
+                Box::new(move |song| any.iter().any(|m| m(song)))
+            }
+            Filter::Not(filter) => {
+                let inner = filter.compile()?;
+                Box::new(move |song| !inner(song))
+            }
+            Filter::Pred(pred) => pred.compile()?,
+        })
+    }
+}
+
+impl SongQuery {
+    pub fn run(&self, songs: &[Song]) -> CoreResult<SongPage> {
+        let matcher = self.filter.as_ref().map(Filter::compile).transpose()?;
+        let mut matched = songs
+            .iter()
+            .filter(|song| matcher.as_ref().is_none_or(|m| m(song)))
+            .map(|song| (self.sort.iter().map(|k| k.field.key(song)).collect(), song))
+            .collect::<Vec<(Vec<Option<Key>>, &Song)>>();
+        if !self.sort.is_empty() {
+            matched.sort_by(|(a, sa), (b, sb)| {
+                self.sort
+                    .iter()
+                    .zip(a.iter().zip(b.iter()))
+                    .map(|(sort, (a, b))| match (a, b) {
+                        // 空值不管升降序都排最后
+                        (None, None) => Ordering::Equal,
+                        (None, Some(_)) => Ordering::Greater,
+                        (Some(_), None) => Ordering::Less,
+                        (Some(a), Some(b)) => {
+                            let ord = a.partial_cmp(b).unwrap_or(Ordering::Equal);
+                            if sort.desc {
+                                ord.reverse()
+                            } else {
+                                ord
+                            }
+                        }
+                    })
+                    .find(|ord| ord.is_ne())
+                    // 翻页要稳定
+                    .unwrap_or_else(|| sa.path.cmp(&sb.path))
+            });
+        }
+        let total = matched.len();
+        Ok(SongPage {
+            total,
+            offset: self.offset,
+            songs: matched
+                .into_iter()
+                .skip(self.offset)
+                .take(self.limit.unwrap_or(usize::MAX))
+                .map(|(_, song)| song.clone())
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn song(name: &str, title: &str, artist: &str, score: Option<Score>) -> Song {
+        Song {
+            title: Some(title.to_string()),
+            artist: Some(artist.to_string()),
+            score,
+            ..Song::sample(format!("/music/{name}.flac").into())
+        }
+    }
+
+    fn library() -> Vec<Song> {
+        vec![
+            song("d", "delta", "x", Some(Score::BigCup)),
+            song("c", "Gamma", "X", Some(Score::MedCup)),
+            song("b", "beta", "Y", None),
+            song("a", "Alpha", "X", Some(Score::BigCup)),
+        ]
+    }
+
+    fn run(query: Value) -> CoreResult<SongPage> {
+        serde_json::from_value::<SongQuery>(query)
+            .unwrap()
+            .run(&library())
+    }
+
+    fn names(page: &SongPage) -> Vec<String> {
+        page.songs
+            .iter()
+            .map(|song| song.path.file_stem().unwrap().to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn bare_predicate_is_a_filter() {
+        let page = run(json!({
+            "filter": { "field": "artist", "op": "eq", "value": "x" },
+            "sort": [{ "field": "path" }]
+        }))
+        .unwrap();
+        assert_eq!(names(&page), ["a", "c", "d"]);
+    }
+
+    #[test]
+    fn nested_filters_with_score_names() {
+        let page = run(json!({
+            "filter": { "and": [
+                { "or": [{ "field": "score", "op": "gte", "value": "BigCup" },
+                         { "field": "score", "op": "is_null" }] },
+                { "not": { "field": "title", "op": "starts_with", "value": "DEL" } }
+            ] },
+            "sort": [{ "field": "title" }]
+        }))
+        .unwrap();
+        assert_eq!(names(&page), ["a", "b"]);
+    }
+
+    #[test]
+    fn score_accepts_names_and_numbers_only() {
+        let by_number = run(json!({
+            "filter": { "field": "score", "op": "eq", "value": 7 },
+            "sort": [{ "field": "path" }]
+        }))
+        .unwrap();
+        assert_eq!(names(&by_number), ["a", "d"]);
+        assert!(
+            run(json!({ "filter": { "field": "score", "op": "eq", "value": "HugeCup" } })).is_err()
+        );
+        assert!(run(json!({ "filter": { "field": "title", "op": "lt", "value": 3 } })).is_err());
+    }
+
+    #[test]
+    fn nulls_sort_last_both_ways() {
+        let desc = run(json!({ "sort": [{ "field": "score", "desc": true }] })).unwrap();
+        assert_eq!(names(&desc), ["a", "d", "c", "b"]);
+        let asc = run(json!({ "sort": [{ "field": "score" }] })).unwrap();
+        assert_eq!(names(&asc), ["c", "a", "d", "b"]);
+    }
+
+    #[test]
+    fn ties_page_in_path_order() {
+        let page = |offset: usize| {
+            run(json!({
+                "sort": [{ "field": "artist" }],
+                "offset": offset,
+                "limit": 2
+            }))
+            .unwrap()
+        };
+        let (first, second) = (page(0), page(2));
+        assert_eq!((first.total, second.total), (4, 4));
+        assert_eq!(names(&first), ["a", "c"]);
+        assert_eq!(names(&second), ["d", "b"]);
+    }
+}
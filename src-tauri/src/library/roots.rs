@@ -0,0 +1,121 @@
+use crate::library::{
+    dir::{is_reachable, under_any},
+    library::Library,
+    scan::ScanHandle,
+};
+use log::{info, warn};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+use tauri::Emitter;
+
+// 外置硬盘 / 网络盘拔掉以后整个 root 都没了, 照常扫描会当成全删,
+// 歌单 / 最近播放 / 缓存里的东西跟着一起清掉. 所以定时看一眼 root 还在不在:
+// 不在了歌先标成不可用, 记录原样留着; 回来了重新 watch, 再扫一遍补上期间的变化.
+// 看的时候不拿锁, 每个 root 单独一个线程, 挂掉的网络盘 read_dir 可能一直卡着
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// a root that doesn't answer within this counts as offline for the round
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RootStatus {
+    pub path: PathBuf,
+    pub online: bool,
+    pub songs: usize,
+}
+
+/// whether each root can be read, every root on its own thread. a probe still stuck
+/// from an earlier round isn't started again, its root stays offline until it returns
+fn probe(roots: Vec<PathBuf>, stuck: &Arc<Mutex<HashSet<PathBuf>>>) -> HashMap<PathBuf, bool> {
+    let (tx, rx) = mpsc::channel();
+    let mut reachable = HashMap::new();
+    let mut probing = 0;
+    roots.into_iter().for_each(|root| {
+        reachable.insert(root.clone(), false);
+        if !stuck.lock().unwrap().insert(root.clone()) {
+            return;
+        }
+        let (tx, stuck) = (tx.clone(), Arc::clone(stuck));
+        probing += 1;
+        thread::spawn(move || {
+            let online = is_reachable(&root);
+            stuck.lock().unwrap().remove(&root);
+            let _ = tx.send((root, online));
+        });
+    });
+    let deadline = Instant::now() + PROBE_TIMEOUT;
+    for _ in 0..probing {
+        match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok((root, online)) => {
+                reachable.insert(root, online);
+            }
+            Err(_) => break,
+        }
+    }
+    reachable
+}
+
+pub fn spawn_root_monitor(library: Arc<Mutex<Library>>, scan: ScanHandle) {
+    let stuck = Arc::new(Mutex::new(HashSet::new()));
+    thread::spawn(move || loop {
+        thread::sleep(CHECK_INTERVAL);
+        let roots = library.lock().unwrap().fs.glob_dirs();
+        let reachable = probe(roots, &stuck);
+        let back = library.lock().unwrap().check_roots(&reachable);
+        if !back.is_empty() {
+            scan.start(library.clone());
+        }
+    });
+}
+
+#[allow(unused)]
+impl Library {
+    pub fn root_status(&self) -> Vec<RootStatus> {
+        let offline = self.fs.offline_roots();
+        self.dir_path
+            .iter()
+            .map(|root| RootStatus {
+                path: root.clone(),
+                online: !offline.contains(root),
+                songs: self
+                    .song_info
+                    .iter()
+                    .filter(|song| song.path.starts_with(root))
+                    .count(),
+            })
+            .collect()
+    }
+
+    /// flag the songs of offline roots in `song_info`, and unflag the ones that are back
+    pub fn mark_unavailable(&mut self) {
+        let offline = self.fs.offline_roots();
+        self.song_info
+            .iter_mut()
+            .for_each(|song| song.unavailable = under_any(&song.path, &offline));
+    }
+
+    /// apply a `probe` of the roots, returns the ones that came back since the last
+    /// check; the caller scans them, their files may have changed while they were away
+    pub fn check_roots(&mut self, reachable: &HashMap<PathBuf, bool>) -> Vec<PathBuf> {
+        let (offline, online) = self.fs.apply_roots(reachable);
+        if offline.is_empty() && online.is_empty() {
+            return online;
+        }
+        offline
+            .iter()
+            .for_each(|root| warn!("Library root {root:?} went offline, keeping its songs"));
+        online
+            .iter()
+            .for_each(|root| info!("Library root {root:?} is back online"));
+        self.mark_unavailable();
+        let _ = self.app.emit("roots_changed", self.root_status());
+        let _ = self.app.emit("lib_updated", ());
+        online
+    }
+}
@@ -0,0 +1,422 @@
+use crate::{
+    library::{
+        dir::{under_any, Dir},
+        library::Library,
+        search::SearchIndex,
+    },
+    misc::{
+        config::get_global,
+        error::{CoreError, CoreResult},
+        utils::resolve_resource_path,
+    },
+    store::json::{
+        controller::{
+            lyric::LyricController,
+            pic::PicController,
+            song::{ScanControl, ScanFailure, ScanProgress, SongController},
+        },
+        entity::song::Song,
+        op::sm::write_json_atomic,
+    },
+};
+use log::{debug, info, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+use tauri::{AppHandle, Emitter};
+
+// 扫描放在后台线程里跑, Library 的锁只在开头拿文件列表 / 结尾换结果时各拿一次
+// 解析进度由 SongController 定期存进 song store, 另外记一个 checkpoint 文件,
+// 扫描被取消或者程序中途退出, 下次扫描从存档接着来, 已经解析过的文件不会再解析
+// 解析失败的文件记在 scan_report.json 里, 前端可以看原因 / 挑几个重试
+
+const CHECKPOINT_FILE: &str = "scan_checkpoint.json";
+const REPORT_FILE: &str = "scan_report.json";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanState {
+    #[default]
+    Idle,
+    Running,
+    Paused,
+    Cancelled,
+    Done,
+    Failed,
+}
+
+/// what `scan_status` returns, also emitted as `scan_state` whenever `state` changes
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanStatus {
+    pub state: ScanState,
+    pub progress: ScanProgress,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    /// an interrupted scan's checkpoint was found when this one started
+    pub resumed: bool,
+    pub error: Option<String>,
+}
+
+/// left on disk while a scan runs, removed once one finishes.
+/// progress itself lives in the song store, this only tells a resumed scan from a new one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Checkpoint {
+    started_at: String,
+    roots: Vec<PathBuf>,
+}
+
+/// files of the last / current scan failing to parse, persisted between runs
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScanReport {
+    pub updated_at: Option<String>,
+    pub failures: Vec<ScanFailure>,
+}
+
+#[allow(unused)]
+impl ScanReport {
+    pub fn load() -> Self {
+        read_store_file(REPORT_FILE).unwrap_or_default()
+    }
+
+    fn save(&mut self) {
+        self.updated_at = Some(chrono::Local::now().to_rfc3339());
+        write_store_file(REPORT_FILE, self);
+    }
+
+    /// a complete scan tried every file without a record, its failures are the whole list
+    pub fn replace(&mut self, failures: Vec<ScanFailure>) {
+        self.failures = failures;
+        self.save();
+    }
+
+    /// `tried` were parsed again (watcher, retry, an unfinished scan) or are gone,
+    /// `failures` are the ones among them still failing
+    pub fn update(&mut self, tried: &[PathBuf], failures: Vec<ScanFailure>) {
+        let tried = tried
+            .iter()
+            .chain(failures.iter().map(|f| &f.path))
+            .collect::<HashSet<_>>();
+        let before = self.failures.len();
+        self.failures.retain(|f| !tried.contains(&f.path));
+        if before == self.failures.len() && failures.is_empty() {
+            return;
+        }
+        self.failures.extend(failures);
+        self.failures.sort_by(|a, b| a.path.cmp(&b.path));
+        self.save();
+    }
+
+    pub fn contains(&self, path: &PathBuf) -> bool {
+        self.failures.iter().any(|f| &f.path == path)
+    }
+}
+
+fn store_file(name: &str) -> CoreResult<PathBuf> {
+    let base = get_global()
+        .get("store_base")
+        .and_then(|v| v.as_str().map(|s| s.to_string()))
+        .ok_or_else(|| {
+            CoreError::OtherError("missing 'store.store_base' key in Config".to_string())
+        })?;
+    resolve_resource_path(&PathBuf::from(base), name)
+}
+
+fn read_store_file<T: DeserializeOwned>(name: &str) -> Option<T> {
+    let content = fs::read_to_string(store_file(name).ok()?).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn write_store_file<T: Serialize>(name: &str, value: &T) {
+    let written = store_file(name).and_then(|path| write_json_atomic(&path, value, || ()));
+    if let Err(e) = written {
+        warn!("Failed to write {name}: {e:?}");
+    }
+}
+
+fn read_checkpoint() -> Option<Checkpoint> {
+    read_store_file(CHECKPOINT_FILE)
+}
+
+fn write_checkpoint(checkpoint: &Checkpoint) {
+    write_store_file(CHECKPOINT_FILE, checkpoint)
+}
+
+fn clear_checkpoint() {
+    if let Ok(path) = store_file(CHECKPOINT_FILE) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// forward progress to the frontend as `scan_progress`, with a percentage of the current phase
+pub fn report_progress(app: &AppHandle, p: &ScanProgress) {
+    let progress = match p.total {
+        0 => 100,
+        total => (p.processed as f32 / total as f32 * 100.0) as u32,
+    };
+    let mut payload = serde_json::to_value(p).unwrap_or_else(|_| json!({}));
+    payload["progress"] = json!(progress);
+    let _ = app.emit("scan_progress", payload);
+    if p.processed % 50 == 0 || p.processed == p.total {
+        info!(
+            "Scan progress ({:?}): {}/{} ({progress}%), {} failed",
+            p.phase, p.processed, p.total, p.failed
+        );
+    }
+}
+
+/// everything a scan needs from the library, taken under the lock by `Library::scan_job`
+pub struct ScanJob {
+    /// online roots, globbed by `run`
+    pub dirs: Vec<Dir>,
+    pub roots: Vec<PathBuf>,
+    /// roots out of reach, their records are kept instead of dropped
+    pub offline: Vec<PathBuf>,
+    /// `song_info` at the time of the snapshot, see `Library::apply_scan`
+    pub before: Vec<Song>,
+    pub songs: SongController,
+    pub pics: PicController,
+    pub lyrics: LyricController,
+}
+
+/// handed back to `Library::apply_scan`
+pub struct ScanOutcome {
+    pub total_files: usize,
+    pub songs: Vec<Song>,
+    pub failures: Vec<ScanFailure>,
+    /// cancelled scans only report on the files they got to
+    pub complete: bool,
+    pub tried: Vec<PathBuf>,
+    pub search: SearchIndex,
+    pub before: Vec<Song>,
+    /// the roots as globbed by the scan, their index goes back to the watcher
+    pub dirs: Vec<Dir>,
+}
+
+impl ScanJob {
+    pub fn run<F>(mut self, progress_callback: &F, control: &ScanControl) -> CoreResult<ScanOutcome>
+    where
+        F: Fn(&ScanProgress),
+    {
+        let files = self
+            .dirs
+            .iter_mut()
+            .flat_map(|dir| dir.glob(true))
+            .collect::<Vec<_>>();
+        debug!("Loading library from {} files", files.len());
+        let total_files = files.len();
+        let result = self.songs.get_from_cache_with_progress(
+            files,
+            &self.offline,
+            progress_callback,
+            control,
+        )?;
+        // 离线的文件读不到, 缓存等回来了再补
+        let reachable = result
+            .songs
+            .iter()
+            .filter(|song| !under_any(&song.path, &self.offline))
+            .cloned()
+            .collect::<Vec<Song>>();
+        self.pics.get_release_arts(&reachable)?;
+        self.lyrics.cache_lyrics(&reachable)?;
+        let search = index(&self.lyrics, &result.songs);
+        Ok(ScanOutcome {
+            total_files,
+            tried: result.songs.iter().map(|s| s.path.clone()).collect(),
+            songs: result.songs,
+            failures: result.failures,
+            complete: result.complete,
+            search,
+            before: self.before,
+            dirs: self.dirs,
+        })
+    }
+
+    /// the stored records as they are, to show the library before the first scan is done
+    pub fn cached(self) -> CoreResult<ScanOutcome> {
+        let songs = self.songs.get_all()?;
+        let search = index(&self.lyrics, &songs);
+        Ok(ScanOutcome {
+            total_files: songs.len(),
+            songs,
+            failures: Vec::new(),
+            complete: false,
+            tried: Vec::new(),
+            search,
+            before: self.before,
+            dirs: Vec::new(),
+        })
+    }
+}
+
+fn index(lyrics: &LyricController, songs: &[Song]) -> SearchIndex {
+    let mut search = SearchIndex::default();
+    songs.iter().for_each(|song| {
+        let lyric = lyrics.get_lyric(song).ok().flatten();
+        search.insert(song, lyric.as_deref());
+    });
+    search
+}
+
+/// songs edited / added / dropped in `current` since `before` (by commands or the watcher
+/// while a scan ran), compared by content since `Song` equality only looks at the path
+pub fn concurrent_changes(before: &[Song], current: &[Song]) -> (Vec<Song>, HashSet<PathBuf>) {
+    let content = |song: &Song| serde_json::to_value(song).ok();
+    let before = before
+        .iter()
+        .map(|song| (&song.path, song))
+        .collect::<HashMap<_, _>>();
+    let changed = current
+        .iter()
+        .filter(|song| {
+            before
+                .get(&song.path)
+                .is_none_or(|old| content(old) != content(song))
+        })
+        .cloned()
+        .collect::<Vec<Song>>();
+    let current = current.iter().map(|s| &s.path).collect::<HashSet<_>>();
+    let dropped = before
+        .keys()
+        .filter(|path| !current.contains(*path))
+        .map(|path| (*path).clone())
+        .collect();
+    (changed, dropped)
+}
+
+/// managed next to the library; commands talk to the running scan through it
+/// without waiting for the library lock
+#[derive(Clone)]
+pub struct ScanHandle {
+    app: AppHandle,
+    control: Arc<ScanControl>,
+    status: Arc<Mutex<ScanStatus>>,
+    running: Arc<AtomicBool>,
+    rerun: Arc<AtomicBool>,
+}
+
+#[allow(unused)]
+impl ScanHandle {
+    pub fn new(app: &AppHandle) -> Self {
+        Self {
+            app: app.clone(),
+            control: Arc::new(ScanControl::default()),
+            status: Arc::new(Mutex::new(ScanStatus::default())),
+            running: Arc::new(AtomicBool::new(false)),
+            rerun: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn status(&self) -> ScanStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    pub fn pause(&self) {
+        if self.is_running() {
+            self.control.pause();
+            self.set_state(ScanState::Paused);
+        }
+    }
+
+    pub fn resume(&self) {
+        self.control.resume();
+        if self.status().state == ScanState::Paused {
+            self.set_state(ScanState::Running);
+        }
+    }
+
+    /// what was parsed so far is kept, the next scan resumes from there
+    pub fn cancel(&self) {
+        self.rerun.store(false, Ordering::SeqCst);
+        if self.is_running() {
+            self.control.cancel();
+        }
+    }
+
+    /// scan in the background; if a scan is already running another one follows it,
+    /// since whatever asked (a new root, a refresh) may not be covered by its file list
+    pub fn start(&self, library: Arc<Mutex<Library>>) {
+        if self.running.swap(true, Ordering::SeqCst) {
+            self.rerun.store(true, Ordering::SeqCst);
+            return;
+        }
+        let handle = self.clone();
+        thread::spawn(move || loop {
+            handle.rerun.store(false, Ordering::SeqCst);
+            handle.run(&library);
+            handle.running.store(false, Ordering::SeqCst);
+            // start() 赶在上面两步之间进来的话只会设置 rerun
+            if !handle.rerun.load(Ordering::SeqCst) || handle.running.swap(true, Ordering::SeqCst) {
+                break;
+            }
+        });
+    }
+
+    fn set_state(&self, state: ScanState) {
+        let status = {
+            let mut status = self.status.lock().unwrap();
+            status.state = state;
+            status.clone()
+        };
+        let _ = self.app.emit("scan_state", status);
+    }
+
+    fn run(&self, library: &Mutex<Library>) {
+        self.control.reset();
+        let started_at = chrono::Local::now().to_rfc3339();
+        let job = library.lock().unwrap().scan_job();
+        // roots 变了就不算接着上次扫
+        let resumed = read_checkpoint().filter(|checkpoint| checkpoint.roots == job.roots);
+        if let Some(checkpoint) = &resumed {
+            info!("Resuming scan started at {}", checkpoint.started_at);
+        }
+        *self.status.lock().unwrap() = ScanStatus {
+            started_at: Some(started_at.clone()),
+            resumed: resumed.is_some(),
+            ..Default::default()
+        };
+        self.set_state(ScanState::Running);
+
+        write_checkpoint(&Checkpoint {
+            started_at: resumed.map_or(started_at, |c| c.started_at),
+            roots: job.roots.clone(),
+        });
+        let result = job
+            .run(
+                &|p: &ScanProgress| {
+                    self.status.lock().unwrap().progress = p.clone();
+                    report_progress(&self.app, p);
+                },
+                &self.control,
+            )
+            .and_then(|outcome| library.lock().unwrap().apply_scan(outcome));
+
+        let state = match result {
+            Err(e) => {
+                warn!("Library scan failed: {e:?}");
+                self.status.lock().unwrap().error = Some(format!("{e:?}"));
+                ScanState::Failed
+            }
+            Ok(()) if self.control.is_cancelled() => ScanState::Cancelled,
+            Ok(()) => {
+                clear_checkpoint();
+                ScanState::Done
+            }
+        };
+        self.status.lock().unwrap().finished_at = Some(chrono::Local::now().to_rfc3339());
+        self.set_state(state);
+    }
+}
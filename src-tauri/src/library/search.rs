@@ -0,0 +1,355 @@
+use crate::store::json::entity::song::Song;
+use regex::Regex;
+use serde::Serialize;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    iter,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+// 倒排索引, 跟着 Library::song_info 和歌词缓存一起更新
+// 西文按词切, 中日文没有分词词典, 按字 + 相邻两字 (bigram) 建索引:
+// 查单字走 unigram, 两字以上拆成 bigram 全部命中才算
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchField {
+    Title,
+    Artist,
+    Release,
+    Lyric,
+}
+
+const FIELDS: [SearchField; 4] = [
+    SearchField::Title,
+    SearchField::Artist,
+    SearchField::Release,
+    SearchField::Lyric,
+];
+
+impl SearchField {
+    fn weight(self) -> f32 {
+        match self {
+            SearchField::Title => 4.0,
+            SearchField::Artist => 3.0,
+            SearchField::Release => 2.0,
+            SearchField::Lyric => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub path: PathBuf,
+    pub score: f32,
+    /// fields that matched at least one query term
+    pub fields: Vec<SearchField>,
+}
+
+/// what the search command returns
+#[derive(Debug, Clone, Serialize)]
+pub struct SongHit {
+    pub song: Song,
+    pub score: f32,
+    pub fields: Vec<SearchField>,
+}
+
+/// occurrences of a term in each of `FIELDS`
+type Counts = [u32; 4];
+
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    postings: BTreeMap<String, HashMap<PathBuf, Counts>>,
+    /// terms of each doc, to unindex it
+    docs: HashMap<PathBuf, Vec<String>>,
+}
+
+// lrc 时间轴 / 元信息, 逐字歌词的 (start,duration), ttml 之类的标签
+static LYRIC_MARKUP: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[[^\]]*\]|<[^>]*>|\(\d+,\d+\)").unwrap());
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}' // kana
+        | '\u{31F0}'..='\u{31FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{20000}'..='\u{2FA1F}')
+}
+
+/// case + width folding for everything, diacritics only dropped from latin letters
+/// so kana voicing marks (が / か) stay distinct
+pub(crate) fn fold(text: &str) -> String {
+    text.nfkc()
+        .flat_map(|c| {
+            let latin = c <= '\u{024F}' || ('\u{1E00}'..='\u{1EFF}').contains(&c);
+            match latin {
+                true => iter::once(c)
+                    .nfd()
+                    .filter(|c| !is_combining_mark(*c))
+                    .collect::<Vec<_>>(),
+                false => vec![c],
+            }
+        })
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// runs of word characters, `true` for cjk runs
+fn runs(text: &str) -> Vec<(Vec<char>, bool)> {
+    let mut runs: Vec<(Vec<char>, bool)> = Vec::new();
+    fold(text).chars().for_each(|c| {
+        let cjk = is_cjk(c);
+        if !cjk && !c.is_alphanumeric() {
+            runs.push((Vec::new(), false));
+            return;
+        }
+        match runs.last_mut() {
+            Some((run, kind)) if *kind == cjk || run.is_empty() => {
+                *kind = cjk;
+                run.push(c);
+            }
+            _ => runs.push((vec![c], cjk)),
+        }
+    });
+    runs.retain(|(run, _)| !run.is_empty());
+    runs
+}
+
+fn index_terms(text: &str) -> Vec<String> {
+    runs(text)
+        .into_iter()
+        .flat_map(|(run, cjk)| match cjk {
+            true => run
+                .iter()
+                .map(|c| c.to_string())
+                .chain(run.windows(2).map(|w| w.iter().collect()))
+                .collect::<Vec<String>>(),
+            false => vec![run.into_iter().collect()],
+        })
+        .collect()
+}
+
+/// `(term, prefix)`, only the last western word is matched as a prefix (search as you type)
+fn query_terms(query: &str) -> Vec<(String, bool)> {
+    let runs = runs(query);
+    let last = runs.len().saturating_sub(1);
+    runs.into_iter()
+        .enumerate()
+        .flat_map(|(i, (run, cjk))| match (cjk, run.len()) {
+            (true, 1) => vec![(run[0].to_string(), false)],
+            (true, _) => run
+                .windows(2)
+                .map(|w| (w.iter().collect(), false))
+                .collect(),
+            (false, _) => vec![(run.into_iter().collect(), i == last)],
+        })
+        .collect()
+}
+
+#[allow(unused)]
+impl SearchIndex {
+    pub fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.postings.clear();
+        self.docs.clear();
+    }
+
+    /// (re)index a song, `lyric` is the raw cached lyric if any
+    pub fn insert(&mut self, song: &Song, lyric: Option<&str>) {
+        self.remove(&song.path);
+        let lyric = lyric.map(|l| LYRIC_MARKUP.replace_all(l, " "));
+        let texts = [
+            song.title.as_deref(),
+            song.artist.as_deref(),
+            song.release.as_deref(),
+            lyric.as_deref(),
+        ];
+        let mut terms = Vec::new();
+        texts.iter().enumerate().for_each(|(field, text)| {
+            index_terms(text.unwrap_or_default())
+                .into_iter()
+                .for_each(|term| {
+                    let counts = self
+                        .postings
+                        .entry(term.clone())
+                        .or_default()
+                        .entry(song.path.clone())
+                        .or_default();
+                    counts[field] += 1;
+                    terms.push(term);
+                })
+        });
+        terms.sort_unstable();
+        terms.dedup();
+        self.docs.insert(song.path.clone(), terms);
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        let Some(terms) = self.docs.remove(path) else {
+            return;
+        };
+        terms.iter().for_each(|term| {
+            if let Some(docs) = self.postings.get_mut(term) {
+                docs.remove(path);
+                if docs.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        });
+    }
+
+    /// every query term has to match (prefix terms through any term they start),
+    /// ranked by field weight * log tf * idf, prefix-only matches count half
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchHit> {
+        let terms = query_terms(query);
+        if terms.is_empty() {
+            return Vec::new();
+        }
+        let total = self.docs.len() as f32;
+        let mut hits: Option<HashMap<&PathBuf, (f32, [bool; 4])>> = None;
+        for (term, prefix) in terms.iter() {
+            let matches: Vec<(&String, &HashMap<PathBuf, Counts>)> = match prefix {
+                true => self
+                    .postings
+                    .range(term.clone()..)
+                    .take_while(|(key, _)| key.starts_with(term.as_str()))
+                    .collect(),
+                false => self.postings.get_key_value(term).into_iter().collect(),
+            };
+            let mut term_hits: HashMap<&PathBuf, (f32, [bool; 4])> = HashMap::new();
+            matches.into_iter().for_each(|(key, docs)| {
+                let idf = (1.0 + total / docs.len() as f32).ln();
+                let factor = if key == term { 1.0 } else { 0.5 };
+                docs.iter().for_each(|(path, counts)| {
+                    let hit = term_hits.entry(path).or_default();
+                    let score = FIELDS
+                        .iter()
+                        .zip(counts.iter())
+                        .filter(|(_, count)| **count > 0)
+                        .map(|(field, count)| field.weight() * (1.0 + (*count as f32).ln()))
+                        .sum::<f32>()
+                        * idf
+                        * factor;
+                    // 一个前缀命中多个词时只取最好的那个
+                    hit.0 = hit.0.max(score);
+                    counts
+                        .iter()
+                        .enumerate()
+                        .for_each(|(i, count)| hit.1[i] |= *count > 0);
+                });
+            });
+            hits = Some(match hits {
+                None => term_hits,
+                Some(prev) => prev
+                    .into_iter()
+                    .filter_map(|(path, (score, fields))| {
+                        term_hits.get(path).map(|(s, f)| {
+                            let mut merged = fields;
+                            merged.iter_mut().zip(f).for_each(|(a, b)| *a |= *b);
+                            (path, (score + s, merged))
+                        })
+                    })
+                    .collect(),
+            });
+        }
+        let mut hits = hits
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(path, (score, fields))| SearchHit {
+                path: path.clone(),
+                score,
+                fields: FIELDS
+                    .iter()
+                    .zip(fields)
+                    .filter_map(|(field, hit)| hit.then_some(*field))
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        hits.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        hits.truncate(limit);
+        hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(name: &str, title: &str, artist: &str) -> Song {
+        Song {
+            title: Some(title.to_string()),
+            artist: Some(artist.to_string()),
+            ..Song::sample(format!("/music/{name}.flac").into())
+        }
+    }
+
+    fn index(songs: &[Song]) -> SearchIndex {
+        let mut index = SearchIndex::default();
+        songs.iter().for_each(|song| index.insert(song, None));
+        index
+    }
+
+    fn names(hits: &[SearchHit]) -> Vec<String> {
+        hits.iter()
+            .map(|hit| hit.path.file_stem().unwrap().to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn cjk_runs_index_unigrams_and_bigrams() {
+        assert_eq!(
+            index_terms("Jay 周杰伦"),
+            ["jay", "周", "杰", "伦", "周杰", "杰伦"]
+        );
+        assert_eq!(query_terms("杰"), [("杰".to_string(), false)]);
+        assert_eq!(
+            query_terms("周杰伦"),
+            [("周杰".to_string(), false), ("杰伦".to_string(), false)]
+        );
+    }
+
+    #[test]
+    fn cjk_queries_need_every_bigram() {
+        let index = index(&[song("a", "晴天", "周杰伦"), song("b", "伦敦", "杰")]);
+        assert_eq!(names(&index.search("杰伦", 10)), ["a"]);
+        assert_eq!(names(&index.search("杰", 10)), ["a", "b"]);
+        assert!(index.search("伦周", 10).is_empty());
+    }
+
+    #[test]
+    fn folds_width_case_and_latin_diacritics_only() {
+        assert_eq!(fold("Beyoncé"), "beyonce");
+        assert_eq!(fold("ＡＢＣ"), "abc");
+        assert_eq!(fold("ｶﾞ"), "ガ");
+        assert_ne!(fold("が"), fold("か"));
+        let index = index(&[song("a", "Café", "x")]);
+        assert_eq!(names(&index.search("CAFE", 10)), ["a"]);
+    }
+
+    #[test]
+    fn only_the_last_word_is_a_prefix() {
+        let index = index(&[
+            song("a", "Lovely Day", "x"),
+            song("b", "Love Song", "x"),
+            song("c", "Other", "x"),
+        ]);
+        // 整词命中排在只靠前缀命中的前面
+        assert_eq!(names(&index.search("love", 10)), ["b", "a"]);
+        assert_eq!(names(&index.search("lov", 10)), ["a", "b"]);
+        assert!(index.search("lov song", 10).is_empty());
+        assert_eq!(names(&index.search("love so", 10)), ["b"]);
+    }
+}
@@ -1,20 +1,46 @@
-use crate::misc::error::{CoreError, CoreResult};
-use notify::{RecommendedWatcher, RecursiveMode};
-use notify_debouncer_mini::{new_debouncer, DebouncedEvent, Debouncer};
+use crate::{
+    library::dir::WatchMode,
+    misc::error::{CoreError, CoreResult},
+};
+use notify::{PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use notify_debouncer_mini::{
+    new_debouncer, new_debouncer_opt, Config, DebounceEventResult, Debouncer,
+};
 use std::{
     // cell::RefCell,
-    collections::HashSet,
+    cmp::max,
+    collections::HashMap,
+    fmt,
     path::{Path, PathBuf},
+    sync::Arc,
     // sync::{mpsc, Weak},
     time::Duration,
 };
 
-#[derive(Debug)]
+/// what every debouncer of the watcher reports to
+pub type WatchHandler = Arc<dyn Fn(DebounceEventResult) + Send + Sync>;
+
+/// a sweep stats every file of the root, don't let it run back to back
+const MIN_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
 pub struct DirectoryWatcher {
     _debouncer: Debouncer<RecommendedWatcher>,
+    // 轮询间隔是整个 PollWatcher 共用的, 所以每个轮询的 root 单独一个
+    pollers: HashMap<PathBuf, Debouncer<PollWatcher>>,
+    timeout: Duration,
+    handler: WatchHandler,
 
     // dirs: Weak<RefCell<HashSet<Dir>>>,
-    watched_paths: HashSet<PathBuf>,
+    watched_paths: HashMap<PathBuf, WatchMode>,
+}
+
+impl fmt::Debug for DirectoryWatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DirectoryWatcher")
+            .field("timeout", &self.timeout)
+            .field("watched_paths", &self.watched_paths)
+            .finish()
+    }
 }
 
 #[allow(unused)]
@@ -22,40 +48,89 @@ impl DirectoryWatcher {
     pub fn new(
         timeout: Duration,
         // dirs: Weak<RefCell<HashSet<Dir>>>,
-        handler: Box<dyn FnMut(Result<Vec<DebouncedEvent>, notify::Error>) + Send>,
+        handler: WatchHandler,
     ) -> Self {
-        let debouncer = new_debouncer(timeout, handler).unwrap();
+        let native = handler.clone();
+        let debouncer =
+            new_debouncer(timeout, move |res: DebounceEventResult| native(res)).unwrap();
         DirectoryWatcher {
             _debouncer: debouncer,
+            pollers: HashMap::new(),
+            timeout,
+            handler,
             // dirs,
-            watched_paths: HashSet::new(),
+            watched_paths: HashMap::new(),
         }
     }
 
     pub fn watch<P: AsRef<Path>>(&mut self, path: P) -> CoreResult<()> {
+        self.watch_with(path, &WatchMode::Native)
+    }
+
+    pub fn watch_with<P: AsRef<Path>>(&mut self, path: P, mode: &WatchMode) -> CoreResult<()> {
         let path_buf = path.as_ref().to_path_buf();
-        self.watched_paths
-            .insert(path_buf.clone())
-            .then_some(())
-            .ok_or(CoreError::FsError("Path already watched".to_string()))?;
-        self._debouncer
-            .watcher()
-            .watch(path_buf.as_ref(), RecursiveMode::Recursive)?;
+        if self.watched_paths.contains_key(&path_buf) {
+            return Err(CoreError::FsError("Path already watched".to_string()));
+        }
+        match mode {
+            WatchMode::Native => self
+                ._debouncer
+                .watcher()
+                .watch(path_buf.as_ref(), RecursiveMode::Recursive)?,
+            WatchMode::Poll { interval_secs } => {
+                self.poll(&path_buf, Duration::from_secs(*interval_secs))?
+            }
+            WatchMode::Manual => {}
+        }
+        self.watched_paths.insert(path_buf, mode.clone());
         Ok(())
     }
 
+    fn poll(&mut self, path: &Path, interval: Duration) -> notify::Result<()> {
+        let handler = self.handler.clone();
+        let config = Config::default()
+            .with_timeout(self.timeout)
+            .with_notify_config(
+                notify::Config::default().with_poll_interval(max(interval, MIN_POLL_INTERVAL)),
+            );
+        let mut debouncer =
+            new_debouncer_opt::<_, PollWatcher>(config, move |res: DebounceEventResult| {
+                handler(res)
+            })?;
+        debouncer.watcher().watch(path, RecursiveMode::Recursive)?;
+        self.pollers.insert(path.to_path_buf(), debouncer);
+        Ok(())
+    }
+
+    /// `None` when the path wasn't watched
+    fn release(&mut self, path: &Path) -> Option<notify::Result<()>> {
+        let mode = self.watched_paths.remove(path)?;
+        Some(match mode {
+            WatchMode::Native => self._debouncer.watcher().unwatch(path),
+            // 扔掉 debouncer 轮询线程就停了
+            WatchMode::Poll { .. } => {
+                self.pollers.remove(path);
+                Ok(())
+            }
+            WatchMode::Manual => Ok(()),
+        })
+    }
+
     pub fn unwatch<P: AsRef<Path>>(&mut self, path: P) -> CoreResult<()> {
-        let path_buf = path.as_ref().to_path_buf();
-        self._debouncer.watcher().unwatch(path_buf.as_ref())?;
-        self.watched_paths
-            .remove(&path_buf)
-            .then_some(())
-            .ok_or(CoreError::FsError("Path not being watched".to_string()))?;
+        self.release(path.as_ref())
+            .ok_or(CoreError::FsError("Path not being watched".to_string()))??;
         Ok(())
     }
 
+    /// watch a root again, with a new mode or after it was unmounted
+    /// (the old watch died with the mount, failing to drop it is fine)
+    pub fn rewatch<P: AsRef<Path>>(&mut self, path: P, mode: &WatchMode) -> CoreResult<()> {
+        let _ = self.release(path.as_ref());
+        self.watch_with(path, mode)
+    }
+
     pub fn glob(&self) -> Vec<PathBuf> {
-        self.watched_paths.iter().cloned().collect()
+        self.watched_paths.keys().cloned().collect()
     }
 
     //     pub fn poll_changed_dirs(&self) -> Option<Result<HashSet<PathBuf>, CoreError>> {
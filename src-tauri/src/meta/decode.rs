@@ -0,0 +1,99 @@
+use crate::misc::error::{CoreError, CoreResult};
+use std::{fs::File, path::Path};
+use symphonia::core::{
+    audio::{Channels, SampleBuffer},
+    codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error,
+    formats::{FormatOptions, FormatReader},
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+// 按包解码, 要多少读多少; 不像 StaticSoundData 那样先把整首歌解到内存里.
+// 用的是 kira 带的 symphonia, 能解的格式跟播放一样
+
+/// decodes a file packet by packet
+pub struct PacketDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    pub sample_rate: u32,
+}
+
+fn decode_err(path: &Path, e: impl ToString) -> CoreError {
+    CoreError::OtherError(format!("Failed to decode {path:?}: {}", e.to_string()))
+}
+
+impl PacketDecoder {
+    pub fn open(path: &Path) -> CoreResult<Self> {
+        let stream = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(extension);
+        }
+        let format = symphonia::default::get_probe()
+            .format(
+                &hint,
+                stream,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .map_err(|e| decode_err(path, e))?
+            .format;
+        let track = format
+            .tracks()
+            .iter()
+            .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| decode_err(path, "no audio track"))?;
+        let sample_rate = track
+            .codec_params
+            .sample_rate
+            .ok_or_else(|| decode_err(path, "unknown sample rate"))?;
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .map_err(|e| decode_err(path, e))?;
+        Ok(Self {
+            track_id: track.id,
+            format,
+            decoder,
+            sample_rate,
+        })
+    }
+
+    /// interleaved samples of the next packet and the channels they are for,
+    /// `None` at the end (or when the stream breaks off)
+    pub fn next_frames(&mut self) -> Option<(Vec<f32>, Channels)> {
+        loop {
+            let packet = self.format.next_packet().ok()?;
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // 坏了一个包, 跳过接着解
+                Err(Error::DecodeError(_)) => continue,
+                Err(_) => return None,
+            };
+            let spec = *decoded.spec();
+            if spec.channels.count() == 0 {
+                continue;
+            }
+            let mut samples = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+            samples.copy_interleaved_ref(decoded);
+            return Some((samples.samples().to_vec(), spec.channels));
+        }
+    }
+
+    /// the next packet downmixed to mono
+    pub fn next_chunk(&mut self) -> Option<Vec<f32>> {
+        let (samples, channels) = self.next_frames()?;
+        let channels = channels.count();
+        Some(
+            samples
+                .chunks(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{
+    meta::probe,
+    store::json::entity::song::{Song, TrackId},
+};
+use lofty::{
+    config::{ParseOptions, ParsingMode},
+    file::AudioFile,
+};
+use log::warn;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+// 取文件尾部前面的一段音频数据做指纹, 标签在文件头 (id3v2 / flac / vorbis) 或者
+// 末尾的一小段 (id3v1 / ape), 跳过这两处之后改标签不会影响 id, 移动 / 改名也不会
+const CHUNK_SIZE: u64 = 64 * 1024;
+const TAIL_SKIP: u64 = 128 * 1024;
+
+fn hex_id(prefix: char, hasher: Sha256) -> TrackId {
+    let digest = format!("{:x}", hasher.finalize());
+    format!("{prefix}{}", &digest[..32])
+}
+
+fn content_id(path: &Path, duration: Option<u32>) -> io::Result<TrackId> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    // 太短的文件没法跳开标签, 整个拿来算
+    let (start, size) = match len > CHUNK_SIZE + TAIL_SKIP {
+        true => (len - TAIL_SKIP - CHUNK_SIZE, CHUNK_SIZE),
+        false => (0, len),
+    };
+    file.seek(SeekFrom::Start(start))?;
+    let mut chunk = Vec::with_capacity(size as usize);
+    file.take(size).read_to_end(&mut chunk)?;
+    let mut hasher = Sha256::new();
+    hasher.update(duration.unwrap_or_default().to_le_bytes());
+    hasher.update(&chunk);
+    Ok(hex_id('c', hasher))
+}
+
+/// the content id a file gets on import, without parsing the tags into a whole `Song`.
+/// `None` if the file can't be read, its id (if any) came from the tags then
+pub fn compute_id(path: &Path) -> Option<TrackId> {
+    let parse_options = ParseOptions::new()
+        .read_properties(true)
+        .parsing_mode(ParsingMode::Relaxed);
+    let tagged_file = probe::open(path).ok()?.options(parse_options).read().ok()?;
+    let duration = tagged_file.properties().duration().as_secs() as u32;
+    content_id(path, Some(duration)).ok()
+}
+
+fn tag_id(song: &Song) -> TrackId {
+    let mut hasher = Sha256::new();
+    [&song.title, &song.artist, &song.release]
+        .iter()
+        .for_each(|field| {
+            hasher.update(field.as_deref().unwrap_or_default().as_bytes());
+            hasher.update([0]);
+        });
+    hasher.update(song.duration.unwrap_or_default().to_le_bytes());
+    hex_id('t', hasher)
+}
+
+#[allow(unused)]
+impl Song {
+    /// content fingerprint of the audio data, falls back to the tags if the file can't be read
+    pub fn compute_id(&self) -> TrackId {
+        content_id(&self.path, self.duration).unwrap_or_else(|e| {
+            warn!(
+                "Failed to fingerprint {:?}: {e:?}, using tags instead",
+                self.path
+            );
+            tag_id(self)
+        })
+    }
+
+    /// songs stored before ids existed get one on first sight
+    pub fn ensure_id(&mut self) -> bool {
+        if self.id.is_empty() {
+            self.id = self.compute_id();
+            return true;
+        }
+        false
+    }
+
+    /// keep the id (and score if unset) of the record this one replaces,
+    /// so re-parsing a file after a tag edit doesn't orphan its references
+    pub fn inherit(mut self, old: &Song) -> Self {
+        if !old.id.is_empty() {
+            self.id = old.id.clone();
+        }
+        if self.score.is_none() {
+            self.score = old.score.clone();
+        }
+        self
+    }
+}
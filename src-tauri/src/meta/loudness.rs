@@ -0,0 +1,434 @@
+use crate::{
+    meta::{decode::PacketDecoder, probe},
+    misc::{
+        config::get_global,
+        error::{CoreError, CoreResult},
+        utils::{get_path_hash, get_time, resolve_resource_path},
+    },
+};
+use lofty::{
+    config::{ParseOptions, ParsingMode},
+    prelude::{ItemKey, TaggedFileExt},
+};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::max,
+    f64::consts::PI,
+    fs,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+use symphonia::core::audio::Channels;
+
+// EBU R128 / ITU-R BS.1770: K 加权, 400ms 的块 (每 100ms 一块, 重叠 75%),
+// -70 LUFS 绝对门限 + 低于平均 10 LU 的相对门限. 响度范围用 3s 的块, 相对门限 20 LU, 取 10% ~ 95%.
+// 真峰值是 4 倍过采样以后的最大值. 一张专辑按所有块一起算, 不是把每首的结果平均
+
+/// ReplayGain 2.0 reference
+pub const REFERENCE_LUFS: f64 = -18.0;
+const ABSOLUTE_GATE: f64 = -70.0;
+const SUB_BLOCK_SECS: f64 = 0.1;
+const MOMENTARY_SUB_BLOCKS: usize = 4;
+const SHORT_TERM_SUB_BLOCKS: usize = 30;
+const OVERSAMPLING: usize = 4;
+/// taps per phase of the true peak interpolator
+const TRUE_PEAK_TAPS: usize = 12;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Loudness {
+    /// LUFS
+    pub integrated: f64,
+    /// LU
+    pub range: f64,
+    /// linear, 1.0 is full scale
+    pub true_peak: f64,
+}
+
+impl Loudness {
+    /// ReplayGain 2.0 gain in dB
+    pub fn gain(&self) -> f64 {
+        REFERENCE_LUFS - self.integrated
+    }
+
+    /// the tracks measured as one programme, a release
+    pub fn of(measurements: &[&Measurement]) -> Self {
+        let blocks = |f: fn(&Measurement) -> &Vec<f64>| {
+            measurements
+                .iter()
+                .flat_map(|m| f(m).iter().copied())
+                .collect::<Vec<f64>>()
+        };
+        Self {
+            integrated: integrated(&blocks(|m| &m.momentary)),
+            range: range(&blocks(|m| &m.short_term)),
+            true_peak: measurements.iter().map(|m| m.true_peak).fold(0.0, f64::max),
+        }
+    }
+}
+
+/// block powers of one track, kept so a release can be measured from its tracks
+#[derive(Debug, Clone)]
+pub struct Measurement {
+    momentary: Vec<f64>,
+    short_term: Vec<f64>,
+    true_peak: f64,
+}
+
+impl Measurement {
+    pub fn loudness(&self) -> Loudness {
+        Loudness::of(&[self])
+    }
+}
+
+fn lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+fn mean(powers: &[f64]) -> f64 {
+    powers.iter().sum::<f64>() / powers.len() as f64
+}
+
+/// blocks above the absolute gate and above `relative` LU under their mean
+fn gated(blocks: &[f64], relative: f64) -> Vec<f64> {
+    let above = blocks
+        .iter()
+        .copied()
+        .filter(|p| lufs(*p) > ABSOLUTE_GATE)
+        .collect::<Vec<f64>>();
+    if above.is_empty() {
+        return above;
+    }
+    let gate = lufs(mean(&above)) - relative;
+    above.into_iter().filter(|p| lufs(*p) > gate).collect()
+}
+
+fn integrated(blocks: &[f64]) -> f64 {
+    let gated = gated(blocks, 10.0);
+    match gated.is_empty() {
+        // 整首都是静音
+        true => ABSOLUTE_GATE,
+        false => lufs(mean(&gated)),
+    }
+}
+
+fn range(blocks: &[f64]) -> f64 {
+    let mut levels = gated(blocks, 20.0)
+        .into_iter()
+        .map(lufs)
+        .collect::<Vec<f64>>();
+    if levels.len() < 2 {
+        return 0.0;
+    }
+    levels.sort_by(f64::total_cmp);
+    let at = |q: f64| levels[((levels.len() - 1) as f64 * q).round() as usize];
+    at(0.95) - at(0.10)
+}
+
+/// transposed direct form II
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[1] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[2] * y;
+        y
+    }
+}
+
+/// the high shelf and high pass of BS.1770, worked out for any sample rate
+#[derive(Clone, Copy)]
+struct KWeighting([Biquad; 2]);
+
+impl KWeighting {
+    fn new(rate: f64) -> Self {
+        let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+        let k = (PI * f0 / rate).tan();
+        let vh = 10f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad {
+            b: [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        };
+        let (f0, q) = (38.13547087602444, 0.5003270373238773);
+        let k = (PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad {
+            b: [1.0, -2.0, 1.0],
+            a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        };
+        Self([shelf, high_pass])
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let [shelf, high_pass] = &mut self.0;
+        high_pass.process(shelf.process(x))
+    }
+}
+
+/// windowed sinc, one row of taps per phase, each row summing to 1
+static INTERPOLATOR: LazyLock<[[f32; TRUE_PEAK_TAPS]; OVERSAMPLING]> = LazyLock::new(|| {
+    let len = TRUE_PEAK_TAPS * OVERSAMPLING;
+    let center = (len - 1) as f64 / 2.0;
+    let mut phases = [[0.0; TRUE_PEAK_TAPS]; OVERSAMPLING];
+    phases.iter_mut().enumerate().for_each(|(phase, taps)| {
+        taps.iter_mut().enumerate().for_each(|(k, tap)| {
+            let n = k * OVERSAMPLING + phase;
+            let t = (n as f64 - center) / OVERSAMPLING as f64;
+            let sinc = match t == 0.0 {
+                true => 1.0,
+                false => (PI * t).sin() / (PI * t),
+            };
+            let hann = 0.5 - 0.5 * (2.0 * PI * n as f64 / (len - 1) as f64).cos();
+            *tap = (sinc * hann) as f32;
+        });
+        let sum = taps.iter().sum::<f32>();
+        taps.iter_mut().for_each(|tap| *tap /= sum);
+    });
+    phases
+});
+
+struct TruePeak {
+    history: [f32; TRUE_PEAK_TAPS],
+    at: usize,
+    peak: f32,
+}
+
+impl TruePeak {
+    fn new() -> Self {
+        Self {
+            history: [0.0; TRUE_PEAK_TAPS],
+            at: 0,
+            peak: 0.0,
+        }
+    }
+
+    fn push(&mut self, x: f32) {
+        self.history[self.at] = x;
+        self.peak = self.peak.max(x.abs());
+        INTERPOLATOR.iter().for_each(|taps| {
+            let y = taps
+                .iter()
+                .enumerate()
+                .map(|(k, tap)| {
+                    // 从最新的一个往回数
+                    tap * self.history[(self.at + TRUE_PEAK_TAPS - k) % TRUE_PEAK_TAPS]
+                })
+                .sum::<f32>();
+            self.peak = self.peak.max(y.abs());
+        });
+        self.at = (self.at + 1) % TRUE_PEAK_TAPS;
+    }
+}
+
+/// BS.1770 channel weights: LFE isn't counted, surrounds weigh 1.41
+fn channel_weight(channel: Channels) -> f64 {
+    let surround =
+        Channels::SIDE_LEFT | Channels::SIDE_RIGHT | Channels::REAR_LEFT | Channels::REAR_RIGHT;
+    match channel {
+        c if c.intersects(Channels::LFE1 | Channels::LFE2) => 0.0,
+        c if c.intersects(surround) => 1.41,
+        _ => 1.0,
+    }
+}
+
+/// filter and peak meter of one channel
+struct ChannelMeter {
+    weight: f64,
+    weighting: KWeighting,
+    peak: TruePeak,
+}
+
+impl ChannelMeter {
+    /// weighted power of one sample
+    fn push(&mut self, x: f32) -> f64 {
+        self.peak.push(x);
+        let y = self.weighting.process(x as f64);
+        self.weight * y * y
+    }
+}
+
+/// decodes packet by packet, only the sub-block powers are kept.
+/// each channel is measured on its own, so mono is counted once
+pub fn measure(path: &Path) -> CoreResult<Measurement> {
+    let mut decoder = PacketDecoder::open(path)?;
+    let rate = decoder.sample_rate as f64;
+    let sub_block = max(1, (rate * SUB_BLOCK_SECS) as usize);
+    let mut meters: Vec<ChannelMeter> = Vec::new();
+    let (mut power, mut filled) = (0.0, 0);
+    let mut sub_blocks = Vec::new();
+    while let Some((samples, channels)) = decoder.next_frames() {
+        if meters.is_empty() {
+            meters = channels
+                .iter()
+                .map(|channel| ChannelMeter {
+                    weight: channel_weight(channel),
+                    weighting: KWeighting::new(rate),
+                    peak: TruePeak::new(),
+                })
+                .collect();
+        }
+        // 中途换了声道布局的包对不上滤波器, 跳过
+        if channels.count() != meters.len() {
+            continue;
+        }
+        samples.chunks(meters.len()).for_each(|frame| {
+            power += frame
+                .iter()
+                .zip(meters.iter_mut())
+                .map(|(x, meter)| meter.push(*x))
+                .sum::<f64>();
+            filled += 1;
+            if filled == sub_block {
+                sub_blocks.push(power / sub_block as f64);
+                (power, filled) = (0.0, 0);
+            }
+        });
+    }
+    if meters.is_empty() {
+        return Err(CoreError::OtherError(format!(
+            "Failed to decode {path:?}: no audio"
+        )));
+    }
+    if filled > 0 {
+        sub_blocks.push(power / filled as f64);
+    }
+    let blocks = |n: usize| {
+        sub_blocks
+            .windows(n)
+            .map(|w| w.iter().sum::<f64>() / n as f64)
+            .collect::<Vec<f64>>()
+    };
+    Ok(Measurement {
+        momentary: blocks(MOMENTARY_SUB_BLOCKS),
+        short_term: blocks(SHORT_TERM_SUB_BLOCKS),
+        true_peak: meters
+            .iter()
+            .map(|meter| meter.peak.peak as f64)
+            .fold(0.0, f64::max),
+    })
+}
+
+/// gains and peaks as the `REPLAYGAIN_*` tags have them, dB and linear
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReplayGain {
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+}
+
+/// "-6.54 dB", "+1.20 dB", "0.988553"
+fn parse_tag_number(value: &str) -> Option<f64> {
+    value
+        .trim()
+        .trim_end_matches(|c: char| c.is_ascii_alphabetic())
+        .trim()
+        .parse()
+        .ok()
+}
+
+pub fn read_replay_gain(path: &Path) -> ReplayGain {
+    let parse_options = ParseOptions::new()
+        .read_properties(false)
+        .parsing_mode(ParsingMode::Relaxed);
+    let tagged_file = match probe::open(path).and_then(|p| Ok(p.options(parse_options).read()?)) {
+        Ok(file) => file,
+        Err(e) => {
+            debug!("No ReplayGain tags read from {path:?}: {e:?}");
+            return ReplayGain::default();
+        }
+    };
+    let Some(tag) = tagged_file
+        .primary_tag()
+        .or_else(|| tagged_file.first_tag())
+    else {
+        return ReplayGain::default();
+    };
+    let get = |key: ItemKey| tag.get_string(&key).and_then(parse_tag_number);
+    ReplayGain {
+        track_gain: get(ItemKey::ReplayGainTrackGain),
+        track_peak: get(ItemKey::ReplayGainTrackPeak),
+        album_gain: get(ItemKey::ReplayGainAlbumGain),
+        album_peak: get(ItemKey::ReplayGainAlbumPeak),
+    }
+}
+
+pub fn get_loudness_dir_path() -> CoreResult<PathBuf> {
+    let loudness_dir = resolve_resource_path(
+        &PathBuf::from(
+            get_global()
+                .get("store_base")
+                .ok_or_else(|| {
+                    CoreError::OtherError("missing 'store_base' key in Config".to_string())
+                })?
+                .as_str()
+                .ok_or_else(|| {
+                    CoreError::OtherError("'store_base' field not a string".to_string())
+                })?
+                .to_string(),
+        ),
+        Path::new(
+            get_global()
+                .get("loudness_store")
+                .and_then(|v| v.as_str().map(|s| s.to_string()))
+                .unwrap_or_else(|| "loudness".to_string())
+                .as_str(),
+        ),
+    )?;
+    Ok(loudness_dir)
+}
+
+/// one json per file, stale once the file was modified
+#[derive(Serialize, Deserialize)]
+struct CachedLoudness {
+    updated_at: String,
+    loudness: Loudness,
+}
+
+fn cache_path(path: &PathBuf) -> CoreResult<PathBuf> {
+    Ok(get_loudness_dir_path()?.join(format!("{}.json", get_path_hash(path))))
+}
+
+pub fn cached(path: &PathBuf) -> Option<Loudness> {
+    let content = fs::read_to_string(cache_path(path).ok()?).ok()?;
+    let cached = serde_json::from_str::<CachedLoudness>(&content).ok()?;
+    (cached.updated_at == get_time(path).1).then_some(cached.loudness)
+}
+
+pub fn cache(path: &PathBuf, loudness: Loudness) -> CoreResult<()> {
+    let cache = cache_path(path)?;
+    if let Some(dir) = cache.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let content = serde_json::to_string(&CachedLoudness {
+        updated_at: get_time(path).1,
+        loudness,
+    })
+    .map_err(|e| CoreError::OtherError(format!("Failed to serialize loudness: {e}")))?;
+    fs::write(cache, content)?;
+    Ok(())
+}
+
+/// the cached loudness, measured and cached if there is none
+pub fn track_loudness(path: &PathBuf) -> CoreResult<Loudness> {
+    if let Some(loudness) = cached(path) {
+        return Ok(loudness);
+    }
+    let loudness = measure(path)?.loudness();
+    cache(path, loudness)?;
+    Ok(loudness)
+}
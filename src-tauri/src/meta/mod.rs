@@ -1,3 +1,7 @@
+pub mod decode;
+pub mod id;
+pub mod loudness;
 pub mod lyric;
 pub mod pic;
+pub mod probe;
 pub mod song;
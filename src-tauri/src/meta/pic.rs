@@ -1,4 +1,5 @@
 use crate::{
+    meta::probe,
     misc::{
         config::get_global,
         error::{CoreError, CoreResult},
@@ -6,7 +7,7 @@ use crate::{
     },
     store::json::entity::song::Song,
 };
-use lofty::{config::ParseOptions, picture::Picture, prelude::*, probe::Probe};
+use lofty::{config::ParseOptions, picture::Picture, prelude::*};
 use log::debug;
 use std::path::{Path, PathBuf};
 
@@ -19,7 +20,7 @@ impl Song {
             .read_properties(true)
             .parsing_mode(lofty::config::ParsingMode::Relaxed);
 
-        let tagged_file = Probe::open(&self.path)?.options(parse_options).read()?;
+        let tagged_file = probe::open(&self.path)?.options(parse_options).read()?;
         let pic = tagged_file
             .primary_tag()
             .and_then(|tag| tag.pictures().first().cloned());
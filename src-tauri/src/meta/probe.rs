@@ -0,0 +1,68 @@
+use crate::misc::{config::get_global, error::CoreResult};
+use lofty::{file::FileType, probe::Probe};
+use std::{fs::File, io::BufReader, path::Path};
+
+// 后缀不可信 (下载时被改了名, .m4a 其实是 mp3 ...), 打开文件一律先看文件头,
+// 文件头认不出来才退回到按后缀
+
+/// extensions never worth opening to sniff
+const NOT_AUDIO: [&str; 24] = [
+    "jpg", "jpeg", "png", "bmp", "gif", "webp", "tiff", "lrc", "ttml", "txt", "cue", "log", "nfo",
+    "pdf", "m3u", "m3u8", "pls", "db", "ini", "json", "xml", "md", "url", "sfv",
+];
+
+/// a probe set to the format the content says, the extension only as a fallback
+pub fn open(path: &Path) -> CoreResult<Probe<BufReader<File>>> {
+    Ok(Probe::open(path)?.guess_file_type()?)
+}
+
+pub fn sniff(path: &Path) -> Option<FileType> {
+    open(path).ok()?.file_type()
+}
+
+/// decides what a glob picks up. `supported_audio_extensions` overrides the sniffing:
+/// listed extensions are taken as they are, `-ext` entries are never taken
+#[derive(Debug, Clone, Default)]
+pub struct AudioFilter {
+    force: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl AudioFilter {
+    pub fn from_config() -> Self {
+        let (deny, force) = get_global()
+            .get("supported_audio_extensions")
+            .and_then(|v| v.as_array().cloned())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|e| e.as_str().map(|s| s.trim().to_lowercase()))
+            .partition::<Vec<String>, _>(|s| s.starts_with('-'));
+        Self {
+            force,
+            deny: deny
+                .into_iter()
+                .map(|s| s.trim_start_matches('-').to_string())
+                .collect(),
+        }
+    }
+
+    /// what the extension alone decides, `None` means the content has to be sniffed
+    pub fn by_extension(&self, path: &Path) -> Option<bool> {
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if self.deny.contains(&ext) {
+            return Some(false);
+        }
+        if self.force.contains(&ext) {
+            return Some(true);
+        }
+        NOT_AUDIO.contains(&ext.as_str()).then_some(false)
+    }
+
+    pub fn is_audio(&self, path: &Path) -> bool {
+        self.by_extension(path)
+            .unwrap_or_else(|| sniff(path).is_some())
+    }
+}
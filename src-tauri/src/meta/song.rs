@@ -1,17 +1,20 @@
 use crate::{
+    meta::{loudness::Loudness, probe},
     misc::{
         error::{CoreError, CoreResult},
         utils::get_time,
     },
-    store::json::entity::{score::Score, song::Song},
+    store::json::entity::{
+        score::Score,
+        song::{Song, TrackId},
+    },
 };
 use lofty::{
     config::{ParseOptions, ParsingMode, WriteOptions},
     file::AudioFile,
     picture::{MimeType, Picture, PictureType},
     prelude::{ItemKey, TaggedFileExt},
-    probe::Probe,
-    tag::{Accessor, ItemValue, TagItem},
+    tag::{Accessor, ItemValue, Tag, TagItem},
 };
 use log::warn;
 use std::{fs, path::PathBuf};
@@ -24,7 +27,7 @@ impl Song {
             .read_properties(true)
             .parsing_mode(lofty::config::ParsingMode::Relaxed);
 
-        let tagged_file = match Probe::open(path) {
+        let tagged_file = match probe::open(path) {
             Ok(probe) => match probe.options(parse_options).read() {
                 Ok(file) => file,
                 Err(e) => {
@@ -37,7 +40,7 @@ impl Song {
             },
             Err(e) => {
                 warn!("Failed to open file {path:?}: {e:?}");
-                return Err(CoreError::LoftyError(e));
+                return Err(e);
             }
         };
 
@@ -58,7 +61,8 @@ impl Song {
             None
         };
 
-        Ok(Song {
+        let mut song = Song {
+            id: TrackId::new(),
             path: path.into(),
             title,
             artist,
@@ -67,7 +71,10 @@ impl Song {
             created_at,
             updated_at,
             score: score.or(score_from_tag),
-        })
+            unavailable: false,
+        };
+        song.id = song.compute_id();
+        Ok(song)
     }
 
     pub fn write_tags(&self, new_cover_path: Option<&PathBuf>) -> CoreResult<()> {
@@ -76,7 +83,7 @@ impl Song {
             .read_properties(true)
             .parsing_mode(ParsingMode::Relaxed);
 
-        let mut tagged_file = Probe::open(&self.path)?.options(parse_options).read()?;
+        let mut tagged_file = probe::open(&self.path)?.options(parse_options).read()?;
 
         let tag_type = tagged_file.primary_tag_type();
         let tag = tagged_file.tag_mut(tag_type).ok_or_else(|| {
@@ -139,4 +146,51 @@ impl Song {
 
         Ok(())
     }
+
+    /// `REPLAYGAIN_*` of the track and its release, in the usual "-6.54 dB" / "0.988553" form
+    pub fn write_replay_gain(&self, track: &Loudness, release: &Loudness) -> CoreResult<()> {
+        let parse_options = ParseOptions::new()
+            .read_properties(true)
+            .parsing_mode(ParsingMode::Relaxed);
+
+        let mut tagged_file = probe::open(&self.path)?.options(parse_options).read()?;
+
+        // 没有标签的文件先补一个格式默认的
+        let tag_type = tagged_file.primary_tag_type();
+        if tagged_file.tag(tag_type).is_none() {
+            tagged_file.insert_tag(Tag::new(tag_type));
+        }
+        let tag = tagged_file.tag_mut(tag_type).ok_or_else(|| {
+            CoreError::OtherError(format!("Failed to get mutable tag for {:?}", &self.path))
+        })?;
+
+        [
+            (
+                ItemKey::ReplayGainTrackGain,
+                format!("{:+.2} dB", track.gain()),
+            ),
+            (
+                ItemKey::ReplayGainTrackPeak,
+                format!("{:.6}", track.true_peak),
+            ),
+            (
+                ItemKey::ReplayGainAlbumGain,
+                format!("{:+.2} dB", release.gain()),
+            ),
+            (
+                ItemKey::ReplayGainAlbumPeak,
+                format!("{:.6}", release.true_peak),
+            ),
+        ]
+        .into_iter()
+        .for_each(|(key, value)| {
+            tag.insert(TagItem::new(key, ItemValue::Text(value)));
+        });
+
+        tagged_file
+            .save_to_path(&self.path, WriteOptions::default())
+            .map_err(CoreError::LoftyError)?;
+
+        Ok(())
+    }
 }
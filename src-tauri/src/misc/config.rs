@@ -1,4 +1,6 @@
-use crate::misc::error::CoreError;
+use crate::{
+    library::dir::ListenPath, misc::error::CoreError, store::json::entity::release::ReleaseKey,
+};
 
 use super::error::CoreResult;
 use serde::{Deserialize, Serialize};
@@ -84,16 +86,44 @@ pub struct Config {
     pub log_prefix: String,
     pub log_level: String,
     pub store_base: String,
+    /// "json" or "sqlite"
+    pub store_backend: String,
+    pub db_store: String,
+    /// how many rotated backups each json store keeps
+    pub store_backup_count: u64,
+    /// json stores are written this long after the last change
+    pub store_flush_delay_ms: u64,
     pub song_store: String,
     pub playlist_store: String,
     pub alist_store: String,
     pub recent_store: String,
     pub release_cover_store: String,
     pub lyric_store: String,
+    /// measured loudness per file, for files without ReplayGain tags
+    pub loudness_store: String,
     pub single_song_store: String,
-    pub listen_paths: Vec<String>,
+    /// library roots, bare paths or paths with their `DirRules`
+    pub listen_paths: Vec<ListenPath>,
     pub debounce_timeout_ms: u64,
+    /// always taken as audio without sniffing, `-ext` entries never are.
+    /// anything else is taken when its content looks like audio
     pub supported_audio_extensions: Vec<String>,
+    /// threads reading tags during a scan, 0 picks from the cpu count
+    pub scan_workers: u64,
+    /// next tracks are pre-opened and start on the exact frame the previous one ends
+    pub gapless: bool,
+    /// releases that are gapless between their own tracks even with `gapless` off
+    pub gapless_releases: Vec<ReleaseKey>,
+    /// fade of pause and resume
+    pub pause_fade_ms: u64,
+    pub stop_fade_ms: u64,
+    /// track changes overlap this long, 0 is off; never within a gapless release
+    pub crossfade_ms: u64,
+    /// "off", "track" or "album"
+    pub replay_gain: String,
+    pub replay_gain_preamp_db: f64,
+    /// for tracks without tags until they are measured
+    pub replay_gain_default_db: f64,
     // pub theme: String,
 }
 
@@ -103,12 +133,17 @@ impl Default for Config {
             log_prefix: "soa".to_string(),
             log_level: "info".to_string(),
             store_base: "".to_string(),
+            store_backend: "json".to_string(),
+            db_store: "soa.db".to_string(),
+            store_backup_count: 5,
+            store_flush_delay_ms: 1000,
             song_store: "songs.json".to_string(),
             playlist_store: "playlists.json".to_string(),
             alist_store: "alist.json".to_string(),
             recent_store: "recents.json".to_string(),
             release_cover_store: "releases_cover".to_string(),
             lyric_store: "lyrics".to_string(),
+            loudness_store: "loudness".to_string(),
             single_song_store: "lib".to_string(),
             listen_paths: vec![],
             debounce_timeout_ms: 2000,
@@ -117,6 +152,15 @@ impl Default for Config {
                 "flac".to_string(),
                 "wav".to_string(),
             ],
+            scan_workers: 0,
+            gapless: true,
+            gapless_releases: vec![],
+            pause_fade_ms: 10,
+            stop_fade_ms: 10,
+            crossfade_ms: 0,
+            replay_gain: "off".to_string(),
+            replay_gain_preamp_db: 0.0,
+            replay_gain_default_db: 0.0,
             // theme: "system".to_string(),
         }
     }
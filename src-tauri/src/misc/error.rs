@@ -6,6 +6,9 @@ use thiserror::Error;
 pub enum CoreError {
     // #[error(transparent)]
     // SqlxError(#[from] sqlx::Error),
+    #[error(transparent)]
+    SqliteError(#[from] rusqlite::Error),
+
     #[error(transparent)]
     LoftyError(#[from] lofty::error::LoftyError),
 
@@ -20,6 +23,9 @@ pub enum CoreError {
     #[error("Tauri error: {0}")]
     TauriError(#[from] tauri::Error),
 
+    #[error("Zip error: {0}")]
+    ZipError(#[from] zip::result::ZipError),
+
     #[error("Notify error: {0}")]
     NotifyError(#[from] notify::Error),
 
@@ -29,6 +35,10 @@ pub enum CoreError {
     #[error("Fs error: {0}")]
     FsError(String),
 
+    /// a store written by a newer version of the app, left alone instead of recovered
+    #[error("Store version error: {0}")]
+    StoreVersionError(String),
+
     #[error("Other error: {0}")]
     OtherError(String),
 }
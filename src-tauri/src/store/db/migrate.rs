@@ -0,0 +1,69 @@
+use crate::{
+    misc::error::CoreResult,
+    store::{
+        config_str,
+        db::{
+            op::{
+                alist::insert_alist, list::insert_playlist, recent::insert_recent,
+                song::insert_song,
+            },
+            sm::DbManager,
+        },
+        json::{
+            entity::{alist::Alist, list::Playlist, recent::Recents, song::Song},
+            op::sm::StoreManager,
+        },
+    },
+};
+use chrono::Local;
+use log::info;
+use rusqlite::params;
+use std::collections::HashSet;
+
+const JSON_MIGRATED_KEY: &str = "json_migrated_at";
+
+/// import songs / playlists / alists / recents from the json stores, once.
+/// the json files are left untouched so switching back to the json backend still works
+pub fn migrate_from_json(db: &DbManager) -> CoreResult<()> {
+    if let Some(migrated_at) = db.get_meta(JSON_MIGRATED_KEY)? {
+        info!("Json stores already migrated to sqlite at {migrated_at}");
+        return Ok(());
+    }
+    let songs = StoreManager::<HashSet<Song>>::new(config_str("song_store")?)?.load()?;
+    let playlists =
+        StoreManager::<HashSet<Playlist>>::new(config_str("playlist_store")?)?.load()?;
+    let alists = StoreManager::<HashSet<Alist>>::new(config_str("alist_store")?)?.load()?;
+    let recents = StoreManager::<Recents>::new(config_str("recent_store")?)?.load()?;
+    info!(
+        "Migrating json stores to sqlite: {} songs, {} playlists, {} alists, {} recents",
+        songs.len(),
+        playlists.len(),
+        alists.len(),
+        recents.len()
+    );
+    db.with_conn(|conn| {
+        let tx = conn.transaction()?;
+        songs
+            .iter()
+            .try_for_each(|song| insert_song(&tx, "INSERT OR REPLACE", song).map(|_| ()))?;
+        playlists
+            .iter()
+            .try_for_each(|playlist| insert_playlist(&tx, playlist))?;
+        alists
+            .iter()
+            .try_for_each(|alist| insert_alist(&tx, alist))?;
+        // recents 最新的在前, 倒着插入让自增 id 保持同样的顺序
+        recents
+            .iter()
+            .rev()
+            .try_for_each(|recent| insert_recent(&tx, recent))?;
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+            params![JSON_MIGRATED_KEY, Local::now().to_rfc3339()],
+        )?;
+        tx.commit()?;
+        Ok(())
+    })?;
+    info!("Json stores migrated to sqlite at {:?}", db.path());
+    Ok(())
+}
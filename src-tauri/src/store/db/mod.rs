@@ -1 +1,37 @@
-// soa 使用 json 持久化喵
+// soa 也可以用 sqlite 持久化喵, 见 Config 的 store_backend
+pub mod migrate;
+pub mod op;
+pub mod sm;
+
+use crate::misc::{
+    config::get_global as get_global_config,
+    error::{CoreError, CoreResult},
+};
+use log::info;
+use sm::DbManager;
+use std::sync::{Arc, OnceLock};
+
+static GLOBAL_DB: OnceLock<Arc<DbManager>> = OnceLock::new();
+
+/// open the database and run the one-shot json migration, only once per process
+pub fn init_global() -> CoreResult<()> {
+    if GLOBAL_DB.get().is_some() {
+        return Ok(());
+    }
+    let db_store = get_global_config()
+        .get("db_store")
+        .and_then(|v| v.as_str().map(|s| s.to_string()))
+        .unwrap_or_else(|| "soa.db".to_string());
+    let db = DbManager::new(db_store)?;
+    migrate::migrate_from_json(&db)?;
+    let _ = GLOBAL_DB.set(Arc::new(db));
+    info!("Sqlite store initialized");
+    Ok(())
+}
+
+pub fn get_global() -> CoreResult<Arc<DbManager>> {
+    GLOBAL_DB
+        .get()
+        .cloned()
+        .ok_or_else(|| CoreError::OtherError("Sqlite store is not initialized".to_string()))
+}
@@ -0,0 +1,159 @@
+use crate::{
+    misc::error::{CoreError, CoreResult},
+    store::{
+        db::sm::DbManager,
+        json::entity::alist::{Aelement, Alist},
+        repo::AlistRepo,
+    },
+};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::sync::Arc;
+
+// Aelement 是嵌套的快照, 直接以 json 文本存在 elements 列
+pub struct AlistDbOp {
+    pub db: Arc<DbManager>,
+}
+
+fn alist_from_row(row: &Row) -> rusqlite::Result<(String, String, String)> {
+    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+}
+
+fn to_alist((name, created_at, elements): (String, String, String)) -> CoreResult<Alist> {
+    let elements = serde_json::from_str::<Vec<Aelement>>(&elements).map_err(|e| {
+        CoreError::OtherError(format!("Failed to parse elements of alist {name:?}: {e}"))
+    })?;
+    Ok(Alist {
+        name,
+        created_at,
+        elements,
+    })
+}
+
+fn elements_to_sql(alist: &Alist) -> CoreResult<String> {
+    serde_json::to_string(&alist.elements)
+        .map_err(|e| CoreError::OtherError(format!("Failed to serialize alist elements: {e}")))
+}
+
+fn not_found(name: &str) -> CoreError {
+    CoreError::OtherError(format!("Alist with name {name:?} not found in the store"))
+}
+
+pub(crate) fn insert_alist(conn: &Connection, alist: &Alist) -> CoreResult<()> {
+    let exists = conn
+        .query_row(
+            "SELECT 1 FROM alists WHERE name = ?1",
+            [&alist.name],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+    if exists {
+        return Err(CoreError::OtherError(format!(
+            "Alist with name {:?} already exists in the store",
+            alist.name
+        )));
+    }
+    conn.execute(
+        "INSERT INTO alists (name, created_at, elements) VALUES (?1, ?2, ?3)",
+        params![alist.name, alist.created_at, elements_to_sql(alist)?],
+    )?;
+    Ok(())
+}
+
+#[allow(unused)]
+impl AlistRepo for AlistDbOp {
+    fn list_all(&self) -> CoreResult<Vec<Alist>> {
+        let rows = self.db.with_conn(|conn| {
+            let mut stmt =
+                conn.prepare("SELECT name, created_at, elements FROM alists ORDER BY name")?;
+            let rows = stmt
+                .query_map([], alist_from_row)?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            Ok(rows)
+        })?;
+        rows.into_iter().map(to_alist).collect()
+    }
+
+    fn locate(&self, name: String) -> CoreResult<Alist> {
+        let row = self.db.with_conn(|conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT name, created_at, elements FROM alists WHERE name = ?1",
+                    [&name],
+                    alist_from_row,
+                )
+                .optional()?)
+        })?;
+        to_alist(row.ok_or_else(|| not_found(&name))?)
+    }
+
+    fn insert(&self, alist: &Alist) -> CoreResult<()> {
+        self.db.with_conn(|conn| insert_alist(conn, alist))
+    }
+
+    fn rename(&self, old_name: String, new_name: String) -> CoreResult<()> {
+        self.db.with_conn(|conn| {
+            let exists = conn
+                .query_row("SELECT 1 FROM alists WHERE name = ?1", [&new_name], |_| {
+                    Ok(())
+                })
+                .optional()?
+                .is_some();
+            if exists {
+                return Err(CoreError::OtherError(format!(
+                    "Alist with name {new_name:?} already exists in the store"
+                )));
+            }
+            match conn.execute(
+                "UPDATE alists SET name = ?2 WHERE name = ?1",
+                params![old_name, new_name],
+            )? {
+                0 => Err(not_found(&old_name)),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn modify(&self, alist: &Alist) -> CoreResult<()> {
+        let elements = elements_to_sql(alist)?;
+        self.db.with_conn(|conn| {
+            match conn.execute(
+                "UPDATE alists SET created_at = ?2, elements = ?3 WHERE name = ?1",
+                params![alist.name, alist.created_at, elements],
+            )? {
+                0 => Err(not_found(&alist.name)),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn remove(&self, name: String) -> CoreResult<()> {
+        self.db.with_conn(|conn| {
+            match conn.execute("DELETE FROM alists WHERE name = ?1", [&name])? {
+                0 => Err(not_found(&name)),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn remove_batch(&self, names: Vec<String>) -> CoreResult<()> {
+        self.db.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            names.iter().try_for_each(|name| {
+                match tx.execute("DELETE FROM alists WHERE name = ?1", [name])? {
+                    0 => Err(not_found(name)),
+                    _ => Ok(()),
+                }
+            })?;
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    fn clear_all(&self) -> CoreResult<()> {
+        self.db.with_conn(|conn| {
+            conn.execute("DELETE FROM alists", [])?;
+            Ok(())
+        })
+    }
+}
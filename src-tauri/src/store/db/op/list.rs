@@ -0,0 +1,196 @@
+use crate::{
+    misc::error::{CoreError, CoreResult},
+    store::{
+        db::sm::DbManager,
+        json::entity::{list::Playlist, song::TrackId},
+        repo::PlaylistRepo,
+    },
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{collections::HashMap, sync::Arc};
+
+pub struct PlaylistDbOp {
+    pub db: Arc<DbManager>,
+}
+
+fn write_songs(conn: &Connection, name: &str, songs: &[TrackId]) -> CoreResult<()> {
+    conn.execute("DELETE FROM playlist_songs WHERE playlist = ?1", [name])?;
+    let mut stmt = conn
+        .prepare("INSERT INTO playlist_songs (playlist, position, song_id) VALUES (?1, ?2, ?3)")?;
+    songs.iter().enumerate().try_for_each(|(position, id)| {
+        stmt.execute(params![name, position as i64, id]).map(|_| ())
+    })?;
+    Ok(())
+}
+
+fn exists(conn: &Connection, name: &str) -> CoreResult<bool> {
+    Ok(conn
+        .query_row(
+            "SELECT 1 FROM playlists WHERE name = ?1",
+            [name],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some())
+}
+
+pub(crate) fn insert_playlist(conn: &Connection, playlist: &Playlist) -> CoreResult<()> {
+    if exists(conn, &playlist.name)? {
+        return Err(CoreError::OtherError(format!(
+            "Playlist with name {:?} already exists in the store",
+            playlist.name
+        )));
+    }
+    conn.execute(
+        "INSERT INTO playlists (name, created_at) VALUES (?1, ?2)",
+        params![playlist.name, playlist.created_at],
+    )?;
+    write_songs(conn, &playlist.name, &playlist.songs)
+}
+
+fn not_found(name: &str) -> CoreError {
+    CoreError::OtherError(format!(
+        "Playlist with name {name:?} not found in the store"
+    ))
+}
+
+#[allow(unused)]
+impl PlaylistRepo for PlaylistDbOp {
+    fn list_all(&self) -> CoreResult<Vec<Playlist>> {
+        self.db.with_conn(|conn| {
+            let mut songs = HashMap::<String, Vec<TrackId>>::new();
+            let mut stmt = conn.prepare(
+                "SELECT playlist, song_id FROM playlist_songs ORDER BY playlist, position",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?;
+            for row in rows {
+                let (playlist, id) = row?;
+                songs.entry(playlist).or_default().push(id);
+            }
+            let mut stmt = conn.prepare("SELECT name, created_at FROM playlists ORDER BY name")?;
+            let playlists = stmt
+                .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))?
+                .map(|row| {
+                    row.map(|(name, created_at)| Playlist {
+                        songs: songs.remove(&name).unwrap_or_default(),
+                        name,
+                        created_at,
+                    })
+                })
+                .collect::<rusqlite::Result<Vec<Playlist>>>()?;
+            Ok(playlists)
+        })
+    }
+
+    fn locate(&self, name: String) -> CoreResult<Playlist> {
+        self.db.with_conn(|conn| {
+            let created_at: String = conn
+                .query_row(
+                    "SELECT created_at FROM playlists WHERE name = ?1",
+                    [&name],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .ok_or_else(|| not_found(&name))?;
+            let mut stmt = conn.prepare(
+                "SELECT song_id FROM playlist_songs WHERE playlist = ?1 ORDER BY position",
+            )?;
+            let songs = stmt
+                .query_map([&name], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<TrackId>>>()?;
+            Ok(Playlist {
+                name,
+                created_at,
+                songs,
+            })
+        })
+    }
+
+    fn insert(&self, playlist: &Playlist) -> CoreResult<()> {
+        self.db.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            insert_playlist(&tx, playlist)?;
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    fn rename(&self, old_name: String, new_name: String) -> CoreResult<()> {
+        self.db.with_conn(|conn| {
+            if exists(conn, &new_name)? {
+                return Err(CoreError::OtherError(format!(
+                    "Playlist with name {new_name:?} already exists in the store"
+                )));
+            }
+            // playlist_songs 跟随 ON UPDATE CASCADE
+            match conn.execute(
+                "UPDATE playlists SET name = ?2 WHERE name = ?1",
+                params![old_name, new_name],
+            )? {
+                0 => Err(not_found(&old_name)),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn modify(&self, playlist: &Playlist) -> CoreResult<()> {
+        self.db.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            if tx.execute(
+                "UPDATE playlists SET created_at = ?2 WHERE name = ?1",
+                params![playlist.name, playlist.created_at],
+            )? == 0
+            {
+                return Err(not_found(&playlist.name));
+            }
+            write_songs(&tx, &playlist.name, &playlist.songs)?;
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    fn remove(&self, name: String) -> CoreResult<()> {
+        self.db.with_conn(|conn| {
+            match conn.execute("DELETE FROM playlists WHERE name = ?1", [&name])? {
+                0 => Err(not_found(&name)),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn remove_batch(&self, names: Vec<String>) -> CoreResult<()> {
+        self.db.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            names.iter().try_for_each(|name| {
+                match tx.execute("DELETE FROM playlists WHERE name = ?1", [name])? {
+                    0 => Err(not_found(name)),
+                    _ => Ok(()),
+                }
+            })?;
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    // 不必整张表读出来, 位置留空洞不影响排序
+    fn remove_song_ids_all(&self, song_ids: Vec<TrackId>) -> CoreResult<()> {
+        self.db.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            song_ids.iter().try_for_each(|id| {
+                tx.execute("DELETE FROM playlist_songs WHERE song_id = ?1", [id])
+                    .map(|_| ())
+            })?;
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    fn clear_all(&self) -> CoreResult<()> {
+        self.db.with_conn(|conn| {
+            conn.execute("DELETE FROM playlists", [])?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,4 @@
+pub mod alist;
+pub mod list;
+pub mod recent;
+pub mod song;
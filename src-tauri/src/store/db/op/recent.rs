@@ -0,0 +1,93 @@
+use crate::{
+    misc::error::{CoreError, CoreResult},
+    store::{
+        db::sm::DbManager,
+        json::entity::{
+            recent::{Recent, Recents},
+            song::TrackId,
+        },
+        repo::RecentRepo,
+    },
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::sync::Arc;
+
+// 自增 id 越大越新, 对应 json 里 insert(0, ..) 的顺序
+pub struct RecentDbOp {
+    pub db: Arc<DbManager>,
+}
+
+pub(crate) fn insert_recent(conn: &Connection, recent: &Recent) -> CoreResult<()> {
+    conn.execute(
+        "INSERT INTO recents (song_id, accessed_at) VALUES (?1, ?2)",
+        params![recent.song, recent.accessed_at],
+    )?;
+    Ok(())
+}
+
+#[allow(unused)]
+impl RecentRepo for RecentDbOp {
+    fn list_all(&self) -> CoreResult<Recents> {
+        self.db.with_conn(|conn| {
+            let mut stmt =
+                conn.prepare("SELECT song_id, accessed_at FROM recents ORDER BY id DESC")?;
+            let recents = stmt
+                .query_map([], |row| {
+                    Ok(Recent {
+                        song: row.get(0)?,
+                        accessed_at: row.get(1)?,
+                    })
+                })?
+                .collect::<rusqlite::Result<Recents>>()?;
+            Ok(recents)
+        })
+    }
+
+    fn clear(&self) -> CoreResult<()> {
+        self.db.with_conn(|conn| {
+            conn.execute("DELETE FROM recents", [])?;
+            Ok(())
+        })
+    }
+
+    fn insert(&self, recent: &Recent) -> CoreResult<()> {
+        self.db.with_conn(|conn| insert_recent(conn, recent))
+    }
+
+    fn remove_by_index(&self, index: usize) -> CoreResult<()> {
+        self.db.with_conn(|conn| {
+            let id: Option<i64> = conn
+                .query_row(
+                    "SELECT id FROM recents ORDER BY id DESC LIMIT 1 OFFSET ?1",
+                    [index as i64],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            match id {
+                Some(id) => {
+                    conn.execute("DELETE FROM recents WHERE id = ?1", [id])?;
+                    Ok(())
+                }
+                None => {
+                    let len: i64 =
+                        conn.query_row("SELECT COUNT(*) FROM recents", [], |row| row.get(0))?;
+                    Err(CoreError::OtherError(format!(
+                        "Index {index} out of bounds for recents of length {len}"
+                    )))
+                }
+            }
+        })
+    }
+
+    fn remove_by_song_ids(&self, song_ids: Vec<&TrackId>) -> CoreResult<()> {
+        self.db.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            song_ids.iter().try_for_each(|id| {
+                tx.execute("DELETE FROM recents WHERE song_id = ?1", [id])
+                    .map(|_| ())
+            })?;
+            tx.commit()?;
+            Ok(())
+        })
+    }
+}
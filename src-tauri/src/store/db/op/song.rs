@@ -0,0 +1,194 @@
+use crate::{
+    misc::error::{CoreError, CoreResult},
+    store::{db::sm::DbManager, json::entity::song::Song, repo::SongRepo},
+};
+use rusqlite::{params, Connection, OptionalExtension, Params, Row};
+use std::{path::PathBuf, sync::Arc};
+
+pub(crate) const SONG_COLUMNS: &str =
+    r#"path, title, duration, score, created_at, updated_at, artist, "release", id"#;
+
+pub struct SongDbOp {
+    pub db: Arc<DbManager>,
+}
+
+pub(crate) fn song_from_row(row: &Row) -> rusqlite::Result<Song> {
+    let path: String = row.get(0)?;
+    let score: Option<String> = row.get(3)?;
+    Ok(Song {
+        path: PathBuf::from(path),
+        title: row.get(1)?,
+        duration: row.get(2)?,
+        score: score.and_then(|s| serde_json::from_str(&s).ok()),
+        created_at: row.get(4)?,
+        updated_at: row.get(5)?,
+        artist: row.get(6)?,
+        release: row.get(7)?,
+        id: row.get(8)?,
+        unavailable: false,
+    })
+}
+
+fn score_to_sql(song: &Song) -> CoreResult<Option<String>> {
+    song.score
+        .as_ref()
+        .map(|score| {
+            serde_json::to_string(score)
+                .map_err(|e| CoreError::OtherError(format!("Failed to serialize score: {e}")))
+        })
+        .transpose()
+}
+
+/// `verb` is `INSERT`, `INSERT OR IGNORE` or `INSERT OR REPLACE`
+pub(crate) fn insert_song(conn: &Connection, verb: &str, song: &Song) -> CoreResult<usize> {
+    Ok(conn.execute(
+        &format!("{verb} INTO songs ({SONG_COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)"),
+        params![
+            song.path.to_string_lossy(),
+            song.title,
+            song.duration,
+            score_to_sql(song)?,
+            song.created_at,
+            song.updated_at,
+            song.artist,
+            song.release,
+            song.id,
+        ],
+    )?)
+}
+
+fn update_song(conn: &Connection, song: &Song) -> CoreResult<()> {
+    let updated = conn.execute(
+        r#"UPDATE songs SET title = ?2, duration = ?3, score = ?4, created_at = ?5,
+        updated_at = ?6, artist = ?7, "release" = ?8, id = ?9 WHERE path = ?1"#,
+        params![
+            song.path.to_string_lossy(),
+            song.title,
+            song.duration,
+            score_to_sql(song)?,
+            song.created_at,
+            song.updated_at,
+            song.artist,
+            song.release,
+            song.id,
+        ],
+    )?;
+    match updated {
+        0 => Err(CoreError::OtherError(format!(
+            "Song with path {:?} not found in the store",
+            song.path
+        ))),
+        _ => Ok(()),
+    }
+}
+
+impl SongDbOp {
+    fn query<P: Params>(&self, filter: &str, params: P) -> CoreResult<Vec<Song>> {
+        self.db.with_conn(|conn| {
+            let mut stmt = conn.prepare(&format!("SELECT {SONG_COLUMNS} FROM songs {filter}"))?;
+            let songs = stmt
+                .query_map(params, song_from_row)?
+                .collect::<rusqlite::Result<Vec<Song>>>()?;
+            Ok(songs)
+        })
+    }
+}
+
+#[allow(unused)]
+impl SongRepo for SongDbOp {
+    fn list_all(&self) -> CoreResult<Vec<Song>> {
+        self.query("", [])
+    }
+
+    fn locate(&self, file: &PathBuf) -> CoreResult<Song> {
+        self.db
+            .with_conn(|conn| {
+                Ok(conn
+                    .query_row(
+                        &format!("SELECT {SONG_COLUMNS} FROM songs WHERE path = ?1"),
+                        [file.to_string_lossy()],
+                        song_from_row,
+                    )
+                    .optional()?)
+            })?
+            .ok_or_else(|| {
+                CoreError::OtherError(format!("Song with path {file:?} not found in the store"))
+            })
+    }
+
+    fn list_by_artist(&self, artist: &str) -> CoreResult<Vec<Song>> {
+        self.query("WHERE artist = ?1", [artist])
+    }
+
+    fn list_unknown_artist(&self) -> CoreResult<Vec<Song>> {
+        self.query("WHERE artist IS NULL", [])
+    }
+
+    fn list_by_artist_release(&self, artist: &str, release: &str) -> CoreResult<Vec<Song>> {
+        self.query(r#"WHERE artist = ?1 AND "release" = ?2"#, [artist, release])
+    }
+
+    fn list_unknown_release(&self) -> CoreResult<Vec<Song>> {
+        self.query(r#"WHERE "release" IS NULL"#, [])
+    }
+
+    fn save_all(&self, songs: &Vec<Song>) -> CoreResult<()> {
+        self.db.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            tx.execute("DELETE FROM songs", [])?;
+            songs
+                .iter()
+                .try_for_each(|song| insert_song(&tx, "INSERT OR REPLACE", song).map(|_| ()))?;
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    // 和 json 后端一致, 已存在的 song 保持不变
+    fn add_save(&self, songs: &Vec<Song>) -> CoreResult<()> {
+        self.db.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            songs
+                .iter()
+                .try_for_each(|song| insert_song(&tx, "INSERT OR IGNORE", song).map(|_| ()))?;
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    fn remove(&self, songs: &Vec<Song>) -> CoreResult<()> {
+        self.remove_by_files(&songs.iter().map(|song| song.path.clone()).collect())
+    }
+
+    fn remove_by_files(&self, files: &Vec<PathBuf>) -> CoreResult<()> {
+        self.db.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            files.iter().try_for_each(|file| {
+                match tx.execute(
+                    "DELETE FROM songs WHERE path = ?1",
+                    [file.to_string_lossy()],
+                )? {
+                    0 => Err(CoreError::OtherError(format!(
+                        "Remove file with path {file:?} not found"
+                    ))),
+                    _ => Ok(()),
+                }
+            })?;
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    fn modify(&self, song: &Song) -> CoreResult<()> {
+        self.db.with_conn(|conn| update_song(conn, song))
+    }
+
+    fn modify_batch(&self, songs: &Vec<Song>) -> CoreResult<()> {
+        self.db.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            songs.iter().try_for_each(|song| update_song(&tx, song))?;
+            tx.commit()?;
+            Ok(())
+        })
+    }
+}
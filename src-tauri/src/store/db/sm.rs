@@ -0,0 +1,224 @@
+use crate::{
+    misc::{
+        config::get_global,
+        error::{CoreError, CoreResult},
+        utils::resolve_resource_path,
+    },
+    store::{
+        db::op::song::{song_from_row, SONG_COLUMNS},
+        json::{
+            entity::song::Song,
+            version::{elements_paths_to_ids, MigrationContext},
+        },
+    },
+};
+use log::info;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::Value;
+use std::{fs::create_dir_all, path::PathBuf, sync::Mutex};
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS songs (
+    path TEXT PRIMARY KEY,
+    title TEXT,
+    duration INTEGER,
+    score TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    artist TEXT,
+    "release" TEXT,
+    id TEXT NOT NULL DEFAULT ''
+);
+CREATE INDEX IF NOT EXISTS idx_songs_artist_release ON songs (artist, "release");
+CREATE INDEX IF NOT EXISTS idx_songs_id ON songs (id);
+CREATE TABLE IF NOT EXISTS playlists (
+    name TEXT PRIMARY KEY,
+    created_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS playlist_songs (
+    playlist TEXT NOT NULL REFERENCES playlists (name) ON DELETE CASCADE ON UPDATE CASCADE,
+    position INTEGER NOT NULL,
+    song_id TEXT NOT NULL,
+    PRIMARY KEY (playlist, position)
+);
+CREATE INDEX IF NOT EXISTS idx_playlist_songs_song_id ON playlist_songs (song_id);
+CREATE TABLE IF NOT EXISTS alists (
+    name TEXT PRIMARY KEY,
+    created_at TEXT NOT NULL,
+    elements TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS recents (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    song_id TEXT NOT NULL,
+    accessed_at TEXT
+);
+CREATE INDEX IF NOT EXISTS idx_recents_song_id ON recents (song_id);
+"#;
+
+// 已有数据库按 PRAGMA user_version 逐步升级, `UPGRADES[i]` 把 version i 升到 i + 1, 只追加
+// 0 -> 1: 引入 track id
+// 1 -> 2: playlist / recent / alist 里的歌单快照从 path 换成 id, 跟 json store 的 version 2 一样
+const UPGRADES: [fn(&Connection) -> CoreResult<()>; 2] = [add_track_ids, paths_to_track_ids];
+
+fn add_track_ids(conn: &Connection) -> CoreResult<()> {
+    Ok(conn.execute_batch(
+        r#"
+ALTER TABLE songs ADD COLUMN id TEXT NOT NULL DEFAULT '';
+ALTER TABLE playlist_songs RENAME COLUMN path TO song_id;
+ALTER TABLE recents RENAME COLUMN path TO song_id;
+DROP INDEX IF EXISTS idx_playlist_songs_path;
+DROP INDEX IF EXISTS idx_recents_path;
+"#,
+    )?)
+}
+
+fn paths_to_track_ids(conn: &Connection) -> CoreResult<()> {
+    let songs = conn
+        .prepare(&format!("SELECT {SONG_COLUMNS} FROM songs"))?
+        .query_map([], song_from_row)?
+        .collect::<rusqlite::Result<Vec<Song>>>()?;
+    let context = MigrationContext::with_songs(&songs);
+    // 还没有 id 的歌先补上, 和下次扫描算出来的是同一个
+    songs
+        .iter()
+        .filter(|song| song.id.is_empty())
+        .try_for_each(|song| {
+            let path = song.path.to_string_lossy();
+            conn.execute(
+                "UPDATE songs SET id = ?2 WHERE path = ?1",
+                params![path, context.track_id(&path)],
+            )
+            .map(|_| ())
+        })?;
+    conn.execute_batch(
+        r#"
+UPDATE playlist_songs SET song_id = (SELECT id FROM songs WHERE songs.path = playlist_songs.song_id)
+    WHERE song_id IN (SELECT path FROM songs);
+UPDATE recents SET song_id = (SELECT id FROM songs WHERE songs.path = recents.song_id)
+    WHERE song_id IN (SELECT path FROM songs);
+"#,
+    )?;
+    let alists = conn
+        .prepare("SELECT name, elements FROM alists")?
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+    alists.into_iter().try_for_each(|(name, elements)| {
+        let mut elements = serde_json::from_str::<Value>(&elements).map_err(|e| {
+            CoreError::OtherError(format!("Failed to parse elements of alist {name:?}: {e}"))
+        })?;
+        elements_paths_to_ids(&mut elements, &context);
+        conn.execute(
+            "UPDATE alists SET elements = ?2 WHERE name = ?1",
+            params![name, elements.to_string()],
+        )?;
+        Ok(())
+    })
+}
+
+fn upgrade_schema(conn: &mut Connection) -> CoreResult<()> {
+    let fresh = conn
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'songs'",
+            [],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_none();
+    if !fresh {
+        let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        let tx = conn.transaction()?;
+        UPGRADES
+            .iter()
+            .enumerate()
+            .skip(version)
+            .try_for_each(|(from, upgrade)| {
+                info!("Upgrading sqlite schema from version {from}");
+                upgrade(&tx)
+            })?;
+        tx.commit()?;
+    }
+    conn.execute_batch(SCHEMA)?;
+    conn.pragma_update(None, "user_version", UPGRADES.len())?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct DbManager {
+    db_path: PathBuf,
+    conn: Mutex<Connection>,
+}
+
+#[allow(unused)]
+impl DbManager {
+    pub fn new(path: String) -> CoreResult<Self> {
+        let config = get_global();
+        let db_path = resolve_resource_path(
+            &PathBuf::from(
+                config
+                    .get("store_base")
+                    .ok_or_else(|| {
+                        CoreError::OtherError(
+                            "missing 'store.store_base' key in Config".to_string(),
+                        )
+                    })?
+                    .as_str()
+                    .ok_or_else(|| {
+                        CoreError::OtherError("`store.store_base` field not a string".to_string())
+                    })?
+                    .to_string(),
+            ),
+            path,
+        )?;
+        if let Some(parent) = db_path.parent() {
+            create_dir_all(parent).map_err(|source| CoreError::FsError(source.to_string()))?;
+        }
+        info!("Opening sqlite store at {db_path:?}...");
+        let mut conn = Connection::open(&db_path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        upgrade_schema(&mut conn)?;
+        Ok(Self {
+            db_path,
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.db_path
+    }
+
+    /// run `f` with the shared connection locked, open a transaction inside if needed
+    pub fn with_conn<R, F>(&self, f: F) -> CoreResult<R>
+    where
+        F: FnOnce(&mut Connection) -> CoreResult<R>,
+    {
+        let mut conn = self.conn.lock().unwrap();
+        f(&mut conn)
+    }
+
+    pub fn get_meta(&self, key: &str) -> CoreResult<Option<String>> {
+        self.with_conn(|conn| {
+            Ok(conn
+                .query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| {
+                    row.get(0)
+                })
+                .optional()?)
+        })
+    }
+
+    pub fn set_meta(&self, key: &str, value: &str) -> CoreResult<()> {
+        self.with_conn(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+                params![key, value],
+            )?;
+            Ok(())
+        })
+    }
+}
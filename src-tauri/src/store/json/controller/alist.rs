@@ -1,53 +1,36 @@
 use crate::{
-    misc::{
-        config::get_global,
-        error::{CoreError, CoreResult},
-    },
-    store::json::{
-        entity::{
+    misc::error::CoreResult,
+    store::{
+        alist_repo,
+        json::entity::{
             alist::{Aelement, Alist},
             list::Playlist,
+            song::Song,
         },
-        op::{alist::AlistOp, sm::StoreManager},
+        repo::AlistRepo,
     },
 };
 use log::info;
-use std::{
-    collections::HashSet,
-    path::PathBuf,
-    sync::{Arc, Mutex},
-};
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
 
 #[derive(Clone)]
 pub struct AlistController {
-    pub op: Arc<AlistOp>,
+    pub op: Arc<dyn AlistRepo>,
 }
 
 #[allow(unused)]
 impl AlistController {
     pub fn new() -> CoreResult<Self> {
-        let config = get_global();
-        let op = Arc::new(AlistOp {
-            sm: Arc::new(Mutex::new(StoreManager::<HashSet<Alist>>::new(
-                config
-                    .get("alist_store")
-                    .ok_or_else(|| {
-                        CoreError::OtherError(
-                            "missing 'store.alist_store' key in Config".to_string(),
-                        )
-                    })?
-                    .as_str()
-                    .ok_or_else(|| {
-                        CoreError::OtherError("`store.alist_store` field not a string".to_string())
-                    })?
-                    .to_string(),
-            )?)),
-        });
+        Ok(Self::with_repo(alist_repo()?))
+    }
+
+    /// build on an explicit backend, e.g. `store::memory` in tests
+    pub fn with_repo(op: Arc<dyn AlistRepo>) -> Self {
         info!("AlistController initialized");
-        Ok(Self { op })
+        Self { op }
     }
 
-    pub fn get_op(&self) -> Arc<AlistOp> {
+    pub fn get_op(&self) -> Arc<dyn AlistRepo> {
         self.op.clone()
     }
 
@@ -60,11 +43,7 @@ impl AlistController {
     }
 
     pub fn get_alists(&self, names: Vec<String>) -> CoreResult<Vec<Alist>> {
-        let alists = names
-            .into_iter()
-            .map(|name| self.op.locate(name))
-            .collect::<CoreResult<Vec<Alist>>>()?;
-        Ok(alists)
+        self.op.locate_batch(names)
     }
 
     pub fn add(&self, alist: &Alist) -> CoreResult<()> {
@@ -122,15 +101,122 @@ impl AlistController {
         self.op.remove_batch(names)
     }
 
-    pub fn list_all_songs(&self, name: String) -> CoreResult<Vec<PathBuf>> {
-        self.op.list_all_songs(name)
+    /// `library` resolves the ids / paths held by the elements
+    pub fn list_all_songs(&self, name: String, library: &[Song]) -> CoreResult<Vec<PathBuf>> {
+        self.op.list_all_songs(name, library)
+    }
+
+    pub fn rewrite_song_paths(&self, moves: &HashMap<PathBuf, PathBuf>) -> CoreResult<()> {
+        self.op.rewrite_song_paths(moves)
     }
 
     pub fn list_all_elements(&self, name: String) -> CoreResult<Vec<Aelement>> {
         self.op.list_all_elements(name)
     }
 
-    pub fn freeze(&self, name: String) -> CoreResult<Playlist> {
-        self.op.freeze(name)
+    pub fn freeze(&self, name: String, library: &[Song]) -> CoreResult<Playlist> {
+        self.op.freeze(name, library)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{json::entity::release::Release, memory::alist::AlistMemOp};
+
+    fn song(id: &str) -> Song {
+        Song {
+            id: id.to_string(),
+            ..Song::sample(format!("/music/{id}.flac").into())
+        }
+    }
+
+    fn controller(elements: Vec<Aelement>) -> AlistController {
+        AlistController::with_repo(Arc::new(AlistMemOp::new(vec![Alist {
+            name: "a".to_string(),
+            created_at: String::new(),
+            elements,
+        }])))
+    }
+
+    fn release(paths: &[&str]) -> Aelement {
+        Aelement::Release(Release {
+            title: "r".to_string(),
+            artist: None,
+            songs: paths.iter().map(|path| path.to_string()).collect(),
+        })
+    }
+
+    #[test]
+    fn list_all_songs_resolves_ids_and_paths_in_order() {
+        let library = vec![song("1"), song("2"), song("3")];
+        let c = controller(vec![
+            Aelement::Playlist(Playlist::new(
+                "p".to_string(),
+                String::new(),
+                vec!["3".into(), "gone".into()],
+            )),
+            release(&["/music/1.flac", "/music/missing.flac"]),
+            Aelement::Song(song("2")),
+        ]);
+        let paths = c.list_all_songs("a".to_string(), &library).unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/music/3.flac"),
+                PathBuf::from("/music/1.flac"),
+                PathBuf::from("/music/2.flac"),
+            ]
+        );
+        let frozen = c.freeze("a".to_string(), &library).unwrap();
+        assert_eq!(frozen.songs, vec!["3", "1", "2"]);
+    }
+
+    #[test]
+    fn shared_id_resolves_to_the_smallest_path() {
+        let copy = |path: &str| Song {
+            id: "c1".to_string(),
+            ..Song::sample(path.into())
+        };
+        let library = vec![copy("/music/z/1.flac"), copy("/music/a/1.flac")];
+        let c = controller(vec![Aelement::Playlist(Playlist::new(
+            "p".to_string(),
+            String::new(),
+            vec!["c1".into()],
+        ))]);
+        let paths = c.list_all_songs("a".to_string(), &library).unwrap();
+        assert_eq!(paths, vec![PathBuf::from("/music/a/1.flac")]);
+    }
+
+    #[test]
+    fn rewrite_song_paths_follows_moves() {
+        let c = controller(vec![release(&["/music/1.flac"]), Aelement::Song(song("2"))]);
+        let moves = HashMap::from([
+            (
+                PathBuf::from("/music/1.flac"),
+                PathBuf::from("/moved/1.flac"),
+            ),
+            (
+                PathBuf::from("/music/2.flac"),
+                PathBuf::from("/moved/2.flac"),
+            ),
+        ]);
+        c.rewrite_song_paths(&moves).unwrap();
+        match c.list_all_elements("a".to_string()).unwrap().as_slice() {
+            [Aelement::Release(release), Aelement::Song(song)] => {
+                assert_eq!(release.songs, vec!["/moved/1.flac"]);
+                assert_eq!(song.path, PathBuf::from("/moved/2.flac"));
+            }
+            other => panic!("unexpected elements {other:?}"),
+        }
+    }
+
+    #[test]
+    fn remove_elements_out_of_bounds_changes_nothing() {
+        let c = controller(vec![Aelement::Song(song("1")), Aelement::Song(song("2"))]);
+        assert!(c.remove_elements("a".to_string(), vec![0, 5]).is_err());
+        assert_eq!(c.list_all_elements("a".to_string()).unwrap().len(), 2);
+        c.remove_elements("a".to_string(), vec![0, 1]).unwrap();
+        assert!(c.list_all_elements("a".to_string()).unwrap().is_empty());
     }
 }
@@ -1,52 +1,35 @@
 use crate::{
-    misc::{
-        config::get_global,
-        error::{CoreError, CoreResult},
-    },
-    store::json::{
-        entity::{list::Playlist, song::Song},
-        op::{list::PlaylistOp, sm::StoreManager},
+    misc::error::CoreResult,
+    store::{
+        json::entity::{
+            list::Playlist,
+            song::{Song, TrackId},
+        },
+        playlist_repo,
+        repo::PlaylistRepo,
     },
 };
 use log::info;
-use std::{
-    collections::HashSet,
-    path::PathBuf,
-    sync::{Arc, Mutex},
-};
+use std::{collections::HashMap, sync::Arc};
 
 #[derive(Clone)]
 pub struct PlaylistController {
-    pub op: Arc<PlaylistOp>,
+    pub op: Arc<dyn PlaylistRepo>,
 }
 
 #[allow(unused)]
 impl PlaylistController {
     pub fn new() -> CoreResult<Self> {
-        let config = get_global();
-        let op = Arc::new(PlaylistOp {
-            sm: Arc::new(Mutex::new(StoreManager::<HashSet<Playlist>>::new(
-                config
-                    .get("playlist_store")
-                    .ok_or_else(|| {
-                        CoreError::OtherError(
-                            "missing 'store.playlist_store' key in Config".to_string(),
-                        )
-                    })?
-                    .as_str()
-                    .ok_or_else(|| {
-                        CoreError::OtherError(
-                            "`store.playlist_store` field not a string".to_string(),
-                        )
-                    })?
-                    .to_string(),
-            )?)),
-        });
+        Ok(Self::with_repo(playlist_repo()?))
+    }
+
+    /// build on an explicit backend, e.g. `store::memory` in tests
+    pub fn with_repo(op: Arc<dyn PlaylistRepo>) -> Self {
         info!("PlaylistController initialized");
-        Ok(Self { op })
+        Self { op }
     }
 
-    pub fn get_op(&self) -> Arc<PlaylistOp> {
+    pub fn get_op(&self) -> Arc<dyn PlaylistRepo> {
         self.op.clone()
     }
 
@@ -59,11 +42,7 @@ impl PlaylistController {
     }
 
     pub fn get_playlists(&self, names: Vec<String>) -> CoreResult<Vec<Playlist>> {
-        let playlists = names
-            .into_iter()
-            .map(|name| self.op.locate(name))
-            .collect::<CoreResult<Vec<Playlist>>>()?;
-        Ok(playlists)
+        self.op.locate_batch(names)
     }
 
     pub fn add(&self, playlist: &Playlist) -> CoreResult<()> {
@@ -94,8 +73,8 @@ impl PlaylistController {
         self.op.remove_songs_all(name, songs)
     }
 
-    pub fn remove_song_path_all(&self, song_paths: Vec<PathBuf>) -> CoreResult<()> {
-        self.op.remove_song_path_all(song_paths)
+    pub fn remove_song_ids_all(&self, song_ids: Vec<TrackId>) -> CoreResult<()> {
+        self.op.remove_song_ids_all(song_ids)
     }
 
     pub fn remove_songs(&self, name: String, index: Vec<usize>) -> CoreResult<()> {
@@ -124,4 +103,133 @@ impl PlaylistController {
     pub fn unique(&self, name: String) -> CoreResult<()> {
         self.op.unique(name)
     }
+
+    /// point entries of the `ids` keys at their values, dropping the ones whose target
+    /// is in the playlist already; returns how many playlists changed
+    pub fn relink_ids(&self, ids: &HashMap<TrackId, TrackId>) -> CoreResult<usize> {
+        let mut changed = 0;
+        self.op
+            .list_all()?
+            .into_iter()
+            .try_for_each(|mut playlist| {
+                if !playlist.songs.iter().any(|id| ids.contains_key(id)) {
+                    return Ok(());
+                }
+                let present = playlist.songs.clone();
+                playlist.songs = playlist
+                    .songs
+                    .into_iter()
+                    .filter_map(|id| match ids.get(&id) {
+                        Some(to) if present.contains(to) => None,
+                        Some(to) => Some(to.clone()),
+                        None => Some(id),
+                    })
+                    .collect();
+                changed += 1;
+                self.op.modify(&playlist)
+            })?;
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::list::PlaylistMemOp;
+
+    fn song(id: &str) -> Song {
+        Song {
+            id: id.to_string(),
+            ..Song::sample(format!("/music/{id}.flac").into())
+        }
+    }
+
+    fn controller(playlists: Vec<Playlist>) -> PlaylistController {
+        PlaylistController::with_repo(Arc::new(PlaylistMemOp::new(playlists)))
+    }
+
+    fn songs_of(controller: &PlaylistController, name: &str) -> Vec<TrackId> {
+        controller.get_playlist(name.to_string()).unwrap().songs
+    }
+
+    #[test]
+    fn add_and_remove_songs() {
+        let c = controller(vec![]);
+        c.create_playlist("a".to_string()).unwrap();
+        c.add_songs("a".to_string(), vec![song("1"), song("2"), song("1")])
+            .unwrap();
+        c.remove_songs("a".to_string(), vec![0]).unwrap();
+        assert_eq!(songs_of(&c, "a"), vec!["2", "1"]);
+        c.remove_song_all("a".to_string(), song("1")).unwrap();
+        assert_eq!(songs_of(&c, "a"), vec!["2"]);
+    }
+
+    #[test]
+    fn unique_keeps_first_occurrence() {
+        let c = controller(vec![Playlist::new(
+            "a".to_string(),
+            String::new(),
+            vec!["2".into(), "1".into(), "2".into(), "3".into(), "1".into()],
+        )]);
+        c.unique("a".to_string()).unwrap();
+        assert_eq!(songs_of(&c, "a"), vec!["2", "1", "3"]);
+    }
+
+    #[test]
+    fn rename_onto_existing_name_fails() {
+        let c = controller(vec![
+            Playlist::sample("a".to_string()),
+            Playlist::sample("b".to_string()),
+        ]);
+        assert!(c.rename("a".to_string(), "b".to_string()).is_err());
+        c.rename("a".to_string(), "c".to_string()).unwrap();
+        let names = c
+            .get_all_playlists()
+            .unwrap()
+            .into_iter()
+            .map(|playlist| playlist.name)
+            .collect::<Vec<String>>();
+        assert_eq!(names, vec!["b", "c"]);
+    }
+
+    #[test]
+    fn modify_unknown_playlist_is_not_inserted() {
+        let c = controller(vec![Playlist::sample("a".to_string())]);
+        assert!(c.op.modify(&Playlist::sample("b".to_string())).is_err());
+        assert!(c.get_playlist("b".to_string()).is_err());
+        assert_eq!(c.get_all_playlists().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn remove_song_ids_from_every_playlist() {
+        let c = controller(vec![
+            Playlist::new("a".to_string(), String::new(), vec!["1".into(), "2".into()]),
+            Playlist::new("b".to_string(), String::new(), vec!["2".into(), "3".into()]),
+        ]);
+        c.remove_song_ids_all(vec!["2".to_string()]).unwrap();
+        assert_eq!(songs_of(&c, "a"), vec!["1"]);
+        assert_eq!(songs_of(&c, "b"), vec!["3"]);
+    }
+
+    #[test]
+    fn relink_ids_drops_targets_already_present() {
+        let c = controller(vec![
+            Playlist::new(
+                "a".to_string(),
+                String::new(),
+                vec!["old".into(), "keep".into()],
+            ),
+            Playlist::new(
+                "b".to_string(),
+                String::new(),
+                vec!["old".into(), "x".into()],
+            ),
+            Playlist::new("c".to_string(), String::new(), vec!["x".into()]),
+        ]);
+        let ids = HashMap::from([("old".to_string(), "keep".to_string())]);
+        assert_eq!(c.relink_ids(&ids).unwrap(), 2);
+        assert_eq!(songs_of(&c, "a"), vec!["keep"]);
+        assert_eq!(songs_of(&c, "b"), vec!["keep", "x"]);
+        assert_eq!(songs_of(&c, "c"), vec!["x"]);
+    }
 }
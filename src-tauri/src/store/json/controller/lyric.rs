@@ -5,6 +5,7 @@ use crate::{
 };
 use log::info;
 
+#[derive(Clone)]
 pub struct LyricController {
     op: LyricOp,
 }
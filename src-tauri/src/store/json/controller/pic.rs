@@ -4,6 +4,7 @@ use crate::{
 };
 use log::info;
 
+#[derive(Clone)]
 pub struct PicController {
     op: PicOp,
 }
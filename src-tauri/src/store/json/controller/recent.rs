@@ -1,56 +1,35 @@
 use crate::{
-    misc::{
-        config::get_global,
-        error::{CoreError, CoreResult},
-    },
-    store::json::{
-        entity::{
+    misc::error::CoreResult,
+    store::{
+        json::entity::{
             recent::{Recent, Recents},
-            song::Song,
+            song::{Song, TrackId},
         },
-        op::{recent::RecentOp, sm::StoreManager},
+        recent_repo,
+        repo::RecentRepo,
     },
 };
 use log::info;
-use std::{
-    path::PathBuf,
-    sync::{Arc, Mutex},
-};
+use std::{collections::HashMap, sync::Arc};
 
 #[derive(Clone)]
 pub struct RecentController {
-    pub op: Arc<RecentOp>,
+    pub op: Arc<dyn RecentRepo>,
 }
 
 #[allow(unused)]
 impl RecentController {
     pub fn new() -> CoreResult<Self> {
-        let config = get_global();
-        let op = Arc::new(RecentOp {
-            sm: Arc::new(Mutex::new(StoreManager::<Recents>::new(
-                // config
-                //     .get("store.recent_store")
-                //     .and_then(|v| v.as_str().map(|s| s.to_string()))
-                //     .unwrap(),
-                config
-                    .get("recent_store")
-                    .ok_or_else(|| {
-                        CoreError::OtherError(
-                            "missing 'store.recent_store' key in Config".to_string(),
-                        )
-                    })?
-                    .as_str()
-                    .ok_or_else(|| {
-                        CoreError::OtherError("`store.recent_store` field not a string".to_string())
-                    })?
-                    .to_string(),
-            )?)),
-        });
+        Ok(Self::with_repo(recent_repo()?))
+    }
+
+    /// build on an explicit backend, e.g. `store::memory` in tests
+    pub fn with_repo(op: Arc<dyn RecentRepo>) -> Self {
         info!("RecentController initialized");
-        Ok(Self { op })
+        Self { op }
     }
 
-    pub fn get_op(&self) -> Arc<RecentOp> {
+    pub fn get_op(&self) -> Arc<dyn RecentRepo> {
         self.op.clone()
     }
 
@@ -70,6 +49,23 @@ impl RecentController {
         self.op.insert(recent)
     }
 
+    /// point recents of the `ids` keys at their values, returns how many entries changed
+    pub fn relink_ids(&self, ids: &HashMap<TrackId, TrackId>) -> CoreResult<usize> {
+        let mut recents = self.op.list_all()?;
+        let mut changed = 0;
+        recents.iter_mut().for_each(|recent| {
+            if let Some(id) = ids.get(&recent.song) {
+                recent.song = id.clone();
+                changed += 1;
+            }
+        });
+        if changed > 0 {
+            self.op.clear()?;
+            self.op.insert_batch(&recents)?;
+        }
+        Ok(changed)
+    }
+
     pub fn remove_by_index(&self, index: usize) -> CoreResult<()> {
         self.op.remove_by_index(index)
     }
@@ -86,11 +82,62 @@ impl RecentController {
         self.op.remove_by_songs_all(songs)
     }
 
-    pub fn remove_by_song_path(&self, song_path: &PathBuf) -> CoreResult<()> {
-        self.op.remove_by_song_path(song_path)
+    pub fn remove_by_song_ids(&self, song_ids: Vec<&TrackId>) -> CoreResult<()> {
+        self.op.remove_by_song_ids(song_ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::recent::RecentMemOp;
+
+    fn recent(id: &str, at: &str) -> Recent {
+        Recent::new(id.to_string(), Some(at.to_string()))
+    }
+
+    fn ids(controller: &RecentController) -> Vec<TrackId> {
+        controller
+            .get_all_recents()
+            .unwrap()
+            .into_iter()
+            .map(|recent| recent.song)
+            .collect()
+    }
+
+    #[test]
+    fn newest_first() {
+        let c = RecentController::with_repo(Arc::new(RecentMemOp::default()));
+        c.insert(&recent("1", "2024-01-01")).unwrap();
+        c.insert(&recent("2", "2024-01-02")).unwrap();
+        c.insert_batch(&vec![recent("4", "2024-01-04"), recent("3", "2024-01-03")])
+            .unwrap();
+        assert_eq!(ids(&c), vec!["4", "3", "2", "1"]);
+        c.remove_by_index(1).unwrap();
+        assert_eq!(ids(&c), vec!["4", "2", "1"]);
+        assert!(c.remove_by_index(3).is_err());
+    }
+
+    #[test]
+    fn remove_by_song_ids_drops_every_entry() {
+        let c = RecentController::with_repo(Arc::new(RecentMemOp::new(vec![
+            recent("1", "2024-01-03"),
+            recent("2", "2024-01-02"),
+            recent("1", "2024-01-01"),
+        ])));
+        c.remove_by_song_ids(vec![&"1".to_string()]).unwrap();
+        assert_eq!(ids(&c), vec!["2"]);
     }
 
-    pub fn remove_by_song_paths(&self, song_paths: Vec<&PathBuf>) -> CoreResult<()> {
-        self.op.remove_by_song_paths(song_paths)
+    #[test]
+    fn relink_ids_keeps_order() {
+        let c = RecentController::with_repo(Arc::new(RecentMemOp::new(vec![
+            recent("old", "2024-01-03"),
+            recent("2", "2024-01-02"),
+            recent("old", "2024-01-01"),
+        ])));
+        let map = HashMap::from([("old".to_string(), "new".to_string())]);
+        assert_eq!(c.relink_ids(&map).unwrap(), 2);
+        assert_eq!(ids(&c), vec!["new", "2", "new"]);
     }
 }
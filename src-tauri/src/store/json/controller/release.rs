@@ -1,18 +1,16 @@
 use crate::{
-    misc::{
-        config::get_global,
-        error::{CoreError, CoreResult},
-    },
-    store::json::{
-        entity::{release::Release, song::Song},
-        op::{release::ReleaseOp, sm::StoreManager},
+    misc::error::CoreResult,
+    store::{
+        json::{
+            entity::{release::Release, song::Song},
+            op::release::ReleaseOp,
+        },
+        repo::SongRepo,
+        song_repo,
     },
 };
 use log::info;
-use std::{
-    collections::HashSet,
-    sync::{Arc, Mutex},
-};
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct ReleaseController {
@@ -22,29 +20,14 @@ pub struct ReleaseController {
 #[allow(unused)]
 impl ReleaseController {
     pub fn new() -> CoreResult<Self> {
-        let config = get_global();
-        let op = Arc::new(ReleaseOp {
-            sm: Arc::new(Mutex::new(StoreManager::<HashSet<Song>>::new(
-                // config
-                //     .get("store.song_store")
-                //     .and_then(|v| v.as_str().map(|s| s.to_string()))
-                //     .unwrap(),
-                config
-                    .get("song_store")
-                    .ok_or_else(|| {
-                        CoreError::OtherError(
-                            "missing 'store.song_store' key in Config".to_string(),
-                        )
-                    })?
-                    .as_str()
-                    .ok_or_else(|| {
-                        CoreError::OtherError("`store.song_store` field not a string".to_string())
-                    })?
-                    .to_string(),
-            )?)),
-        });
+        Ok(Self::with_repo(song_repo()?))
+    }
+
+    /// build on an explicit song backend, e.g. `store::memory` in tests
+    pub fn with_repo(songs: Arc<dyn SongRepo>) -> Self {
+        let op = Arc::new(ReleaseOp { songs });
         info!("ReleaseController initialized");
-        Ok(Self { op })
+        Self { op }
     }
 
     pub fn get_op(&self) -> Arc<ReleaseOp> {
@@ -4,160 +4,396 @@ use crate::{
         error::{CoreError, CoreResult},
         utils::get_time,
     },
-    store::json::{
-        entity::song::Song,
-        op::{sm::StoreManager, song::SongOp},
+    store::{
+        json::entity::song::{Song, TrackId},
+        repo::SongRepo,
+        song_repo,
     },
 };
-use log::{debug, error, info, warn};
+use lofty::error::ErrorKind;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashSet,
+    cmp::min,
+    collections::{HashMap, HashSet},
     fs::remove_file,
     path::PathBuf,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
 };
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanPhase {
+    /// checking cached records against file mtimes
+    #[default]
+    Cached,
+    /// reading tags of new / changed files
+    Parse,
+    Save,
+}
+
+/// reported through `scan_progress`, `processed` / `total` count the current phase
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanProgress {
+    pub phase: ScanPhase,
+    pub processed: usize,
+    pub total: usize,
+    pub added: usize,
+    pub unchanged: usize,
+    pub expired: usize,
+    pub removed: usize,
+    pub failed: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureReason {
+    /// missing, no permission, io error while reading
+    Unreadable,
+    /// not a container / codec lofty knows
+    Unsupported,
+    /// known format but truncated or inconsistent
+    Malformed,
+    /// the audio is fine, its tags are not
+    BadTags,
+    Other,
+}
+
+/// a file a scan had to skip
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanFailure {
+    pub path: PathBuf,
+    pub reason: FailureReason,
+    /// the error as lofty / io reported it
+    pub message: String,
+}
+
+impl ScanFailure {
+    pub fn new(path: &PathBuf, e: &CoreError) -> Self {
+        let reason = match e {
+            CoreError::IoError(_) | CoreError::FsError(_) => FailureReason::Unreadable,
+            CoreError::LoftyError(e) => match e.kind() {
+                ErrorKind::Io(_) => FailureReason::Unreadable,
+                ErrorKind::UnknownFormat => FailureReason::Unsupported,
+                ErrorKind::NotEnoughData
+                | ErrorKind::SizeMismatch
+                | ErrorKind::TooMuchData
+                | ErrorKind::BadAtom(_)
+                | ErrorKind::FileDecoding(_) => FailureReason::Malformed,
+                _ => FailureReason::BadTags,
+            },
+            _ => FailureReason::Other,
+        };
+        Self {
+            path: path.clone(),
+            reason,
+            message: e.to_string(),
+        }
+    }
+}
+
+/// what a scan hands back: the records it saved and the files it skipped
+#[derive(Debug, Clone, Default)]
+pub struct ScanResult {
+    pub songs: Vec<Song>,
+    pub failures: Vec<ScanFailure>,
+    /// false when cancelled before every file was parsed
+    pub complete: bool,
+}
+
+/// cancel / pause switches of a running scan, checked by the tag reading workers
+#[derive(Debug, Default)]
+pub struct ScanControl {
+    cancel: AtomicBool,
+    pause: AtomicBool,
+}
+
+#[allow(unused)]
+impl ScanControl {
+    pub fn cancel(&self) {
+        self.cancel.store(true, AtomicOrdering::Relaxed);
+    }
+
+    pub fn pause(&self) {
+        self.pause.store(true, AtomicOrdering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.pause.store(false, AtomicOrdering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        self.cancel.store(false, AtomicOrdering::Relaxed);
+        self.pause.store(false, AtomicOrdering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(AtomicOrdering::Relaxed)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.pause.load(AtomicOrdering::Relaxed)
+    }
+
+    /// block while paused, `false` once cancelled
+    pub fn wait(&self) -> bool {
+        while self.is_paused() && !self.is_cancelled() {
+            thread::sleep(Duration::from_millis(100));
+        }
+        !self.is_cancelled()
+    }
+}
+
+/// size of the tag reading pool, `scan_workers` in Config, 0 picks from the cpu count
+fn scan_workers() -> usize {
+    match get_global()
+        .get("scan_workers")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(0)
+    {
+        0 => thread::available_parallelism()
+            .map(|n| min(n.get(), 8))
+            .unwrap_or(4),
+        n => n as usize,
+    }
+}
+
 #[derive(Clone)]
 pub struct SongController {
-    pub op: Arc<SongOp>,
+    pub op: Arc<dyn SongRepo>,
 }
 
 #[allow(unused)]
 impl SongController {
     /// new new new!
     pub fn new() -> CoreResult<Self> {
-        let config = get_global();
-        print!("112");
-        let op = Arc::new(SongOp {
-            sm: Arc::new(Mutex::new(StoreManager::<HashSet<Song>>::new(
-                // config
-                //     // .get("store.song_store")
-                //     .get("store")
-                //     .and_then(|v| v.as_str().map(|s| s.to_string()))
-                //     .unwrap(),
-                config
-                    .get("song_store")
-                    .ok_or_else(|| {
-                        CoreError::OtherError(
-                            "missing 'store.song_store' key in Config".to_string(),
-                        )
-                    })?
-                    .as_str()
-                    .ok_or_else(|| {
-                        CoreError::OtherError("`store.song_store` field not a string".to_string())
-                    })?
-                    .to_string(),
-            )?)),
-        });
-        print!("112");
+        Ok(Self::with_repo(song_repo()?))
+    }
+
+    /// build on an explicit backend, e.g. `store::memory` in tests
+    pub fn with_repo(op: Arc<dyn SongRepo>) -> Self {
         info!("SongController initialized");
-        Ok(Self { op })
+        Self { op }
     }
 
-    pub fn get_op(&self) -> Arc<SongOp> {
+    pub fn get_op(&self) -> Arc<dyn SongRepo> {
         self.op.clone()
     }
 
     /// get song information from cache and update store
     pub fn get_from_cache(&self, files: Vec<PathBuf>) -> CoreResult<Vec<Song>> {
-        self.get_from_cache_with_progress(files, &|_, _| {})
+        self.get_from_cache_with_progress(files, &[], &|_| {}, &ScanControl::default())
+            .map(|result| result.songs)
     }
 
-    /// get song information from cache with progress callback
+    /// get song information from cache with progress callback.
+    /// new / changed files are parsed on a worker pool. the store is checkpointed along the
+    /// way and on cancel, together with records still waiting to be re-parsed or re-linked,
+    /// so the next scan only parses what is left.
+    /// records under `offline` roots are kept as they are, their files are just out of reach
     pub fn get_from_cache_with_progress<F>(
         &self,
         files: Vec<PathBuf>,
+        offline: &[PathBuf],
         progress_callback: &F,
-    ) -> CoreResult<Vec<Song>>
+        control: &ScanControl,
+    ) -> CoreResult<ScanResult>
     where
-        F: Fn(usize, usize),
+        F: Fn(&ScanProgress),
     {
-        let cached_songs = self.op.list_all()?;
-        let cached_files = cached_songs
+        let mut cached = self
+            .op
+            .list_all()?
             .into_iter()
-            .map(|song| song.path.clone())
-            .collect::<Vec<PathBuf>>();
-        let (added_files, common_files, removed_files) = files.into_iter().fold(
-            (
-                Vec::<PathBuf>::new(),
-                Vec::<PathBuf>::new(),
-                cached_files.clone(),
-            ),
-            |(mut added, mut common, mut removed), file| {
-                if removed.contains(&file) {
-                    common.push(file.clone());
-                    removed.retain(|f| f != &file);
-                } else {
-                    added.push(file.clone());
+            .map(|song| (song.path.clone(), song))
+            .collect::<HashMap<PathBuf, Song>>();
+        let kept = cached
+            .extract_if(|path, _| offline.iter().any(|root| path.starts_with(root)))
+            .map(|(_, song)| song)
+            .collect::<Vec<Song>>();
+        if !kept.is_empty() {
+            info!("Keeping {} songs of offline roots {offline:?}", kept.len());
+        }
+        let (added_files, common_songs) = files.into_iter().fold(
+            (Vec::<PathBuf>::new(), Vec::<Song>::new()),
+            |(mut added, mut common), file| {
+                match cached.remove(&file) {
+                    Some(song) => common.push(song),
+                    None => added.push(file),
                 }
-                (added, common, removed)
+                (added, common)
             },
         );
+        // 剩下的就是磁盘上已经没有的旧记录, 按 id 索引, 新文件 id 相同说明是被移动 / 改名了
+        let removed_songs: HashMap<TrackId, Song> = cached
+            .into_values()
+            .filter(|song| !song.id.is_empty())
+            .map(|song| (song.id.clone(), song))
+            .collect();
         debug!("added files: {added_files:#?}");
-        debug!("common files: {common_files:#?}");
-        debug!("removed files: {removed_files:#?}");
-        let (mut song_infos, expired_song_infos) = common_files.iter().fold(
+        debug!(
+            "removed files: {:#?}",
+            removed_songs.values().map(|s| &s.path).collect::<Vec<_>>()
+        );
+
+        let mut progress = ScanProgress {
+            phase: ScanPhase::Cached,
+            total: common_songs.len(),
+            added: added_files.len(),
+            removed: removed_songs.len(),
+            ..Default::default()
+        };
+        progress_callback(&progress);
+        let (mut song_infos, expired_songs) = common_songs.into_iter().fold(
             (Vec::<Song>::new(), Vec::<Song>::new()),
-            |(mut valid_songs, mut expired_songs), file| {
-                match self.op.locate(file) {
-                    Ok(song) => match (song.updated_at == get_time(file).1) {
-                        true => valid_songs.push(song),
-                        false => {
-                            expired_songs.push(song.clone());
-                            match Song::from_path(file, song.score) {
-                                Ok(updated_song) => valid_songs.push(updated_song),
-                                Err(e) => {
-                                    warn!("Failed to re-parse updated file {file:?}: {e:?}");
-                                }
-                            }
-                        }
-                    },
-                    Err(e) => {
-                        error!("Failed to locate cached song {file:?}: {e:?}");
+            |(mut valid_songs, mut expired_songs), mut song| {
+                match song.updated_at == get_time(&song.path).1 {
+                    true => {
+                        song.ensure_id();
+                        valid_songs.push(song)
                     }
+                    false => expired_songs.push(song),
                 }
                 (valid_songs, expired_songs)
             },
         );
+        song_infos.extend(kept);
+        progress.processed = progress.total;
+        progress.unchanged = song_infos.len();
+        progress.expired = expired_songs.len();
+        progress_callback(&progress);
 
-        const BATCH_SIZE: usize = 50;
-        let total_new_files = added_files.len();
-        let mut failed_files = Vec::new();
-        let mut processed = 0;
-
-        info!("Processing {total_new_files} new files in batches of {BATCH_SIZE}");
+        // 过期的带着旧记录一起重新解析, 新文件没有
+        let jobs = expired_songs
+            .into_iter()
+            .map(|song| (song.path.clone(), Some(song)))
+            .chain(added_files.into_iter().map(|file| (file, None)))
+            .collect::<Vec<(PathBuf, Option<Song>)>>();
+        let workers = scan_workers();
+        progress.phase = ScanPhase::Parse;
+        progress.processed = 0;
+        progress.total = jobs.len();
+        progress_callback(&progress);
+        info!("Parsing {} files on {workers} workers", jobs.len());
 
-        for chunk in added_files.chunks(BATCH_SIZE) {
-            for file in chunk {
-                match Song::from_path(file, None) {
-                    Ok(song) => song_infos.push(song),
-                    Err(e) => {
+        const BATCH_SIZE: usize = 50;
+        let next = AtomicUsize::new(0);
+        let mut done = vec![false; jobs.len()];
+        let mut relinked = HashSet::<TrackId>::new();
+        let mut failures = Vec::<ScanFailure>::new();
+        // 存档时带上还没处理到的旧记录: 过期了还没重新解析的, 以及还没被认领的 (可能是被移动了)
+        let unparsed = |done: &[bool]| {
+            jobs.iter()
+                .zip(done)
+                .filter_map(|((_, old), done)| (!done).then_some(old.clone()).flatten())
+                .collect::<Vec<Song>>()
+        };
+        let unclaimed = |relinked: &HashSet<TrackId>| {
+            removed_songs
+                .values()
+                .filter(|song| !relinked.contains(&song.id))
+                .cloned()
+                .collect::<Vec<Song>>()
+        };
+        let (tx, rx) = mpsc::channel::<(usize, CoreResult<Song>)>();
+        thread::scope(|scope| {
+            (0..workers).for_each(|_| {
+                let tx = tx.clone();
+                let (next, jobs) = (&next, &jobs);
+                scope.spawn(move || loop {
+                    if !control.wait() {
+                        break;
+                    }
+                    let job = next.fetch_add(1, AtomicOrdering::Relaxed);
+                    let Some((file, old)) = jobs.get(job) else {
+                        break;
+                    };
+                    let score = old.as_ref().and_then(|song| song.score.clone());
+                    if tx.send((job, Song::from_path(file, score))).is_err() {
+                        break;
+                    }
+                });
+            });
+            drop(tx);
+            // 结果都在当前线程收, 进度回调和存盘不用跨线程
+            rx.into_iter().for_each(|(job, parsed)| {
+                let (file, old) = &jobs[job];
+                done[job] = true;
+                match (parsed, old) {
+                    (Ok(song), Some(old)) => song_infos.push(song.inherit(old)),
+                    (Ok(song), None) => match removed_songs.get(&song.id) {
+                        Some(old) => {
+                            info!("Re-linked moved file {:?} -> {file:?}", old.path);
+                            relinked.insert(old.id.clone());
+                            song_infos.push(song.inherit(old));
+                        }
+                        None => song_infos.push(song),
+                    },
+                    (Err(e), _) => {
                         warn!("Skipping file {file:?} due to parse error: {e:?}");
-                        failed_files.push(file.clone());
+                        failures.push(ScanFailure::new(file, &e));
+                        progress.failed += 1;
                     }
                 }
-                processed += 1;
-            }
-
-            progress_callback(processed, total_new_files);
+                progress.processed += 1;
+                if progress.processed % BATCH_SIZE == 0 || progress.processed == progress.total {
+                    progress_callback(&progress);
+                }
+                if progress.processed % (BATCH_SIZE * 5) == 0 {
+                    let mut checkpoint = song_infos.clone();
+                    checkpoint.extend(unparsed(&done));
+                    checkpoint.extend(unclaimed(&relinked));
+                    checkpoint.sort();
+                    if let Err(e) = self.op.save_all(&checkpoint) {
+                        warn!("Failed to save scan progress: {e:?}");
+                    }
+                    debug!(
+                        "Saved progress: {}/{} files",
+                        progress.processed, progress.total
+                    );
+                }
+            });
+        });
 
-            if processed % (BATCH_SIZE * 5) == 0 || processed == total_new_files {
-                song_infos.sort();
-                self.op.save_all(&song_infos);
-                debug!("Saved progress: {processed}/{total_new_files} files");
-            }
+        let cancelled = control.is_cancelled() && done.iter().any(|done| !done);
+        if cancelled {
+            info!(
+                "Scan cancelled after {}/{} files",
+                progress.processed, progress.total
+            );
         }
-
-        if !failed_files.is_empty() {
+        if progress.failed > 0 {
             info!(
                 "Failed to parse {} files. Check logs for details.",
-                failed_files.len()
+                progress.failed
             );
         }
 
+        progress.phase = ScanPhase::Save;
+        progress_callback(&progress);
+        // 取消时过期的旧记录先照旧用着, 时间对不上下次还会再解析;
+        // 没被认领的只留在存储里, 等下次扫描再认领
+        if cancelled {
+            song_infos.extend(unparsed(&done));
+            let mut saved = song_infos.clone();
+            saved.extend(unclaimed(&relinked));
+            saved.sort();
+            self.op.save_all(&saved)?;
+        }
         song_infos.sort();
-        self.op.save_all(&song_infos);
-        Ok(song_infos)
+        if !cancelled {
+            self.op.save_all(&song_infos)?;
+        }
+        Ok(ScanResult {
+            songs: song_infos,
+            failures,
+            complete: !cancelled,
+        })
     }
 
     pub fn get_all(&self) -> CoreResult<Vec<Song>> {
@@ -218,32 +454,29 @@ impl SongController {
 
     /// get song information by files from store without updating
     pub fn get_by_files(&self, files: Vec<PathBuf>) -> CoreResult<Vec<Song>> {
-        let songs = files
-            .into_iter()
-            .filter_map(|file| self.op.locate(&file).ok())
-            .collect();
-        Ok(songs)
+        self.op.locate_batch(&files)
     }
 
-    pub fn add_song_infos(&self, files: Vec<PathBuf>) -> CoreResult<()> {
+    /// files that could not be parsed are skipped and returned
+    pub fn add_song_infos(&self, files: Vec<PathBuf>) -> CoreResult<Vec<ScanFailure>> {
         let mut song_infos = Vec::new();
-        let mut failed_count = 0;
+        let mut failures = Vec::new();
         for file in files.iter() {
             match Song::from_path(file, None) {
                 Ok(song) => song_infos.push(song),
                 Err(e) => {
                     warn!("Failed to parse file {file:?}: {e:?}");
-                    failed_count += 1;
+                    failures.push(ScanFailure::new(file, &e));
                 }
             }
         }
-        if failed_count > 0 {
-            info!("Skipped {failed_count} files due to parse errors");
+        if !failures.is_empty() {
+            info!("Skipped {} files due to parse errors", failures.len());
         }
         if !song_infos.is_empty() {
             self.op.add_save(&song_infos)?;
         }
-        Ok(())
+        Ok(failures)
     }
 
     pub fn remove_song_infos(&self, songs: Vec<Song>) -> CoreResult<()> {
@@ -256,23 +489,46 @@ impl SongController {
         Ok(())
     }
 
+    /// swap the records at `from` for `songs`, which are the same tracks at their new paths
+    pub fn move_song_infos(&self, from: Vec<PathBuf>, songs: Vec<Song>) -> CoreResult<()> {
+        self.op.remove_by_files(&from)?;
+        self.op.add_save(&songs)?;
+        Ok(())
+    }
+
     pub fn update_song_info(&self, song: &Song) -> CoreResult<()> {
         self.op.modify(song)?;
         Ok(())
     }
 
     pub fn update_song_infos(&self, songs: Vec<Song>) -> CoreResult<()> {
-        songs.iter().try_for_each(|song| self.op.modify(song))?;
+        self.op.modify_batch(&songs)?;
         Ok(())
     }
 
-    pub fn update_song_infos_by_files(&self, files: Vec<PathBuf>) -> CoreResult<()> {
-        files.iter().try_for_each(|file| {
-            let old_score = self.op.locate(file).ok().and_then(|s| s.score);
-            let song = Song::from_path(file, old_score)?;
-            self.op.modify(&song)
-        })?;
-        Ok(())
+    /// files that fail to parse keep their old record and are returned
+    pub fn update_song_infos_by_files(&self, files: Vec<PathBuf>) -> CoreResult<Vec<ScanFailure>> {
+        let old_songs = self.op.locate_batch(&files)?;
+        let mut failures = Vec::new();
+        let songs = files
+            .iter()
+            .filter_map(|file| {
+                let old = old_songs.iter().find(|song| &song.path == file);
+                match Song::from_path(file, old.and_then(|song| song.score.clone())) {
+                    Ok(song) => Some(match old {
+                        Some(old) => song.inherit(old),
+                        None => song,
+                    }),
+                    Err(e) => {
+                        warn!("Failed to re-parse modified file {file:?}: {e:?}");
+                        failures.push(ScanFailure::new(file, &e));
+                        None
+                    }
+                }
+            })
+            .collect::<Vec<Song>>();
+        self.op.modify_batch(&songs)?;
+        Ok(failures)
     }
 
     pub fn get_song_info(&self, file: &PathBuf) -> CoreResult<Song> {
@@ -299,3 +555,68 @@ impl SongController {
             .try_for_each(|song| remove_file(song).map_err(|e| CoreError::FsError(e.to_string())))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::song::SongMemOp;
+
+    fn song(name: &str, artist: Option<&str>) -> Song {
+        Song {
+            id: name.to_string(),
+            title: Some(name.to_string()),
+            artist: artist.map(|a| a.to_string()),
+            ..Song::sample(format!("/music/{name}.flac").into())
+        }
+    }
+
+    fn controller(songs: Vec<Song>) -> SongController {
+        SongController::with_repo(Arc::new(SongMemOp::new(songs)))
+    }
+
+    #[test]
+    fn get_by_artist_and_unknown_artist() {
+        let c = controller(vec![
+            song("a", Some("x")),
+            song("b", None),
+            song("c", Some("x")),
+        ]);
+        let mut by_x = c.get_by_artist("x").unwrap();
+        by_x.sort();
+        assert_eq!(by_x, vec![song("a", None), song("c", None)]);
+        assert_eq!(c.get_unknown_artist_songs().unwrap(), vec![song("b", None)]);
+    }
+
+    #[test]
+    fn move_song_infos_swaps_records() {
+        let c = controller(vec![song("a", None), song("b", None)]);
+        let moved = Song {
+            path: "/moved/a.flac".into(),
+            ..song("a", None)
+        };
+        c.move_song_infos(vec!["/music/a.flac".into()], vec![moved.clone()])
+            .unwrap();
+        assert!(c.get_by_file(&"/music/a.flac".into()).is_err());
+        assert_eq!(c.get_by_file(&"/moved/a.flac".into()).unwrap().id, "a");
+    }
+
+    #[test]
+    fn failed_batch_leaves_store_untouched() {
+        let c = controller(vec![song("a", None)]);
+        let edited = Song {
+            title: Some("edited".to_string()),
+            ..song("a", None)
+        };
+        assert!(c
+            .update_song_infos(vec![edited, song("missing", None)])
+            .is_err());
+        assert_eq!(
+            c.get_by_file(&"/music/a.flac".into()).unwrap().title,
+            Some("a".to_string())
+        );
+        assert!(c
+            .remove_song_infos_by_files(vec!["/music/a.flac".into(), "/music/missing.flac".into()])
+            .is_err());
+        assert_eq!(c.get_all().unwrap().len(), 1);
+    }
+}
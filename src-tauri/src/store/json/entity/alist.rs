@@ -1,8 +1,15 @@
-use super::{artist::Artist, list::Playlist, release::Release, song::Song};
+use super::{
+    artist::Artist,
+    list::Playlist,
+    release::Release,
+    song::{songs_by_id, Song},
+};
 use serde::{Deserialize, Serialize};
 use std::{
     cmp::Ordering,
+    collections::HashMap,
     hash::{Hash, Hasher},
+    path::{Path, PathBuf},
 };
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, Eq)]
@@ -36,6 +43,73 @@ impl PartialEq for Alist {
     }
 }
 
+impl Alist {
+    /// flatten all elements into songs of `library`, keeping element order.
+    /// playlists hold track ids, releases / artists hold paths; unresolved entries are skipped
+    pub fn songs<'a>(&self, library: &'a [Song]) -> Vec<&'a Song> {
+        let by_path: HashMap<&Path, &Song> = library
+            .iter()
+            .map(|song| (song.path.as_path(), song))
+            .collect();
+        let by_id = songs_by_id(library);
+        let from_paths = |paths: &Vec<String>| -> Vec<&'a Song> {
+            paths
+                .iter()
+                .filter_map(|path| by_path.get(Path::new(path)).copied())
+                .collect()
+        };
+        self.elements
+            .iter()
+            .flat_map(|element| match element {
+                Aelement::Song(song) => by_path
+                    .get(song.path.as_path())
+                    .or_else(|| by_id.get(song.id.as_str()))
+                    .copied()
+                    .into_iter()
+                    .collect(),
+                Aelement::Playlist(playlist) => playlist
+                    .songs
+                    .iter()
+                    .filter_map(|id| by_id.get(id.as_str()).copied())
+                    .collect(),
+                Aelement::Release(release) => from_paths(&release.songs),
+                Aelement::Artist(artist) => from_paths(&artist.songs),
+            })
+            .collect()
+    }
+}
+
+impl Alist {
+    /// follow moved files, returns whether anything changed
+    pub fn rewrite_song_paths(&mut self, moves: &HashMap<PathBuf, PathBuf>) -> bool {
+        let rewrite = |paths: &mut Vec<String>| {
+            paths.iter_mut().fold(false, |changed, path| {
+                match moves.get(Path::new(path.as_str())) {
+                    Some(to) => {
+                        *path = to.to_string_lossy().to_string();
+                        true
+                    }
+                    None => changed,
+                }
+            })
+        };
+        self.elements
+            .iter_mut()
+            .fold(false, |changed, element| match element {
+                Aelement::Song(song) => match moves.get(&song.path) {
+                    Some(to) => {
+                        song.path = to.clone();
+                        true
+                    }
+                    None => changed,
+                },
+                Aelement::Playlist(_) => changed,
+                Aelement::Release(release) => rewrite(&mut release.songs) || changed,
+                Aelement::Artist(artist) => rewrite(&mut artist.songs) || changed,
+            })
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
 pub enum Aelement {
     Song(Song),
@@ -1,15 +1,15 @@
+use super::song::TrackId;
 use serde::{Deserialize, Serialize};
 use std::{
     cmp::Ordering,
     hash::{Hash, Hasher},
-    path::PathBuf,
 };
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, Eq)]
 pub struct Playlist {
     pub name: String,
     pub created_at: String,
-    pub songs: Vec<PathBuf>,
+    pub songs: Vec<TrackId>,
 }
 
 impl Hash for Playlist {
@@ -38,7 +38,7 @@ impl Ord for Playlist {
 
 #[allow(unused)]
 impl Playlist {
-    pub fn new(name: String, created_at: String, songs: Vec<PathBuf>) -> Self {
+    pub fn new(name: String, created_at: String, songs: Vec<TrackId>) -> Self {
         Playlist {
             name,
             created_at,
@@ -1,14 +1,13 @@
-use super::song::Song;
+use super::song::{Song, TrackId};
 use chrono::Local;
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
-use std::path::PathBuf;
 // use std::collections::VecDeque;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq)]
 pub struct Recent {
     // pub song: Song,
-    pub song: PathBuf,
+    pub song: TrackId,
     pub accessed_at: Option<String>,
     // (((((
     // pub play_count: u32,
@@ -42,7 +41,7 @@ pub type Recents = Vec<Recent>;
 
 #[allow(unused)]
 impl Recent {
-    pub fn new(song: PathBuf, accessed_at: Option<String>) -> Self {
+    pub fn new(song: TrackId, accessed_at: Option<String>) -> Self {
         Self {
             song,
             accessed_at,
@@ -53,7 +52,7 @@ impl Recent {
     pub fn from_song(song: Song) -> Self {
         let accessed_at = Some(Local::now().to_rfc3339());
         Self {
-            song: song.path.clone(),
+            song: song.id,
             accessed_at,
             // play_count: 0,
         }
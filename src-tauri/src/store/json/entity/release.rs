@@ -42,6 +42,25 @@ impl Ord for Release {
 
 // impl Eq for Release {}
 
+/// a release the way `ReleaseOp::from_songs` keys it
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ReleaseKey {
+    pub title: String,
+    pub artist: Option<String>,
+}
+
+impl ReleaseKey {
+    pub fn of(song: &Song) -> Self {
+        Self {
+            title: song
+                .release
+                .clone()
+                .unwrap_or_else(|| "unknown_release".to_string()),
+            artist: song.artist.clone(),
+        }
+    }
+}
+
 #[allow(unused)]
 impl Release {
     pub fn new(title: String, artist: Option<String>, songs: Vec<String>) -> Self {
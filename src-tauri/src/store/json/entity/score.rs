@@ -17,3 +17,22 @@ pub enum Score {
     HardToSay,
     SuperSmallCup,
 }
+
+impl Score {
+    /// higher is better, same scale as the songs page; unscored songs count as 0
+    pub fn value(&self) -> u8 {
+        match self {
+            Score::SuperBigCupUp => 11,
+            Score::SuperBigCup => 10,
+            Score::SuperBigCupDown => 9,
+            Score::BigCupUp => 8,
+            Score::BigCup => 7,
+            Score::BigCupDown => 6,
+            Score::MedCupUp => 5,
+            Score::MedCup => 4,
+            Score::MedCupDown => 3,
+            Score::HardToSay => 2,
+            Score::SuperSmallCup => 1,
+        }
+    }
+}
@@ -2,13 +2,20 @@ use super::score::Score;
 use serde::{Deserialize, Serialize};
 use std::{
     cmp::Ordering,
+    collections::HashMap,
     hash::{Hash, Hasher},
     path::PathBuf,
 };
 
+/// stable per-track id, see `meta::id`; playlists and recents reference songs by it.
+/// byte-identical copies share one, `songs_by_id` decides which of them it means
+pub type TrackId = String;
+
+// Eq / Hash 仍然按 path, song store 是按文件存的; 跨文件的引用用 id
 #[derive(Serialize, Deserialize, Debug, Clone, Eq)]
 pub struct Song {
-    // pub id: String,
+    #[serde(default)]
+    pub id: TrackId,
     pub path: PathBuf,
     pub title: Option<String>,
     pub duration: Option<u32>,
@@ -19,6 +26,9 @@ pub struct Song {
     pub artist: Option<String>,
     // pub release_id: String,
     pub release: Option<String>,
+    /// the song's root is offline, see `Library::mark_unavailable`; only ever written out when set
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unavailable: bool,
 }
 
 impl PartialEq for Song {
@@ -48,6 +58,7 @@ impl PartialOrd for Song {
 #[allow(unused)]
 impl Song {
     pub fn new(
+        id: TrackId,
         path: PathBuf,
         title: Option<String>,
         duration: Option<u32>,
@@ -58,6 +69,7 @@ impl Song {
         release: Option<String>,
     ) -> Self {
         Song {
+            id,
             path,
             title,
             duration,
@@ -66,11 +78,13 @@ impl Song {
             updated_at,
             artist,
             release,
+            unavailable: false,
         }
     }
 
     pub fn sample(path: PathBuf) -> Self {
         Song {
+            id: TrackId::new(),
             path,
             title: None,
             duration: None,
@@ -79,6 +93,26 @@ impl Song {
             updated_at: String::new(),
             artist: None,
             release: None,
+            unavailable: false,
         }
     }
 }
+
+/// songs by track id. byte-identical copies share an id (see `meta::id`); a reference
+/// resolves to the copy with the smallest path, so every lookup picks the same one.
+/// `merge_duplicates` folds such copies into a single song
+pub fn songs_by_id<'a>(songs: impl IntoIterator<Item = &'a Song>) -> HashMap<&'a str, &'a Song> {
+    songs
+        .into_iter()
+        .filter(|song| !song.id.is_empty())
+        .fold(HashMap::new(), |mut acc, song| {
+            acc.entry(song.id.as_str())
+                .and_modify(|kept: &mut &Song| {
+                    if song.path < kept.path {
+                        *kept = song;
+                    }
+                })
+                .or_insert(song);
+            acc
+        })
+}
@@ -1,3 +1,4 @@
 pub mod controller;
 pub mod entity;
 pub mod op;
+pub mod version;
@@ -1,16 +1,15 @@
 use crate::{
     misc::error::{CoreError, CoreResult},
-    store::json::{
-        entity::{
-            alist::{Aelement, Alist},
-            list::Playlist,
+    store::{
+        json::{
+            entity::alist::{Aelement, Alist},
+            op::sm::StoreManager,
         },
-        op::sm::StoreManager,
+        repo::AlistRepo,
     },
 };
 use std::{
     collections::HashSet,
-    path::PathBuf,
     sync::{Arc, Mutex},
 };
 
@@ -18,348 +17,176 @@ pub struct AlistOp {
     pub sm: Arc<Mutex<StoreManager<HashSet<Alist>>>>,
 }
 
-#[allow(unused)]
+fn not_found(name: &str) -> CoreError {
+    CoreError::OtherError(format!("Alist with name {name:?} not found in the store"))
+}
+
+fn already_exists(name: &str) -> CoreError {
+    CoreError::OtherError(format!(
+        "Alist with name {name:?} already exists in the store"
+    ))
+}
+
+/// alists hash and compare by name only
+fn key(name: &str) -> Alist {
+    Alist {
+        name: name.to_string(),
+        elements: Vec::new(),
+        created_at: String::new(),
+    }
+}
+
 impl AlistOp {
-    pub fn create(&self, name: String) -> CoreResult<()> {
-        let mut alists = self.sm.lock().unwrap().load()?;
-        let alist = Alist {
-            name: name.clone(),
+    /// change one alist in place, nothing is changed when `f` fails
+    fn update_one(
+        &self,
+        name: String,
+        f: impl FnOnce(&mut Alist) -> CoreResult<()>,
+    ) -> CoreResult<()> {
+        self.sm.lock().unwrap().update(|alists| {
+            let mut alist = alists.take(&key(&name)).ok_or_else(|| not_found(&name))?;
+            let result = f(&mut alist);
+            alists.insert(alist);
+            result
+        })
+    }
+}
+
+#[allow(unused)]
+impl AlistRepo for AlistOp {
+    fn create(&self, name: String) -> CoreResult<()> {
+        self.insert(&Alist {
+            name,
             elements: Vec::new(),
             created_at: chrono::Local::now().timestamp().to_string(),
-        };
-        if alists.insert(alist) {
-            self.sm.lock().unwrap().save(&alists)?;
-            Ok(())
-        } else {
-            Err(CoreError::OtherError(format!(
-                "Alist with name {name:?} already exists in the store"
-            )))
-        }
+        })
     }
 
-    pub fn list_all(&self) -> CoreResult<Vec<Alist>> {
-        let alists = self.sm.lock().unwrap().load()?;
-        let mut vec = alists.iter().cloned().collect::<Vec<_>>();
+    fn list_all(&self) -> CoreResult<Vec<Alist>> {
+        let mut vec = self
+            .sm
+            .lock()
+            .unwrap()
+            .read(|alists| alists.iter().cloned().collect::<Vec<_>>())?;
         vec.sort();
         Ok(vec)
     }
 
-    pub fn insert(&self, alist: &Alist) -> CoreResult<()> {
-        let mut alists = self.sm.lock().unwrap().load()?;
-        if alists.insert(alist.clone()) {
-            self.sm.lock().unwrap().save(&alists)?;
-            Ok(())
-        } else {
-            Err(CoreError::OtherError(format!(
-                "Alist with name {:?} already exists in the store",
-                alist.name
-            )))
-        }
+    fn insert(&self, alist: &Alist) -> CoreResult<()> {
+        self.sm.lock().unwrap().update(|alists| {
+            if alists.insert(alist.clone()) {
+                Ok(())
+            } else {
+                Err(already_exists(&alist.name))
+            }
+        })
     }
 
-    pub fn locate(&self, name: String) -> CoreResult<Alist> {
-        let alists = self.sm.lock().unwrap().load()?;
-        alists
-            .into_iter()
-            .find(|alist| alist.name == name)
-            .ok_or_else(|| {
-                CoreError::OtherError(format!("Alist with name {name:?} not found in the store"))
-            })
+    fn locate(&self, name: String) -> CoreResult<Alist> {
+        self.sm
+            .lock()
+            .unwrap()
+            .read(|alists| alists.get(&key(&name)).cloned())?
+            .ok_or_else(|| not_found(&name))
     }
 
-    pub fn rename(&self, old_name: String, new_name: String) -> CoreResult<()> {
-        let mut alists = self.sm.lock().unwrap().load()?;
-        if alists.iter().any(|alist| alist.name == new_name) {
-            return Err(CoreError::OtherError(format!(
-                "Alist with name {new_name:?} already exists in the store"
-            )));
-        }
-        let mut alist = alists
-            .take(&Alist {
-                name: old_name.clone(),
-                elements: Vec::new(),
-                created_at: String::new(),
-            })
-            .ok_or_else(|| {
-                CoreError::OtherError(format!(
-                    "Alist with name {old_name:?} not found in the store"
-                ))
-            })?;
-        alist.name = new_name;
-        alists.insert(alist);
-        self.sm.lock().unwrap().save(&alists)?;
-        Ok(())
+    fn rename(&self, old_name: String, new_name: String) -> CoreResult<()> {
+        self.sm.lock().unwrap().update(|alists| {
+            if alists.contains(&key(&new_name)) {
+                return Err(already_exists(&new_name));
+            }
+            let mut alist = alists
+                .take(&key(&old_name))
+                .ok_or_else(|| not_found(&old_name))?;
+            alist.name = new_name;
+            alists.insert(alist);
+            Ok(())
+        })
     }
 
-    pub fn modify(&self, alist: &Alist) -> CoreResult<()> {
-        let mut alists = self.sm.lock().unwrap().load()?;
-        if alists.remove(alist) {
-            alists.insert(alist.clone());
-            self.sm.lock().unwrap().save(&alists)?;
+    fn modify(&self, alist: &Alist) -> CoreResult<()> {
+        self.sm.lock().unwrap().update(|alists| {
+            if !alists.contains(alist) {
+                return Err(not_found(&alist.name));
+            }
+            alists.replace(alist.clone());
             Ok(())
-        } else {
-            Err(CoreError::OtherError(format!(
-                "Alist with name {:?} not found in the store",
-                alist.name
-            )))
-        }
+        })
     }
 
-    pub fn remove(&self, name: String) -> CoreResult<()> {
-        let mut alists = self.sm.lock().unwrap().load()?;
-        let alist = Alist {
-            name: name.clone(),
-            elements: Vec::new(),
-            created_at: String::new(),
-        };
-        if alists.remove(&alist) {
-            self.sm.lock().unwrap().save(&alists)?;
-            Ok(())
-        } else {
-            Err(CoreError::OtherError(format!(
-                "Alist with name {name:?} not found in the store"
-            )))
-        }
+    fn remove(&self, name: String) -> CoreResult<()> {
+        self.sm.lock().unwrap().update(|alists| {
+            alists
+                .remove(&key(&name))
+                .then_some(())
+                .ok_or_else(|| not_found(&name))
+        })
     }
 
-    pub fn remove_batch(&self, names: Vec<String>) -> CoreResult<()> {
-        let mut alists = self.sm.lock().unwrap().load()?;
-        names.iter().try_for_each(|name| {
-            let alist = Alist {
-                name: name.clone(),
-                elements: Vec::new(),
-                created_at: String::new(),
-            };
-            alists.remove(&alist).then_some(()).ok_or_else(|| {
-                CoreError::OtherError(format!("Alist with name {name:?} not found in the store"))
-            })
-        })?;
-        self.sm.lock().unwrap().save(&alists)?;
-        Ok(())
+    fn remove_batch(&self, names: Vec<String>) -> CoreResult<()> {
+        self.sm.lock().unwrap().update(|alists| {
+            if let Some(missing) = names.iter().find(|name| !alists.contains(&key(name))) {
+                return Err(not_found(missing));
+            }
+            names.iter().for_each(|name| {
+                alists.remove(&key(name));
+            });
+            Ok(())
+        })
     }
 
-    pub fn add_element(&self, alist_name: String, element: Aelement) -> CoreResult<()> {
-        let mut alists = self.sm.lock().unwrap().load()?;
-        let mut alist = alists
-            .take(&Alist {
-                name: alist_name.clone(),
-                elements: Vec::new(),
-                created_at: String::new(),
-            })
-            .ok_or_else(|| {
-                CoreError::OtherError(format!(
-                    "Alist with name {alist_name:?} not found in the store"
-                ))
-            })?;
-        alist.elements.push(element);
-        alists.insert(alist);
-        self.sm.lock().unwrap().save(&alists)?;
-        Ok(())
+    fn add_element(&self, alist_name: String, element: Aelement) -> CoreResult<()> {
+        self.update_one(alist_name, |alist| {
+            alist.elements.push(element);
+            Ok(())
+        })
     }
 
-    pub fn remove_element(&self, alist_name: String, index: usize) -> CoreResult<()> {
-        let mut alists = self.sm.lock().unwrap().load()?;
-        let mut alist = alists
-            .take(&Alist {
-                name: alist_name.clone(),
-                elements: Vec::new(),
-                created_at: String::new(),
-            })
-            .ok_or_else(|| {
-                CoreError::OtherError(format!(
-                    "Alist with name {alist_name:?} not found in the store"
-                ))
-            })?;
-        if index >= alist.elements.len() {
-            return Err(CoreError::OtherError(format!(
-                "Index {index} out of bounds for alist {alist_name:?}"
-            )));
-        }
-        alist.elements.remove(index);
-        alists.insert(alist);
-        self.sm.lock().unwrap().save(&alists)?;
-        Ok(())
+    fn remove_element(&self, alist_name: String, index: usize) -> CoreResult<()> {
+        self.remove_elements(alist_name, vec![index])
     }
 
-    pub fn remove_elements(&self, alist_name: String, indices: Vec<usize>) -> CoreResult<()> {
-        let mut alists = self.sm.lock().unwrap().load()?;
-        let mut alist = alists
-            .take(&Alist {
-                name: alist_name.clone(),
-                elements: Vec::new(),
-                created_at: String::new(),
-            })
-            .ok_or_else(|| {
-                CoreError::OtherError(format!(
-                    "Alist with name {alist_name:?} not found in the store"
-                ))
-            })?;
-        let mut sorted_indices = indices;
-        sorted_indices.sort_unstable_by(|a, b| b.cmp(a));
-        sorted_indices.iter().try_for_each(|&index| {
-            if index >= alist.elements.len() {
+    fn remove_elements(&self, alist_name: String, indices: Vec<usize>) -> CoreResult<()> {
+        self.update_one(alist_name.clone(), |alist| {
+            if let Some(index) = indices.iter().find(|&&index| index >= alist.elements.len()) {
                 return Err(CoreError::OtherError(format!(
                     "Index {index} out of bounds for alist {alist_name:?}"
                 )));
             }
-            alist.elements.remove(index);
+            let mut sorted_indices = indices;
+            sorted_indices.sort_unstable_by(|a, b| b.cmp(a));
+            sorted_indices.dedup();
+            sorted_indices.iter().for_each(|&index| {
+                alist.elements.remove(index);
+            });
             Ok(())
-        })?;
-        alists.insert(alist);
-        self.sm.lock().unwrap().save(&alists)?;
-        Ok(())
+        })
     }
 
-    pub fn remove_element_all(&self, alist_name: String, element: Aelement) -> CoreResult<()> {
-        let mut alists = self.sm.lock().unwrap().load()?;
-        let mut alist = alists
-            .take(&Alist {
-                name: alist_name.clone(),
-                elements: Vec::new(),
-                created_at: String::new(),
-            })
-            .ok_or_else(|| {
-                CoreError::OtherError(format!(
-                    "Alist with name {alist_name:?} not found in the store"
-                ))
-            })?;
-        alist.elements.retain(|e| e != &element);
-        alists.insert(alist);
-        self.sm.lock().unwrap().save(&alists)?;
-        Ok(())
+    fn remove_element_all(&self, alist_name: String, element: Aelement) -> CoreResult<()> {
+        self.remove_elements_all(alist_name, vec![element])
     }
 
-    pub fn remove_elements_all(
-        &self,
-        alist_name: String,
-        elements: Vec<Aelement>,
-    ) -> CoreResult<()> {
-        let mut alists = self.sm.lock().unwrap().load()?;
-        let mut alist = alists
-            .take(&Alist {
-                name: alist_name.clone(),
-                elements: Vec::new(),
-                created_at: String::new(),
-            })
-            .ok_or_else(|| {
-                CoreError::OtherError(format!(
-                    "Alist with name {alist_name:?} not found in the store"
-                ))
-            })?;
+    fn remove_elements_all(&self, alist_name: String, elements: Vec<Aelement>) -> CoreResult<()> {
         let element_set: HashSet<Aelement> = elements.into_iter().collect();
-        alist.elements.retain(|e| !element_set.contains(e));
-        alists.insert(alist);
-        self.sm.lock().unwrap().save(&alists)?;
-        Ok(())
-    }
-
-    pub fn clear(&self, alist_name: String) -> CoreResult<()> {
-        let mut alists = self.sm.lock().unwrap().load()?;
-        let mut alist = alists
-            .take(&Alist {
-                name: alist_name.clone(),
-                elements: Vec::new(),
-                created_at: String::new(),
-            })
-            .ok_or_else(|| {
-                CoreError::OtherError(format!(
-                    "Alist with name {alist_name:?} not found in the store"
-                ))
-            })?;
-        alist.elements.clear();
-        alists.insert(alist);
-        self.sm.lock().unwrap().save(&alists)?;
-        Ok(())
-    }
-
-    pub fn clear_all(&self) -> CoreResult<()> {
-        let alists = HashSet::new();
-        self.sm.lock().unwrap().save(&alists)
+        self.update_one(alist_name, |alist| {
+            alist.elements.retain(|e| !element_set.contains(e));
+            Ok(())
+        })
     }
 
-    pub fn list_all_songs(&self, alist_name: String) -> CoreResult<Vec<PathBuf>> {
-        let alists = self.sm.lock().unwrap().load()?;
-        let alist = alists
-            .into_iter()
-            .find(|alist| alist.name == alist_name)
-            .ok_or_else(|| {
-                CoreError::OtherError(format!(
-                    "Alist with name {alist_name:?} not found in the store"
-                ))
-            })?;
-        let songs = alist
-            .elements
-            .into_iter()
-            .filter_map(|element| match element {
-                Aelement::Song(song) => Some(vec![song.path]),
-                Aelement::Playlist(playlist) => Some(playlist.songs),
-                Aelement::Release(release) => {
-                    Some(release.songs.iter().map(PathBuf::from).collect())
-                }
-                Aelement::Artist(artist) => Some(artist.songs.iter().map(PathBuf::from).collect()),
-                _ => None,
-            })
-            .flatten()
-            .collect();
-        Ok(songs)
+    fn clear(&self, alist_name: String) -> CoreResult<()> {
+        self.update_one(alist_name, |alist| {
+            alist.elements.clear();
+            Ok(())
+        })
     }
 
-    pub fn list_all_elements(&self, alist_name: String) -> CoreResult<Vec<Aelement>> {
-        let alists = self.sm.lock().unwrap().load()?;
-        let alist = alists
-            .into_iter()
-            .find(|alist| alist.name == alist_name)
-            .ok_or_else(|| {
-                CoreError::OtherError(format!(
-                    "Alist with name {alist_name:?} not found in the store"
-                ))
-            })?;
-        Ok(alist.elements)
+    fn clear_all(&self) -> CoreResult<()> {
+        self.sm.lock().unwrap().save(HashSet::new())
     }
 
-    // remove an alist and convert it to a playlist
-    pub fn freeze(&self, alist_name: String) -> CoreResult<Playlist> {
-        let mut alists = self.sm.lock().unwrap().load()?;
-        // let alist = alists
-        //     .into_iter()
-        //     .find(|alist| alist.name == alist_name)
-        //     .ok_or_else(|| {
-        //         CoreError::OtherError(format!(
-        //             "Alist with name {:?} not found in the store",
-        //             alist_name
-        //         ))
-        //     })?;
-        let alist = alists
-            .take(&Alist {
-                name: alist_name.clone(),
-                elements: Vec::new(),
-                created_at: String::new(),
-            })
-            .ok_or_else(|| {
-                CoreError::OtherError(format!(
-                    "Alist with name {alist_name:?} not found in the store"
-                ))
-            })?;
-        let playlist = Playlist {
-            name: alist.name,
-            songs: alist
-                .elements
-                .into_iter()
-                .filter_map(|element| match element {
-                    Aelement::Song(song) => Some(vec![song.path]),
-                    Aelement::Playlist(playlist) => Some(playlist.songs),
-                    Aelement::Release(release) => {
-                        Some(release.songs.iter().map(PathBuf::from).collect())
-                    }
-                    Aelement::Artist(artist) => {
-                        Some(artist.songs.iter().map(PathBuf::from).collect())
-                    }
-                    _ => None,
-                })
-                .flatten()
-                .collect(),
-            created_at: alist.created_at,
-        };
-        Ok(playlist)
+    fn list_all_elements(&self, alist_name: String) -> CoreResult<Vec<Aelement>> {
+        Ok(self.locate(alist_name)?.elements)
     }
 }
@@ -1,23 +1,20 @@
 use crate::{
     misc::error::CoreResult,
-    store::json::{
-        entity::{artist::Artist, song::Song},
-        op::sm::StoreManager,
+    store::{
+        json::entity::{artist::Artist, song::Song},
+        repo::SongRepo,
     },
 };
-use std::{
-    collections::{HashMap, HashSet},
-    sync::{Arc, Mutex},
-};
+use std::{collections::HashMap, sync::Arc};
 
 pub struct ArtistOp {
-    pub sm: Arc<Mutex<StoreManager<HashSet<Song>>>>,
+    pub songs: Arc<dyn SongRepo>,
 }
 
 #[allow(unused)]
 impl ArtistOp {
     pub fn list_all(&self) -> CoreResult<Vec<Artist>> {
-        let songs = self.sm.lock().unwrap().load()?.into_iter().collect();
+        let songs = self.songs.list_all()?;
         Ok(self.from_songs(&songs))
     }
 
@@ -1,13 +1,18 @@
 use crate::{
     misc::error::{CoreError, CoreResult},
-    store::json::{
-        entity::{list::Playlist, song::Song},
-        op::sm::StoreManager,
+    store::{
+        json::{
+            entity::{
+                list::Playlist,
+                song::{Song, TrackId},
+            },
+            op::sm::StoreManager,
+        },
+        repo::PlaylistRepo,
     },
 };
 use std::{
     collections::HashSet,
-    path::PathBuf,
     sync::{Arc, Mutex},
 };
 
@@ -15,217 +20,169 @@ pub struct PlaylistOp {
     pub sm: Arc<Mutex<StoreManager<HashSet<Playlist>>>>,
 }
 
-#[allow(unused)]
+fn not_found(name: &str) -> CoreError {
+    CoreError::OtherError(format!(
+        "Playlist with name {name:?} not found in the store"
+    ))
+}
+
+fn already_exists(name: &str) -> CoreError {
+    CoreError::OtherError(format!(
+        "Playlist with name {name:?} already exists in the store"
+    ))
+}
+
 impl PlaylistOp {
-    pub fn create(&self, name: String) -> CoreResult<()> {
-        let mut lists = self.sm.lock().unwrap().load()?;
-        let playlist = Playlist {
-            name: name.clone(),
+    /// change one playlist in place
+    fn update_one(&self, name: String, f: impl FnOnce(&mut Playlist)) -> CoreResult<()> {
+        self.sm.lock().unwrap().update(|lists| {
+            let mut playlist = lists
+                .take(&Playlist::sample(name.clone()))
+                .ok_or_else(|| not_found(&name))?;
+            f(&mut playlist);
+            lists.insert(playlist);
+            Ok(())
+        })
+    }
+}
+
+#[allow(unused)]
+impl PlaylistRepo for PlaylistOp {
+    fn create(&self, name: String) -> CoreResult<()> {
+        self.insert(&Playlist {
+            name,
             songs: Vec::new(),
             created_at: chrono::Local::now().timestamp().to_string(),
-        };
-        if lists.insert(playlist) {
-            self.sm.lock().unwrap().save(&lists)?;
-            Ok(())
-        } else {
-            Err(CoreError::OtherError(format!(
-                "Playlist with name {name:?} already exists in the store"
-            )))
-        }
+        })
     }
 
-    pub fn list_all(&self) -> CoreResult<Vec<Playlist>> {
-        let lists = self.sm.lock().unwrap().load()?;
-        let mut vec = lists.iter().cloned().collect::<Vec<_>>();
+    fn list_all(&self) -> CoreResult<Vec<Playlist>> {
+        let mut vec = self
+            .sm
+            .lock()
+            .unwrap()
+            .read(|lists| lists.iter().cloned().collect::<Vec<_>>())?;
         vec.sort();
         Ok(vec)
     }
 
-    pub fn insert(&self, playlist: &Playlist) -> CoreResult<()> {
-        let mut lists = self.sm.lock().unwrap().load()?;
-        if lists.insert(playlist.clone()) {
-            self.sm.lock().unwrap().save(&lists)?;
-            Ok(())
-        } else {
-            Err(CoreError::OtherError(format!(
-                "Playlist with name {:?} already exists in the store",
-                playlist.name
-            )))
-        }
-    }
-
-    pub fn locate(&self, name: String) -> CoreResult<Playlist> {
-        let lists = self.sm.lock().unwrap().load()?;
-        lists
-            .get(&Playlist::sample(name.clone()))
-            .cloned()
-            .ok_or_else(|| {
-                CoreError::OtherError(format!(
-                    "Playlist with name {name:?} not found in the store"
-                ))
-            })
-    }
-
-    pub fn rename(&self, old_name: String, new_name: String) -> CoreResult<()> {
-        let mut lists = self.sm.lock().unwrap().load()?;
-        if let Some(mut playlist) = lists.take(&Playlist::sample(old_name.clone())) {
-            playlist.name = new_name.clone();
-            if lists.contains(&playlist) {
-                return Err(CoreError::OtherError(format!(
-                    "Playlist with name {new_name:?} already exists in the store"
-                )));
+    fn insert(&self, playlist: &Playlist) -> CoreResult<()> {
+        self.sm.lock().unwrap().update(|lists| {
+            if lists.insert(playlist.clone()) {
+                Ok(())
+            } else {
+                Err(already_exists(&playlist.name))
+            }
+        })
+    }
+
+    fn locate(&self, name: String) -> CoreResult<Playlist> {
+        self.sm
+            .lock()
+            .unwrap()
+            .read(|lists| lists.get(&Playlist::sample(name.clone())).cloned())?
+            .ok_or_else(|| not_found(&name))
+    }
+
+    fn rename(&self, old_name: String, new_name: String) -> CoreResult<()> {
+        self.sm.lock().unwrap().update(|lists| {
+            if lists.contains(&Playlist::sample(new_name.clone())) {
+                return Err(already_exists(&new_name));
             }
+            let mut playlist = lists
+                .take(&Playlist::sample(old_name.clone()))
+                .ok_or_else(|| not_found(&old_name))?;
+            playlist.name = new_name;
             lists.insert(playlist);
-            self.sm.lock().unwrap().save(&lists)?;
             Ok(())
-        } else {
-            Err(CoreError::OtherError(format!(
-                "Playlist with name {old_name:?} not found in the store"
-            )))
-        }
-    }
-
-    pub fn modify(&self, playlist: &Playlist) -> CoreResult<()> {
-        let mut lists = self.sm.lock().unwrap().load()?;
-        lists.replace(playlist.clone()).ok_or_else(|| {
-            CoreError::OtherError(format!(
-                "Playlist with name {:?} not found in the store",
-                playlist.name
-            ))
-        })?;
-        self.sm.lock().unwrap().save(&lists)
-    }
-
-    pub fn remove(&self, name: String) -> CoreResult<()> {
-        let mut lists = self.sm.lock().unwrap().load()?;
-        if lists.remove(&Playlist::sample(name.clone())) {
-            self.sm.lock().unwrap().save(&lists)?;
+        })
+    }
+
+    fn modify(&self, playlist: &Playlist) -> CoreResult<()> {
+        self.sm.lock().unwrap().update(|lists| {
+            if !lists.contains(playlist) {
+                return Err(not_found(&playlist.name));
+            }
+            lists.replace(playlist.clone());
             Ok(())
-        } else {
-            Err(CoreError::OtherError(format!(
-                "Playlist with name {name:?} not found in the store"
-            )))
-        }
+        })
     }
 
-    pub fn remove_batch(&self, names: Vec<String>) -> CoreResult<()> {
-        let mut lists = self.sm.lock().unwrap().load()?;
-        names.iter().try_for_each(|name| {
+    fn remove(&self, name: String) -> CoreResult<()> {
+        self.sm.lock().unwrap().update(|lists| {
             lists
                 .remove(&Playlist::sample(name.clone()))
                 .then_some(())
-                .ok_or_else(|| {
-                    CoreError::OtherError(format!(
-                        "Playlist with name {name:?} not found in the store"
-                    ))
-                })
-        })?;
-        self.sm.lock().unwrap().save(&lists)?;
-        Ok(())
-    }
-
-    pub fn add_songs(&self, name: String, songs: Vec<Song>) -> CoreResult<()> {
-        let mut lists = self.sm.lock().unwrap().load()?;
-        if let Some(mut playlist) = lists.take(&Playlist::sample(name.clone())) {
-            songs.iter().for_each(|song| {
-                playlist.songs.push(song.path.clone());
+                .ok_or_else(|| not_found(&name))
+        })
+    }
+
+    fn remove_batch(&self, names: Vec<String>) -> CoreResult<()> {
+        self.sm.lock().unwrap().update(|lists| {
+            if let Some(missing) = names
+                .iter()
+                .find(|name| !lists.contains(&Playlist::sample(name.to_string())))
+            {
+                return Err(not_found(missing));
+            }
+            names.iter().for_each(|name| {
+                lists.remove(&Playlist::sample(name.clone()));
             });
-            lists.insert(playlist);
-            self.sm.lock().unwrap().save(&lists)?;
             Ok(())
-        } else {
-            Err(CoreError::OtherError(format!(
-                "Playlist with name {name:?} not found in the store"
-            )))
-        }
+        })
     }
 
-    pub fn unique(&self, name: String) -> CoreResult<()> {
-        let mut lists = self.sm.lock().unwrap().load()?;
-        if let Some(mut playlist) = lists.take(&Playlist::sample(name.clone())) {
+    fn add_songs(&self, name: String, songs: Vec<Song>) -> CoreResult<()> {
+        self.update_one(name, |playlist| {
+            playlist.songs.extend(songs.into_iter().map(|song| song.id));
+        })
+    }
+
+    fn unique(&self, name: String) -> CoreResult<()> {
+        self.update_one(name, |playlist| {
             let mut seen = HashSet::new();
-            playlist.songs.retain(|path| seen.insert(path.clone()));
-            lists.insert(playlist);
-            self.sm.lock().unwrap().save(&lists)?;
-            Ok(())
-        } else {
-            Err(CoreError::OtherError(format!(
-                "Playlist with name {name:?} not found in the store"
-            )))
-        }
-    }
-
-    pub fn remove_songs_all(&self, name: String, songs: Vec<Song>) -> CoreResult<()> {
-        let mut lists = self.sm.lock().unwrap().load()?;
-        if let Some(mut playlist) = lists.take(&Playlist::sample(name.clone())) {
-            let song_paths: HashSet<PathBuf> = songs.iter().map(|song| song.path.clone()).collect();
-            playlist.songs.retain(|path| !song_paths.contains(path));
-            lists.insert(playlist);
-            self.sm.lock().unwrap().save(&lists)?;
-            Ok(())
-        } else {
-            Err(CoreError::OtherError(format!(
-                "Playlist with name {name:?} not found in the store"
-            )))
-        }
-    }
-
-    pub fn remove_song_path_all(&self, song_paths: Vec<PathBuf>) -> CoreResult<()> {
-        let mut lists = self.sm.lock().unwrap().load()?;
-        let playlists_to_update: Vec<Playlist> = lists
-            .iter()
-            .filter(|pl| pl.songs.iter().any(|path| song_paths.contains(path)))
-            .cloned()
-            .collect();
-        playlists_to_update.into_iter().for_each(|mut playlist| {
-            playlist.songs.retain(|path| !song_paths.contains(path));
-            lists.replace(playlist).unwrap();
-        });
-        self.sm.lock().unwrap().save(&lists)
-    }
-
-    pub fn remove_songs(&self, name: String, index: Vec<usize>) -> CoreResult<()> {
-        let mut lists = self.sm.lock().unwrap().load()?;
-        if let Some(mut playlist) = lists.take(&Playlist::sample(name.clone())) {
-            let index_set: HashSet<usize> = index.into_iter().collect();
-            playlist.songs = playlist
-                .songs
+            playlist.songs.retain(|id| seen.insert(id.clone()));
+        })
+    }
+
+    fn remove_songs_all(&self, name: String, songs: Vec<Song>) -> CoreResult<()> {
+        let song_ids: HashSet<TrackId> = songs.into_iter().map(|song| song.id).collect();
+        self.update_one(name, |playlist| {
+            playlist.songs.retain(|id| !song_ids.contains(id));
+        })
+    }
+
+    fn remove_song_ids_all(&self, song_ids: Vec<TrackId>) -> CoreResult<()> {
+        self.sm.lock().unwrap().update(|lists| {
+            let playlists_to_update: Vec<Playlist> = lists
                 .iter()
-                .enumerate()
-                .filter_map(|(idx, path)| {
-                    if index_set.contains(&idx) {
-                        None
-                    } else {
-                        Some(path.clone())
-                    }
-                })
+                .filter(|pl| pl.songs.iter().any(|id| song_ids.contains(id)))
+                .cloned()
                 .collect();
-            lists.insert(playlist);
-            self.sm.lock().unwrap().save(&lists)?;
+            playlists_to_update.into_iter().for_each(|mut playlist| {
+                playlist.songs.retain(|id| !song_ids.contains(id));
+                lists.replace(playlist);
+            });
             Ok(())
-        } else {
-            Err(CoreError::OtherError(format!(
-                "Playlist with name {name:?} not found in the store"
-            )))
-        }
+        })
     }
 
-    pub fn clear_songs(&self, name: String) -> CoreResult<()> {
-        let mut lists = self.sm.lock().unwrap().load()?;
-        if let Some(mut playlist) = lists.take(&Playlist::sample(name.clone())) {
-            playlist.songs.clear();
-            lists.insert(playlist);
-            self.sm.lock().unwrap().save(&lists)?;
-            Ok(())
-        } else {
-            Err(CoreError::OtherError(format!(
-                "Playlist with name {name:?} not found in the store"
-            )))
-        }
+    fn remove_songs(&self, name: String, index: Vec<usize>) -> CoreResult<()> {
+        let index_set: HashSet<usize> = index.into_iter().collect();
+        self.update_one(name, |playlist| {
+            playlist.songs = std::mem::take(&mut playlist.songs)
+                .into_iter()
+                .enumerate()
+                .filter_map(|(idx, id)| (!index_set.contains(&idx)).then_some(id))
+                .collect();
+        })
+    }
+
+    fn clear_songs(&self, name: String) -> CoreResult<()> {
+        self.update_one(name, |playlist| playlist.songs.clear())
     }
 
-    pub fn clear_all(&self) -> CoreResult<()> {
-        let lists: HashSet<Playlist> = HashSet::new();
-        self.sm.lock().unwrap().save(&lists)
+    fn clear_all(&self) -> CoreResult<()> {
+        self.sm.lock().unwrap().save(HashSet::new())
     }
 }
@@ -2,6 +2,7 @@ use crate::{misc::error::CoreResult, store::json::entity::song::Song};
 use log::debug;
 use std::fs;
 
+#[derive(Clone)]
 pub struct LyricOp();
 
 #[allow(unused)]
@@ -2,6 +2,7 @@ use crate::{meta::pic::get_art_dir_path, misc::error::CoreError, store::json::en
 use log::debug;
 use std::fs;
 
+#[derive(Clone)]
 pub struct PicOp();
 
 impl PicOp {
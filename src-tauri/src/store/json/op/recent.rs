@@ -1,67 +1,48 @@
 use crate::{
     misc::error::{CoreError, CoreResult},
-    store::json::{
-        entity::{
-            recent::{Recent, Recents},
-            song::Song,
+    store::{
+        json::{
+            entity::{
+                recent::{Recent, Recents},
+                song::{Song, TrackId},
+            },
+            op::sm::StoreManager,
         },
-        op::sm::StoreManager,
+        repo::RecentRepo,
     },
 };
-use std::{
-    path::PathBuf,
-    sync::{Arc, Mutex},
-};
+use std::sync::{Arc, Mutex};
 
 pub struct RecentOp {
     pub sm: Arc<Mutex<StoreManager<Recents>>>,
 }
 
 #[allow(unused)]
-impl RecentOp {
+impl RecentRepo for RecentOp {
     // optional wip: add new
 
-    pub fn list_all(&self) -> CoreResult<Recents> {
-        let recents = self.sm.lock().unwrap().load()?;
-        Ok(recents)
+    fn list_all(&self) -> CoreResult<Recents> {
+        self.sm.lock().unwrap().load()
     }
 
-    pub fn clear(&self) -> CoreResult<()> {
-        let recents = Recents::new();
-        self.sm.lock().unwrap().save(&recents)?;
-        Ok(())
+    fn clear(&self) -> CoreResult<()> {
+        self.sm.lock().unwrap().save(Recents::new())
     }
 
-    pub fn add(&self, song: Song) -> CoreResult<()> {
+    fn add(&self, song: Song) -> CoreResult<()> {
         let recent = Recent::from_song(song);
         self.insert(&recent)
     }
 
-    pub fn insert(&self, recent: &Recent) -> CoreResult<()> {
-        let mut recents = self.sm.lock().unwrap().load()?;
-        recents.insert(0, recent.clone());
-        self.sm.lock().unwrap().save(&recents)?;
-        Ok(())
-    }
-
-    pub fn remove_by_index(&self, index: usize) -> CoreResult<()> {
-        let mut recents = self.sm.lock().unwrap().load()?;
-        if index < recents.len() {
-            recents.remove(index);
-            self.sm.lock().unwrap().save(&recents)?;
+    fn insert(&self, recent: &Recent) -> CoreResult<()> {
+        self.sm.lock().unwrap().update(|recents| {
+            recents.insert(0, recent.clone());
             Ok(())
-        } else {
-            Err(CoreError::OtherError(format!(
-                "Index {} out of bounds for recents of length {}",
-                index,
-                recents.len()
-            )))
-        }
+        })
     }
 
-    pub fn remove_by_indexs(&self, indices: Vec<usize>) -> CoreResult<()> {
-        let mut recents = self.sm.lock().unwrap().load()?;
-        indices.iter().try_for_each(|&index| {
+    fn remove_by_index(&self, index: usize) -> CoreResult<()> {
+        self.sm.lock().unwrap().update(|recents| {
             if index < recents.len() {
                 recents.remove(index);
                 Ok(())
@@ -72,37 +53,43 @@ impl RecentOp {
                     recents.len()
                 )))
             }
-        });
-        self.sm.lock().unwrap().save(&recents)?;
-        Ok(())
+        })
     }
 
-    pub fn remove_by_song_all(&self, song: Song) -> CoreResult<()> {
-        let mut recents = self.sm.lock().unwrap().load()?;
-        recents.retain(|recent| recent.song != song.path);
-        self.sm.lock().unwrap().save(&recents)?;
-        Ok(())
+    fn remove_by_indexs(&self, indices: Vec<usize>) -> CoreResult<()> {
+        self.sm.lock().unwrap().update(|recents| {
+            // 一个个删, 每删一个后面的长度就少一, 先全部检查过再动
+            let len = recents.len();
+            if let Some((removed, index)) = indices
+                .iter()
+                .enumerate()
+                .find(|(removed, &index)| index >= len.saturating_sub(*removed))
+            {
+                return Err(CoreError::OtherError(format!(
+                    "Index {} out of bounds for recents of length {}",
+                    index,
+                    len - removed
+                )));
+            }
+            indices.iter().for_each(|&index| {
+                recents.remove(index);
+            });
+            Ok(())
+        })
     }
 
-    pub fn remove_by_songs_all(&self, songs: Vec<Song>) -> CoreResult<()> {
-        let song_paths: Vec<_> = songs.iter().map(|s| s.path.clone()).collect();
-        let mut recents = self.sm.lock().unwrap().load()?;
-        recents.retain(|recent| !song_paths.contains(&recent.song));
-        self.sm.lock().unwrap().save(&recents)?;
-        Ok(())
+    fn remove_by_song_all(&self, song: Song) -> CoreResult<()> {
+        self.remove_by_song_ids(vec![&song.id])
     }
 
-    pub fn remove_by_song_path(&self, song: &PathBuf) -> CoreResult<()> {
-        let mut recents = self.sm.lock().unwrap().load()?;
-        recents.retain(|recent| &recent.song != song);
-        self.sm.lock().unwrap().save(&recents)?;
-        Ok(())
+    fn remove_by_songs_all(&self, songs: Vec<Song>) -> CoreResult<()> {
+        self.remove_by_song_ids(songs.iter().map(|s| &s.id).collect())
     }
 
-    pub fn remove_by_song_paths(&self, song_paths: Vec<&PathBuf>) -> CoreResult<()> {
-        let mut recents = self.sm.lock().unwrap().load()?;
-        recents.retain(|recent| !song_paths.contains(&&recent.song));
-        self.sm.lock().unwrap().save(&recents)?;
-        Ok(())
+    fn remove_by_song_ids(&self, song_ids: Vec<&TrackId>) -> CoreResult<()> {
+        self.sm.lock().unwrap().update(|recents| {
+            recents.retain(|recent| !song_ids.contains(&&recent.song));
+            Ok(())
+        })
     }
 }
@@ -1,53 +1,30 @@
 use crate::{
-    misc::error::{CoreError, CoreResult},
-    store::json::{
-        entity::{release::Release, song::Song},
-        op::sm::StoreManager,
+    misc::error::CoreResult,
+    store::{
+        json::entity::{release::Release, song::Song},
+        repo::SongRepo,
     },
 };
-use std::{
-    collections::{HashMap, HashSet},
-    path::PathBuf,
-    sync::{Arc, Mutex},
-};
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
 
 pub struct ReleaseOp {
-    pub sm: Arc<Mutex<StoreManager<HashSet<Song>>>>,
+    pub songs: Arc<dyn SongRepo>,
 }
 
 #[allow(unused)]
 impl ReleaseOp {
     /// unused
     pub fn list_by_artist_release(&self, artist: &str, release: &str) -> CoreResult<Vec<Song>> {
-        let songs = self.sm.lock().unwrap().load()?;
-        let filtered_songs: Vec<Song> = songs
-            .iter()
-            .filter(|song| {
-                if let (Some(song_artist), Some(song_release)) = (&song.artist, &song.release) {
-                    song_artist == artist && song_release == release
-                } else {
-                    false
-                }
-            })
-            .cloned()
-            .collect();
-        Ok(filtered_songs)
+        self.songs.list_by_artist_release(artist, release)
     }
 
     pub fn remove_by_artist_release(&self, artist: &str, release: &str) -> CoreResult<()> {
-        let mut songs = self.sm.lock().unwrap().load()?;
-        songs.retain(|song| {
-            if let (Some(song_artist), Some(song_release)) = (&song.artist, &song.release) {
-                !(song_artist == artist && song_release == release)
-            } else {
-                true
-            }
-        });
-        self.sm.lock().unwrap().save(&songs)
+        let songs = self.songs.list_by_artist_release(artist, release)?;
+        self.songs.remove(&songs)
     }
 
     pub fn select_by_song(&self, song: &Song) -> CoreResult<Vec<Song>> {
-        let songs = self.sm.lock().unwrap().load()?;
+        let songs = self.songs.list_all()?;
         let filtered_songs: Vec<Song> = songs
             .iter()
             .filter(|s| {
@@ -113,20 +90,11 @@ impl ReleaseOp {
     }
 
     pub fn add_save(&self, files: &Vec<PathBuf>) -> CoreResult<()> {
-        let mut songs = self.sm.lock().unwrap().load()?;
-        let releases = self.from_songs(&songs.iter().cloned().collect::<Vec<Song>>());
-        files.iter().try_for_each(|file| {
-            let song = Song::from_path(file, None)?;
-            releases.iter().for_each(|release| {
-                if let Some(release_title) = &song.release {
-                    if release.title == *release_title && release.artist == song.artist {}
-                }
-            });
-            songs.insert(song).then_some(()).ok_or_else(|| {
-                CoreError::OtherError(format!("Add song with path {file:?} already exists"))
-            })
-        });
-        self.sm.lock().unwrap().save(&songs)
+        let songs = files
+            .iter()
+            .map(|file| Song::from_path(file, None))
+            .collect::<CoreResult<Vec<Song>>>()?;
+        self.songs.add_save(&songs)
     }
 
     /// not inspected
@@ -164,8 +132,7 @@ impl ReleaseOp {
     }
 
     pub fn list_all(&self) -> CoreResult<Vec<Release>> {
-        let songs = self.sm.lock().unwrap().load()?;
-        let releases = self.from_songs(&songs.iter().cloned().collect::<Vec<Song>>());
-        Ok(releases)
+        let songs = self.songs.list_all()?;
+        Ok(self.from_songs(&songs))
     }
 }
@@ -1,30 +1,146 @@
 // use super::error::{StoreError, StoreResult};
 // use super::entity::AppStore;
-use crate::misc::{
-    config::get_global,
-    error::{CoreError, CoreResult},
-    utils::resolve_resource_path,
+use crate::{
+    misc::{
+        config::get_global,
+        error::{CoreError, CoreResult},
+        utils::resolve_resource_path,
+    },
+    store::json::version::{upgrade, wrap, MigrationContext, Versioned},
 };
-use log::{info, warn};
-use serde::{de::DeserializeOwned, Serialize};
+use chrono::Local;
+use log::{error, info, warn};
+use serde::Serialize;
 use std::{
-    fs::{create_dir_all, File},
-    io::{BufReader, BufWriter},
-    marker::PhantomData,
-    path::PathBuf,
+    fs::{copy, create_dir_all, hard_link, read_dir, remove_file, rename, File},
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Once, Weak},
+    thread,
+    time::{Duration, Instant},
 };
 
 // const STORE_FILENAME: &str = "store.json";
 
+const BACKUP_DIR: &str = "backups";
+
+/// keeps the deserialized store in memory; `save` only marks it dirty,
+/// the file is written by `flush` (debounced in the background, and on app exit)
 #[derive(Debug)]
 pub struct StoreManager<T> {
     store_path: PathBuf,
-    _phantom: PhantomData<T>,
+    backup_count: usize,
+    cache: Option<T>,
+    /// (first, last) change since the last flush, `None` when clean
+    dirty: Option<(Instant, Instant)>,
+}
+
+/// type-erased handle so stores of different `T` can be flushed together
+pub trait Flush: Send + Sync {
+    fn flush(&self) -> CoreResult<()>;
+
+    /// flush only if the store has not changed for at least `delay`,
+    /// or has been dirty for `MAX_DIRTY_DELAYS` times that
+    fn flush_idle(&self, delay: Duration) -> CoreResult<()>;
+}
+
+static REGISTRY: Mutex<Vec<Weak<dyn Flush>>> = Mutex::new(Vec::new());
+static FLUSHER: Once = Once::new();
+
+/// a store that keeps changing is still written after this many `delay`s
+const MAX_DIRTY_DELAYS: u32 = 10;
+
+// 后台线程定期把最后一次改动后静默超过 store_flush_delay_ms 的 store 写盘, 一直在改的也不会无限拖下去
+fn spawn_flusher() {
+    let delay = Duration::from_millis(
+        get_global()
+            .get("store_flush_delay_ms")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(1000),
+    );
+    let tick = std::cmp::min(delay, Duration::from_millis(250));
+    thread::spawn(move || loop {
+        thread::sleep(tick);
+        for_each_store(|store| store.flush_idle(delay));
+    });
+}
+
+fn for_each_store<F: Fn(&dyn Flush) -> CoreResult<()>>(f: F) {
+    let stores: Vec<Arc<dyn Flush>> = {
+        let mut registry = REGISTRY.lock().unwrap();
+        registry.retain(|store| store.strong_count() > 0);
+        registry.iter().filter_map(Weak::upgrade).collect()
+    };
+    stores.iter().for_each(|store| {
+        if let Err(e) = f(store.as_ref()) {
+            error!("Failed to flush store: {e:?}");
+        }
+    });
+}
+
+/// write every dirty store now, call before the process goes away
+pub fn flush_all() {
+    info!("Flushing all json stores...");
+    for_each_store(|store| store.flush());
+}
+
+fn fs_err(e: impl ToString) -> CoreError {
+    CoreError::FsError(e.to_string())
+}
+
+fn read_store<T: Versioned>(path: &Path) -> CoreResult<T> {
+    let file = File::open(path).map_err(fs_err)?;
+    let reader = BufReader::new(file);
+    upgrade(
+        serde_json::from_reader(reader).map_err(fs_err)?,
+        &MigrationContext::from_config(),
+    )
+}
+
+// rename 之后把目录也 fsync 一下, 不然掉电时 rename 本身可能丢
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> CoreResult<()> {
+    File::open(dir).and_then(|d| d.sync_all()).map_err(fs_err)
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> CoreResult<()> {
+    Ok(())
+}
+
+/// write `value` as json to a temp file next to `path`, fsync, then rename it over `path`,
+/// so a crash leaves either the old or the new content but never half of it.
+/// `before_rename` runs once the new content is safely on disk
+pub(crate) fn write_json_atomic<V: Serialize>(
+    path: &Path,
+    value: &V,
+    before_rename: impl FnOnce(),
+) -> CoreResult<()> {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let tmp_path = path.with_file_name(format!("{file_name}.tmp"));
+    let file = File::create(&tmp_path).map_err(fs_err)?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut writer, value).map_err(fs_err)?;
+    writer.flush().map_err(fs_err)?;
+    writer
+        .into_inner()
+        .map_err(fs_err)?
+        .sync_all()
+        .map_err(fs_err)?;
+    before_rename();
+    rename(&tmp_path, path).map_err(fs_err)?;
+    if let Some(parent) = path.parent() {
+        sync_dir(parent)?;
+    }
+    Ok(())
 }
 
 impl<T> StoreManager<T>
 where
-    T: Serialize + DeserializeOwned + Default,
+    T: Versioned + Default + Clone,
 {
     pub fn new(path: String) -> CoreResult<Self> {
         let config = get_global();
@@ -52,33 +168,218 @@ where
         if let Some(parent) = store_path.parent() {
             create_dir_all(parent).map_err(|source| CoreError::FsError(source.to_string()))?;
         }
+        let backup_count = config
+            .get("store_backup_count")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(5) as usize;
         Ok(Self {
             store_path,
-            _phantom: PhantomData,
+            backup_count,
+            cache: None,
+            dirty: None,
         })
     }
 
-    pub fn load(&self) -> CoreResult<T> {
+    /// shared handle registered for background and exit flushing
+    pub fn shared(path: String) -> CoreResult<Arc<Mutex<Self>>>
+    where
+        T: Send + 'static,
+    {
+        let sm = Arc::new(Mutex::new(Self::new(path)?));
+        let weak: Weak<dyn Flush> = Arc::downgrade(&(sm.clone() as Arc<dyn Flush>));
+        REGISTRY.lock().unwrap().push(weak);
+        FLUSHER.call_once(spawn_flusher);
+        Ok(sm)
+    }
+
+    fn file_name(&self) -> String {
+        self.store_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    fn backup_dir(&self) -> PathBuf {
+        self.store_path
+            .parent()
+            .map(|parent| parent.join(BACKUP_DIR))
+            .unwrap_or_else(|| PathBuf::from(BACKUP_DIR))
+    }
+
+    /// backups of this store, newest first.
+    /// named `<file>.<timestamp>.bak`, so sorting by name is sorting by time
+    pub fn backups(&self) -> Vec<PathBuf> {
+        let prefix = format!("{}.", self.file_name());
+        let mut backups: Vec<PathBuf> = read_dir(self.backup_dir())
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| {
+                        path.file_name()
+                            .map(|name| name.to_string_lossy())
+                            .is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".bak"))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        backups.sort_unstable_by(|a, b| b.cmp(a));
+        backups
+    }
+
+    /// snapshot the current store file before it gets replaced, then drop the oldest ones
+    fn backup(&self) -> CoreResult<()> {
+        if self.backup_count == 0 || !self.store_path.exists() {
+            return Ok(());
+        }
+        let backup_dir = self.backup_dir();
+        create_dir_all(&backup_dir).map_err(fs_err)?;
+        let backup_path = backup_dir.join(format!(
+            "{}.{}.bak",
+            self.file_name(),
+            Local::now().format("%Y%m%d%H%M%S%3f")
+        ));
+        // 新文件是 rename 过来的, 旧 inode 不会再被写, 硬链接就够了; 不支持的文件系统退回 copy
+        if hard_link(&self.store_path, &backup_path).is_err() {
+            copy(&self.store_path, &backup_path).map_err(fs_err)?;
+        }
+        self.backups()
+            .into_iter()
+            .skip(self.backup_count)
+            .for_each(|old| {
+                if let Err(e) = remove_file(&old) {
+                    warn!("Failed to remove old backup {old:?}: {e}");
+                }
+            });
+        Ok(())
+    }
+
+    /// try backups newest first, the broken store file (if any) is moved aside
+    fn recover(&self, reason: &str) -> CoreResult<T> {
+        let backups = self.backups();
+        for backup in backups.iter() {
+            match read_store(backup) {
+                Ok(store) => {
+                    warn!(
+                        "{:?} {reason}, recovered from backup {backup:?}",
+                        self.store_path
+                    );
+                    if self.store_path.exists() {
+                        let corrupt_path = self.backup_dir().join(format!(
+                            "{}.{}.corrupt",
+                            self.file_name(),
+                            Local::now().format("%Y%m%d%H%M%S%3f")
+                        ));
+                        rename(&self.store_path, &corrupt_path).map_err(fs_err)?;
+                        warn!("Moved broken store to {corrupt_path:?}");
+                    }
+                    copy(backup, &self.store_path).map_err(fs_err)?;
+                    return Ok(store);
+                }
+                Err(e) => warn!("Backup {backup:?} is not valid either: {e:?}"),
+            }
+        }
+        Err(CoreError::FsError(format!(
+            "{:?} {reason} and none of {} backups could be loaded",
+            self.store_path,
+            backups.len()
+        )))
+    }
+
+    fn cached(&mut self) -> CoreResult<&mut T> {
+        let store = match self.cache.take() {
+            Some(cache) => cache,
+            None => self.read_disk()?,
+        };
+        Ok(self.cache.insert(store))
+    }
+
+    /// a copy of the cached value, read from disk on first use
+    pub fn load(&mut self) -> CoreResult<T> {
+        self.cached().cloned()
+    }
+
+    /// look at the cached value in place, under the caller's lock
+    pub fn read<R>(&mut self, f: impl FnOnce(&T) -> R) -> CoreResult<R> {
+        Ok(f(self.cached()?))
+    }
+
+    /// change the cached value in place, under the caller's lock. only marked dirty
+    /// when `f` succeeds, so `f` checks everything before it changes anything
+    pub fn update<R>(&mut self, f: impl FnOnce(&mut T) -> CoreResult<R>) -> CoreResult<R> {
+        let result = f(self.cached()?)?;
+        self.touch();
+        Ok(result)
+    }
+
+    /// replace the cached value, it hits the disk on the next flush
+    pub fn save(&mut self, store: T) -> CoreResult<()> {
+        self.cache = Some(store);
+        self.touch();
+        Ok(())
+    }
+
+    fn touch(&mut self) {
+        let now = Instant::now();
+        let first = self.dirty.map_or(now, |(first, _)| first);
+        self.dirty = Some((first, now));
+    }
+
+    pub fn flush(&mut self) -> CoreResult<()> {
+        if let (Some(_), Some(cache)) = (self.dirty, &self.cache) {
+            self.write_disk(cache)?;
+            self.dirty = None;
+        }
+        Ok(())
+    }
+
+    fn read_disk(&self) -> CoreResult<T> {
         if !self.store_path.exists() {
-            warn!("{:?} does not exist", self.store_path);
-            return Ok(T::default());
+            if self.backups().is_empty() {
+                warn!("{:?} does not exist", self.store_path);
+                return Ok(T::default());
+            }
+            return self.recover("does not exist");
         }
         info!("Loading store from {:?}...", self.store_path);
-        let file = File::open(&self.store_path)
-            .map_err(|source| CoreError::FsError(source.to_string()))?;
-        let reader = BufReader::new(file);
-        let store =
-            serde_json::from_reader(reader).map_err(|e| CoreError::FsError(e.to_string()))?;
-        Ok(store)
+        match read_store(&self.store_path) {
+            Ok(store) => Ok(store),
+            // written by a newer version, don't touch it
+            Err(e @ CoreError::StoreVersionError(_)) => Err(e),
+            Err(e) => {
+                error!("Failed to load {:?}: {e:?}", self.store_path);
+                self.recover("is broken")
+            }
+        }
     }
 
-    pub fn save(&self, store: &T) -> CoreResult<()> {
+    /// the old file is backed up right before the new one replaces it
+    fn write_disk(&self, store: &T) -> CoreResult<()> {
         info!("Saving store to {:?}...", self.store_path);
-        let file = File::create(&self.store_path)
-            .map_err(|source| CoreError::FsError(source.to_string()))?;
-        let writer = BufWriter::new(file);
-        serde_json::to_writer_pretty(writer, store)
-            .map_err(|e| CoreError::FsError(e.to_string()))?;
-        Ok(())
+        write_json_atomic(&self.store_path, &wrap(store), || {
+            if let Err(e) = self.backup() {
+                warn!("Failed to back up {:?}: {e:?}", self.store_path);
+            }
+        })
+    }
+}
+
+impl<T> Flush for Mutex<StoreManager<T>>
+where
+    T: Versioned + Default + Clone + Send,
+{
+    fn flush(&self) -> CoreResult<()> {
+        self.lock().unwrap().flush()
+    }
+
+    fn flush_idle(&self, delay: Duration) -> CoreResult<()> {
+        let mut sm = self.lock().unwrap();
+        match sm.dirty {
+            Some((first, last))
+                if last.elapsed() >= delay || first.elapsed() >= delay * MAX_DIRTY_DELAYS =>
+            {
+                sm.flush()
+            }
+            _ => Ok(()),
+        }
     }
 }
@@ -0,0 +1,393 @@
+use crate::{
+    misc::{
+        error::{CoreError, CoreResult},
+        utils::resolve_resource_path,
+    },
+    store::{
+        config_str,
+        json::entity::{
+            alist::Alist,
+            list::Playlist,
+            recent::Recents,
+            song::{Song, TrackId},
+        },
+    },
+};
+use log::{info, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::BufReader,
+    path::PathBuf,
+    sync::OnceLock,
+};
+
+// 每个 store 文件外面包一层 { "version": n, "data": ... }
+// 没有这层的老文件 (直接是数组) 当作 version 0, 第一步 migration 负责把它包起来
+
+/// upgrades a document of version `i` to `i + 1`, works on raw json so old shapes don't need old structs
+pub type Migration = fn(Value, &MigrationContext) -> CoreResult<Value>;
+
+/// what a migration may need besides the document itself
+#[derive(Debug, Default)]
+pub struct MigrationContext {
+    song_store: Option<PathBuf>,
+    /// path -> track id of every song in the song store
+    ids: OnceLock<HashMap<String, TrackId>>,
+}
+
+impl MigrationContext {
+    /// the song store named in Config, read when a migration first asks for an id
+    pub fn from_config() -> Self {
+        let song_store = config_str("store_base").and_then(|base| {
+            resolve_resource_path(&PathBuf::from(base), config_str("song_store")?)
+        });
+        Self {
+            song_store: song_store.ok(),
+            ..Default::default()
+        }
+    }
+
+    /// ids of the given songs, e.g. the song store of an archive
+    pub fn with_songs<'a>(songs: impl IntoIterator<Item = &'a Song>) -> Self {
+        let context = Self::default();
+        let _ = context.ids.set(path_ids(songs));
+        context
+    }
+
+    /// id of the song stored at `path`
+    pub fn track_id(&self, path: &str) -> Option<&TrackId> {
+        self.ids.get_or_init(|| self.read_song_store()).get(path)
+    }
+
+    // 直接读文件, 不经过 StoreManager; 读不了的话引用先保持原样
+    fn read_song_store(&self) -> HashMap<String, TrackId> {
+        let Some(path) = self.song_store.as_ref().filter(|path| path.exists()) else {
+            return HashMap::new();
+        };
+        let songs = File::open(path)
+            .map_err(|e| CoreError::FsError(e.to_string()))
+            .and_then(|file| {
+                serde_json::from_reader(BufReader::new(file))
+                    .map_err(|e| CoreError::FsError(e.to_string()))
+            })
+            .and_then(|doc| upgrade::<HashSet<Song>>(doc, &MigrationContext::default()));
+        match songs {
+            Ok(songs) => path_ids(&songs),
+            Err(e) => {
+                warn!("Failed to read song store {path:?} for migration: {e:?}");
+                HashMap::new()
+            }
+        }
+    }
+}
+
+/// songs stored before ids existed get the id the next scan would give them
+fn path_ids<'a>(songs: impl IntoIterator<Item = &'a Song>) -> HashMap<String, TrackId> {
+    songs
+        .into_iter()
+        .map(|song| {
+            let id = match song.id.is_empty() {
+                true => song.compute_id(),
+                false => song.id.clone(),
+            };
+            (song.path.to_string_lossy().to_string(), id)
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+pub struct Envelope<'a, T> {
+    pub version: u32,
+    pub data: &'a T,
+}
+
+#[derive(Deserialize)]
+struct RawEnvelope {
+    version: u32,
+    data: Value,
+}
+
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// `migrations()[i]` upgrades version `i` to `i + 1`, append only
+    fn migrations() -> &'static [Migration];
+
+    fn version() -> u32 {
+        Self::migrations().len() as u32
+    }
+}
+
+fn is_envelope(doc: &Value) -> bool {
+    matches!(doc, Value::Object(map) if map.contains_key("version") && map.contains_key("data"))
+}
+
+fn unwrap_envelope(doc: Value) -> CoreResult<RawEnvelope> {
+    serde_json::from_value(doc)
+        .map_err(|e| CoreError::FsError(format!("Malformed store envelope: {e}")))
+}
+
+fn version_of(doc: &Value) -> CoreResult<u32> {
+    match is_envelope(doc) {
+        true => doc["version"]
+            .as_u64()
+            .map(|version| version as u32)
+            .ok_or_else(|| CoreError::FsError("Malformed store envelope version".to_string())),
+        false => Ok(0),
+    }
+}
+
+/// parse a store document of any known version into the current shape
+pub fn upgrade<T: Versioned>(mut doc: Value, context: &MigrationContext) -> CoreResult<T> {
+    let migrations = T::migrations();
+    let mut version = version_of(&doc)?;
+    if version as usize > migrations.len() {
+        return Err(CoreError::StoreVersionError(format!(
+            "Store version {version} is newer than supported version {}",
+            migrations.len()
+        )));
+    }
+    while (version as usize) < migrations.len() {
+        info!("Migrating store document from version {version}");
+        doc = migrations[version as usize](doc, context)?;
+        let next = version_of(&doc)?;
+        if next != version + 1 {
+            return Err(CoreError::FsError(format!(
+                "Migration from store version {version} produced version {next}"
+            )));
+        }
+        version = next;
+    }
+    serde_json::from_value(unwrap_envelope(doc)?.data)
+        .map_err(|e| CoreError::FsError(e.to_string()))
+}
+
+pub fn wrap<T: Versioned>(data: &T) -> Envelope<'_, T> {
+    Envelope {
+        version: T::version(),
+        data,
+    }
+}
+
+// 0 -> 1, 老文件本身就是数据
+fn bare_to_envelope(doc: Value, _: &MigrationContext) -> CoreResult<Value> {
+    match doc {
+        Value::Array(_) => Ok(json!({ "version": 1, "data": doc })),
+        _ => Err(CoreError::FsError(
+            "Store document is neither an envelope nor a bare array".to_string(),
+        )),
+    }
+}
+
+// 很早的 recents.json 里 song 存的是整个 Song, 后来改成只存 path; 都是没有信封的老文件
+fn recent_song_to_path(doc: Value, context: &MigrationContext) -> CoreResult<Value> {
+    let Value::Array(recents) = doc else {
+        return bare_to_envelope(doc, context);
+    };
+    bare_to_envelope(
+        Value::Array(
+            recents
+                .into_iter()
+                .map(|mut recent| {
+                    if let Some(path) = recent
+                        .get_mut("song")
+                        .and_then(|song| song.get_mut("path"))
+                        .map(Value::take)
+                    {
+                        recent["song"] = path;
+                    }
+                    recent
+                })
+                .collect(),
+        ),
+        context,
+    )
+}
+
+// 只改 data 的一步
+fn map_data(doc: Value, f: impl FnOnce(&mut Value)) -> CoreResult<Value> {
+    let RawEnvelope { version, mut data } = unwrap_envelope(doc)?;
+    f(&mut data);
+    Ok(json!({ "version": version + 1, "data": data }))
+}
+
+fn each(value: &mut Value, f: impl FnMut(&mut Value)) {
+    if let Value::Array(items) = value {
+        items.iter_mut().for_each(f);
+    }
+}
+
+/// a reference from before track ids existed (a path) becomes the id of the song stored there,
+/// ids and paths the song store doesn't know stay as they are
+fn path_to_id(reference: &mut Value, context: &MigrationContext) {
+    if let Some(id) = reference.as_str().and_then(|path| context.track_id(path)) {
+        *reference = Value::String(id.clone());
+    }
+}
+
+/// playlist snapshots inside alist elements, the other elements keep their paths
+pub fn elements_paths_to_ids(elements: &mut Value, context: &MigrationContext) {
+    each(elements, |element| {
+        if let Some(songs) = element.get_mut("Playlist").and_then(|p| p.get_mut("songs")) {
+            each(songs, |song| path_to_id(song, context));
+        }
+    });
+}
+
+// 1 -> 2: 歌单 / 最近播放 / alist 里的歌单快照从 path 换成 track id
+fn playlist_paths_to_ids(doc: Value, context: &MigrationContext) -> CoreResult<Value> {
+    map_data(doc, |playlists| {
+        each(playlists, |playlist| {
+            if let Some(songs) = playlist.get_mut("songs") {
+                each(songs, |song| path_to_id(song, context));
+            }
+        })
+    })
+}
+
+fn alist_paths_to_ids(doc: Value, context: &MigrationContext) -> CoreResult<Value> {
+    map_data(doc, |alists| {
+        each(alists, |alist| {
+            if let Some(elements) = alist.get_mut("elements") {
+                elements_paths_to_ids(elements, context);
+            }
+        })
+    })
+}
+
+fn recent_paths_to_ids(doc: Value, context: &MigrationContext) -> CoreResult<Value> {
+    map_data(doc, |recents| {
+        each(recents, |recent| {
+            if let Some(song) = recent.get_mut("song") {
+                path_to_id(song, context);
+            }
+        })
+    })
+}
+
+impl Versioned for HashSet<Song> {
+    fn migrations() -> &'static [Migration] {
+        &[bare_to_envelope]
+    }
+}
+
+impl Versioned for HashSet<Playlist> {
+    fn migrations() -> &'static [Migration] {
+        &[bare_to_envelope, playlist_paths_to_ids]
+    }
+}
+
+impl Versioned for HashSet<Alist> {
+    fn migrations() -> &'static [Migration] {
+        &[bare_to_envelope, alist_paths_to_ids]
+    }
+}
+
+impl Versioned for Recents {
+    fn migrations() -> &'static [Migration] {
+        &[recent_song_to_path, recent_paths_to_ids]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::json::entity::recent::Recent;
+
+    fn context() -> MigrationContext {
+        let mut a = Song::sample(PathBuf::from("/music/a.flac"));
+        a.id = "ca".to_string();
+        let mut b = Song::sample(PathBuf::from("/music/b.flac"));
+        b.id = "cb".to_string();
+        MigrationContext::with_songs(&[a, b])
+    }
+
+    #[test]
+    fn bare_array_is_version_zero() {
+        let doc =
+            json!([{ "name": "a", "created_at": "2024-01-01", "songs": ["/music/a.flac", "cb"] }]);
+        let playlists: HashSet<Playlist> = upgrade(doc, &context()).unwrap();
+        let playlist = playlists.iter().next().unwrap();
+        assert_eq!(playlists.len(), 1);
+        assert_eq!(playlist.name, "a");
+        assert_eq!(playlist.songs, vec!["ca".to_string(), "cb".to_string()]);
+    }
+
+    #[test]
+    fn old_recents_embedding_the_song_end_up_with_its_id() {
+        let doc = json!([
+            {
+                "song": {
+                    "path": "/music/a.flac",
+                    "title": "a",
+                    "duration": 180,
+                    "score": null,
+                    "created_at": "2024-01-01",
+                    "updated_at": "2024-01-01",
+                    "artist": null,
+                    "release": null
+                },
+                "accessed_at": "2024-01-02"
+            },
+            { "song": "/music/gone.flac", "accessed_at": null }
+        ]);
+        let recents: Recents = upgrade(doc, &context()).unwrap();
+        assert_eq!(
+            recents,
+            vec![
+                Recent {
+                    song: "ca".to_string(),
+                    accessed_at: Some("2024-01-02".to_string()),
+                },
+                Recent {
+                    song: "/music/gone.flac".to_string(),
+                    accessed_at: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn alist_playlist_snapshots_switch_to_ids() {
+        let doc = json!({
+            "version": 1,
+            "data": [{
+                "name": "mix",
+                "created_at": "2024-01-01",
+                "elements": [
+                    { "Playlist": { "name": "p", "created_at": "2024-01-01", "songs": ["/music/b.flac"] } },
+                    { "Release": { "title": "r", "artist": null, "songs": ["/music/a.flac"] } }
+                ]
+            }]
+        });
+        let upgraded = alist_paths_to_ids(doc, &context()).unwrap();
+        let elements = &upgraded["data"][0]["elements"];
+        assert_eq!(upgraded["version"], json!(2));
+        assert_eq!(elements[0]["Playlist"]["songs"], json!(["cb"]));
+        assert_eq!(elements[1]["Release"]["songs"], json!(["/music/a.flac"]));
+    }
+
+    #[test]
+    fn current_envelope_is_read_as_is() {
+        let songs = HashSet::from([Song::sample(PathBuf::from("/music/a.flac"))]);
+        let doc = serde_json::to_value(wrap(&songs)).unwrap();
+        assert_eq!(doc["version"], json!(HashSet::<Song>::version()));
+        let read: HashSet<Song> = upgrade(doc, &MigrationContext::default()).unwrap();
+        assert_eq!(read, songs);
+    }
+
+    #[test]
+    fn newer_version_is_refused() {
+        let doc = json!({ "version": HashSet::<Alist>::version() + 1, "data": [] });
+        let result: CoreResult<HashSet<Alist>> = upgrade(doc, &context());
+        assert!(matches!(result, Err(CoreError::StoreVersionError(_))));
+    }
+
+    #[test]
+    fn neither_envelope_nor_array_is_malformed() {
+        let result: CoreResult<HashSet<Song>> =
+            upgrade(json!({ "songs": [] }), &MigrationContext::default());
+        assert!(matches!(result, Err(CoreError::FsError(_))));
+    }
+}
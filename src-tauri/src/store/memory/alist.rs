@@ -0,0 +1,95 @@
+use crate::{
+    misc::error::{CoreError, CoreResult},
+    store::{json::entity::alist::Alist, repo::AlistRepo},
+};
+use std::sync::Mutex;
+
+// alist 的 Hash/Eq 只看 name, 这里直接用 Vec 按 name 找
+#[derive(Default)]
+pub struct AlistMemOp {
+    alists: Mutex<Vec<Alist>>,
+}
+
+impl AlistMemOp {
+    pub fn new(alists: Vec<Alist>) -> Self {
+        Self {
+            alists: Mutex::new(alists),
+        }
+    }
+}
+
+fn not_found(name: &str) -> CoreError {
+    CoreError::OtherError(format!("Alist with name {name:?} not found in the store"))
+}
+
+fn already_exists(name: &str) -> CoreError {
+    CoreError::OtherError(format!(
+        "Alist with name {name:?} already exists in the store"
+    ))
+}
+
+#[allow(unused)]
+impl AlistRepo for AlistMemOp {
+    fn list_all(&self) -> CoreResult<Vec<Alist>> {
+        let mut vec = self.alists.lock().unwrap().clone();
+        vec.sort();
+        Ok(vec)
+    }
+
+    fn locate(&self, name: String) -> CoreResult<Alist> {
+        self.alists
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|alist| alist.name == name)
+            .cloned()
+            .ok_or_else(|| not_found(&name))
+    }
+
+    fn insert(&self, alist: &Alist) -> CoreResult<()> {
+        let mut alists = self.alists.lock().unwrap();
+        if alists.iter().any(|a| a.name == alist.name) {
+            return Err(already_exists(&alist.name));
+        }
+        alists.push(alist.clone());
+        Ok(())
+    }
+
+    fn rename(&self, old_name: String, new_name: String) -> CoreResult<()> {
+        let mut alists = self.alists.lock().unwrap();
+        if alists.iter().any(|alist| alist.name == new_name) {
+            return Err(already_exists(&new_name));
+        }
+        let alist = alists
+            .iter_mut()
+            .find(|alist| alist.name == old_name)
+            .ok_or_else(|| not_found(&old_name))?;
+        alist.name = new_name;
+        Ok(())
+    }
+
+    fn modify(&self, alist: &Alist) -> CoreResult<()> {
+        let mut alists = self.alists.lock().unwrap();
+        let existing = alists
+            .iter_mut()
+            .find(|a| a.name == alist.name)
+            .ok_or_else(|| not_found(&alist.name))?;
+        *existing = alist.clone();
+        Ok(())
+    }
+
+    fn remove(&self, name: String) -> CoreResult<()> {
+        let mut alists = self.alists.lock().unwrap();
+        let index = alists
+            .iter()
+            .position(|alist| alist.name == name)
+            .ok_or_else(|| not_found(&name))?;
+        alists.remove(index);
+        Ok(())
+    }
+
+    fn clear_all(&self) -> CoreResult<()> {
+        self.alists.lock().unwrap().clear();
+        Ok(())
+    }
+}
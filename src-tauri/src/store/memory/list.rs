@@ -0,0 +1,98 @@
+use crate::{
+    misc::error::{CoreError, CoreResult},
+    store::{json::entity::list::Playlist, repo::PlaylistRepo},
+};
+use std::{collections::HashSet, sync::Mutex};
+
+#[derive(Default)]
+pub struct PlaylistMemOp {
+    lists: Mutex<HashSet<Playlist>>,
+}
+
+impl PlaylistMemOp {
+    pub fn new(lists: Vec<Playlist>) -> Self {
+        Self {
+            lists: Mutex::new(lists.into_iter().collect()),
+        }
+    }
+}
+
+fn not_found(name: &str) -> CoreError {
+    CoreError::OtherError(format!(
+        "Playlist with name {name:?} not found in the store"
+    ))
+}
+
+#[allow(unused)]
+impl PlaylistRepo for PlaylistMemOp {
+    fn list_all(&self) -> CoreResult<Vec<Playlist>> {
+        let mut vec = self
+            .lists
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        vec.sort();
+        Ok(vec)
+    }
+
+    fn locate(&self, name: String) -> CoreResult<Playlist> {
+        self.lists
+            .lock()
+            .unwrap()
+            .get(&Playlist::sample(name.clone()))
+            .cloned()
+            .ok_or_else(|| not_found(&name))
+    }
+
+    fn insert(&self, playlist: &Playlist) -> CoreResult<()> {
+        if self.lists.lock().unwrap().insert(playlist.clone()) {
+            Ok(())
+        } else {
+            Err(CoreError::OtherError(format!(
+                "Playlist with name {:?} already exists in the store",
+                playlist.name
+            )))
+        }
+    }
+
+    fn rename(&self, old_name: String, new_name: String) -> CoreResult<()> {
+        let mut lists = self.lists.lock().unwrap();
+        if lists.contains(&Playlist::sample(new_name.clone())) {
+            return Err(CoreError::OtherError(format!(
+                "Playlist with name {new_name:?} already exists in the store"
+            )));
+        }
+        let mut playlist = lists
+            .take(&Playlist::sample(old_name.clone()))
+            .ok_or_else(|| not_found(&old_name))?;
+        playlist.name = new_name;
+        lists.insert(playlist);
+        Ok(())
+    }
+
+    // replace 在没有的时候也会插进去, 先确认存在, 和 json 后端一样不落地
+    fn modify(&self, playlist: &Playlist) -> CoreResult<()> {
+        let mut lists = self.lists.lock().unwrap();
+        if !lists.contains(playlist) {
+            return Err(not_found(&playlist.name));
+        }
+        lists.replace(playlist.clone());
+        Ok(())
+    }
+
+    fn remove(&self, name: String) -> CoreResult<()> {
+        self.lists
+            .lock()
+            .unwrap()
+            .remove(&Playlist::sample(name.clone()))
+            .then_some(())
+            .ok_or_else(|| not_found(&name))
+    }
+
+    fn clear_all(&self) -> CoreResult<()> {
+        self.lists.lock().unwrap().clear();
+        Ok(())
+    }
+}
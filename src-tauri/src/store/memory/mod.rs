@@ -0,0 +1,5 @@
+// 纯内存后端, 不读 Config 也不碰文件系统, 主要给 controller 的测试用
+pub mod alist;
+pub mod list;
+pub mod recent;
+pub mod song;
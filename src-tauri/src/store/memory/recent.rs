@@ -0,0 +1,64 @@
+use crate::{
+    misc::error::{CoreError, CoreResult},
+    store::{
+        json::entity::{
+            recent::{Recent, Recents},
+            song::TrackId,
+        },
+        repo::RecentRepo,
+    },
+};
+use std::sync::Mutex;
+
+// 和 json 后端一样, 最新的在最前面
+#[derive(Default)]
+pub struct RecentMemOp {
+    recents: Mutex<Recents>,
+}
+
+impl RecentMemOp {
+    pub fn new(recents: Recents) -> Self {
+        Self {
+            recents: Mutex::new(recents),
+        }
+    }
+}
+
+#[allow(unused)]
+impl RecentRepo for RecentMemOp {
+    fn list_all(&self) -> CoreResult<Recents> {
+        Ok(self.recents.lock().unwrap().clone())
+    }
+
+    fn clear(&self) -> CoreResult<()> {
+        self.recents.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn insert(&self, recent: &Recent) -> CoreResult<()> {
+        self.recents.lock().unwrap().insert(0, recent.clone());
+        Ok(())
+    }
+
+    fn remove_by_index(&self, index: usize) -> CoreResult<()> {
+        let mut recents = self.recents.lock().unwrap();
+        if index < recents.len() {
+            recents.remove(index);
+            Ok(())
+        } else {
+            Err(CoreError::OtherError(format!(
+                "Index {} out of bounds for recents of length {}",
+                index,
+                recents.len()
+            )))
+        }
+    }
+
+    fn remove_by_song_ids(&self, song_ids: Vec<&TrackId>) -> CoreResult<()> {
+        self.recents
+            .lock()
+            .unwrap()
+            .retain(|recent| !song_ids.contains(&&recent.song));
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+use crate::{
+    misc::error::{CoreError, CoreResult},
+    store::{json::entity::song::Song, repo::SongRepo},
+};
+use std::{collections::HashSet, path::PathBuf, sync::Mutex};
+
+#[derive(Default)]
+pub struct SongMemOp {
+    songs: Mutex<HashSet<Song>>,
+}
+
+impl SongMemOp {
+    pub fn new(songs: Vec<Song>) -> Self {
+        Self {
+            songs: Mutex::new(songs.into_iter().collect()),
+        }
+    }
+}
+
+#[allow(unused)]
+impl SongRepo for SongMemOp {
+    fn list_all(&self) -> CoreResult<Vec<Song>> {
+        Ok(self.songs.lock().unwrap().iter().cloned().collect())
+    }
+
+    fn locate(&self, file: &PathBuf) -> CoreResult<Song> {
+        self.songs
+            .lock()
+            .unwrap()
+            .get(&Song::sample(file.clone()))
+            .cloned()
+            .ok_or_else(|| {
+                CoreError::OtherError(format!("Song with path {file:?} not found in the store"))
+            })
+    }
+
+    fn save_all(&self, songs: &Vec<Song>) -> CoreResult<()> {
+        *self.songs.lock().unwrap() = songs.iter().cloned().collect();
+        Ok(())
+    }
+
+    fn add_save(&self, songs: &Vec<Song>) -> CoreResult<()> {
+        let mut existing_songs = self.songs.lock().unwrap();
+        songs.iter().for_each(|song| {
+            existing_songs.insert(song.clone());
+        });
+        Ok(())
+    }
+
+    fn remove(&self, songs: &Vec<Song>) -> CoreResult<()> {
+        self.remove_by_files(&songs.iter().map(|song| song.path.clone()).collect())
+    }
+
+    // 先在副本上改, 出错时不留下半截状态, 和 json 后端一致
+    fn remove_by_files(&self, files: &Vec<PathBuf>) -> CoreResult<()> {
+        let mut existing_songs = self.songs.lock().unwrap();
+        let mut songs = existing_songs.clone();
+        files.iter().try_for_each(|file| {
+            songs
+                .remove(&Song::sample(file.clone()))
+                .then_some(())
+                .ok_or_else(|| {
+                    CoreError::OtherError(format!("Remove file with path {file:?} not found"))
+                })
+        })?;
+        *existing_songs = songs;
+        Ok(())
+    }
+
+    fn modify(&self, song: &Song) -> CoreResult<()> {
+        self.modify_batch(&vec![song.clone()])
+    }
+
+    fn modify_batch(&self, songs: &Vec<Song>) -> CoreResult<()> {
+        let mut existing_songs = self.songs.lock().unwrap();
+        let mut updated = existing_songs.clone();
+        songs.iter().try_for_each(|song| {
+            updated.replace(song.clone()).map(|_| ()).ok_or_else(|| {
+                CoreError::OtherError(format!(
+                    "Song with path {:?} not found in the store",
+                    song.path
+                ))
+            })
+        })?;
+        *existing_songs = updated;
+        Ok(())
+    }
+}
@@ -1,2 +1,103 @@
 pub mod db;
 pub mod json;
+#[cfg(test)]
+pub mod memory;
+pub mod repo;
+
+use crate::misc::{
+    config::get_global,
+    error::{CoreError, CoreResult},
+};
+use db::op::{alist::AlistDbOp, list::PlaylistDbOp, recent::RecentDbOp, song::SongDbOp};
+use json::{
+    entity::{alist::Alist, list::Playlist, recent::Recents, song::Song},
+    op::{
+        alist::AlistOp,
+        list::PlaylistOp,
+        recent::RecentOp,
+        sm::{self, StoreManager},
+        song::SongOp,
+    },
+};
+use repo::{AlistRepo, PlaylistRepo, RecentRepo, SongRepo};
+use std::{collections::HashSet, sync::Arc};
+
+/// which persistence backend the controllers are built on, see `store_backend` in Config
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Json,
+    Sqlite,
+}
+
+impl Backend {
+    pub fn from_config() -> Self {
+        match get_global()
+            .get("store_backend")
+            .and_then(|v| v.as_str().map(|s| s.to_string()))
+            .as_deref()
+        {
+            Some("sqlite") => Backend::Sqlite,
+            _ => Backend::Json,
+        }
+    }
+}
+
+pub(crate) fn config_str(key: &str) -> CoreResult<String> {
+    Ok(get_global()
+        .get(key)
+        .ok_or_else(|| CoreError::OtherError(format!("missing 'store.{key}' key in Config")))?
+        .as_str()
+        .ok_or_else(|| CoreError::OtherError(format!("`store.{key}` field not a string")))?
+        .to_string())
+}
+
+// 以下按 Config 选后端, 测试里直接用 store::memory 下的实现
+
+pub fn song_repo() -> CoreResult<Arc<dyn SongRepo>> {
+    Ok(match Backend::from_config() {
+        Backend::Sqlite => Arc::new(SongDbOp {
+            db: db::get_global()?,
+        }),
+        Backend::Json => Arc::new(SongOp {
+            sm: StoreManager::<HashSet<Song>>::shared(config_str("song_store")?)?,
+        }),
+    })
+}
+
+pub fn playlist_repo() -> CoreResult<Arc<dyn PlaylistRepo>> {
+    Ok(match Backend::from_config() {
+        Backend::Sqlite => Arc::new(PlaylistDbOp {
+            db: db::get_global()?,
+        }),
+        Backend::Json => Arc::new(PlaylistOp {
+            sm: StoreManager::<HashSet<Playlist>>::shared(config_str("playlist_store")?)?,
+        }),
+    })
+}
+
+pub fn alist_repo() -> CoreResult<Arc<dyn AlistRepo>> {
+    Ok(match Backend::from_config() {
+        Backend::Sqlite => Arc::new(AlistDbOp {
+            db: db::get_global()?,
+        }),
+        Backend::Json => Arc::new(AlistOp {
+            sm: StoreManager::<HashSet<Alist>>::shared(config_str("alist_store")?)?,
+        }),
+    })
+}
+
+pub fn recent_repo() -> CoreResult<Arc<dyn RecentRepo>> {
+    Ok(match Backend::from_config() {
+        Backend::Sqlite => Arc::new(RecentDbOp {
+            db: db::get_global()?,
+        }),
+        Backend::Json => Arc::new(RecentOp {
+            sm: StoreManager::<Recents>::shared(config_str("recent_store")?)?,
+        }),
+    })
+}
+
+/// write pending json changes to disk, the sqlite backend has nothing to flush
+pub fn flush() {
+    sm::flush_all();
+}
@@ -0,0 +1,324 @@
+use crate::{
+    misc::error::{CoreError, CoreResult},
+    store::json::entity::{
+        alist::{Aelement, Alist},
+        list::Playlist,
+        recent::{Recent, Recents},
+        song::{Song, TrackId},
+    },
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+// 不同后端 (json / sqlite / memory) 共用的操作集合, controller 只认这些 trait
+
+#[allow(unused)]
+pub trait SongRepo: Send + Sync {
+    fn list_all(&self) -> CoreResult<Vec<Song>>;
+
+    fn locate(&self, file: &PathBuf) -> CoreResult<Song>;
+
+    fn save_all(&self, songs: &Vec<Song>) -> CoreResult<()>;
+
+    fn add_save(&self, songs: &Vec<Song>) -> CoreResult<()>;
+
+    fn remove(&self, songs: &Vec<Song>) -> CoreResult<()>;
+
+    fn remove_by_files(&self, files: &Vec<PathBuf>) -> CoreResult<()>;
+
+    fn modify(&self, song: &Song) -> CoreResult<()>;
+
+    fn insert(&self, song: &Song) -> CoreResult<()> {
+        self.add_save(&vec![song.clone()])
+    }
+
+    /// songs not in the store are skipped
+    fn locate_batch(&self, files: &Vec<PathBuf>) -> CoreResult<Vec<Song>> {
+        Ok(files
+            .iter()
+            .filter_map(|file| self.locate(file).ok())
+            .collect())
+    }
+
+    fn modify_batch(&self, songs: &Vec<Song>) -> CoreResult<()> {
+        songs.iter().try_for_each(|song| self.modify(song))
+    }
+
+    fn list_by_artist(&self, artist: &str) -> CoreResult<Vec<Song>> {
+        Ok(self
+            .list_all()?
+            .into_iter()
+            .filter(|song| song.artist.as_deref() == Some(artist))
+            .collect())
+    }
+
+    fn list_unknown_artist(&self) -> CoreResult<Vec<Song>> {
+        Ok(self
+            .list_all()?
+            .into_iter()
+            .filter(|song| song.artist.is_none())
+            .collect())
+    }
+
+    fn list_by_artist_release(&self, artist: &str, release: &str) -> CoreResult<Vec<Song>> {
+        Ok(self
+            .list_all()?
+            .into_iter()
+            .filter(|song| {
+                song.artist.as_deref() == Some(artist) && song.release.as_deref() == Some(release)
+            })
+            .collect())
+    }
+
+    fn list_unknown_release(&self) -> CoreResult<Vec<Song>> {
+        Ok(self
+            .list_all()?
+            .into_iter()
+            .filter(|song| song.release.is_none())
+            .collect())
+    }
+}
+
+#[allow(unused)]
+pub trait PlaylistRepo: Send + Sync {
+    fn list_all(&self) -> CoreResult<Vec<Playlist>>;
+
+    fn locate(&self, name: String) -> CoreResult<Playlist>;
+
+    fn insert(&self, playlist: &Playlist) -> CoreResult<()>;
+
+    fn rename(&self, old_name: String, new_name: String) -> CoreResult<()>;
+
+    fn modify(&self, playlist: &Playlist) -> CoreResult<()>;
+
+    fn remove(&self, name: String) -> CoreResult<()>;
+
+    fn clear_all(&self) -> CoreResult<()>;
+
+    fn locate_batch(&self, names: Vec<String>) -> CoreResult<Vec<Playlist>> {
+        names.into_iter().map(|name| self.locate(name)).collect()
+    }
+
+    fn insert_batch(&self, playlists: &Vec<Playlist>) -> CoreResult<()> {
+        playlists
+            .iter()
+            .try_for_each(|playlist| self.insert(playlist))
+    }
+
+    fn create(&self, name: String) -> CoreResult<()> {
+        self.insert(&Playlist {
+            name,
+            songs: Vec::new(),
+            created_at: chrono::Local::now().timestamp().to_string(),
+        })
+    }
+
+    fn remove_batch(&self, names: Vec<String>) -> CoreResult<()> {
+        names.into_iter().try_for_each(|name| self.remove(name))
+    }
+
+    fn add_songs(&self, name: String, songs: Vec<Song>) -> CoreResult<()> {
+        let mut playlist = self.locate(name)?;
+        playlist.songs.extend(songs.into_iter().map(|song| song.id));
+        self.modify(&playlist)
+    }
+
+    fn unique(&self, name: String) -> CoreResult<()> {
+        let mut playlist = self.locate(name)?;
+        let mut seen = HashSet::new();
+        playlist.songs.retain(|id| seen.insert(id.clone()));
+        self.modify(&playlist)
+    }
+
+    fn remove_songs_all(&self, name: String, songs: Vec<Song>) -> CoreResult<()> {
+        let mut playlist = self.locate(name)?;
+        let song_ids: HashSet<TrackId> = songs.into_iter().map(|song| song.id).collect();
+        playlist.songs.retain(|id| !song_ids.contains(id));
+        self.modify(&playlist)
+    }
+
+    /// drop the given tracks from every playlist
+    fn remove_song_ids_all(&self, song_ids: Vec<TrackId>) -> CoreResult<()> {
+        self.list_all()?
+            .into_iter()
+            .filter(|pl| pl.songs.iter().any(|id| song_ids.contains(id)))
+            .try_for_each(|mut playlist| {
+                playlist.songs.retain(|id| !song_ids.contains(id));
+                self.modify(&playlist)
+            })
+    }
+
+    fn remove_songs(&self, name: String, index: Vec<usize>) -> CoreResult<()> {
+        let mut playlist = self.locate(name)?;
+        let index_set: HashSet<usize> = index.into_iter().collect();
+        playlist.songs = playlist
+            .songs
+            .into_iter()
+            .enumerate()
+            .filter_map(|(idx, path)| (!index_set.contains(&idx)).then_some(path))
+            .collect();
+        self.modify(&playlist)
+    }
+
+    fn clear_songs(&self, name: String) -> CoreResult<()> {
+        let mut playlist = self.locate(name)?;
+        playlist.songs.clear();
+        self.modify(&playlist)
+    }
+}
+
+#[allow(unused)]
+pub trait AlistRepo: Send + Sync {
+    fn list_all(&self) -> CoreResult<Vec<Alist>>;
+
+    fn locate(&self, name: String) -> CoreResult<Alist>;
+
+    fn insert(&self, alist: &Alist) -> CoreResult<()>;
+
+    fn rename(&self, old_name: String, new_name: String) -> CoreResult<()>;
+
+    fn modify(&self, alist: &Alist) -> CoreResult<()>;
+
+    fn remove(&self, name: String) -> CoreResult<()>;
+
+    fn clear_all(&self) -> CoreResult<()>;
+
+    fn locate_batch(&self, names: Vec<String>) -> CoreResult<Vec<Alist>> {
+        names.into_iter().map(|name| self.locate(name)).collect()
+    }
+
+    fn insert_batch(&self, alists: &Vec<Alist>) -> CoreResult<()> {
+        alists.iter().try_for_each(|alist| self.insert(alist))
+    }
+
+    fn create(&self, name: String) -> CoreResult<()> {
+        self.insert(&Alist {
+            name,
+            elements: Vec::new(),
+            created_at: chrono::Local::now().timestamp().to_string(),
+        })
+    }
+
+    fn remove_batch(&self, names: Vec<String>) -> CoreResult<()> {
+        names.into_iter().try_for_each(|name| self.remove(name))
+    }
+
+    fn add_element(&self, alist_name: String, element: Aelement) -> CoreResult<()> {
+        let mut alist = self.locate(alist_name)?;
+        alist.elements.push(element);
+        self.modify(&alist)
+    }
+
+    fn remove_element(&self, alist_name: String, index: usize) -> CoreResult<()> {
+        self.remove_elements(alist_name, vec![index])
+    }
+
+    fn remove_elements(&self, alist_name: String, indices: Vec<usize>) -> CoreResult<()> {
+        let mut alist = self.locate(alist_name.clone())?;
+        let mut sorted_indices = indices;
+        sorted_indices.sort_unstable_by(|a, b| b.cmp(a));
+        sorted_indices.iter().try_for_each(|&index| {
+            if index >= alist.elements.len() {
+                return Err(CoreError::OtherError(format!(
+                    "Index {index} out of bounds for alist {alist_name:?}"
+                )));
+            }
+            alist.elements.remove(index);
+            Ok(())
+        })?;
+        self.modify(&alist)
+    }
+
+    fn remove_element_all(&self, alist_name: String, element: Aelement) -> CoreResult<()> {
+        self.remove_elements_all(alist_name, vec![element])
+    }
+
+    fn remove_elements_all(&self, alist_name: String, elements: Vec<Aelement>) -> CoreResult<()> {
+        let mut alist = self.locate(alist_name)?;
+        let element_set: HashSet<Aelement> = elements.into_iter().collect();
+        alist.elements.retain(|e| !element_set.contains(e));
+        self.modify(&alist)
+    }
+
+    fn clear(&self, alist_name: String) -> CoreResult<()> {
+        let mut alist = self.locate(alist_name)?;
+        alist.elements.clear();
+        self.modify(&alist)
+    }
+
+    fn list_all_songs(&self, alist_name: String, library: &[Song]) -> CoreResult<Vec<PathBuf>> {
+        Ok(self
+            .locate(alist_name)?
+            .songs(library)
+            .into_iter()
+            .map(|song| song.path.clone())
+            .collect())
+    }
+
+    /// point song / release / artist elements of every alist at the new paths
+    fn rewrite_song_paths(&self, moves: &HashMap<PathBuf, PathBuf>) -> CoreResult<()> {
+        self.list_all()?
+            .into_iter()
+            .filter_map(|mut alist| alist.rewrite_song_paths(moves).then_some(alist))
+            .try_for_each(|alist| self.modify(&alist))
+    }
+
+    fn list_all_elements(&self, alist_name: String) -> CoreResult<Vec<Aelement>> {
+        Ok(self.locate(alist_name)?.elements)
+    }
+
+    // convert an alist to a playlist
+    fn freeze(&self, alist_name: String, library: &[Song]) -> CoreResult<Playlist> {
+        let alist = self.locate(alist_name)?;
+        Ok(Playlist {
+            songs: alist
+                .songs(library)
+                .into_iter()
+                .map(|song| song.id.clone())
+                .collect(),
+            name: alist.name,
+            created_at: alist.created_at,
+        })
+    }
+}
+
+#[allow(unused)]
+pub trait RecentRepo: Send + Sync {
+    fn list_all(&self) -> CoreResult<Recents>;
+
+    fn clear(&self) -> CoreResult<()>;
+
+    fn insert(&self, recent: &Recent) -> CoreResult<()>;
+
+    fn remove_by_index(&self, index: usize) -> CoreResult<()>;
+
+    fn remove_by_song_ids(&self, song_ids: Vec<&TrackId>) -> CoreResult<()>;
+
+    /// `recents` is newest first, like `list_all`
+    fn insert_batch(&self, recents: &Recents) -> CoreResult<()> {
+        recents
+            .iter()
+            .rev()
+            .try_for_each(|recent| self.insert(recent))
+    }
+
+    fn add(&self, song: Song) -> CoreResult<()> {
+        self.insert(&Recent::from_song(song))
+    }
+
+    fn remove_by_indexs(&self, indices: Vec<usize>) -> CoreResult<()> {
+        indices
+            .into_iter()
+            .try_for_each(|index| self.remove_by_index(index))
+    }
+
+    fn remove_by_song_all(&self, song: Song) -> CoreResult<()> {
+        self.remove_by_song_ids(vec![&song.id])
+    }
+
+    fn remove_by_songs_all(&self, songs: Vec<Song>) -> CoreResult<()> {
+        self.remove_by_song_ids(songs.iter().map(|s| &s.id).collect())
+    }
+}
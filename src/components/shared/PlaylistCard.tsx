@@ -3,7 +3,7 @@ import { Playlist, Song } from "@/types";
 import { ListMusic, Music, MoreHorizontal, Play } from "lucide-react";
 import React, { useState, useEffect } from "react";
 import { useNavigate } from "react-router-dom";
-import { cn } from "@/lib/utils";
+import { cn, trackIdsToPaths } from "@/lib/utils";
 import { useTranslation } from "react-i18next";
 import { getPlaylistCover } from "@/lib/cover";
 import {
@@ -64,7 +64,7 @@ export function PlaylistCard({
       return;
     }
     setIsLoading(true);
-    getPlaylistCover(playlist.songs, songs)
+    getPlaylistCover(trackIdsToPaths(playlist.songs, songs), songs)
       .then(setCoverUrl)
       .finally(() => setIsLoading(false));
   }, [playlist, songs, customCoverUrl]);
@@ -89,7 +89,7 @@ export function PlaylistCard({
   const handlePlay = (e: React.MouseEvent) => {
     e.stopPropagation();
     if (playlist.songs.length > 0) {
-      playerActions.playQueue(playlist.songs, 0);
+      playerActions.playQueue(trackIdsToPaths(playlist.songs, songs), 0);
     }
   };
 
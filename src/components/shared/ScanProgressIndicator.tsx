@@ -12,7 +12,8 @@ export function ScanProgressIndicator({
   className,
 }: ScanProgressIndicatorProps) {
   const { t } = useTranslation();
-  const { isScanning, processed, total, progress } = useScanProgress();
+  const { isScanning, isPaused, phase, processed, total, progress, failed } =
+    useScanProgress();
 
   if (!isScanning && progress === 0) return null;
 
@@ -33,7 +34,11 @@ export function ScanProgressIndicator({
         <div className="flex-1 space-y-2">
           <div className="flex items-center justify-between">
             <p className="text-sm font-medium">
-              {isScanning ? t("Scanning library...") : t("Scan complete")}
+              {isScanning
+                ? isPaused
+                  ? t("Scan paused")
+                  : t("Scanning library...")
+                : t("Scan complete")}
             </p>
             <span className="text-xs text-muted-foreground">{progress}%</span>
           </div>
@@ -42,8 +47,9 @@ export function ScanProgressIndicator({
 
           <p className="text-xs text-muted-foreground">
             {isScanning
-              ? `${processed} / ${total} files`
+              ? `${phase === "cached" ? t("Checking") : t("Reading tags")} ${processed} / ${total} files`
               : `${processed} songs loaded`}
+            {failed > 0 && ` · ${failed} ${t("failed")}`}
           </p>
         </div>
       </div>
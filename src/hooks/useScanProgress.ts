@@ -1,39 +1,75 @@
 import { useEffect, useState } from "react";
 import { listen } from "@tauri-apps/api/event";
+import type { ScanPhase, ScanStatus } from "@/types";
 
 export interface ScanProgress {
   isScanning: boolean;
+  isPaused: boolean;
+  phase: ScanPhase | null;
   processed: number;
   total: number;
   progress: number;
+  added: number;
+  removed: number;
+  failed: number;
 }
 
+const idle: ScanProgress = {
+  isScanning: false,
+  isPaused: false,
+  phase: null,
+  processed: 0,
+  total: 0,
+  progress: 0,
+  added: 0,
+  removed: 0,
+  failed: 0,
+};
+
 export function useScanProgress() {
-  const [scanStatus, setScanStatus] = useState<ScanProgress>({
-    isScanning: false,
-    processed: 0,
-    total: 0,
-    progress: 0,
-  });
+  const [scanStatus, setScanStatus] = useState<ScanProgress>(idle);
 
   useEffect(() => {
     let progressUnlisten: (() => void) | null = null;
     let completeUnlisten: (() => void) | null = null;
+    let stateUnlisten: (() => void) | null = null;
 
     const setupListeners = async () => {
       progressUnlisten = await listen<{
+        phase: ScanPhase;
         processed: number;
         total: number;
         progress: number;
+        added: number;
+        removed: number;
+        failed: number;
       }>("scan_progress", (event) => {
-        const { processed, total, progress } = event.payload;
+        const { phase, processed, total, progress, added, removed, failed } =
+          event.payload;
 
-        setScanStatus({
+        setScanStatus((prev) => ({
           isScanning: true,
+          isPaused: prev.isPaused,
+          phase,
           processed,
           total,
           progress,
-        });
+          added,
+          removed,
+          failed,
+        }));
+      });
+
+      stateUnlisten = await listen<ScanStatus>("scan_state", (event) => {
+        const { state } = event.payload;
+        setScanStatus((prev) => ({
+          ...prev,
+          isPaused: state === "paused",
+        }));
+        // 失败时不会有 scan_complete
+        if (state === "failed") {
+          setScanStatus(idle);
+        }
       });
 
       completeUnlisten = await listen<{
@@ -42,20 +78,17 @@ export function useScanProgress() {
       }>("scan_complete", (event) => {
         const { total, loaded } = event.payload;
 
-        setScanStatus({
+        setScanStatus((prev) => ({
+          ...prev,
           isScanning: false,
+          phase: null,
           processed: loaded,
           total,
           progress: 100,
-        });
+        }));
 
         setTimeout(() => {
-          setScanStatus({
-            isScanning: false,
-            processed: 0,
-            total: 0,
-            progress: 0,
-          });
+          setScanStatus(idle);
         }, 5000);
       });
     };
@@ -65,6 +98,7 @@ export function useScanProgress() {
     return () => {
       if (progressUnlisten) progressUnlisten();
       if (completeUnlisten) completeUnlisten();
+      if (stateUnlisten) stateUnlisten();
     };
   }, []);
 
@@ -44,6 +44,7 @@ export function expandAlistSongs(
   context: AlistExpansionContext
 ): Song[] {
   const songMap = new Map(context.songs.map((song) => [song.path, song]));
+  const songIdMap = new Map(context.songs.map((song) => [song.id, song]));
   const playlistMap = new Map(context.playlists.map((pl) => [pl.name, pl]));
   const releaseMap = new Map(
     context.releases.map((rel) => [releaseKey(rel), rel])
@@ -54,8 +55,7 @@ export function expandAlistSongs(
   const seen = new Set<string>();
   const result: Song[] = [];
 
-  const pushSong = (path: string) => {
-    const song = songMap.get(path);
+  const pushSong = (song: Song | undefined) => {
     if (song && !seen.has(song.path)) {
       seen.add(song.path);
       result.push(song);
@@ -64,27 +64,29 @@ export function expandAlistSongs(
 
   alist.elements.forEach((element) => {
     if ("Song" in element) {
-      pushSong(element.Song.path);
+      pushSong(
+        songMap.get(element.Song.path) ?? songIdMap.get(element.Song.id)
+      );
       return;
     }
 
     if ("Playlist" in element) {
       const playlist =
         playlistMap.get(element.Playlist.name) ?? element.Playlist;
-      playlist.songs.forEach(pushSong);
+      playlist.songs.forEach((id) => pushSong(songIdMap.get(id)));
       return;
     }
 
     if ("Release" in element) {
       const release =
         releaseMap.get(releaseKey(element.Release)) ?? element.Release;
-      release.songs.forEach(pushSong);
+      release.songs.forEach((path) => pushSong(songMap.get(path)));
       return;
     }
 
     if ("Artist" in element) {
       const artist = artistMap.get(element.Artist.name) ?? element.Artist;
-      artist.songs.forEach(pushSong);
+      artist.songs.forEach((path) => pushSong(songMap.get(path)));
     }
   });
 
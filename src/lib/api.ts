@@ -8,12 +8,34 @@ import type {
   Recent,
   Alist,
   Aelement,
+  DirRules,
+  DoctorReport,
+  DuplicateGroup,
+  DuplicateMerge,
+  DuplicateOptions,
+  ListenPath,
+  LoudnessOptions,
+  LoudnessStatus,
+  RootStatus,
+  LibraryManifest,
+  QueueRepeat,
+  QueueSnapshot,
+  QueueSource,
+  ScanReport,
+  ScanStatus,
+  SongHit,
+  SongPage,
+  SongQuery,
 } from "@/types";
 
 export const api = {
   ping: () => invoke<string>("ping"),
 
   getAllSongs: () => invoke<Song[]>("get_all_songs"),
+  querySongs: (query: SongQuery) =>
+    invoke<SongPage>("query_songs", { query }),
+  searchSongs: (query: string, limit?: number) =>
+    invoke<SongHit[]>("search_songs", { query, limit }),
   getAllReleases: () => invoke<Release[]>("get_all_releases"),
   getAllArtists: () => invoke<Artist[]>("get_all_artists"),
   getAllPlaylists: () => invoke<Playlist[]>("get_all_playlists"),
@@ -28,7 +50,29 @@ export const api = {
   get_lyric: (song: Song) => invoke<string | null>("get_lyric", { song }),
 
   refreshLibrary: () => invoke<void>("refresh_library"),
-  addDir: (dir: string) => invoke<void>("add_dir", { dir }),
+  scanStatus: () => invoke<ScanStatus>("scan_status"),
+  getScanReport: () => invoke<ScanReport>("get_scan_report"),
+  retryScanFailures: (files?: string[]) =>
+    invoke<ScanReport>("retry_scan_failures", { files }),
+  pauseScan: () => invoke<void>("pause_scan"),
+  resumeScan: () => invoke<void>("resume_scan"),
+  cancelScan: () => invoke<void>("cancel_scan"),
+  addDir: (dir: string, rules?: DirRules) =>
+    invoke<void>("add_dir", { dir, rules }),
+  getListenPaths: () => invoke<ListenPath[]>("get_listen_paths"),
+  getRootStatus: () => invoke<RootStatus[]>("get_root_status"),
+  libraryDoctor: (repair: boolean) =>
+    invoke<DoctorReport>("library_doctor", { repair }),
+  findDuplicates: (options?: DuplicateOptions) =>
+    invoke<DuplicateGroup[]>("find_duplicates", { options }),
+  mergeDuplicates: (merges: DuplicateMerge[]) =>
+    invoke<void>("merge_duplicates", { merges }),
+  analyzeLoudness: (options?: LoudnessOptions) =>
+    invoke<void>("analyze_loudness", { options }),
+  loudnessStatus: () => invoke<LoudnessStatus>("loudness_status"),
+  cancelLoudnessAnalysis: () => invoke<void>("cancel_loudness_analysis"),
+  setDirRules: (dir: string, rules: DirRules) =>
+    invoke<void>("set_dir_rules", { dir, rules }),
   removeDir: (dir: string) => invoke<void>("remove_dir", { dir }),
   addSingleSong: (file: string) => invoke<void>("add_single_song", { file }),
 
@@ -105,6 +149,17 @@ export const api = {
 
   clearRecents: () => invoke<void>("clear_recents"),
 
+  exportLibrary: (path: string, withCaches: boolean) =>
+    invoke<LibraryManifest>("export_library", { path, withCaches }),
+  readLibraryArchive: (path: string) =>
+    invoke<LibraryManifest>("read_library_archive", { path }),
+  // roots: archive root -> where it lives on this machine
+  importLibrary: (
+    path: string,
+    roots: Record<string, string>,
+    withCaches: boolean
+  ) => invoke<LibraryManifest>("import_library", { path, roots, withCaches }),
+
   player_play_file: (path: string) =>
     invoke<void>("player_play_file", { path }),
   player_play: () => invoke<void>("player_play"),
@@ -114,4 +169,25 @@ export const api = {
     invoke<void>("player_seek", { positionSeconds }),
   player_set_volume: (volume: number) =>
     invoke<void>("player_set_volume", { volume }),
+  player_get_queue: () => invoke<QueueSnapshot>("player_get_queue"),
+  player_play_queue: (source: QueueSource, start?: number) =>
+    invoke<void>("player_play_queue", { source, start }),
+  player_enqueue: (source: QueueSource) =>
+    invoke<void>("player_enqueue", { source }),
+  player_insert_next: (source: QueueSource) =>
+    invoke<void>("player_insert_next", { source }),
+  player_remove_from_queue: (indices: number[]) =>
+    invoke<void>("player_remove_from_queue", { indices }),
+  player_move_in_queue: (from: number, to: number) =>
+    invoke<void>("player_move_in_queue", { from, to }),
+  player_jump: (index: number) => invoke<void>("player_jump", { index }),
+  player_clear_queue: () => invoke<void>("player_clear_queue"),
+  player_next: () => invoke<void>("player_next"),
+  player_prev: () => invoke<void>("player_prev"),
+  player_set_repeat: (repeat: QueueRepeat) =>
+    invoke<void>("player_set_repeat", { repeat }),
+  player_set_shuffle: (shuffle: boolean) =>
+    invoke<void>("player_set_shuffle", { shuffle }),
+  setReleaseGapless: (title: string, artist: string | null, gapless: boolean) =>
+    invoke<void>("set_release_gapless", { title, artist, gapless }),
 };
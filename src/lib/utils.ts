@@ -1,5 +1,6 @@
 import { clsx, type ClassValue } from "clsx";
 import { twMerge } from "tailwind-merge";
+import type { Song } from "@/types";
 
 export function cn(...inputs: ClassValue[]) {
   return twMerge(clsx(inputs));
@@ -18,3 +19,11 @@ export function convertMsToMinutesAndSeconds(ms: number): string {
 
   return `${formattedMinutes}:${formattedSeconds}`;
 }
+
+// playlists / recents reference songs by track id, most of the player still works on paths
+export function trackIdsToPaths(ids: string[], songs: Song[]): string[] {
+  const pathMap = new Map(songs.map((song) => [song.id, song.path]));
+  return ids
+    .map((id) => pathMap.get(id))
+    .filter((path): path is string => !!path);
+}
@@ -124,9 +124,9 @@ export function PlaylistDetailPage() {
 
   const playlistSongs = useMemo(() => {
     if (!playlist) return [];
-    const songMap = new Map(songs.map((s) => [s.path, s]));
+    const songMap = new Map(songs.map((s) => [s.id, s]));
     return playlist.songs
-      .map((songPath) => songMap.get(songPath))
+      .map((songId) => songMap.get(songId))
       .filter((song): song is Song => !!song);
   }, [playlist, songs]);
 
@@ -138,10 +138,13 @@ export function PlaylistDetailPage() {
       return;
     }
     setIsCoverLoading(true);
-    getPlaylistCover(playlist.songs, songs)
+    getPlaylistCover(
+      playlistSongs.map((song) => song.path),
+      songs
+    )
       .then(setCoverUrl)
       .finally(() => setIsCoverLoading(false));
-  }, [playlist, songs, customCoverUrl]);
+  }, [playlist, playlistSongs, songs, customCoverUrl]);
 
   const isDropTarget =
     playlist &&
@@ -35,7 +35,14 @@ export function RecentsPage() {
 
   const recentsWithSongData: RecentWithSong[] = React.useMemo(() => {
     return recents.map((recent) => {
-      const songData = songs.find((song) => song.path === recent.song) || null;
+      // byte-identical copies share an id, pick the smallest path like the backend does
+      const songData =
+        songs
+          .filter((song) => song.id === recent.song)
+          .reduce<Song | null>(
+            (kept, song) => (kept && kept.path <= song.path ? kept : song),
+            null,
+          );
       return {
         ...recent,
         songData,
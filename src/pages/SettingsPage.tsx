@@ -47,6 +47,7 @@ import {
 import { useSettingsStore } from "@/stores/settingStore";
 import { useLibraryStore } from "@/stores/libraryStore";
 import { api } from "@/lib/api";
+import type { ListenPath } from "@/types";
 import { open as openDialog } from "@tauri-apps/plugin-dialog";
 import { openPath as openOpener } from "@tauri-apps/plugin-opener";
 import { appDataDir } from "@tauri-apps/api/path";
@@ -67,7 +68,7 @@ interface AppConfig {
   release_cover_store: string;
   lyric_store: string;
   single_song_store: string;
-  listen_paths: string[];
+  listen_paths: ListenPath[];
   debounce_timeout_ms: number;
   supported_audio_extensions: string[];
   theme: "dark" | "light" | "system";
@@ -1,4 +1,3 @@
-
 import { create } from "zustand";
 import { listen } from "@tauri-apps/api/event";
 import { api } from "@/lib/api";
@@ -8,63 +7,30 @@ import {
   PlaybackTrackLoadedPayload,
   PlaybackProgressPayload,
   PlaybackEndedPayload,
+  QueueRepeat,
+  QueueSnapshot,
 } from "@/types";
 import { toast } from "sonner";
 
-
-enum InternalPlaybackMode {
-  SEQUENTIAL,
-  LOOP,
-  CURRENT_ITEM_IN_LOOP,
-  RANDOM,
-}
-function getInternalPlaybackMode(
-  isShuffling: boolean,
-  repeatMode: RepeatMode
-): InternalPlaybackMode {
-  if (repeatMode === RepeatMode.CURRENT_ITEM_IN_LOOP) {
-    return InternalPlaybackMode.CURRENT_ITEM_IN_LOOP;
-  }
-  if (isShuffling) {
-    return InternalPlaybackMode.RANDOM;
-  }
-  if (repeatMode === RepeatMode.LOOP) {
-    return InternalPlaybackMode.LOOP;
-  }
-  return InternalPlaybackMode.SEQUENTIAL;
-}
-function shuffleArray<T>(array: T[]): T[] {
-  const arr = [...array];
-  for (let i = arr.length - 1; i > 0; i--) {
-    const j = Math.floor(Math.random() * (i + 1));
-    [arr[i], arr[j]] = [arr[j], arr[i]];
-  }
-  return arr;
-}
-function generateShuffleQueue(
-  queueLength: number,
-  firstIndex: number
-): number[] {
-  const indices = Array.from({ length: queueLength }, (_, i) => i);
-  if (firstIndex >= 0 && firstIndex < queueLength) {
-    indices.splice(firstIndex, 1);
-  }
-  const shuffledRest = shuffleArray(indices);
-  if (firstIndex >= 0 && firstIndex < queueLength) {
-    return [firstIndex, ...shuffledRest];
-  } else {
-    return shuffledRest;
-  }
-}
+// the queue lives in the rust playback service, which also moves on when a
+// track ends; this store only mirrors it from playback-queue-changed
+const toQueueRepeat: Record<RepeatMode, QueueRepeat> = {
+  [RepeatMode.SEQUENTIAL]: "off",
+  [RepeatMode.LOOP]: "all",
+  [RepeatMode.CURRENT_ITEM_IN_LOOP]: "one",
+};
+const fromQueueRepeat: Record<QueueRepeat, RepeatMode> = {
+  off: RepeatMode.SEQUENTIAL,
+  all: RepeatMode.LOOP,
+  one: RepeatMode.CURRENT_ITEM_IN_LOOP,
+};
 
 
 interface PlayerState {
   isShuffling: boolean;
   repeatMode: RepeatMode;
   queue: string[];
-  shuffleQueue: number[];
   currentIndex: number;
-  currentShuffleIndex: number;
   currentPath: string | null;
   isPlaying: boolean;
   currentTime: number;
@@ -87,7 +53,7 @@ interface PlayerState {
     _setIsSeeking: (isSeeking: boolean) => void;
     _setLocalCurrentTime: (time: number) => void;
     _setSeekRequestTime: (time: number | null) => void;
-    _handleTrackEnded: () => void;
+    _handleQueueChanged: (payload: QueueSnapshot) => void;
     _handleRustStateChange: (payload: PlaybackStatePayload) => void;
     _handleTrackLoaded: (payload: PlaybackTrackLoadedPayload) => void;
     _handleProgress: (payload: PlaybackProgressPayload) => void;
@@ -99,9 +65,7 @@ export const usePlayerStore = create<PlayerState>((set, get) => ({
   isShuffling: false,
   repeatMode: RepeatMode.SEQUENTIAL,
   queue: [],
-  shuffleQueue: [],
   currentIndex: -1,
-  currentShuffleIndex: -1,
   currentPath: null,
   isPlaying: false,
   currentTime: 0,
@@ -113,50 +77,20 @@ export const usePlayerStore = create<PlayerState>((set, get) => ({
 
   actions: {
     playSong: (songPath) => {
-      const queue = [songPath];
-      set({
-        queue: queue,
-        shuffleQueue: [0],
-        currentIndex: 0,
-        currentShuffleIndex: 0,
-        isSeeking: false,
-        seekRequestTime: null,
-      });
-      api.player_play_file(songPath);
+      set({ isSeeking: false, seekRequestTime: null });
+      api.player_play_queue({ kind: "paths", paths: [songPath] }, 0);
     },
     toggleLyrics: () =>
       set((state) => ({ isLyricsVisible: !state.isLyricsVisible })),
     playQueue: (songPaths, startIndex) => {
-      const { isShuffling } = get();
       if (songPaths.length === 0) return;
 
       const validIndex = Math.max(
         0,
         Math.min(startIndex, songPaths.length - 1)
       );
-
-      if (isShuffling) {
-        const shuffleQueue = generateShuffleQueue(songPaths.length, validIndex);
-        set({
-          queue: songPaths,
-          shuffleQueue: shuffleQueue,
-          currentIndex: validIndex,
-          currentShuffleIndex: 0,
-          isSeeking: false,
-          seekRequestTime: null,
-        });
-        api.player_play_file(songPaths[validIndex]);
-      } else {
-        set({
-          queue: songPaths,
-          shuffleQueue: [],
-          currentIndex: validIndex,
-          currentShuffleIndex: -1,
-          isSeeking: false,
-          seekRequestTime: null,
-        });
-        api.player_play_file(songPaths[validIndex]);
-      }
+      set({ isSeeking: false, seekRequestTime: null });
+      api.player_play_queue({ kind: "paths", paths: songPaths }, validIndex);
     },
     togglePlayPause: () => {
       const { isPlaying, currentPath } = get();
@@ -169,22 +103,8 @@ export const usePlayerStore = create<PlayerState>((set, get) => ({
       }
     },
     toggleShuffle: () => {
-      const { isShuffling, queue, currentIndex } = get();
-      const newIsShuffling = !isShuffling;
-
-      if (newIsShuffling) {
-        const newShuffleQueue = generateShuffleQueue(
-          queue.length,
-          currentIndex
-        );
-        set({
-          isShuffling: true,
-          shuffleQueue: newShuffleQueue,
-          currentShuffleIndex: 0,
-        });
-      } else {
-        set({ isShuffling: false });
-      }
+      const newIsShuffling = !get().isShuffling;
+      api.player_set_shuffle(newIsShuffling);
       toast.info(newIsShuffling ? "Shuffle enabled" : "Shuffle disabled");
     },
     cycleRepeatMode: () => {
@@ -202,208 +122,18 @@ export const usePlayerStore = create<PlayerState>((set, get) => ({
         newRepeatMode = RepeatMode.SEQUENTIAL;
         toastMessage = "Sequential playback enabled";
       }
-      set({ repeatMode: newRepeatMode });
+      api.player_set_repeat(toQueueRepeat[newRepeatMode]);
       toast.info(toastMessage);
     },
 
-    
     nextTrack: () => {
-      const wasPaused = !get().isPlaying;
-      const {
-        queue,
-        currentIndex,
-        isShuffling,
-        repeatMode,
-        shuffleQueue,
-        currentShuffleIndex,
-      } = get();
-
-      if (queue.length === 0) return;
-
-      if (repeatMode === RepeatMode.CURRENT_ITEM_IN_LOOP) {
-        const nextIndex = (currentIndex + 1) % queue.length;
-        const nextPath = queue[nextIndex];
-        api.player_play_file(nextPath);
-        if (wasPaused) {
-          api.player_pause();
-        }
-        const newShuffleIndex = shuffleQueue.indexOf(nextIndex);
-        set({
-          currentIndex: nextIndex,
-          currentShuffleIndex: newShuffleIndex !== -1 ? newShuffleIndex : 0,
-          isSeeking: false,
-          seekRequestTime: null,
-        });
-        return;
-      }
-
-      const internalMode = getInternalPlaybackMode(isShuffling, repeatMode);
-
-      if (internalMode === InternalPlaybackMode.RANDOM) {
-        const nextShuffleIndex = currentShuffleIndex + 1;
-        if (nextShuffleIndex >= shuffleQueue.length) {
-          if (repeatMode === RepeatMode.LOOP) {
-            const newShuffleQueue = generateShuffleQueue(queue.length, -1);
-            const nextIndex = newShuffleQueue[0];
-            api.player_play_file(queue[nextIndex]);
-            if (wasPaused) {
-              api.player_pause();
-            }
-            set({
-              shuffleQueue: newShuffleQueue,
-              currentShuffleIndex: 0,
-              currentIndex: nextIndex,
-              isSeeking: false,
-              seekRequestTime: null,
-            });
-          } else {
-            api.player_stop();
-          }
-        } else {
-          const nextIndex = shuffleQueue[nextShuffleIndex];
-          api.player_play_file(queue[nextIndex]);
-          if (wasPaused) {
-            api.player_pause();
-          }
-          set({
-            currentShuffleIndex: nextShuffleIndex,
-            currentIndex: nextIndex,
-            isSeeking: false,
-            seekRequestTime: null,
-          });
-        }
-      } else {
-        let nextIndex = currentIndex + 1;
-        if (nextIndex >= queue.length) {
-          if (repeatMode === RepeatMode.LOOP) {
-            nextIndex = 0;
-            api.player_play_file(queue[nextIndex]);
-            if (wasPaused) {
-              api.player_pause();
-            }
-            set({
-              currentIndex: nextIndex,
-              isSeeking: false,
-              seekRequestTime: null,
-            });
-          } else {
-            api.player_stop();
-          }
-        } else {
-          api.player_play_file(queue[nextIndex]);
-          if (wasPaused) {
-            api.player_pause();
-          }
-          set({
-            currentIndex: nextIndex,
-            isSeeking: false,
-            seekRequestTime: null,
-          });
-        }
-      }
+      set({ isSeeking: false, seekRequestTime: null });
+      api.player_next();
     },
 
-    
     prevTrack: () => {
-      const wasPaused = !get().isPlaying;
-      const {
-        queue,
-        currentIndex,
-        isShuffling,
-        repeatMode,
-        shuffleQueue,
-        currentShuffleIndex,
-        currentTime,
-      } = get();
-
-      if (queue.length === 0) return;
-
-      if (currentTime > 3) {
-        api.player_seek(0);
-        return;
-      }
-
-      if (repeatMode === RepeatMode.CURRENT_ITEM_IN_LOOP) {
-        const nextIndex = (currentIndex - 1 + queue.length) % queue.length;
-        const nextPath = queue[nextIndex];
-        api.player_play_file(nextPath);
-        if (wasPaused) {
-          api.player_pause();
-        }
-        const newShuffleIndex = shuffleQueue.indexOf(nextIndex);
-        set({
-          currentIndex: nextIndex,
-          currentShuffleIndex: newShuffleIndex !== -1 ? newShuffleIndex : 0,
-          isSeeking: false,
-          seekRequestTime: null,
-        });
-        return;
-      }
-
-      const internalMode = getInternalPlaybackMode(isShuffling, repeatMode);
-
-      if (internalMode === InternalPlaybackMode.RANDOM) {
-        const nextShuffleIndex = currentShuffleIndex - 1;
-        if (nextShuffleIndex < 0) {
-          if (repeatMode === RepeatMode.LOOP) {
-            const newShuffleQueue = generateShuffleQueue(queue.length, -1);
-            const nextIndex = newShuffleQueue[newShuffleQueue.length - 1];
-            api.player_play_file(queue[nextIndex]);
-            if (wasPaused) {
-              api.player_pause();
-            }
-            set({
-              shuffleQueue: newShuffleQueue,
-              currentShuffleIndex: newShuffleQueue.length - 1,
-              currentIndex: nextIndex,
-              isSeeking: false,
-              seekRequestTime: null,
-            });
-          } else {
-            api.player_stop();
-          }
-        } else {
-          const nextIndex = shuffleQueue[nextShuffleIndex];
-          api.player_play_file(queue[nextIndex]);
-          if (wasPaused) {
-            api.player_pause();
-          }
-          set({
-            currentShuffleIndex: nextShuffleIndex,
-            currentIndex: nextIndex,
-            isSeeking: false,
-            seekRequestTime: null,
-          });
-        }
-      } else {
-        let nextIndex = currentIndex - 1;
-        if (nextIndex < 0) {
-          if (repeatMode === RepeatMode.LOOP) {
-            nextIndex = queue.length - 1;
-            api.player_play_file(queue[nextIndex]);
-            if (wasPaused) {
-              api.player_pause();
-            }
-            set({
-              currentIndex: nextIndex,
-              isSeeking: false,
-              seekRequestTime: null,
-            });
-          } else {
-            api.player_stop();
-          }
-        } else {
-          api.player_play_file(queue[nextIndex]);
-          if (wasPaused) {
-            api.player_pause();
-          }
-          set({
-            currentIndex: nextIndex,
-            isSeeking: false,
-            seekRequestTime: null,
-          });
-        }
-      }
+      set({ isSeeking: false, seekRequestTime: null });
+      api.player_prev();
     },
 
     seek: (time) => {
@@ -424,19 +154,15 @@ export const usePlayerStore = create<PlayerState>((set, get) => ({
     },
 
 
-    _handleTrackEnded: () => {
-      const { queue, currentIndex, isShuffling, repeatMode } = get();
-      const internalMode = getInternalPlaybackMode(isShuffling, repeatMode);
-
-      if (internalMode === InternalPlaybackMode.CURRENT_ITEM_IN_LOOP) {
-        const currentPath = queue[currentIndex];
-        api.player_play_file(currentPath);
-        return;
-      }
-      get().actions.nextTrack();
+    _handleQueueChanged: (payload) => {
+      set({
+        queue: payload.tracks,
+        currentIndex: payload.current ?? -1,
+        isShuffling: payload.shuffle,
+        repeatMode: fromQueueRepeat[payload.repeat],
+      });
     },
 
-    
     _handleRustStateChange: (payload) => {
       set({
         isPlaying: payload.state === "playing",
@@ -487,6 +213,12 @@ listen<PlaybackProgressPayload>("playback-progress", (event) => {
   usePlayerStore.getState().actions._handleProgress(event.payload);
 });
 listen<PlaybackEndedPayload>("playback-ended", (event) => {
-  console.log("Track ended, triggering next track logic:", event.payload.path);
-  usePlayerStore.getState().actions._handleTrackEnded();
+  console.log("Track ended:", event.payload.path);
+});
+listen<QueueSnapshot>("playback-queue-changed", (event) => {
+  usePlayerStore.getState().actions._handleQueueChanged(event.payload);
 });
+api
+  .player_get_queue()
+  .then((queue) => usePlayerStore.getState().actions._handleQueueChanged(queue))
+  .catch(console.error);
@@ -14,6 +14,7 @@ export enum Score {
 }
 
 export interface Song {
+  id: string;
   path: string;
   title: string | null;
   artist: string | null;
@@ -22,6 +23,8 @@ export interface Song {
   score: Score | null;
   created_at: string;
   updated_at: string;
+  // set while the song's library root is offline (unplugged drive)
+  unavailable?: boolean;
 }
 
 export interface Release {
@@ -37,6 +40,7 @@ export interface Artist {
 
 export interface Playlist {
   name: string;
+  // track ids, see Song.id
   songs: string[];
   created_at: string;
 }
@@ -55,7 +59,69 @@ export interface Alist {
   elements: Aelement[];
 }
 
+export type SongField =
+  | "title"
+  | "artist"
+  | "release"
+  | "path"
+  | "score"
+  | "duration"
+  | "created_at"
+  | "updated_at";
+
+// score takes a Score name or a number, dates take rfc3339 or epoch millis
+export type SongPredicate = { field: SongField } & (
+  | {
+      op: "eq" | "ne" | "lt" | "lte" | "gt" | "gte";
+      value: string | number;
+    }
+  | { op: "in"; value: (string | number)[] }
+  | { op: "contains" | "starts_with"; value: string }
+  | { op: "is_null" | "not_null" }
+);
+
+export type SongFilter =
+  | { and: SongFilter[] }
+  | { or: SongFilter[] }
+  | { not: SongFilter }
+  | SongPredicate;
+
+export interface SongQuery {
+  filter?: SongFilter;
+  sort?: { field: SongField; desc?: boolean }[];
+  offset?: number;
+  limit?: number;
+}
+
+export interface SongPage {
+  total: number;
+  offset: number;
+  songs: Song[];
+}
+
+export interface SongHit {
+  song: Song;
+  score: number;
+  fields: ("title" | "artist" | "release" | "lyric")[];
+}
+
+export interface LibraryManifest {
+  format: number;
+  app_version: string;
+  created_at: string;
+  // first one is the single song dir
+  roots: string[];
+  // the same roots with their rules, empty in older archives
+  listen_paths: ListenPath[];
+  songs: number;
+  playlists: number;
+  alists: number;
+  recents: number;
+  caches: boolean;
+}
+
 export interface Recent {
+  // track id
   song: string;
   accessed_at: string | null;
 }
@@ -99,3 +165,168 @@ export interface PlaybackProgressPayload {
 export interface PlaybackEndedPayload {
   path: string;
 }
+
+export type QueueRepeat = "off" | "one" | "all";
+
+export type QueueSource =
+  | { kind: "paths"; paths: string[] }
+  | { kind: "playlist"; name: string }
+  | { kind: "alist"; name: string }
+  | { kind: "release"; title: string; artist: string | null };
+
+// playback-queue-changed
+export interface QueueSnapshot {
+  tracks: string[];
+  // index into tracks
+  current: number | null;
+  // play order, indices into tracks
+  order: number[];
+  repeat: QueueRepeat;
+  shuffle: boolean;
+}
+
+export type ScanPhase = "cached" | "parse" | "save";
+
+export type ScanState =
+  | "idle"
+  | "running"
+  | "paused"
+  | "cancelled"
+  | "done"
+  | "failed";
+
+export interface ScanStatus {
+  state: ScanState;
+  progress: {
+    phase: ScanPhase;
+    processed: number;
+    total: number;
+    added: number;
+    unchanged: number;
+    expired: number;
+    removed: number;
+    failed: number;
+  };
+  started_at: string | null;
+  finished_at: string | null;
+  // an interrupted scan was picked up
+  resumed: boolean;
+  error: string | null;
+}
+
+export type ScanFailureReason =
+  | "unreadable"
+  | "unsupported"
+  | "malformed"
+  | "bad_tags"
+  | "other";
+
+export interface ScanFailure {
+  path: string;
+  reason: ScanFailureReason;
+  message: string;
+}
+
+export interface ScanReport {
+  updated_at: string | null;
+  failures: ScanFailure[];
+}
+
+// patterns with a "/" match the path relative to the root, others any file / folder name
+export interface DirRules {
+  include: string[];
+  exclude: string[];
+  max_depth: number | null;
+  follow_symlinks: boolean;
+  skip_hidden: boolean;
+  watch: WatchMode;
+}
+
+// network shares / FUSE mounts send no file events, poll them instead
+export type WatchMode =
+  | { mode: "native" }
+  | { mode: "poll"; interval_secs: number }
+  | { mode: "manual" };
+
+// a bare path when the root has no rules
+export type ListenPath = string | ({ path: string } & Partial<DirRules>);
+
+export type DoctorIssue =
+  | { kind: "missing_file"; path: string }
+  | { kind: "stale_placeholder"; cache: string; song: string }
+  | { kind: "orphaned_cache"; cache: string }
+  | { kind: "playlist_ref"; playlist: string; song: string }
+  | { kind: "recent_ref"; song: string }
+  | { kind: "alist_missing"; alist: string; index: number }
+  | { kind: "alist_drifted"; alist: string; index: number };
+
+export interface DoctorReport {
+  checked_at: string;
+  repaired: boolean;
+  counts: Partial<Record<DoctorIssue["kind"], number>>;
+  issues: DoctorIssue[];
+}
+
+export interface DuplicateOptions {
+  match_release?: boolean;
+  duration_tolerance_secs?: number;
+  fingerprint?: boolean;
+}
+
+export interface AudioQuality {
+  lossless: boolean;
+  bit_depth: number;
+  sample_rate: number;
+  bitrate: number;
+}
+
+export interface DuplicateGroup {
+  keeper: string;
+  songs: { song: Song; quality: AudioQuality | null }[];
+}
+
+export interface DuplicateMerge {
+  keeper: string;
+  others: string[];
+}
+
+// releases keyed like `ReleaseOp::from_songs`, all of them if empty
+export interface LoudnessOptions {
+  releases?: { title: string; artist: string | null }[];
+  // write REPLAYGAIN_* tags into the files
+  write_tags?: boolean;
+}
+
+export interface Loudness {
+  // LUFS
+  integrated: number;
+  // LU
+  range: number;
+  // linear, 1.0 is full scale
+  true_peak: number;
+}
+
+export interface ReleaseLoudness {
+  title: string;
+  artist: string | null;
+  loudness: Loudness;
+  tracks: { path: string; loudness: Loudness }[];
+}
+
+export type LoudnessState = "idle" | "running" | "cancelled" | "done";
+
+export interface LoudnessStatus {
+  state: LoudnessState;
+  processed: number;
+  total: number;
+  started_at: string | null;
+  finished_at: string | null;
+  releases: ReleaseLoudness[];
+  failures: { path: string; error: string }[];
+}
+
+export interface RootStatus {
+  path: string;
+  online: boolean;
+  songs: number;
+}